@@ -20,6 +20,19 @@ export declare class ClientBuilder {
   setBaseUrl(url: string): void
   /** Set the API key for authentication */
   setApiKey(apiKey: string): void
+  /** Set how many times a failed upload is retried */
+  setMaxRetries(maxRetries: number): void
+  /** Set a directory where uploads are kept until the server accepted them */
+  setSpoolDir(dir: string): void
+  /**
+   * Set how many observations can wait for upload before the queue policy
+   * applies
+   */
+  setQueueCapacity(capacity: number): void
+  /** Compress uploaded payloads with `gzip` or `zstd` */
+  setCompression(encoding: string): void
+  /** Set the project executions are created in */
+  setProject(project: string): void
   /** Build the client */
   build(): Client
 }
@@ -1,28 +1,69 @@
 //! Client for communicating with the observation-tools server
 
+use crate::error::Error;
 use crate::error::Result;
+use crate::error::UploadError;
 use crate::execution::BeginExecution;
 use crate::execution::ExecutionHandle;
 use crate::observation_handle::ObservationHandle;
-use async_channel;
+use crate::queue::QueuePolicy;
+use crate::queue::UploadQueue;
+use crate::queue::UploadQueueReceiver;
+use crate::queue::DEFAULT_QUEUE_CAPACITY;
+use crate::retry::RetryPolicy;
+use crate::server_client::types::SnapshotCheck;
+use crate::server_client::ObservationToolsServerClientOpts;
+use crate::spool::Spool;
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
 use log::error;
 use log::info;
 use log::trace;
+use log::warn;
 use napi_derive::napi;
+use chrono::DateTime;
+use chrono::Utc;
 use observation_tools_shared::models::Execution;
+use observation_tools_shared::models::ExecutionId;
+use observation_tools_shared::ContentEncoding;
+use observation_tools_shared::ExecutionStatus;
 use observation_tools_shared::Observation;
+use observation_tools_shared::ObservationId;
+use observation_tools_shared::ProjectId;
+use serde::Deserialize;
+use serde::Serialize;
 // Re-export constants from shared crate for convenience
 pub use observation_tools_shared::BATCH_SIZE;
 pub use observation_tools_shared::BLOB_THRESHOLD_BYTES;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
 
 /// Result type for observation upload completion notifications via watch
-/// channel Uses String for error since crate::Error doesn't implement Clone
-pub(crate) type ObservationUploadResult = Option<std::result::Result<ObservationHandle, String>>;
+/// channel Uses UploadError since crate::Error doesn't implement Clone
+pub(crate) type ObservationUploadResult =
+  Option<std::result::Result<ObservationHandle, UploadError>>;
 
 /// Result type for execution upload completion notifications via watch channel
-/// Uses String for error since crate::Error doesn't implement Clone
-pub(crate) type ExecutionUploadResult = Option<std::result::Result<ExecutionHandle, String>>;
+/// Uses UploadError since crate::Error doesn't implement Clone
+pub(crate) type ExecutionUploadResult = Option<std::result::Result<ExecutionHandle, UploadError>>;
+
+/// Result type for execution update notifications via watch channel
+/// Uses UploadError since crate::Error doesn't implement Clone
+pub(crate) type ExecutionUpdateResult = Option<std::result::Result<(), UploadError>>;
+
+/// Changes to a stored execution, fields that are `None` are left unchanged
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct ExecutionUpdate {
+  pub(crate) status: Option<ExecutionStatus>,
+  pub(crate) ended_at: Option<DateTime<Utc>>,
+  pub(crate) error: Option<String>,
+  pub(crate) metadata: HashMap<String, String>,
+  pub(crate) add_tags: Vec<String>,
+}
 
 /// Message types for the background uploader task
 pub(crate) enum UploaderMessage {
@@ -31,17 +72,41 @@ pub(crate) enum UploaderMessage {
     handle: ExecutionHandle,
     uploaded_tx: tokio::sync::watch::Sender<ExecutionUploadResult>,
   },
+  ExecutionUpdate {
+    execution_id: ExecutionId,
+    update: ExecutionUpdate,
+    uploaded_tx: tokio::sync::watch::Sender<ExecutionUpdateResult>,
+  },
+  /// An observation together with its default payload, queued as one item
+  /// so that the queue policy never drops one without the other
   Observation {
     observation: Observation,
+    payload: PayloadUploadData,
     handle: ObservationHandle,
     uploaded_tx: tokio::sync::watch::Sender<ObservationUploadResult>,
   },
+  /// An additional named payload of an observation, with the count of
+  /// dropped payloads of the handle that sent it
   Payload {
-    observation_id: observation_tools_shared::ObservationId,
-    execution_id: observation_tools_shared::models::ExecutionId,
-    payload_id: observation_tools_shared::PayloadId,
-    name: String,
-    payload: observation_tools_shared::Payload,
+    payload: PayloadUploadData,
+    dropped: Arc<AtomicU64>,
+  },
+  /// An observation whose default payload is read from a stream. The
+  /// uploader sends the stream as soon as it takes the message from the
+  /// queue.
+  StreamedObservation {
+    observation: Observation,
+    mime_type: String,
+    reader: PayloadReader,
+    handle: ObservationHandle,
+    uploaded_tx: tokio::sync::watch::Sender<ObservationUploadResult>,
+  },
+  /// Compare an uploaded observation with its snapshot baseline
+  CheckSnapshot {
+    execution_id: ExecutionId,
+    observation_id: ObservationId,
+    accept: bool,
+    result_tx: tokio::sync::oneshot::Sender<Result<SnapshotCheck>>,
   },
   Flush,
   Shutdown,
@@ -55,6 +120,15 @@ impl std::fmt::Debug for UploaderMessage {
         .debug_struct("Execution")
         .field("execution", execution)
         .finish(),
+      Self::ExecutionUpdate {
+        execution_id,
+        update,
+        ..
+      } => f
+        .debug_struct("ExecutionUpdate")
+        .field("execution_id", execution_id)
+        .field("update", update)
+        .finish(),
       Self::Observation {
         observation,
         handle,
@@ -64,16 +138,31 @@ impl std::fmt::Debug for UploaderMessage {
         .field("observation", observation)
         .field("handle", handle)
         .finish(),
-      Self::Payload {
-        observation_id,
-        payload_id,
-        name,
+      Self::StreamedObservation {
+        observation,
+        handle,
         ..
       } => f
+        .debug_struct("StreamedObservation")
+        .field("observation", observation)
+        .field("handle", handle)
+        .finish(),
+      Self::Payload { payload, .. } => f
         .debug_struct("Payload")
+        .field("observation_id", &payload.observation_id)
+        .field("payload_id", &payload.payload_id)
+        .field("name", &payload.name)
+        .finish(),
+      Self::CheckSnapshot {
+        execution_id,
+        observation_id,
+        accept,
+        ..
+      } => f
+        .debug_struct("CheckSnapshot")
+        .field("execution_id", execution_id)
         .field("observation_id", observation_id)
-        .field("payload_id", payload_id)
-        .field("name", name)
+        .field("accept", accept)
         .finish(),
       Self::Flush => write!(f, "Flush"),
       Self::Shutdown => write!(f, "Shutdown"),
@@ -81,6 +170,9 @@ impl std::fmt::Debug for UploaderMessage {
   }
 }
 
+/// Source of a streamed payload
+pub(crate) type PayloadReader = Box<dyn tokio::io::AsyncRead + Send + Unpin>;
+
 /// Client for observation-tools
 #[napi]
 #[derive(Clone)]
@@ -90,7 +182,8 @@ pub struct Client {
 
 struct ClientInner {
   base_url: String,
-  uploader_tx: async_channel::Sender<UploaderMessage>,
+  project: Option<ProjectId>,
+  queue: UploadQueue,
   shutdown_rx: std::sync::Mutex<Option<tokio::sync::oneshot::Receiver<()>>>,
   // If we create a runtime for the uploader, we hold it here to keep it alive
   _runtime: Option<Arc<tokio::runtime::Runtime>>,
@@ -157,30 +250,49 @@ impl Client {
     self.begin_execution_internal(execution)
   }
 
-  fn begin_execution_internal(&self, execution: Execution) -> Result<BeginExecution> {
+  /// Begin a new execution with metadata. Metadata and tags can also be
+  /// added after the execution started, see [`ExecutionHandle::set_metadata`]
+  /// and [`ExecutionHandle::add_tag`].
+  pub fn begin_execution_with_metadata(
+    &self,
+    name: impl Into<String>,
+    metadata: HashMap<String, String>,
+  ) -> Result<BeginExecution> {
+    let execution = Execution::with_metadata(name.into(), metadata);
+    self.begin_execution_internal(execution)
+  }
+
+  fn begin_execution_internal(&self, mut execution: Execution) -> Result<BeginExecution> {
+    if let Some(project) = &self.inner.project {
+      execution.project = project.clone();
+    }
     trace!("Beginning new execution with ID {}", execution.id);
     let handle = ExecutionHandle::new(
       execution.id,
-      self.inner.uploader_tx.clone(),
+      self.inner.queue.clone(),
       self.inner.base_url.clone(),
     );
     let (uploaded_tx, uploaded_rx) = tokio::sync::watch::channel(None);
-    self
-      .inner
-      .uploader_tx
-      .try_send(UploaderMessage::Execution {
-        execution: execution.clone(),
-        handle: handle.clone(),
-        uploaded_tx,
-      })?;
+    self.inner.queue.send_control(UploaderMessage::Execution {
+      execution: execution.clone(),
+      handle: handle.clone(),
+      uploaded_tx,
+    })?;
     Ok(BeginExecution::new(handle, uploaded_rx))
   }
 
+  /// Number of observations and named payloads dropped so far because the
+  /// upload queue was full, see [`ClientBuilder::queue_policy`]
+  pub fn dropped_observations(&self) -> u64 {
+    self.inner.queue.dropped()
+  }
+
   /// Shutdown the client and wait for pending uploads
   pub async fn shutdown(&self) -> Result<()> {
-    self.inner.uploader_tx.try_send(UploaderMessage::Shutdown)?;
+    self.inner.queue.send_control(UploaderMessage::Shutdown)?;
     // Wait for the uploader thread to finish
-    if let Some(rx) = self.inner.shutdown_rx.lock().unwrap().take() {
+    let shutdown_rx = self.inner.shutdown_rx.lock().unwrap().take();
+    if let Some(rx) = shutdown_rx {
       let _ = rx.await;
     }
     Ok(())
@@ -190,24 +302,22 @@ impl Client {
 impl Drop for ClientInner {
   fn drop(&mut self) {
     // Best effort shutdown notification
-    let _ = self.uploader_tx.try_send(UploaderMessage::Shutdown);
+    let _ = self.queue.send_control(UploaderMessage::Shutdown);
   }
 }
 
 /// Builder for Client
 #[napi]
+#[derive(Default)]
 pub struct ClientBuilder {
   base_url: Option<String>,
   api_key: Option<String>,
-}
-
-impl Default for ClientBuilder {
-  fn default() -> Self {
-    Self {
-      base_url: None,
-      api_key: None,
-    }
-  }
+  retry: RetryPolicy,
+  spool_dir: Option<PathBuf>,
+  queue_capacity: Option<usize>,
+  queue_policy: QueuePolicy,
+  compression: ContentEncoding,
+  project: Option<ProjectId>,
 }
 
 #[napi]
@@ -229,6 +339,39 @@ impl ClientBuilder {
   pub fn set_api_key(&mut self, api_key: String) {
     self.api_key = Some(api_key);
   }
+
+  /// Set how many times a failed upload is retried
+  #[napi]
+  pub fn set_max_retries(&mut self, max_retries: u32) {
+    self.retry.max_retries = max_retries;
+  }
+
+  /// Set a directory where uploads are kept until the server accepted them
+  #[napi]
+  pub fn set_spool_dir(&mut self, dir: String) {
+    self.spool_dir = Some(dir.into());
+  }
+
+  /// Set how many observations can wait for upload before the queue policy
+  /// applies
+  #[napi]
+  pub fn set_queue_capacity(&mut self, capacity: u32) {
+    self.queue_capacity = Some(capacity as usize);
+  }
+
+  /// Compress uploaded payloads with `gzip` or `zstd`
+  #[napi]
+  pub fn set_compression(&mut self, encoding: String) -> napi::Result<()> {
+    self.compression = encoding.parse().map_err(Error::from)?;
+    Ok(())
+  }
+
+  /// Set the project executions are created in
+  #[napi]
+  pub fn set_project(&mut self, project: String) -> napi::Result<()> {
+    self.project = Some(ProjectId::parse(&project).map_err(Error::from)?);
+    Ok(())
+  }
 }
 
 impl ClientBuilder {
@@ -243,6 +386,59 @@ impl ClientBuilder {
     self.api_key = Some(api_key.into());
     self
   }
+
+  /// Set how many times an upload that failed with a transient error, such
+  /// as a connection failure or a 5xx response, is retried. Defaults to
+  /// [`DEFAULT_MAX_RETRIES`](crate::DEFAULT_MAX_RETRIES).
+  pub fn max_retries(mut self, max_retries: u32) -> Self {
+    self.retry.max_retries = max_retries;
+    self
+  }
+
+  /// Set the delay before the first retry, doubled for every further attempt.
+  /// Defaults to [`DEFAULT_RETRY_BACKOFF`](crate::DEFAULT_RETRY_BACKOFF).
+  pub fn retry_backoff(mut self, backoff: Duration) -> Self {
+    self.retry.initial_backoff = backoff;
+    self
+  }
+
+  /// Keep uploads in this directory until the server accepted them, so that
+  /// observations survive a server outage or a crash. Uploads left from a
+  /// previous run are sent when the client starts.
+  pub fn spool_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+    self.spool_dir = Some(dir.into());
+    self
+  }
+
+  /// Set how many observations can wait for upload before the queue policy
+  /// applies. Defaults to [`DEFAULT_QUEUE_CAPACITY`].
+  pub fn queue_capacity(mut self, capacity: usize) -> Self {
+    self.queue_capacity = Some(capacity);
+    self
+  }
+
+  /// Set what happens to observations sent while the upload queue is full.
+  /// Defaults to [`QueuePolicy::Block`].
+  pub fn queue_policy(mut self, policy: QueuePolicy) -> Self {
+    self.queue_policy = policy;
+    self
+  }
+
+  /// Compress uploaded payloads with this encoding. Only small payloads and
+  /// payloads that do not get smaller are sent as they are. The server must
+  /// support compressed uploads, so this is off by default.
+  pub fn compression(mut self, encoding: ContentEncoding) -> Self {
+    self.compression = encoding;
+    self
+  }
+
+  /// Create executions in this project. Defaults to the server's default
+  /// project. An API key bound to a project can only create executions in
+  /// that project.
+  pub fn project(mut self, project: ProjectId) -> Self {
+    self.project = Some(project);
+    self
+  }
 }
 
 #[napi]
@@ -254,9 +450,10 @@ impl ClientBuilder {
       .base_url
       .clone()
       .unwrap_or_else(|| "http://localhost:3000".to_string());
-    let (tx, rx) = async_channel::unbounded();
+    let capacity = self.queue_capacity.unwrap_or(DEFAULT_QUEUE_CAPACITY);
+    let (queue, rx) = UploadQueue::new(capacity, self.queue_policy);
     let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
-    let timer_tx = tx.clone();
+    let timer_tx = queue.control_sender();
     let uploader_base_url = base_url.clone();
     let api_key = self.api_key.clone();
     let (handle, runtime) = match tokio::runtime::Handle::try_current() {
@@ -271,7 +468,15 @@ impl ClientBuilder {
         (runtime.handle().clone(), Some(runtime))
       }
     };
-    let api_client = crate::server_client::create_client(&uploader_base_url, api_key.clone())?;
+    let api_client = crate::server_client::create_client_with_opts(
+      &uploader_base_url,
+      ObservationToolsServerClientOpts {
+        api_key: api_key.clone(),
+        compression: self.compression,
+      },
+    )?;
+    let spool = self.spool_dir.as_ref().map(Spool::open).transpose()?;
+    let uploader = Uploader::new(api_client, self.retry, spool, capacity);
     handle.spawn(async move {
       tokio::spawn(async move {
         let mut interval = tokio::time::interval(tokio::time::Duration::from_millis(100));
@@ -283,13 +488,14 @@ impl ClientBuilder {
           }
         }
       });
-      uploader_task(api_client, rx).await;
+      crate::queue::run_uploader(uploader_task(uploader, rx)).await;
       let _ = shutdown_tx.send(());
     });
     Ok(Client {
       inner: Arc::new(ClientInner {
         base_url,
-        uploader_tx: tx,
+        project: self.project.clone(),
+        queue,
         shutdown_rx: std::sync::Mutex::new(Some(shutdown_rx)),
         _runtime: runtime,
       }),
@@ -298,7 +504,7 @@ impl ClientBuilder {
 }
 
 /// Data for a payload ready to be uploaded
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub(crate) struct PayloadUploadData {
   pub(crate) observation_id: observation_tools_shared::ObservationId,
   pub(crate) execution_id: observation_tools_shared::models::ExecutionId,
@@ -306,58 +512,399 @@ pub(crate) struct PayloadUploadData {
   pub(crate) name: String,
   pub(crate) mime_type: String,
   pub(crate) size: usize,
+  #[serde(with = "base64_bytes")]
   pub(crate) data: Vec<u8>,
+  /// Digest of content already stored on the server, `data` is empty
+  #[serde(default)]
+  pub(crate) digest: Option<String>,
+}
+
+impl PayloadUploadData {
+  pub(crate) fn new(
+    observation_id: observation_tools_shared::ObservationId,
+    execution_id: observation_tools_shared::models::ExecutionId,
+    name: String,
+    payload: observation_tools_shared::Payload,
+  ) -> Self {
+    Self {
+      observation_id,
+      execution_id,
+      payload_id: observation_tools_shared::PayloadId::new(),
+      name,
+      mime_type: payload.mime_type,
+      size: payload.size,
+      data: payload.data,
+      digest: None,
+    }
+  }
+}
+
+/// Serialize payload bytes as base64 rather than a JSON array of numbers
+mod base64_bytes {
+  use super::*;
+
+  pub fn serialize<S: serde::Serializer>(
+    data: &[u8],
+    serializer: S,
+  ) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&BASE64_STANDARD.encode(data))
+  }
+
+  pub fn deserialize<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+  ) -> std::result::Result<Vec<u8>, D::Error> {
+    let encoded = String::deserialize(deserializer)?;
+    BASE64_STANDARD
+      .decode(encoded)
+      .map_err(serde::de::Error::custom)
+  }
+}
+
+/// A single request to the server, kept until it succeeded
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum Upload {
+  Execution {
+    execution: Execution,
+  },
+  ExecutionUpdate {
+    execution_id: ExecutionId,
+    update: ExecutionUpdate,
+  },
+  Observations {
+    observations: Vec<Observation>,
+    payloads: Vec<PayloadUploadData>,
+  },
 }
 
-async fn uploader_task(
+impl Upload {
+  /// Short description for log messages
+  fn describe(&self) -> String {
+    match self {
+      Upload::Execution { execution } => format!("execution {}", execution.id),
+      Upload::ExecutionUpdate { execution_id, .. } => {
+        format!("update of execution {}", execution_id)
+      }
+      Upload::Observations {
+        observations,
+        payloads,
+      } => format!(
+        "{} observations and {} payloads",
+        observations.len(),
+        payloads.len()
+      ),
+    }
+  }
+
+  async fn send(&self, client: &crate::server_client::Client) -> Result<()> {
+    match self {
+      Upload::Execution { execution } => upload_execution(client, execution).await,
+      Upload::ExecutionUpdate {
+        execution_id,
+        update,
+      } => upload_execution_update(client, *execution_id, update).await,
+      Upload::Observations {
+        observations,
+        payloads,
+      } => upload_observations(client, observations, payloads).await,
+    }
+  }
+}
+
+// Buffer type for observation senders: (handle, sender)
+type ObservationSender = (
+  ObservationHandle,
+  tokio::sync::watch::Sender<ObservationUploadResult>,
+);
+
+/// Sends uploads to the server, retrying transient failures and, if a spool
+/// is configured, keeping uploads on disk until they succeeded
+struct Uploader {
   api_client: crate::server_client::Client,
-  rx: async_channel::Receiver<UploaderMessage>,
-) {
-  info!("Uploader task started");
+  retry: RetryPolicy,
+  spool: Option<Spool>,
+  /// Set while the server is unavailable, the time of the next attempt to
+  /// send spooled uploads
+  replay_at: Option<Instant>,
+  /// Number of observations buffered before the uploader stops taking more
+  /// from the queue and waits for the next flush
+  buffer_capacity: usize,
+  observation_buffer: Vec<Observation>,
+  sender_buffer: Vec<ObservationSender>,
+  payload_buffer: Vec<PayloadUploadData>,
+}
 
-  // Buffer type for observation senders: (handle, sender)
-  type ObservationSender = (
-    ObservationHandle,
-    tokio::sync::watch::Sender<ObservationUploadResult>,
-  );
+impl Uploader {
+  fn new(
+    api_client: crate::server_client::Client,
+    retry: RetryPolicy,
+    spool: Option<Spool>,
+    buffer_capacity: usize,
+  ) -> Self {
+    Self {
+      api_client,
+      retry,
+      spool,
+      replay_at: None,
+      buffer_capacity,
+      observation_buffer: Vec::new(),
+      sender_buffer: Vec::new(),
+      payload_buffer: Vec::new(),
+    }
+  }
 
-  let flush = async |observation_buffer: &mut Vec<Observation>,
-                     senders: &mut Vec<ObservationSender>,
-                     payload_buffer: &mut Vec<PayloadUploadData>| {
-    if observation_buffer.is_empty() && payload_buffer.is_empty() {
-      return;
+  /// Send an upload, retrying transient failures
+  async fn send(&mut self, upload: Upload) -> Result<()> {
+    let Some(spool) = &mut self.spool else {
+      return self.send_with_retry(&upload).await;
+    };
+    let seq = match spool.push(&upload) {
+      Ok(seq) => seq,
+      Err(e) => {
+        error!("Failed to spool {}, sending directly: {}", upload.describe(), e);
+        return self.send_with_retry(&upload).await;
+      }
+    };
+    if self.replay_at.is_some_and(|at| Instant::now() < at) {
+      return Err(Error::UploadFailed(format!(
+        "server unavailable, {} kept in spool",
+        upload.describe()
+      )));
     }
-    let result =
-      upload_observations(&api_client, observation_buffer.drain(..).collect(), payload_buffer.drain(..).collect()).await;
-    match result {
-      Ok(()) => {
-        // Signal all senders that observations were uploaded successfully
-        for (handle, sender) in senders.drain(..) {
-          let _ = sender.send(Some(Ok(handle)));
+    self.replay(Some(seq)).await
+  }
+
+  async fn send_with_retry(&self, upload: &Upload) -> Result<()> {
+    let what = upload.describe();
+    self
+      .retry
+      .run(&what, || upload.send(&self.api_client))
+      .await
+  }
+
+  /// Send spooled uploads oldest first, stopping at the first one the server
+  /// is unavailable for. Returns the result for the upload `target`.
+  async fn replay(&mut self, target: Option<u64>) -> Result<()> {
+    let Some(spool) = &self.spool else {
+      return Ok(());
+    };
+    let mut target_result = Ok(());
+    for seq in spool.pending()? {
+      let upload = match spool.read(seq) {
+        Ok(upload) => upload,
+        Err(e) => {
+          error!("Dropping unreadable spooled upload {}: {}", seq, e);
+          spool.remove(seq)?;
+          continue;
+        }
+      };
+      match self.send_with_retry(&upload).await {
+        Ok(()) => spool.remove(seq)?,
+        Err(e) if e.is_transient() => {
+          warn!("Server unavailable, keeping uploads in spool: {}", e);
+          self.replay_at = Some(Instant::now() + self.retry.backoff(self.retry.max_retries));
+          return Err(Error::UploadFailed(format!(
+            "{}, upload kept in spool",
+            e
+          )));
+        }
+        Err(e) => {
+          error!("Dropping {} rejected by the server: {}", upload.describe(), e);
+          spool.remove(seq)?;
+          if target == Some(seq) {
+            target_result = Err(e);
+          }
         }
       }
-      Err(e) => {
-        let error_msg = e.to_string();
-        error!("Failed to upload observations: {}", error_msg);
-        // Signal all senders with the error (as String for Clone compatibility)
-        for (_, sender) in senders.drain(..) {
-          let _ = sender.send(Some(Err(error_msg.clone())));
+    }
+    self.replay_at = None;
+    target_result
+  }
+
+  /// Retry spooled uploads once the server may be available again
+  async fn replay_if_due(&mut self) {
+    if self.replay_at.is_some_and(|at| Instant::now() >= at) {
+      if let Err(e) = self.replay(None).await {
+        trace!("Spooled uploads not sent yet: {}", e);
+      }
+    }
+  }
+
+  fn has_room(&self) -> bool {
+    self.observation_buffer.len() < self.buffer_capacity
+  }
+
+  /// Buffer an observation or payload taken from the queue
+  async fn buffer(&mut self, msg: UploaderMessage) {
+    match msg {
+      UploaderMessage::Observation {
+        observation,
+        payload,
+        handle,
+        uploaded_tx,
+      } => {
+        self.observation_buffer.push(observation);
+        self.payload_buffer.push(payload);
+        self.sender_buffer.push((handle, uploaded_tx));
+      }
+      UploaderMessage::Payload { payload, .. } => self.payload_buffer.push(payload),
+      UploaderMessage::StreamedObservation {
+        observation,
+        mime_type,
+        reader,
+        handle,
+        uploaded_tx,
+      } => {
+        let mut payload = PayloadUploadData {
+          observation_id: observation.id,
+          execution_id: observation.execution_id,
+          payload_id: observation_tools_shared::PayloadId::new(),
+          name: crate::server_client::DEFAULT_PAYLOAD_NAME.to_string(),
+          mime_type,
+          size: 0,
+          data: Vec::new(),
+          digest: None,
+        };
+        match self.upload_stream(&payload, reader).await {
+          Ok((size, digest)) => {
+            payload.size = size;
+            payload.digest = Some(digest);
+          }
+          Err(e) => {
+            let error_msg = e.to_string();
+            error!("Failed to upload streamed payload: {}", error_msg);
+            let _ = uploaded_tx.send(Some(Err(UploadError::from(&e))));
+            return;
+          }
         }
+        self.observation_buffer.push(observation);
+        self.payload_buffer.push(payload);
+        self.sender_buffer.push((handle, uploaded_tx));
       }
+      other => warn!("Unexpected message in observation queue: {:?}", other),
     }
-  };
-  let mut observation_buffer: Vec<Observation> = Vec::new();
-  let mut sender_buffer: Vec<ObservationSender> = Vec::new();
-  let mut payload_buffer: Vec<PayloadUploadData> = Vec::new();
+  }
+
+  /// Send a streamed payload's content. A stream can only be read once, so
+  /// it is neither retried nor spooled.
+  async fn upload_stream(
+    &self,
+    payload: &PayloadUploadData,
+    reader: PayloadReader,
+  ) -> Result<(usize, String)> {
+    let body = reqwest::Body::wrap_stream(tokio_util::io::ReaderStream::new(reader));
+    let (size, digest) = self
+      .api_client
+      .put_payload(
+        &payload.execution_id.to_string(),
+        payload.observation_id,
+        &payload.payload_id,
+        body,
+        None,
+      )
+      .await
+      .map_err(Error::from_anyhow)?;
+    Ok((size as usize, digest))
+  }
+
+  /// Upload everything already queued, e.g. before an execution update or
+  /// on shutdown
+  async fn drain(&mut self, rx: &async_channel::Receiver<UploaderMessage>) {
+    loop {
+      while self.has_room() {
+        match rx.try_recv() {
+          Ok(msg) => self.buffer(msg).await,
+          Err(_) => break,
+        }
+      }
+      self.flush().await;
+      if rx.is_empty() {
+        break;
+      }
+    }
+  }
+
+  /// Upload all buffered observations and payloads
+  async fn flush(&mut self) {
+    if self.observation_buffer.is_empty() && self.payload_buffer.is_empty() {
+      return;
+    }
+    // One upload per execution, so that retrying or spooling a batch that
+    // failed does not send the batches of other executions again
+    let mut batches: HashMap<ExecutionId, (Vec<Observation>, Vec<PayloadUploadData>)> =
+      HashMap::new();
+    for observation in self.observation_buffer.drain(..) {
+      batches
+        .entry(observation.execution_id)
+        .or_default()
+        .0
+        .push(observation);
+    }
+    for payload in self.payload_buffer.drain(..) {
+      batches.entry(payload.execution_id).or_default().1.push(payload);
+    }
+    let mut senders = std::mem::take(&mut self.sender_buffer);
+    for (execution_id, (observations, payloads)) in batches {
+      let upload = Upload::Observations {
+        observations,
+        payloads,
+      };
+      let result = self.send(upload).await;
+      let (batch_senders, rest): (Vec<_>, Vec<_>) = senders
+        .into_iter()
+        .partition(|(handle, _)| handle.execution_id == execution_id);
+      senders = rest;
+      match result {
+        Ok(()) => {
+          // Signal all senders that observations were uploaded successfully
+          for (handle, sender) in batch_senders {
+            let _ = sender.send(Some(Ok(handle)));
+          }
+        }
+        Err(e) => {
+          let error_msg = e.to_string();
+          error!("Failed to upload observations: {}", error_msg);
+          // Signal all senders with the error
+          let upload_error = UploadError::from(&e);
+          for (_, sender) in batch_senders {
+            let _ = sender.send(Some(Err(upload_error.clone())));
+          }
+        }
+      }
+    }
+  }
+}
+
+async fn uploader_task(mut uploader: Uploader, rx: UploadQueueReceiver) {
+  info!("Uploader task started");
+
+  // Send uploads left over from a previous run first
+  if uploader.spool.is_some() {
+    if let Err(e) = uploader.replay(None).await {
+      warn!("Failed to send spooled uploads: {}", e);
+    }
+  }
   loop {
-    let msg = rx.recv().await.ok();
+    // Control messages go first. Observations are only taken from the queue
+    // while the buffer has room, a full buffer is uploaded right away.
+    let msg = tokio::select! {
+      biased;
+      msg = rx.control_rx.recv() => msg.ok(),
+      Ok(msg) = rx.observation_rx.recv(), if uploader.has_room() => {
+        uploader.buffer(msg).await;
+        if !uploader.has_room() {
+          uploader.flush().await;
+        }
+        continue;
+      }
+    };
     match msg {
       Some(UploaderMessage::Execution {
         execution,
         handle,
         uploaded_tx,
       }) => {
-        let result = upload_execution(&api_client, execution).await;
+        let result = uploader.send(Upload::Execution { execution }).await;
         match result {
           Ok(()) => {
             let _ = uploaded_tx.send(Some(Ok(handle)));
@@ -365,40 +912,58 @@ async fn uploader_task(
           Err(e) => {
             let error_msg = e.to_string();
             error!("Failed to upload execution: {}", error_msg);
-            let _ = uploaded_tx.send(Some(Err(error_msg)));
+            let _ = uploaded_tx.send(Some(Err(UploadError::from(&e))));
           }
         }
       }
-      Some(UploaderMessage::Observation {
-        observation,
-        handle,
+      Some(UploaderMessage::ExecutionUpdate {
+        execution_id,
+        update,
         uploaded_tx,
       }) => {
-        observation_buffer.push(observation);
-        sender_buffer.push((handle, uploaded_tx));
+        // Send queued observations first so that they arrive before the
+        // execution is marked as finished
+        uploader.drain(&rx.observation_rx).await;
+        let result = uploader
+          .send(Upload::ExecutionUpdate {
+            execution_id,
+            update,
+          })
+          .await;
+        match result {
+          Ok(()) => {
+            let _ = uploaded_tx.send(Some(Ok(())));
+          }
+          Err(e) => {
+            let error_msg = e.to_string();
+            error!("Failed to update execution: {}", error_msg);
+            let _ = uploaded_tx.send(Some(Err(UploadError::from(&e))));
+          }
+        }
       }
-      Some(UploaderMessage::Payload {
-        observation_id,
+      Some(
+        msg @ (UploaderMessage::Observation { .. }
+        | UploaderMessage::Payload { .. }
+        | UploaderMessage::StreamedObservation { .. }),
+      ) => {
+        uploader.buffer(msg).await;
+      }
+      Some(UploaderMessage::CheckSnapshot {
         execution_id,
-        payload_id,
-        name,
-        payload,
+        observation_id,
+        accept,
+        result_tx,
       }) => {
-        payload_buffer.push(PayloadUploadData {
-          observation_id,
-          execution_id,
-          payload_id,
-          name,
-          mime_type: payload.mime_type,
-          size: payload.size,
-          data: payload.data,
-        });
+        let result =
+          check_snapshot(&uploader.api_client, execution_id, observation_id, accept).await;
+        let _ = result_tx.send(result);
       }
       Some(UploaderMessage::Flush) => {
-        flush(&mut observation_buffer, &mut sender_buffer, &mut payload_buffer).await;
+        uploader.flush().await;
+        uploader.replay_if_due().await;
       }
       Some(UploaderMessage::Shutdown) | None => {
-        flush(&mut observation_buffer, &mut sender_buffer, &mut payload_buffer).await;
+        uploader.drain(&rx.observation_rx).await;
         break;
       }
     }
@@ -408,12 +973,12 @@ async fn uploader_task(
 // Async upload functions (used by both native and WASM)
 async fn upload_execution(
   client: &crate::server_client::Client,
-  execution: Execution,
+  execution: &Execution,
 ) -> Result<()> {
   trace!("Uploading execution");
 
   // Convert from shared type to OpenAPI type via serde
-  let execution_json = serde_json::to_value(&execution)?;
+  let execution_json = serde_json::to_value(execution)?;
   let openapi_execution: crate::server_client::types::Execution =
     serde_json::from_value(execution_json)?;
 
@@ -422,21 +987,69 @@ async fn upload_execution(
     .body_map(|b| b.execution(openapi_execution))
     .send()
     .await
-    .map_err(|e| crate::error::Error::Config(e.to_string()))?;
+    .map_err(Error::from_api)?;
 
   Ok(())
 }
 
+async fn upload_execution_update(
+  client: &crate::server_client::Client,
+  execution_id: ExecutionId,
+  update: &ExecutionUpdate,
+) -> Result<()> {
+  trace!("Updating execution {}", execution_id);
+
+  // Convert from shared type to OpenAPI type via serde
+  let status: Option<crate::server_client::types::ExecutionStatus> = update
+    .status
+    .map(|status| serde_json::from_value(serde_json::to_value(status)?))
+    .transpose()?;
+
+  client
+    .update_execution()
+    .id(execution_id.to_string())
+    .body_map(|b| {
+      b.status(status)
+        .ended_at(update.ended_at)
+        .error(update.error.clone())
+        .metadata(update.metadata.clone())
+        .add_tags(update.add_tags.clone())
+    })
+    .send()
+    .await
+    .map_err(Error::from_api)?;
+
+  Ok(())
+}
+
+async fn check_snapshot(
+  client: &crate::server_client::Client,
+  execution_id: ExecutionId,
+  observation_id: ObservationId,
+  accept: bool,
+) -> Result<SnapshotCheck> {
+  trace!("Checking snapshot {}", observation_id);
+  let response = client
+    .check_snapshot()
+    .execution_id(execution_id.to_string())
+    .observation_id(observation_id.to_string())
+    .body_map(|b| b.accept(accept))
+    .send()
+    .await
+    .map_err(Error::from_api)?;
+  Ok(response.into_inner().snapshot)
+}
+
 async fn upload_observations(
   client: &crate::server_client::Client,
-  observations: Vec<Observation>,
-  payloads: Vec<PayloadUploadData>,
+  observations: &[Observation],
+  payloads: &[PayloadUploadData],
 ) -> Result<()> {
   if observations.is_empty() && payloads.is_empty() {
     return Ok(());
   }
 
-  // Group by execution_id
+  // Uploads hold a single execution, except ones spooled by older versions
   let mut by_execution: std::collections::HashMap<_, (Vec<_>, Vec<_>)> =
     std::collections::HashMap::new();
   for obs in observations {
@@ -464,9 +1077,9 @@ async fn upload_observations(
     );
 
     client
-      .create_observations_multipart(&execution_id.to_string(), observations, payloads)
+      .create_observations_multipart(&execution_id.to_string(), &observations, &payloads)
       .await
-      .map_err(|e| crate::error::Error::Config(e.to_string()))?;
+      .map_err(Error::from_anyhow)?;
   }
 
   Ok(())
@@ -2,6 +2,7 @@
 
 use crate::client::UploaderMessage;
 use async_channel::TrySendError;
+use observation_tools_shared::QuotaExceeded;
 use thiserror::Error;
 
 /// Result type for client operations
@@ -14,10 +15,18 @@ pub enum Error {
   #[error("HTTP error: {0}")]
   Http(#[from] reqwest::Error),
 
+  /// The server responded with an error status
+  #[error("Server responded with {status}: {message}")]
+  Status { status: u16, message: String },
+
   /// Serialization error
   #[error("Serialization error: {0}")]
   Serialization(#[from] serde_json::Error),
 
+  /// IO error, e.g. while writing to the spool directory
+  #[error("IO error: {0}")]
+  Io(#[from] std::io::Error),
+
   /// Shared library error
   #[error("Shared library error: {0}")]
   Shared(#[from] observation_tools_shared::Error),
@@ -26,6 +35,14 @@ pub enum Error {
   #[error("Failed to send observation: channel closed")]
   ChannelClosed,
 
+  /// The server rejected the upload because it would exceed a quota
+  #[error("{0}")]
+  QuotaExceeded(QuotaExceeded),
+
+  /// The observation was dropped because the upload queue was full
+  #[error("Observation dropped because the upload queue was full")]
+  QueueFull,
+
   /// No execution context available
   #[error("No execution context available")]
   NoExecutionContext,
@@ -47,6 +64,91 @@ pub enum Error {
 
   #[error("Creation error")]
   CreationError,
+
+  /// A snapshot differs from its baseline
+  #[error(
+    "Snapshot '{name}' does not match its baseline, see {url} or set {}=1 to accept it",
+    crate::ACCEPT_SNAPSHOTS_ENV
+  )]
+  SnapshotMismatch { name: String, url: String },
+}
+
+impl Error {
+  /// Convert an error from the generated API client, keeping the HTTP status
+  /// so that transient failures can be retried
+  pub(crate) fn from_api<E>(err: progenitor_client::Error<E>) -> Self
+  where
+    progenitor_client::Error<E>: std::fmt::Display,
+  {
+    let message = err.to_string();
+    if let progenitor_client::Error::CommunicationError(e) = err {
+      return Error::Http(e);
+    }
+    match err.status() {
+      Some(status) => Error::Status {
+        status: status.as_u16(),
+        message,
+      },
+      None => Error::Config(message),
+    }
+  }
+
+  /// Convert an error from the hand-written requests in `server_client`
+  pub(crate) fn from_anyhow(err: anyhow::Error) -> Self {
+    let err = match err.downcast::<Error>() {
+      Ok(e) => return e,
+      Err(e) => e,
+    };
+    match err.downcast::<reqwest::Error>() {
+      Ok(e) => Error::Http(e),
+      Err(e) => Error::Config(e.to_string()),
+    }
+  }
+
+  /// Whether retrying the request might succeed, e.g. after a connection
+  /// failure or a 5xx response
+  pub fn is_transient(&self) -> bool {
+    match self {
+      Error::Http(e) => {
+        e.is_connect()
+          || e.is_timeout()
+          || e.is_request()
+          || e.status().is_some_and(|s| is_transient_status(s.as_u16()))
+      }
+      Error::Status { status, .. } => is_transient_status(*status),
+      _ => false,
+    }
+  }
+}
+
+fn is_transient_status(status: u16) -> bool {
+  matches!(status, 408 | 429 | 500..=599)
+}
+
+/// Upload failure reported to everyone waiting for an upload. Unlike
+/// [`Error`] it can be cloned, so it only keeps what callers match on.
+#[derive(Debug, Clone)]
+pub(crate) enum UploadError {
+  QuotaExceeded(QuotaExceeded),
+  Other(String),
+}
+
+impl From<&Error> for UploadError {
+  fn from(err: &Error) -> Self {
+    match err {
+      Error::QuotaExceeded(quota) => UploadError::QuotaExceeded(quota.clone()),
+      other => UploadError::Other(other.to_string()),
+    }
+  }
+}
+
+impl From<UploadError> for Error {
+  fn from(err: UploadError) -> Self {
+    match err {
+      UploadError::QuotaExceeded(quota) => Error::QuotaExceeded(quota),
+      UploadError::Other(message) => Error::UploadFailed(message),
+    }
+  }
 }
 
 impl From<TrySendError<UploaderMessage>> for Error {
@@ -1,12 +1,15 @@
 //! Execution handle for managing observation context
 
+use crate::client::ExecutionUpdate;
+use crate::client::ExecutionUpdateResult;
 use crate::client::ExecutionUploadResult;
 use crate::client::UploaderMessage;
+use crate::queue::UploadQueue;
 use crate::error::Result;
 use crate::Error;
-use async_channel;
 use napi_derive::napi;
 use observation_tools_shared::models::ExecutionId;
+use observation_tools_shared::ExecutionStatus;
 
 pub struct BeginExecution {
   handle: ExecutionHandle,
@@ -32,7 +35,7 @@ impl BeginExecution {
         let value = self.uploaded_rx.borrow_and_update();
         match &*value {
           Some(Ok(handle)) => return Ok(handle.clone()),
-          Some(Err(error_msg)) => return Err(Error::UploadFailed(error_msg.clone())),
+          Some(Err(error)) => return Err(error.clone().into()),
           None => {}
         }
       }
@@ -53,24 +56,50 @@ impl BeginExecution {
   }
 }
 
+/// An execution update that is queued for upload
+pub struct SendExecutionUpdate {
+  uploaded_rx: tokio::sync::watch::Receiver<ExecutionUpdateResult>,
+}
+
+impl SendExecutionUpdate {
+  /// Wait for the update to be applied on the server
+  pub async fn wait_for_upload(mut self) -> Result<()> {
+    loop {
+      {
+        let value = self.uploaded_rx.borrow_and_update();
+        match &*value {
+          Some(Ok(())) => return Ok(()),
+          Some(Err(error)) => return Err(error.clone().into()),
+          None => {}
+        }
+      }
+      self
+        .uploaded_rx
+        .changed()
+        .await
+        .map_err(|_| Error::ChannelClosed)?;
+    }
+  }
+}
+
 /// Handle to an execution that can be used to send observations
 #[napi]
 #[derive(Clone, Debug)]
 pub struct ExecutionHandle {
   pub(crate) execution_id: ExecutionId,
-  pub(crate) uploader_tx: async_channel::Sender<UploaderMessage>,
+  pub(crate) queue: UploadQueue,
   pub(crate) base_url: String,
 }
 
 impl ExecutionHandle {
   pub(crate) fn new(
     execution_id: ExecutionId,
-    uploader_tx: async_channel::Sender<UploaderMessage>,
+    queue: UploadQueue,
     base_url: String,
   ) -> Self {
     Self {
       execution_id,
-      uploader_tx,
+      queue,
       base_url,
     }
   }
@@ -85,11 +114,58 @@ impl ExecutionHandle {
     &self.base_url
   }
 
+  /// Record that the execution finished with the given status and an
+  /// optional error message. Observations sent before this are uploaded
+  /// first.
+  pub fn finish(
+    &self,
+    status: ExecutionStatus,
+    error: Option<String>,
+  ) -> Result<SendExecutionUpdate> {
+    self.send_update(ExecutionUpdate {
+      status: Some(status),
+      ended_at: Some(chrono::Utc::now()),
+      error,
+      ..Default::default()
+    })
+  }
+
+  /// Set a metadata value on the execution, replacing any existing value for
+  /// the key
+  pub fn set_metadata(
+    &self,
+    key: impl Into<String>,
+    value: impl Into<String>,
+  ) -> Result<SendExecutionUpdate> {
+    self.send_update(ExecutionUpdate {
+      metadata: [(key.into(), value.into())].into(),
+      ..Default::default()
+    })
+  }
+
+  /// Add a tag to the execution, e.g. `nightly`
+  pub fn add_tag(&self, tag: impl Into<String>) -> Result<SendExecutionUpdate> {
+    self.send_update(ExecutionUpdate {
+      add_tags: vec![tag.into()],
+      ..Default::default()
+    })
+  }
+
+  fn send_update(&self, update: ExecutionUpdate) -> Result<SendExecutionUpdate> {
+    let (uploaded_tx, uploaded_rx) = tokio::sync::watch::channel(None);
+    self.queue.send_control(UploaderMessage::ExecutionUpdate {
+      execution_id: self.execution_id,
+      update,
+      uploaded_tx,
+    })?;
+    Ok(SendExecutionUpdate { uploaded_rx })
+  }
+
   /// Create a placeholder handle (for stub observations when no execution context exists)
   pub(crate) fn placeholder() -> Self {
     Self {
       execution_id: ExecutionId::nil(),
-      uploader_tx: async_channel::unbounded().0,
+      queue: UploadQueue::closed(),
       base_url: String::new(),
     }
   }
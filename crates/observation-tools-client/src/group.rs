@@ -1,10 +1,10 @@
 //! Group builder and handle types for hierarchical observation grouping
 
-use crate::client::UploaderMessage;
 use crate::context;
 use crate::execution::ExecutionHandle;
 use crate::observation::ObservationBuilder;
 use crate::observation_handle::SendObservation;
+use crate::queue::UploadQueue;
 use crate::Error;
 use observation_tools_shared::GroupId;
 use observation_tools_shared::LogLevel;
@@ -105,13 +105,13 @@ impl GroupBuilder {
 
   /// Build and send the group with an explicit execution handle
   pub fn build_with_execution(self, execution: &ExecutionHandle) -> SendGroup {
-    let group_id = self.custom_id.unwrap_or_else(GroupId::new);
+    let group_id = self.custom_id.unwrap_or_default();
     let observation_id = ObservationId::new();
 
     let group_handle = GroupHandle {
       group_id,
       execution_id: execution.id(),
-      uploader_tx: execution.uploader_tx.clone(),
+      queue: execution.queue.clone(),
       base_url: execution.base_url().to_string(),
     };
 
@@ -151,7 +151,7 @@ impl GroupBuilder {
 pub struct GroupHandle {
   pub(crate) group_id: GroupId,
   pub(crate) execution_id: observation_tools_shared::models::ExecutionId,
-  pub(crate) uploader_tx: async_channel::Sender<UploaderMessage>,
+  pub(crate) queue: UploadQueue,
   pub(crate) base_url: String,
 }
 
@@ -174,7 +174,7 @@ impl GroupHandle {
     Self {
       group_id,
       execution_id: execution.id(),
-      uploader_tx: execution.uploader_tx.clone(),
+      queue: execution.queue.clone(),
       base_url: execution.base_url().to_string(),
     }
   }
@@ -192,7 +192,7 @@ impl SendGroup {
       group_handle: GroupHandle {
         group_id: GroupId::new(),
         execution_id: observation_tools_shared::models::ExecutionId::nil(),
-        uploader_tx: async_channel::unbounded().0,
+        queue: UploadQueue::closed(),
         base_url: String::new(),
       },
       send: SendObservation::stub(error),
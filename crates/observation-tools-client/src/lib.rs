@@ -14,7 +14,10 @@ mod group;
 mod logger;
 mod observation;
 mod observation_handle;
+mod queue;
+mod retry;
 pub mod server_client;
+mod spool;
 
 pub use client::Client;
 pub use client::ClientBuilder;
@@ -27,21 +30,32 @@ pub use error::Error;
 pub use error::Result;
 pub use execution::BeginExecution;
 pub use execution::ExecutionHandle;
+pub use execution::SendExecutionUpdate;
 pub use group::GroupBuilder;
 pub use group::GroupHandle;
 pub use group::SendGroup;
 pub use logger::ObservationLogger;
 pub use observation::ObservationBuilder;
+pub use observation::ACCEPT_SNAPSHOTS_ENV;
 pub use observation_handle::ObservationHandle;
 pub use observation_handle::ObservationPayloadHandle;
 pub use observation_handle::SendObservation;
+pub use queue::QueuePolicy;
+pub use queue::DEFAULT_QUEUE_CAPACITY;
+pub use retry::DEFAULT_MAX_RETRIES;
+pub use retry::DEFAULT_RETRY_BACKOFF;
 // Re-export procedural macros
 pub use observation_tools_macros::group;
 pub use observation_tools_macros::observe;
 // Re-export from shared for convenience
+pub use observation_tools_shared::ContentEncoding;
+pub use observation_tools_shared::ExecutionStatus;
 pub use observation_tools_shared::GroupId;
 pub use observation_tools_shared::Payload;
 pub use observation_tools_shared::PayloadBuilder;
+pub use observation_tools_shared::ProjectId;
+pub use observation_tools_shared::Quota;
+pub use observation_tools_shared::QuotaExceeded;
 
 /// Register a global execution shared across all threads
 ///
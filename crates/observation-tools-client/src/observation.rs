@@ -1,6 +1,7 @@
 //! Observation builder API
 
 use crate::client::ObservationUploadResult;
+use crate::client::PayloadUploadData;
 use crate::client::UploaderMessage;
 use crate::context;
 use crate::execution::ExecutionHandle;
@@ -8,7 +9,9 @@ use crate::group::GroupHandle;
 use crate::observation_handle::ObservationHandle;
 use crate::observation_handle::ObservationPayloadHandle;
 use crate::observation_handle::SendObservation;
+use crate::server_client::types::SnapshotStatus;
 use crate::Error;
+use crate::Result;
 use napi_derive::napi;
 use observation_tools_shared::GroupId;
 use observation_tools_shared::Html;
@@ -18,13 +21,17 @@ use observation_tools_shared::Observation;
 use observation_tools_shared::ObservationId;
 use observation_tools_shared::ObservationType;
 use observation_tools_shared::Payload;
-use observation_tools_shared::PayloadId;
 use observation_tools_shared::SourceInfo;
 use serde::Serialize;
 use std::any::TypeId;
 use std::collections::HashMap;
 use std::fmt::Debug;
 
+/// Environment variable that makes [`ObservationBuilder::snapshot`] accept
+/// snapshots that differ from their baseline, e.g.
+/// `OBSERVATION_TOOLS_ACCEPT_SNAPSHOTS=1 cargo test`
+pub const ACCEPT_SNAPSHOTS_ENV: &str = "OBSERVATION_TOOLS_ACCEPT_SNAPSHOTS";
+
 /// Builder for creating observations
 ///
 /// Use the `observe!` macro or `ObservationBuilder::new()` to create a builder,
@@ -163,6 +170,49 @@ impl ObservationBuilder {
     self.send_observation(payload)
   }
 
+  /// Serialize the value as JSON, send the observation and compare it with
+  /// the snapshot baseline of the same observation name in executions with
+  /// the same name
+  ///
+  /// The first snapshot of a name becomes its baseline. A snapshot that
+  /// differs from its baseline is an error linking to the differences, where
+  /// it can be accepted as the new baseline. Setting [`ACCEPT_SNAPSHOTS_ENV`]
+  /// accepts differing snapshots instead.
+  pub async fn snapshot<T: ?Sized + Serialize + 'static>(
+    mut self,
+    value: &T,
+  ) -> Result<ObservationHandle> {
+    let execution = self
+      .execution
+      .take()
+      .or_else(context::get_current_execution)
+      .ok_or(Error::NoExecutionContext)?;
+    let name = self.name.clone();
+    let mut send = self.execution(&execution).serde(value);
+    let handle = send.wait_for_upload().await?;
+    let accept = std::env::var(ACCEPT_SNAPSHOTS_ENV).is_ok_and(|v| !v.is_empty() && v != "0");
+    let (result_tx, result_rx) = tokio::sync::oneshot::channel();
+    execution.queue.send_control(UploaderMessage::CheckSnapshot {
+      execution_id: execution.id(),
+      observation_id: *handle.id(),
+      accept,
+      result_tx,
+    })?;
+    let check = result_rx.await.map_err(|_| Error::ChannelClosed)??;
+    match check.status {
+      SnapshotStatus::Matched => {}
+      SnapshotStatus::Created => log::info!("Recorded baseline of snapshot '{}'", name),
+      SnapshotStatus::Accepted => log::warn!("Accepted new baseline of snapshot '{}'", name),
+      SnapshotStatus::Mismatched => {
+        return Err(Error::SnapshotMismatch {
+          name,
+          url: handle.url(),
+        })
+      }
+    }
+    Ok(handle)
+  }
+
   /// Send the observation with a named serde-serialized payload, returning a
   /// handle that allows adding more named payloads later.
   pub fn named_serde<T: ?Sized + Serialize + 'static>(
@@ -196,6 +246,47 @@ impl ObservationBuilder {
     self.send_named_observation(name, payload.into())
   }
 
+  /// Send the observation with a payload read from `reader`
+  ///
+  /// The content is streamed to the server without being held in memory,
+  /// which suits payloads such as large files. Unlike other payloads, a
+  /// streamed payload is not retried or kept in the spool directory when the
+  /// upload fails, since the reader can only be consumed once.
+  pub fn stream_payload(
+    mut self,
+    mime_type: impl Into<String>,
+    reader: impl tokio::io::AsyncRead + Send + Unpin + 'static,
+  ) -> SendObservation {
+    let Some(execution) = self
+      .execution
+      .take()
+      .or_else(context::get_current_execution)
+    else {
+      log::error!(
+        "No execution context available for observation '{}'",
+        self.name
+      );
+      return SendObservation::stub(Error::NoExecutionContext);
+    };
+
+    let (observation, handle) = self.build(&execution);
+    let (uploaded_tx, uploaded_rx) = tokio::sync::watch::channel::<ObservationUploadResult>(None);
+    let log_level = observation.log_level;
+    let msg = UploaderMessage::StreamedObservation {
+      observation,
+      mime_type: mime_type.into(),
+      reader: Box::new(reader),
+      handle: handle.clone(),
+      uploaded_tx,
+    };
+    if let Err(e) = execution.queue.send_observation(msg, Some(log_level)) {
+      log::error!("Failed to send observation: {}", e);
+      return SendObservation::stub(e);
+    }
+
+    SendObservation::new(handle, uploaded_rx)
+  }
+
   fn send_named_observation(
     mut self,
     name: impl Into<String>,
@@ -250,7 +341,29 @@ impl ObservationBuilder {
     payload_name: impl Into<String>,
     execution: &ExecutionHandle,
   ) -> SendObservation {
-    let observation_id = self.custom_id.unwrap_or_else(ObservationId::new);
+    let (observation, handle) = self.build(execution);
+    let (uploaded_tx, uploaded_rx) = tokio::sync::watch::channel::<ObservationUploadResult>(None);
+
+    let log_level = observation.log_level;
+    let payload =
+      PayloadUploadData::new(observation.id, execution.id(), payload_name.into(), payload);
+    let msg = UploaderMessage::Observation {
+      observation,
+      payload,
+      handle: handle.clone(),
+      uploaded_tx,
+    };
+    if let Err(e) = execution.queue.send_observation(msg, Some(log_level)) {
+      log::error!("Failed to send observation: {}", e);
+      return SendObservation::stub(e);
+    }
+
+    SendObservation::new(handle, uploaded_rx)
+  }
+
+  /// Build the observation for a resolved execution
+  fn build(self, execution: &ExecutionHandle) -> (Observation, ObservationHandle) {
+    let observation_id = self.custom_id.unwrap_or_default();
 
     let handle = ObservationHandle {
       base_url: execution.base_url().to_string(),
@@ -281,8 +394,6 @@ impl ObservationBuilder {
       created_at: chrono::Utc::now(),
     };
 
-    let (uploaded_tx, uploaded_rx) = tokio::sync::watch::channel::<ObservationUploadResult>(None);
-
     // Log before sending so any error comes afterward
     log::info!(
       "Sending: {}/exe/{}/obs/{}",
@@ -291,29 +402,7 @@ impl ObservationBuilder {
       observation_id
     );
 
-    // Send observation metadata
-    if let Err(e) = execution
-      .uploader_tx
-      .try_send(UploaderMessage::Observation {
-        observation,
-        handle: handle.clone(),
-        uploaded_tx,
-      })
-    {
-      log::error!("Failed to send observation: {}", e);
-      return SendObservation::stub(Error::ChannelClosed);
-    }
-
-    // Send the payload as a separate message
-    let _ = execution.uploader_tx.try_send(UploaderMessage::Payload {
-      observation_id,
-      execution_id: execution.id(),
-      payload_id: PayloadId::new(),
-      name: payload_name.into(),
-      payload,
-    });
-
-    SendObservation::new(handle, uploaded_rx)
+    (observation, handle)
   }
 }
 
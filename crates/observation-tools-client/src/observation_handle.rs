@@ -6,6 +6,9 @@ use crate::Error;
 use napi_derive::napi;
 use observation_tools_shared::models::ExecutionId;
 use observation_tools_shared::ObservationId;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 
 #[napi]
 pub struct SendObservation {
@@ -55,7 +58,7 @@ impl SendObservation {
         let value = rx.borrow_and_update();
         match &*value {
           Some(Ok(handle)) => return Ok(handle.clone()),
-          Some(Err(error_msg)) => return Err(Error::UploadFailed(error_msg.clone())),
+          Some(Err(error)) => return Err(error.clone().into()),
           None => {}
         }
       }
@@ -80,6 +83,11 @@ impl SendObservation {
   }
 
   /// Wait for the observation to be uploaded to the server
+  ///
+  /// # Safety
+  ///
+  /// napi requires `&mut self` async methods to be `unsafe`; the caller must
+  /// not use this object from JS while the returned promise is pending.
   #[napi(js_name = "waitForUpload")]
   pub async unsafe fn wait_for_upload_napi(&mut self) -> napi::Result<ObservationHandle> {
     self
@@ -138,11 +146,16 @@ impl From<SendObservation> for ObservationHandle {
 pub struct ObservationPayloadHandle {
   handle: ObservationHandle,
   execution: crate::execution::ExecutionHandle,
+  dropped: Arc<AtomicU64>,
 }
 
 impl ObservationPayloadHandle {
   pub(crate) fn new(handle: ObservationHandle, execution: crate::execution::ExecutionHandle) -> Self {
-    Self { handle, execution }
+    Self {
+      handle,
+      execution,
+      dropped: Arc::new(AtomicU64::new(0)),
+    }
   }
 
   /// Add a named payload serialized via serde
@@ -164,19 +177,28 @@ impl ObservationPayloadHandle {
 
   /// Add a named payload
   pub fn payload(&self, name: impl Into<String>, payload: impl Into<observation_tools_shared::Payload>) -> &Self {
-    let _ = self
-      .execution
-      .uploader_tx
-      .try_send(crate::client::UploaderMessage::Payload {
-        observation_id: self.handle.observation_id,
-        execution_id: self.handle.execution_id,
-        payload_id: observation_tools_shared::PayloadId::new(),
-        name: name.into(),
-        payload: payload.into(),
-      });
+    let payload = crate::client::PayloadUploadData::new(
+      self.handle.observation_id,
+      self.handle.execution_id,
+      name.into(),
+      payload.into(),
+    );
+    let msg = crate::client::UploaderMessage::Payload {
+      payload,
+      dropped: self.dropped.clone(),
+    };
+    if let Err(e) = self.execution.queue.send_observation(msg, None) {
+      log::error!("Failed to send payload: {}", e);
+    }
     self
   }
 
+  /// Number of payloads added with this handle that were dropped because
+  /// the upload queue was full, see [`crate::ClientBuilder::queue_policy`]
+  pub fn dropped_payloads(&self) -> u64 {
+    self.dropped.load(Ordering::Relaxed)
+  }
+
   /// Get a reference to the observation handle
   pub fn handle(&self) -> &ObservationHandle {
     &self.handle
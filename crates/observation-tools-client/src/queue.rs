@@ -0,0 +1,201 @@
+//! Queue between code sending observations and the background uploader
+//!
+//! Executions, execution updates and uploader commands go through an
+//! unbounded control channel and are never dropped. Observations and their
+//! payloads go through a bounded channel, and a [`QueuePolicy`] decides what
+//! happens when it is full.
+
+use crate::client::UploaderMessage;
+use crate::error::Error;
+use crate::error::Result;
+use crate::error::UploadError;
+use async_channel::TrySendError;
+use observation_tools_shared::LogLevel;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::runtime::RuntimeFlavor;
+
+/// Default number of observations that can be queued for upload
+pub const DEFAULT_QUEUE_CAPACITY: usize = 10_000;
+
+/// What to do with an observation sent while the upload queue is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QueuePolicy {
+  /// Wait until the uploader made room. On a current-thread tokio runtime,
+  /// and for observations emitted by the uploader itself, waiting would stall
+  /// the uploader, so the observation is dropped as with
+  /// [`QueuePolicy::DropNewest`] instead and a warning is logged.
+  #[default]
+  Block,
+
+  /// Drop the observation being sent
+  DropNewest,
+
+  /// Drop the oldest queued observation to make room
+  DropOldest,
+
+  /// Once the queue is half full, drop observations below this level. When
+  /// the queue is full, drop the observation being sent.
+  SampleByLevel(LogLevel),
+}
+
+tokio::task_local! {
+  /// Set while running the uploader, which must never wait on its own queue
+  static IN_UPLOADER: ();
+}
+
+/// Run the uploader, marking it so that observations it emits itself, e.g.
+/// through a logger, are never blocked on the queue
+pub(crate) async fn run_uploader<F: std::future::Future>(future: F) -> F::Output {
+  IN_UPLOADER.scope((), future).await
+}
+
+/// Sending side of the upload queue
+#[derive(Clone, Debug)]
+pub(crate) struct UploadQueue {
+  control_tx: async_channel::Sender<UploaderMessage>,
+  observation_tx: async_channel::Sender<UploaderMessage>,
+  policy: QueuePolicy,
+  dropped: Arc<AtomicU64>,
+}
+
+/// Receiving side of the upload queue, owned by the uploader
+pub(crate) struct UploadQueueReceiver {
+  pub(crate) control_rx: async_channel::Receiver<UploaderMessage>,
+  pub(crate) observation_rx: async_channel::Receiver<UploaderMessage>,
+}
+
+impl UploadQueue {
+  pub(crate) fn new(capacity: usize, policy: QueuePolicy) -> (Self, UploadQueueReceiver) {
+    let (control_tx, control_rx) = async_channel::unbounded();
+    let (observation_tx, observation_rx) = async_channel::bounded(capacity.max(1));
+    let queue = Self {
+      control_tx,
+      observation_tx,
+      policy,
+      dropped: Arc::new(AtomicU64::new(0)),
+    };
+    (
+      queue,
+      UploadQueueReceiver {
+        control_rx,
+        observation_rx,
+      },
+    )
+  }
+
+  /// A queue without an uploader, for placeholder handles
+  pub(crate) fn closed() -> Self {
+    Self::new(1, QueuePolicy::DropNewest).0
+  }
+
+  /// Sender for control messages, e.g. for the flush timer
+  pub(crate) fn control_sender(&self) -> async_channel::Sender<UploaderMessage> {
+    self.control_tx.clone()
+  }
+
+  /// Send a message that must not be dropped, such as a new execution
+  pub(crate) fn send_control(&self, msg: UploaderMessage) -> Result<()> {
+    Ok(self.control_tx.try_send(msg)?)
+  }
+
+  /// Number of observations and payloads dropped because the queue was full
+  pub(crate) fn dropped(&self) -> u64 {
+    self.dropped.load(Ordering::Relaxed)
+  }
+
+  /// Queue an observation or payload, applying the queue policy. Dropped
+  /// observations are reported to whoever waits for their upload, dropped
+  /// payloads to the handle that added them.
+  pub(crate) fn send_observation(
+    &self,
+    msg: UploaderMessage,
+    log_level: Option<LogLevel>,
+  ) -> Result<()> {
+    let tx = &self.observation_tx;
+    if let QueuePolicy::SampleByLevel(min_level) = self.policy {
+      let capacity = tx.capacity().unwrap_or(usize::MAX);
+      if tx.len() * 2 >= capacity && log_level.is_some_and(|level| level < min_level) {
+        self.record_drop(msg);
+        return Ok(());
+      }
+    }
+    let msg = match tx.try_send(msg) {
+      Ok(()) => return Ok(()),
+      Err(TrySendError::Closed(_)) => return Err(Error::ChannelClosed),
+      Err(TrySendError::Full(msg)) => msg,
+    };
+    match self.policy {
+      QueuePolicy::Block => self.send_blocking(msg),
+      QueuePolicy::DropNewest | QueuePolicy::SampleByLevel(_) => {
+        self.record_drop(msg);
+        Ok(())
+      }
+      QueuePolicy::DropOldest => match tx.force_send(msg) {
+        Ok(Some(oldest)) => {
+          self.record_drop(oldest);
+          Ok(())
+        }
+        Ok(None) => Ok(()),
+        Err(_) => Err(Error::ChannelClosed),
+      },
+    }
+  }
+
+  /// Wait for room in the queue, or drop `msg` where waiting would stall the
+  /// uploader. Dropped observations fail to upload with the
+  /// [`Error::QueueFull`] message and dropped payloads are counted on their
+  /// handle.
+  fn send_blocking(&self, msg: UploaderMessage) -> Result<()> {
+    let tx = &self.observation_tx;
+    if IN_UPLOADER.try_with(|_| ()).is_ok() {
+      log::warn!("Upload queue full, dropping an observation emitted by the uploader");
+      self.record_drop(msg);
+      return Ok(());
+    }
+    match tokio::runtime::Handle::try_current() {
+      Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::CurrentThread => {
+        log::warn!(
+          "Upload queue full, dropping an observation because blocking would stall \
+           the uploader on a current-thread runtime"
+        );
+        self.record_drop(msg);
+        Ok(())
+      }
+      Ok(_) => tokio::task::block_in_place(|| {
+        tx.send_blocking(msg).map_err(|_| Error::ChannelClosed)
+      }),
+      Err(_) => tx.send_blocking(msg).map_err(|_| Error::ChannelClosed),
+    }
+  }
+
+  fn record_drop(&self, msg: UploaderMessage) {
+    match msg {
+      UploaderMessage::Observation {
+        observation,
+        uploaded_tx,
+        ..
+      }
+      | UploaderMessage::StreamedObservation {
+        observation,
+        uploaded_tx,
+        ..
+      } => {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        log::trace!("Upload queue full, dropping observation {}", observation.id);
+        let _ = uploaded_tx.send(Some(Err(UploadError::from(&Error::QueueFull))));
+      }
+      UploaderMessage::Payload { payload, dropped } => {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        dropped.fetch_add(1, Ordering::Relaxed);
+        log::trace!(
+          "Upload queue full, dropping payload {} of observation {}",
+          payload.name,
+          payload.observation_id
+        );
+      }
+      other => log::trace!("Upload queue full, dropping {:?}", other),
+    }
+  }
+}
@@ -0,0 +1,62 @@
+//! Retrying uploads that failed with a transient error
+
+use crate::error::Result;
+use log::warn;
+use std::future::Future;
+use std::time::Duration;
+
+/// Default number of times a failed upload is retried
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+
+/// Default delay before the first retry, doubled for every further attempt
+pub const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(200);
+
+/// Upper bound for the delay between two attempts
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(10);
+
+/// How uploads that failed with a transient error are retried
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RetryPolicy {
+  pub(crate) max_retries: u32,
+  pub(crate) initial_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+  fn default() -> Self {
+    Self {
+      max_retries: DEFAULT_MAX_RETRIES,
+      initial_backoff: DEFAULT_RETRY_BACKOFF,
+    }
+  }
+}
+
+impl RetryPolicy {
+  /// Delay before retry number `attempt`, starting at 0
+  pub(crate) fn backoff(&self, attempt: u32) -> Duration {
+    self
+      .initial_backoff
+      .saturating_mul(2u32.saturating_pow(attempt))
+      .min(MAX_RETRY_BACKOFF)
+  }
+
+  /// Run `f` until it succeeds, fails with a permanent error, or the retries
+  /// are exhausted
+  pub(crate) async fn run<T, F, Fut>(&self, what: &str, mut f: F) -> Result<T>
+  where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+  {
+    let mut attempt = 0;
+    loop {
+      match f().await {
+        Err(e) if e.is_transient() && attempt < self.max_retries => {
+          let delay = self.backoff(attempt);
+          warn!("Failed to upload {}, retrying in {:?}: {}", what, delay, e);
+          tokio::time::sleep(delay).await;
+          attempt += 1;
+        }
+        result => return result,
+      }
+    }
+  }
+}
@@ -1,5 +1,12 @@
 use crate::server_client::types::PayloadOrPointerResponse;
+use observation_tools_shared::content_digest;
+use observation_tools_shared::ContentEncoding;
 use observation_tools_shared::Observation;
+use observation_tools_shared::ObservationId;
+use observation_tools_shared::PayloadId;
+use observation_tools_shared::QuotaExceeded;
+use observation_tools_shared::BLOB_THRESHOLD_BYTES;
+use observation_tools_shared::COMPRESSION_MIN_BYTES;
 use reqwest::multipart::Part;
 use serde::Serialize;
 use std::time::Duration;
@@ -30,21 +37,37 @@ impl types::GetObservation {
 }
 
 pub fn create_client(base_url: &str, api_key: Option<String>) -> anyhow::Result<Client> {
+  create_client_with_opts(
+    base_url,
+    ObservationToolsServerClientOpts {
+      api_key,
+      compression: ContentEncoding::Identity,
+    },
+  )
+}
+
+pub fn create_client_with_opts(
+  base_url: &str,
+  opts: ObservationToolsServerClientOpts,
+) -> anyhow::Result<Client> {
   Ok(Client::new_with_client(
-    &base_url,
+    base_url,
     reqwest::ClientBuilder::new()
       .connect_timeout(Duration::from_secs(30))
       // Increase timeout for large blob uploads (default is 30s which is too short for large
       // payloads)
       .timeout(Duration::from_secs(300)) // 5 minutes for uploads
       .build()?,
-    ObservationToolsServerClientOpts { api_key },
+    opts,
   ))
 }
 
 #[derive(Clone, Debug)]
 pub struct ObservationToolsServerClientOpts {
   pub api_key: Option<String>,
+  /// Compression for uploaded payloads. Servers older than the compression
+  /// support would store compressed payloads as they are.
+  pub compression: ContentEncoding,
 }
 
 pub async fn pre_hook_async(
@@ -69,7 +92,16 @@ struct PayloadManifestEntry {
   payload_id: String,
   name: String,
   mime_type: String,
+  /// Size before compression
   size: usize,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  content_encoding: Option<ContentEncoding>,
+  /// Content is already stored on the server, so there is no payload part
+  #[serde(skip_serializing_if = "std::ops::Not::not")]
+  uploaded: bool,
+  /// Digest of `uploaded` content
+  #[serde(skip_serializing_if = "Option::is_none")]
+  digest: Option<String>,
 }
 
 // Extension methods for Client
@@ -77,8 +109,8 @@ impl Client {
   pub(crate) async fn create_observations_multipart(
     &self,
     execution_id: &str,
-    observations: Vec<Observation>,
-    payloads: Vec<crate::client::PayloadUploadData>,
+    observations: &[&Observation],
+    payloads: &[&crate::client::PayloadUploadData],
   ) -> anyhow::Result<()> {
     if observations.is_empty() && payloads.is_empty() {
       return Ok(());
@@ -97,29 +129,49 @@ impl Client {
     let mut form = reqwest::multipart::Form::new();
 
     // Part 1: observations JSON
-    let observations_json = serde_json::to_vec(&observations)?;
+    let observations_json = serde_json::to_vec(observations)?;
     let observations_part = Part::bytes(observations_json).mime_str("application/json")?;
     form = form.part("observations", observations_part);
 
-    // Part 2: payload manifest JSON
-    let manifest: Vec<PayloadManifestEntry> = payloads
-      .iter()
-      .map(|p| PayloadManifestEntry {
+    // Compress payloads where that makes them smaller. Large payloads are
+    // uploaded with separate requests, so they never count towards the
+    // multipart body limit, and only referenced by digest here.
+    let mut manifest = Vec::with_capacity(payloads.len());
+    let mut parts = Vec::with_capacity(payloads.len());
+    for p in payloads {
+      let digest = match &p.digest {
+        Some(digest) => Some(digest.clone()),
+        None if p.data.len() >= BLOB_THRESHOLD_BYTES => {
+          Some(self.upload_content(execution_id, p).await?)
+        }
+        None => None,
+      };
+      let mut entry = PayloadManifestEntry {
         observation_id: p.observation_id.to_string(),
         payload_id: p.payload_id.as_str().to_string(),
         name: p.name.clone(),
         mime_type: p.mime_type.clone(),
         size: p.size,
-      })
-      .collect();
+        content_encoding: None,
+        uploaded: digest.is_some(),
+        digest,
+      };
+      if !entry.uploaded {
+        let (content_encoding, data) = self.compress(&p.data)?;
+        entry.content_encoding = content_encoding;
+        let part_key = format!("{}:{}:{}", p.observation_id, p.payload_id.as_str(), p.name);
+        parts.push((part_key, Part::bytes(data)));
+      }
+      manifest.push(entry);
+    }
+
+    // Part 2: payload manifest JSON
     let manifest_json = serde_json::to_vec(&manifest)?;
     let manifest_part = Part::bytes(manifest_json).mime_str("application/json")?;
     form = form.part("payload_manifest", manifest_part);
 
     // Part 3: payload data parts
-    for p in payloads {
-      let part_key = format!("{}:{}:{}", p.observation_id, p.payload_id.as_str(), p.name);
-      let part = Part::bytes(p.data);
+    for (part_key, part) in parts {
       form = form.part(part_key, part);
     }
 
@@ -129,7 +181,118 @@ impl Client {
     }
 
     let response = request_builder.send().await?;
-    let _response = response.error_for_status()?;
+    let status = response.status();
+    if status == reqwest::StatusCode::PAYLOAD_TOO_LARGE
+      || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+    {
+      // Quota errors describe the quota, other errors with these statuses
+      // come from the body limit or a proxy
+      let body = response.bytes().await?;
+      if let Ok(quota) = serde_json::from_slice::<QuotaExceeded>(&body) {
+        return Err(crate::Error::QuotaExceeded(quota).into());
+      }
+      return Err(
+        crate::Error::Status {
+          status: status.as_u16(),
+          message: String::from_utf8_lossy(&body).into_owned(),
+        }
+        .into(),
+      );
+    }
+    response.error_for_status()?;
     Ok(())
   }
+
+  /// Make sure the server stores a payload's content ahead of its
+  /// observation, returning the content digest. Content the server already
+  /// has is not sent again.
+  async fn upload_content(
+    &self,
+    execution_id: &str,
+    payload: &crate::client::PayloadUploadData,
+  ) -> anyhow::Result<String> {
+    let digest = content_digest(&payload.data);
+    if self.has_content(execution_id, &digest).await? {
+      log::trace!("Server already stores content {}", digest);
+      return Ok(digest);
+    }
+    let (content_encoding, data) = self.compress(&payload.data)?;
+    let (_, digest) = self
+      .put_payload(
+        execution_id,
+        payload.observation_id,
+        &payload.payload_id,
+        reqwest::Body::from(data),
+        content_encoding,
+      )
+      .await?;
+    Ok(digest)
+  }
+
+  /// Whether payloads of an execution may reference content with the given
+  /// digest that the server already stores
+  async fn has_content(&self, execution_id: &str, digest: &str) -> anyhow::Result<bool> {
+    let url = format!("{}/api/exe/{}/blobs/{}", self.baseurl, execution_id, digest);
+    let mut request_builder = self.client.head(&url);
+    if let Some(ref api_key) = self.inner.api_key {
+      request_builder = request_builder.bearer_auth(api_key);
+    }
+    let response = request_builder.send().await?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+      return Ok(false);
+    }
+    response.error_for_status()?;
+    Ok(true)
+  }
+
+  /// Upload the content of a payload ahead of its observation, returning its
+  /// size and content digest
+  pub(crate) async fn put_payload(
+    &self,
+    execution_id: &str,
+    observation_id: ObservationId,
+    payload_id: &PayloadId,
+    body: reqwest::Body,
+    content_encoding: Option<ContentEncoding>,
+  ) -> anyhow::Result<(u64, String)> {
+    let url = format!(
+      "{}/api/exe/{}/obs/{}/payload/{}",
+      self.baseurl,
+      execution_id,
+      observation_id,
+      payload_id.as_str()
+    );
+    log::trace!("Uploading payload: url={}", url);
+
+    let mut request_builder = self
+      .client
+      .put(&url)
+      .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+      .body(body);
+    if let Some(encoding) = content_encoding {
+      request_builder =
+        request_builder.header(reqwest::header::CONTENT_ENCODING, encoding.as_str());
+    }
+    if let Some(ref api_key) = self.inner.api_key {
+      request_builder = request_builder.bearer_auth(api_key);
+    }
+
+    let response = request_builder.send().await?.error_for_status()?;
+    let response: types::PutPayloadResponse = response.json().await?;
+    Ok((u64::try_from(response.size)?, response.digest))
+  }
+
+  /// Compress payload data with the configured encoding, returning the
+  /// encoding used if it made the data smaller
+  fn compress(&self, data: &[u8]) -> anyhow::Result<(Option<ContentEncoding>, Vec<u8>)> {
+    let encoding = self.inner.compression;
+    if encoding.is_identity() || data.len() < COMPRESSION_MIN_BYTES {
+      return Ok((None, data.to_vec()));
+    }
+    let compressed = encoding.encode(data)?;
+    if compressed.len() >= data.len() {
+      return Ok((None, data.to_vec()));
+    }
+    Ok((Some(encoding), compressed))
+  }
 }
@@ -0,0 +1,128 @@
+//! On-disk spool of pending uploads
+//!
+//! When a spool directory is configured, every upload is written to it before
+//! being sent and removed once the server accepted it. Uploads left behind by
+//! a server outage or a crash are replayed, oldest first, the next time a
+//! client starts with the same directory. A spool directory must not be shared
+//! between clients running at the same time.
+
+use crate::client::Upload;
+use crate::error::Result;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+const EXTENSION: &str = "json";
+
+/// Directory of pending uploads, one JSON file per upload named by a sequence
+/// number that preserves the upload order
+pub(crate) struct Spool {
+  dir: PathBuf,
+  next_seq: u64,
+}
+
+impl Spool {
+  /// Open a spool directory, creating it if needed
+  pub(crate) fn open(dir: impl AsRef<Path>) -> Result<Self> {
+    let dir = dir.as_ref().to_path_buf();
+    std::fs::create_dir_all(&dir)?;
+    let mut spool = Self { dir, next_seq: 0 };
+    spool.next_seq = spool.pending()?.last().map_or(0, |seq| seq + 1);
+    Ok(spool)
+  }
+
+  fn path(&self, seq: u64) -> PathBuf {
+    self.dir.join(format!("{:020}.{}", seq, EXTENSION))
+  }
+
+  /// Durably store an upload, returning its sequence number
+  pub(crate) fn push(&mut self, upload: &Upload) -> Result<u64> {
+    let seq = self.next_seq;
+    let path = self.path(seq);
+    // Write to a temporary file first so that a crash never leaves a partial
+    // entry behind, and sync it before renaming so that the entry is never
+    // renamed into place before its content is on disk
+    let tmp_path = path.with_extension("tmp");
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(&serde_json::to_vec(upload)?)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, &path)?;
+    self.sync_dir()?;
+    self.next_seq += 1;
+    Ok(seq)
+  }
+
+  /// Make the rename of an entry durable
+  #[cfg(unix)]
+  fn sync_dir(&self) -> Result<()> {
+    File::open(&self.dir)?.sync_all()?;
+    Ok(())
+  }
+
+  /// Directories cannot be opened for syncing on other platforms
+  #[cfg(not(unix))]
+  fn sync_dir(&self) -> Result<()> {
+    Ok(())
+  }
+
+  /// Sequence numbers of all pending uploads, oldest first
+  pub(crate) fn pending(&self) -> Result<Vec<u64>> {
+    let mut pending = Vec::new();
+    for entry in std::fs::read_dir(&self.dir)? {
+      let path = entry?.path();
+      if path.extension().and_then(|e| e.to_str()) != Some(EXTENSION) {
+        continue;
+      }
+      let seq = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .and_then(|stem| stem.parse().ok());
+      if let Some(seq) = seq {
+        pending.push(seq);
+      }
+    }
+    pending.sort_unstable();
+    Ok(pending)
+  }
+
+  /// Read a pending upload
+  pub(crate) fn read(&self, seq: u64) -> Result<Upload> {
+    Ok(serde_json::from_slice(&std::fs::read(self.path(seq))?)?)
+  }
+
+  /// Remove an upload once it no longer needs to be sent
+  pub(crate) fn remove(&self, seq: u64) -> Result<()> {
+    match std::fs::remove_file(self.path(seq)) {
+      Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+      _ => Ok(()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use observation_tools_shared::models::Execution;
+
+  #[test]
+  fn test_spool_preserves_order_across_reopen() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let mut spool = Spool::open(dir.path())?;
+    let first = Execution::new("first");
+    let second = Execution::new("second");
+    spool.push(&Upload::Execution { execution: first })?;
+    let seq = spool.push(&Upload::Execution { execution: second })?;
+
+    let mut spool = Spool::open(dir.path())?;
+    assert_eq!(spool.pending()?, vec![0, 1]);
+    spool.remove(0)?;
+    let Upload::Execution { execution } = spool.read(seq)? else {
+      anyhow::bail!("expected an execution upload");
+    };
+    assert_eq!(execution.name, "second");
+    assert_eq!(spool.push(&Upload::Execution { execution })?, 2);
+    assert_eq!(spool.pending()?, vec![1, 2]);
+    Ok(())
+  }
+}
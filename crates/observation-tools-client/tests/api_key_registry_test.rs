@@ -0,0 +1,188 @@
+mod common;
+
+use chrono::Duration;
+use chrono::Utc;
+use common::post_observation;
+use common::TestServer;
+use observation_tools::ObservationBuilder;
+use observation_tools_server::auth::ApiKeyRecord;
+use observation_tools_server::auth::ApiKeyScope;
+use observation_tools_shared::Execution;
+use observation_tools_shared::ProjectId;
+use reqwest::StatusCode;
+
+/// Create an execution with a raw request, returning it with the status
+async fn create_execution(
+  server: &TestServer,
+  api_key: &str,
+  name: &str,
+) -> anyhow::Result<(Execution, StatusCode)> {
+  let execution = Execution::new(name);
+  let response = reqwest::Client::new()
+    .post(format!("{}/api/exe", server.base_url()))
+    .bearer_auth(api_key)
+    .json(&serde_json::json!({ "execution": execution }))
+    .send()
+    .await?;
+  Ok((execution, response.status()))
+}
+
+async fn delete_execution(
+  server: &TestServer,
+  api_key: &str,
+  execution: &Execution,
+) -> anyhow::Result<StatusCode> {
+  let response = reqwest::Client::new()
+    .delete(format!("{}/api/exe/{}", server.base_url(), execution.id))
+    .bearer_auth(api_key)
+    .send()
+    .await?;
+  Ok(response.status())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_revoked_and_expired_keys() -> anyhow::Result<()> {
+  let valid = ApiKeyRecord::new("valid", ApiKeyScope::WriteOnly);
+  let mut revoked = ApiKeyRecord::new("revoked", ApiKeyScope::WriteOnly);
+  revoked.revoked_at = Some(Utc::now());
+  let mut expired = ApiKeyRecord::new("expired", ApiKeyScope::WriteOnly);
+  expired.expires_at = Some(Utc::now() - Duration::minutes(1));
+  let server = TestServer::new_with_api_keys(&[valid, revoked, expired]).await?;
+
+  let (_, status) = create_execution(&server, server.registered_api_key(0), "ok").await?;
+  assert_eq!(status, StatusCode::OK);
+  let (_, status) = create_execution(&server, server.registered_api_key(1), "revoked").await?;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+  let (_, status) = create_execution(&server, server.registered_api_key(2), "expired").await?;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+  // Keys generated without the registry keep working, but may only write
+  let legacy = server.generate_api_key()?;
+  let (execution, status) = create_execution(&server, &legacy, "legacy").await?;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(delete_execution(&server, &legacy, &execution).await?, StatusCode::FORBIDDEN);
+  Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_key_scopes() -> anyhow::Result<()> {
+  let server = TestServer::new_with_api_keys(&[
+    ApiKeyRecord::new("dashboard", ApiKeyScope::ReadOnly),
+    ApiKeyRecord::new("ci", ApiKeyScope::WriteOnly),
+    ApiKeyRecord::new("ops", ApiKeyScope::Admin),
+  ])
+  .await?;
+  let (read_only, write_only, admin) = (
+    server.registered_api_key(0),
+    server.registered_api_key(1),
+    server.registered_api_key(2),
+  );
+
+  let (_, status) = create_execution(&server, read_only, "read-only").await?;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+
+  let (execution, status) = create_execution(&server, write_only, "write-only").await?;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(delete_execution(&server, write_only, &execution).await?, StatusCode::FORBIDDEN);
+  assert_eq!(delete_execution(&server, read_only, &execution).await?, StatusCode::FORBIDDEN);
+  assert_eq!(delete_execution(&server, admin, &execution).await?, StatusCode::OK);
+  Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_key_execution_prefixes() -> anyhow::Result<()> {
+  let mut restricted = ApiKeyRecord::new("ci", ApiKeyScope::WriteOnly);
+  restricted.execution_prefixes = vec!["ci-".to_string()];
+  let server = TestServer::new_with_api_keys(&[
+    restricted,
+    ApiKeyRecord::new("other", ApiKeyScope::WriteOnly),
+  ])
+  .await?;
+  let (restricted, other) = (server.registered_api_key(0), server.registered_api_key(1));
+
+  let (_, status) = create_execution(&server, restricted, "nightly").await?;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+
+  let client = server.create_client_with_api_key(restricted)?;
+  let execution = client.begin_execution("ci-build")?.wait_for_upload().await?;
+  ObservationBuilder::new("allowed")
+    .execution(&execution)
+    .payload("ok")
+    .wait_for_upload()
+    .await?;
+
+  // Executions created with other keys are off limits
+  let (other_execution, status) = create_execution(&server, other, "nightly").await?;
+  assert_eq!(status, StatusCode::OK);
+  let response = reqwest::Client::new()
+    .patch(format!("{}/api/exe/{}", server.base_url(), other_execution.id))
+    .bearer_auth(restricted)
+    .json(&serde_json::json!({ "add_tags": ["hijacked"] }))
+    .send()
+    .await?;
+  assert_eq!(response.status(), StatusCode::FORBIDDEN);
+  // Including replacing them under an allowed name
+  let mut replacement = Execution::new("ci-nightly");
+  replacement.id = other_execution.id;
+  let response = reqwest::Client::new()
+    .post(format!("{}/api/exe", server.base_url()))
+    .bearer_auth(restricted)
+    .json(&serde_json::json!({ "execution": replacement }))
+    .send()
+    .await?;
+  assert_eq!(response.status(), StatusCode::FORBIDDEN);
+  // Or posting their observations to an allowed execution
+  let status = post_observation(&server, restricted, execution.id(), other_execution.id).await?;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+  assert!(server.list_observations(&other_execution.id).await?.is_empty());
+
+  client.shutdown().await?;
+  assert_eq!(server.list_observations(&execution.id()).await?.len(), 1);
+  Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_revoke_keys_through_the_api() -> anyhow::Result<()> {
+  let mut team_admin = ApiKeyRecord::new("team-ops", ApiKeyScope::Admin);
+  team_admin.project = Some(ProjectId::parse("team-a")?);
+  let ci = ApiKeyRecord::new("ci", ApiKeyScope::WriteOnly);
+  let ci_id = ci.id;
+  let server = TestServer::new_with_api_keys(&[
+    ApiKeyRecord::new("ops", ApiKeyScope::Admin),
+    ci,
+    team_admin,
+  ])
+  .await?;
+  let (admin, ci, team_admin) = (
+    server.registered_api_key(0),
+    server.registered_api_key(1),
+    server.registered_api_key(2),
+  );
+  let revoke = |api_key: &str, id: String| {
+    reqwest::Client::new()
+      .delete(format!("{}/api/keys/{}", server.base_url(), id))
+      .bearer_auth(api_key)
+      .send()
+  };
+
+  // Only admins may revoke keys, and project admins only keys of the project
+  assert_eq!(revoke(ci, ci_id.to_string()).await?.status(), StatusCode::FORBIDDEN);
+  assert_eq!(revoke(team_admin, ci_id.to_string()).await?.status(), StatusCode::FORBIDDEN);
+  let (_, status) = create_execution(&server, ci, "still-valid").await?;
+  assert_eq!(status, StatusCode::OK);
+
+  let response = revoke(admin, ci_id.to_string()).await?;
+  assert_eq!(response.status(), StatusCode::OK);
+  let body: serde_json::Value = response.json().await?;
+  assert_eq!(body["name"], "ci");
+  let (_, status) = create_execution(&server, ci, "revoked").await?;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+  // Keys missing from the registry are revoked by the key itself
+  let legacy = server.generate_api_key()?;
+  let response = revoke(admin, legacy.clone()).await?;
+  assert_eq!(response.status(), StatusCode::OK);
+  let (_, status) = create_execution(&server, &legacy, "legacy").await?;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+  Ok(())
+}
@@ -0,0 +1,43 @@
+//! Integration tests for storing payload blobs in an object store
+
+mod common;
+
+use common::TestServer;
+use futures::TryStreamExt;
+use observation_tools::server_client::types::PayloadOrPointerResponse;
+use observation_tools::ObservationBuilder;
+
+#[test_log::test(tokio::test)]
+async fn test_large_payload_in_memory_object_store() -> anyhow::Result<()> {
+  let server = TestServer::new_with_blob_url("memory:///observation-tools/blobs").await;
+  let large_payload = "y".repeat(observation_tools::BLOB_THRESHOLD_BYTES + 1);
+  let expected = large_payload.clone();
+  let (execution, _) = server
+    .with_execution("object-store-execution", async {
+      ObservationBuilder::new("large").payload(large_payload);
+    })
+    .await?;
+
+  let observations = server.list_observations(&execution.id()).await?;
+  assert_eq!(observations.len(), 1);
+  let obs = &observations[0];
+  assert!(matches!(obs.payload(), PayloadOrPointerResponse::Pointer { .. }));
+
+  let api_client = server.create_api_client()?;
+  let blob_bytes: bytes::Bytes = api_client
+    .get_observation_blob()
+    .execution_id(execution.id().to_string())
+    .observation_id(obs.id.to_string())
+    .payload_id(obs.payloads[0].id.to_string())
+    .send()
+    .await?
+    .into_inner_stream()
+    .try_collect::<Vec<_>>()
+    .await?
+    .into_iter()
+    .flatten()
+    .collect();
+  assert_eq!(blob_bytes, expected.as_bytes());
+
+  Ok(())
+}
@@ -1,39 +1,157 @@
 use observation_tools::server_client::types::GetObservation;
 use observation_tools::Client;
 use observation_tools::ClientBuilder;
+use observation_tools::ContentEncoding;
 use observation_tools::ExecutionHandle;
 use observation_tools_server::auth::generate_api_key;
+use observation_tools_server::auth::ApiKeyRecord;
 use observation_tools_server::auth::ApiKeySecret;
+use observation_tools_server::config::RetentionConfig;
+use observation_tools_server::Config;
+use observation_tools_shared::ExecutionId;
+use observation_tools_shared::LogLevel;
+use observation_tools_shared::Observation;
+use observation_tools_shared::ObservationId;
+use observation_tools_shared::ObservationType;
+
+/// The `name=value` pair of a cookie set by the response
+#[allow(unused)]
+pub fn set_cookie(response: &reqwest::Response, name: &str) -> Option<String> {
+  response
+    .headers()
+    .get_all(reqwest::header::SET_COOKIE)
+    .iter()
+    .filter_map(|value| value.to_str().ok())
+    .find(|value| value.starts_with(&format!("{}=", name)))
+    .map(|value| value.split(';').next().unwrap().to_string())
+}
+
+/// Post a single observation belonging to `observation_execution` to the
+/// batch endpoint of `path_execution`, returning the response status
+#[allow(unused)]
+pub async fn post_observation(
+  server: &TestServer,
+  api_key: &str,
+  path_execution: ExecutionId,
+  observation_execution: ExecutionId,
+) -> anyhow::Result<reqwest::StatusCode> {
+  let observation = Observation {
+    id: ObservationId::new(),
+    execution_id: observation_execution,
+    name: "posted".to_string(),
+    observation_type: ObservationType::Payload,
+    log_level: LogLevel::Info,
+    source: None,
+    metadata: Default::default(),
+    group_ids: Vec::new(),
+    parent_group_id: None,
+    parent_span_id: None,
+    created_at: chrono::Utc::now(),
+  };
+  let form = reqwest::multipart::Form::new()
+    .text("observations", serde_json::to_string(&[&observation])?)
+    .text(observation.id.to_string(), "data");
+  let response = reqwest::Client::new()
+    .post(format!("{}/api/exe/{}/obs", server.base_url(), path_execution))
+    .bearer_auth(api_key)
+    .multipart(form)
+    .send()
+    .await?;
+  Ok(response.status())
+}
 
 /// Test server wrapper that provides convenient client creation
 pub struct TestServer {
   base_url: String,
   api_secret: Option<ApiKeySecret>,
+  /// Keys registered with `new_with_api_keys`, in order
+  registered_keys: Vec<String>,
   _handle: Option<tokio::task::JoinHandle<()>>,
 }
 
 impl TestServer {
+  #[allow(unused)]
   pub async fn new() -> Self {
-    Self::new_internal(None).await
+    Self::new_internal(None, &[], |config| config).await
   }
 
   /// Create a new test server with API key authentication enabled
   #[allow(unused)]
   pub async fn new_with_auth() -> anyhow::Result<Self> {
+    Self::new_with_auth_and_config(|config| config).await
+  }
+
+  /// Create a new test server with API key authentication enabled and any
+  /// other configuration
+  #[allow(unused)]
+  pub async fn new_with_auth_and_config(
+    configure: impl FnOnce(Config) -> Config,
+  ) -> anyhow::Result<Self> {
     use rand::Rng;
     let mut rng = rand::thread_rng();
     let random1: u64 = rng.gen();
     let random2: u64 = rng.gen();
     let secret = format!("test-secret-key-{:016x}{:016x}", random1, random2);
     let api_secret = ApiKeySecret::new(&secret)?;
-    Ok(Self::new_internal(Some(api_secret)).await)
+    Ok(Self::new_internal(Some(api_secret), &[], configure).await)
+  }
+
+  /// Create a new test server with API key authentication enabled and the
+  /// given keys in its key registry, see [`TestServer::registered_api_key`]
+  #[allow(unused)]
+  pub async fn new_with_api_keys(keys: &[ApiKeyRecord]) -> anyhow::Result<Self> {
+    Self::new_with_api_keys_and_config(keys, |config| config).await
+  }
+
+  /// Create a new test server with registered API keys and any other
+  /// configuration
+  #[allow(unused)]
+  pub async fn new_with_api_keys_and_config(
+    keys: &[ApiKeyRecord],
+    configure: impl FnOnce(Config) -> Config,
+  ) -> anyhow::Result<Self> {
+    let api_secret = ApiKeySecret::new("test-secret-key-for-registered-keys")?;
+    Ok(Self::new_internal(Some(api_secret), keys, configure).await)
+  }
+
+  /// Create a new test server with a retention policy
+  #[allow(unused)]
+  pub async fn new_with_retention(retention: RetentionConfig) -> Self {
+    Self::new_internal(None, &[], |config| config.with_retention(retention)).await
   }
 
-  async fn new_internal(api_secret: Option<ApiKeySecret>) -> Self {
+  /// Create a new test server storing blobs in the given object store
+  #[allow(unused)]
+  pub async fn new_with_blob_url(blob_url: &str) -> Self {
+    Self::new_internal(None, &[], |config| {
+      config.with_blob_url(Some(blob_url.to_string()))
+    })
+    .await
+  }
+
+  /// Create a new test server compressing stored payloads
+  #[allow(unused)]
+  pub async fn new_with_payload_encoding(encoding: ContentEncoding) -> Self {
+    Self::new_internal(None, &[], |config| config.with_payload_encoding(encoding)).await
+  }
+
+  /// Create a new test server with any other configuration
+  #[allow(unused)]
+  pub async fn new_with_config(configure: impl FnOnce(Config) -> Config) -> Self {
+    Self::new_internal(None, &[], configure).await
+  }
+
+  async fn new_internal(
+    api_secret: Option<ApiKeySecret>,
+    api_keys: &[ApiKeyRecord],
+    configure: impl FnOnce(Config) -> Config,
+  ) -> Self {
     if let Ok(url) = std::env::var("SERVER_URL") {
+      assert!(api_keys.is_empty(), "Cannot register keys with a remote server");
       return Self {
         base_url: url,
         api_secret,
+        registered_keys: Vec::new(),
         _handle: None,
       };
     }
@@ -46,10 +164,26 @@ impl TestServer {
 
     let addr = listener.local_addr().expect("Failed to get local address");
 
-    let config = observation_tools_server::Config::new()
-      .with_bind_addr(addr)
-      .with_data_dir(Some(data_dir.path().to_path_buf()))
-      .with_api_secret(api_secret.clone());
+    // Run against a SQL metadata backend with TEST_METADATA_URL, where
+    // `sqlite` uses a database file in the temp dir
+    let metadata_url = std::env::var("TEST_METADATA_URL").ok().map(|url| match url.as_str() {
+      "sqlite" => format!("sqlite://{}", data_dir.path().join("metadata.db").display()),
+      _ => url,
+    });
+
+    let config = configure(
+      Config::new()
+        .with_bind_addr(addr)
+        .with_data_dir(Some(data_dir.path().to_path_buf()))
+        .with_metadata_url(metadata_url)
+        .with_api_secret(api_secret.clone()),
+    );
+
+    let mut registered_keys = Vec::new();
+    for key in api_keys {
+      let server = observation_tools_server::Server::new(config.clone());
+      registered_keys.push(server.create_api_key(key).await.expect("Failed to register key"));
+    }
 
     let server = observation_tools_server::Server::new(config);
 
@@ -61,6 +195,7 @@ impl TestServer {
     Self {
       base_url: format!("http://{}", addr),
       api_secret,
+      registered_keys,
       _handle: Some(handle),
     }
   }
@@ -80,6 +215,12 @@ impl TestServer {
     Ok(generate_api_key(secret)?)
   }
 
+  /// The key created for the `index`th record passed to `new_with_api_keys`
+  #[allow(unused)]
+  pub fn registered_api_key(&self, index: usize) -> &str {
+    &self.registered_keys[index]
+  }
+
   /// Create an observation tools client connected to this test server
   #[allow(unused)]
   pub fn create_client(&self) -> anyhow::Result<Client> {
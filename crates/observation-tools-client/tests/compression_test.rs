@@ -0,0 +1,114 @@
+//! Integration tests for compressed payload uploads and storage
+
+mod common;
+
+use common::TestServer;
+use observation_tools::server_client::types::PayloadOrPointerResponse;
+use observation_tools::ClientBuilder;
+use observation_tools::ContentEncoding;
+use observation_tools::ExecutionHandle;
+use observation_tools::ObservationBuilder;
+use serde_json::json;
+
+/// Upload a small text payload and a JSON payload large enough to be stored
+/// as a blob, returning the execution and the JSON value
+async fn upload_payloads(
+  server: &TestServer,
+  compression: ContentEncoding,
+) -> anyhow::Result<(ExecutionHandle, serde_json::Value)> {
+  let rows: Vec<_> = (0..2000)
+    .map(|i| json!({"id": i, "name": format!("row-{}", i), "enabled": true}))
+    .collect();
+  let value = json!({ "rows": rows });
+  assert!(value.to_string().len() > observation_tools::BLOB_THRESHOLD_BYTES);
+
+  let client = ClientBuilder::new()
+    .base_url(server.base_url())
+    .compression(compression)
+    .build()?;
+  let execution = client.begin_execution("compressed")?.wait_for_upload().await?;
+  ObservationBuilder::new("text")
+    .execution(&execution)
+    .payload("compress me ".repeat(200))
+    .wait_for_upload()
+    .await?;
+  ObservationBuilder::new("json")
+    .execution(&execution)
+    .serde(&value)
+    .wait_for_upload()
+    .await?;
+  client.shutdown().await?;
+  Ok((execution, value))
+}
+
+/// Fetch the content of the observation's first payload
+async fn fetch_content(
+  server: &TestServer,
+  execution: &ExecutionHandle,
+  name: &str,
+  accept_encoding: Option<&str>,
+) -> anyhow::Result<reqwest::Response> {
+  let observations = server.list_observations(&execution.id()).await?;
+  let obs = observations.iter().find(|o| o.name == name).unwrap();
+  let url = format!(
+    "{}/api/exe/{}/obs/{}/payload/{}/content",
+    server.base_url(),
+    execution.id(),
+    obs.id,
+    obs.payloads[0].id
+  );
+  let mut request = reqwest::Client::new().get(url);
+  if let Some(accept_encoding) = accept_encoding {
+    request = request.header("accept-encoding", accept_encoding);
+  }
+  Ok(request.send().await?.error_for_status()?)
+}
+
+#[test_log::test(tokio::test)]
+async fn test_compressed_upload_is_stored_decompressed() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let (execution, value) = upload_payloads(&server, ContentEncoding::Zstd).await?;
+
+  let observations = server.list_observations(&execution.id()).await?;
+  let text = observations.iter().find(|o| o.name == "text").unwrap();
+  let text = server.get_observation(&execution.id(), &text.id).await?;
+  assert_eq!(text.payload().as_str(), Some("compress me ".repeat(200).as_str()));
+  assert_eq!(text.payloads[0].size, 12 * 200);
+
+  let response = fetch_content(&server, &execution, "json", Some("zstd")).await?;
+  assert!(response.headers().get("content-encoding").is_none());
+  assert_eq!(serde_json::from_slice::<serde_json::Value>(&response.bytes().await?)?, value);
+  Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_payloads_compressed_at_rest() -> anyhow::Result<()> {
+  let server = TestServer::new_with_payload_encoding(ContentEncoding::Zstd).await;
+  let (execution, value) = upload_payloads(&server, ContentEncoding::Gzip).await?;
+
+  // Inline payloads are decompressed for the API
+  let observations = server.list_observations(&execution.id()).await?;
+  let text = observations.iter().find(|o| o.name == "text").unwrap();
+  let text = server.get_observation(&execution.id(), &text.id).await?;
+  assert_eq!(text.payload().as_str(), Some("compress me ".repeat(200).as_str()));
+  let json = observations.iter().find(|o| o.name == "json").unwrap();
+  assert!(matches!(json.payload(), PayloadOrPointerResponse::Pointer { .. }));
+
+  // Clients accepting the stored encoding get the compressed data
+  let response = fetch_content(&server, &execution, "json", Some("gzip, zstd")).await?;
+  assert_eq!(response.headers()["content-encoding"], "zstd");
+  let compressed = response.bytes().await?;
+  assert!(compressed.len() < value.to_string().len() / 10);
+  let decoded = ContentEncoding::Zstd.decode(&compressed, usize::MAX)?;
+  assert_eq!(serde_json::from_slice::<serde_json::Value>(&decoded)?, value);
+
+  // Everyone else gets it decompressed
+  for accept_encoding in [None, Some("gzip"), Some("zstd;q=0")] {
+    let response = fetch_content(&server, &execution, "json", accept_encoding).await?;
+    assert!(response.headers().get("content-encoding").is_none());
+    assert_eq!(serde_json::from_slice::<serde_json::Value>(&response.bytes().await?)?, value);
+  }
+  let response = fetch_content(&server, &execution, "text", None).await?;
+  assert_eq!(response.bytes().await?, "compress me ".repeat(200).as_bytes());
+  Ok(())
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use common::set_cookie;
+use common::TestServer;
+use observation_tools_shared::Execution;
+use reqwest::header::COOKIE;
+use reqwest::header::ORIGIN;
+use reqwest::StatusCode;
+
+#[test_log::test(tokio::test)]
+async fn test_csrf_token_from_ui() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let client = reqwest::Client::new();
+
+  let response = client.get(format!("{}/exe", server.base_url())).send().await?;
+  let cookie = set_cookie(&response, "csrf_token").expect("CSRF cookie");
+  let token = cookie.strip_prefix("csrf_token=").unwrap().to_string();
+  assert!(response.text().await?.contains(&token));
+
+  // The token is kept while it is valid, so pages already loaded keep working
+  let response = client
+    .get(format!("{}/exe", server.base_url()))
+    .header(COOKIE, &cookie)
+    .send()
+    .await?;
+  assert_eq!(response.status(), StatusCode::OK);
+  assert!(set_cookie(&response, "csrf_token").is_none());
+
+  let create = |origin: Option<&str>, token: Option<&str>, bearer: bool| {
+    let mut request = client
+      .post(format!("{}/api/exe", server.base_url()))
+      .header(COOKIE, &cookie)
+      .json(&serde_json::json!({ "execution": Execution::new("csrf") }));
+    if let Some(origin) = origin {
+      request = request.header(ORIGIN, origin);
+    }
+    if let Some(token) = token {
+      request = request.header("x-csrf-token", token);
+    }
+    if bearer {
+      request = request.bearer_auth("obs_key");
+    }
+    request.send()
+  };
+  let same_origin = server.base_url().to_string();
+
+  let response = create(Some(&same_origin), Some(&token), false).await?;
+  assert_eq!(response.status(), StatusCode::OK);
+  let response = create(None, Some(&token), false).await?;
+  assert_eq!(response.status(), StatusCode::OK);
+
+  let response = create(Some("https://evil.example"), Some(&token), false).await?;
+  assert_eq!(response.status(), StatusCode::FORBIDDEN);
+  assert!(response.text().await?.contains("Cross-origin"));
+  let response = create(Some(&same_origin), None, false).await?;
+  assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+  // Bearer-authenticated clients are exempt
+  let response = create(Some("https://evil.example"), None, true).await?;
+  assert_eq!(response.status(), StatusCode::OK);
+  Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_unsigned_csrf_token() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let token = "0123456789abcdef0123456789abcdef";
+  let response = reqwest::Client::new()
+    .post(format!("{}/api/exe", server.base_url()))
+    .header(COOKIE, format!("csrf_token={}", token))
+    .header("x-csrf-token", token)
+    .json(&serde_json::json!({ "execution": Execution::new("csrf") }))
+    .send()
+    .await?;
+  assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+  // UI pages replace the cookie
+  let response = reqwest::Client::new()
+    .get(format!("{}/exe", server.base_url()))
+    .header(COOKIE, format!("csrf_token={}", token))
+    .send()
+    .await?;
+  let cookie = set_cookie(&response, "csrf_token").expect("CSRF cookie");
+  assert_ne!(cookie, format!("csrf_token={}", token));
+  Ok(())
+}
@@ -0,0 +1,222 @@
+//! Integration tests for comparing executions
+
+mod common;
+
+use common::TestServer;
+use observation_tools::GroupBuilder;
+use observation_tools::ObservationBuilder;
+use observation_tools_shared::Payload;
+use serde_json::json;
+
+#[test_log::test(tokio::test)]
+async fn test_diff_executions() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let (passing, _) = server
+    .with_execution("passing", async {
+      let group = GroupBuilder::new("checkout").build().into_handle();
+      ObservationBuilder::new("request")
+        .group(&group)
+        .serde(&json!({"user": "alice", "items": [1, 2], "retry": false}));
+      ObservationBuilder::new("log").payload(Payload::text("start\nconnect\ndone\n"));
+      ObservationBuilder::new("unchanged").payload(Payload::text("same"));
+      ObservationBuilder::new("only-passing").payload(Payload::text("gone"));
+    })
+    .await?;
+  let (failing, _) = server
+    .with_execution("failing", async {
+      let group = GroupBuilder::new("checkout").build().into_handle();
+      ObservationBuilder::new("request")
+        .group(&group)
+        .serde(&json!({"user": "alice", "items": [1, 3], "error": "timeout"}));
+      ObservationBuilder::new("log").payload(Payload::text("start\nconnect\ntimeout\n"));
+      ObservationBuilder::new("unchanged").payload(Payload::text("same"));
+      ObservationBuilder::new("only-failing").payload(Payload::text("new"));
+    })
+    .await?;
+  let client = reqwest::Client::new();
+
+  let diff: serde_json::Value = client
+    .get(format!(
+      "{}/api/diff?left={}&right={}",
+      server.base_url(),
+      passing.id(),
+      failing.id()
+    ))
+    .send()
+    .await?
+    .error_for_status()?
+    .json()
+    .await?;
+  assert_eq!(diff["left"]["name"], "passing");
+  assert_eq!(
+    diff["summary"],
+    json!({"added": 1, "removed": 1, "changed": 2, "unchanged": 2})
+  );
+  let observation = |name: &str| {
+    diff["observations"]
+      .as_array()
+      .unwrap()
+      .iter()
+      .find(|o| o["key"]["name"] == name)
+      .unwrap_or_else(|| panic!("{} not in diff", name))
+      .clone()
+  };
+
+  // Observations are aligned by group path and name, and JSON payloads are
+  // compared structurally
+  let request = observation("request");
+  assert_eq!(request["status"], "Changed");
+  assert_eq!(request["key"]["group_path"], json!(["checkout"]));
+  let changes = &request["payloads"][0]["diff"]["Json"];
+  assert_eq!(
+    changes,
+    &json!([
+      {"path": "/items/1", "status": "Changed", "left": 2, "right": 3},
+      {"path": "/retry", "status": "Removed", "left": false},
+      {"path": "/error", "status": "Added", "right": "timeout"},
+    ])
+  );
+
+  // Text payloads are compared line by line
+  let lines = &observation("log")["payloads"][0]["diff"]["Text"];
+  let changed: Vec<_> = lines
+    .as_array()
+    .unwrap()
+    .iter()
+    .filter(|l| l["status"] != "Unchanged")
+    .map(|l| (l["status"].as_str().unwrap(), l["text"].as_str().unwrap()))
+    .collect();
+  assert_eq!(changed, vec![("Removed", "done"), ("Added", "timeout")]);
+
+  assert_eq!(observation("unchanged")["status"], "Unchanged");
+  assert_eq!(observation("checkout")["status"], "Unchanged");
+  assert_eq!(observation("only-passing")["status"], "Removed");
+  assert_eq!(observation("only-failing")["status"], "Added");
+
+  // The diff page shows the changed observations
+  let page = client
+    .get(format!(
+      "{}/diff?left={}&right={}",
+      server.base_url(),
+      passing.id(),
+      failing.id()
+    ))
+    .send()
+    .await?
+    .error_for_status()?
+    .text()
+    .await?;
+  assert!(page.contains("only-failing"));
+  assert!(page.contains("timeout"));
+  assert!(!page.contains("DiffNoChanges"));
+
+  let response = client
+    .get(format!("{}/api/diff?left={}&right=invalid", server.base_url(), passing.id()))
+    .send()
+    .await?;
+  assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+  Ok(())
+}
+
+#[derive(Debug)]
+#[allow(dead_code)]
+struct Config {
+  retries: u32,
+  hosts: Vec<&'static str>,
+}
+
+#[test_log::test(tokio::test)]
+async fn test_diff_payloads() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let (execution, _) = server
+    .with_execution("payloads", async {
+      ObservationBuilder::new("expected").serde(&json!({"user": "alice", "items": [1, 2]}));
+      ObservationBuilder::new("actual").serde(&json!({"items": [1, 2], "admin": true}));
+      ObservationBuilder::new("config-before").debug(&Config {
+        retries: 3,
+        hosts: vec!["primary", "replica", "backup"],
+      });
+      ObservationBuilder::new("config-after").debug(&Config {
+        retries: 5,
+        hosts: vec!["replica", "backup", "primary"],
+      });
+    })
+    .await?;
+  let observations = server.list_observations(&execution.id()).await?;
+  let observation = |name: &str| observations.iter().find(|o| o.name == name).unwrap();
+  let client = reqwest::Client::new();
+  let diff_url = |name: &str, with: &str| {
+    let obs = observation(name);
+    format!(
+      "{}/api/exe/{}/obs/{}/payload/{}/diff?with={}",
+      server.base_url(),
+      execution.id(),
+      obs.id,
+      obs.payloads[0].id,
+      observation(with).id
+    )
+  };
+  let changes = |diff: &serde_json::Value| -> Vec<(String, String)> {
+    diff["payload"]["diff"]["Json"]
+      .as_array()
+      .unwrap()
+      .iter()
+      .map(|c| (c["status"].as_str().unwrap().into(), c["path"].as_str().unwrap().into()))
+      .collect()
+  };
+
+  // JSON payloads report added and removed keys
+  let diff: serde_json::Value =
+    client.get(diff_url("actual", "expected")).send().await?.error_for_status()?.json().await?;
+  assert_eq!(diff["payload"]["status"], "Changed");
+  assert_eq!(
+    changes(&diff),
+    vec![("Removed".into(), "/user".into()), ("Added".into(), "/admin".into())]
+  );
+
+  // Debug payloads are parsed and compared the same way, including elements
+  // moved within arrays
+  let diff: serde_json::Value = client
+    .get(diff_url("config-after", "config-before"))
+    .send()
+    .await?
+    .error_for_status()?
+    .json()
+    .await?;
+  let changes = changes(&diff);
+  assert!(changes.iter().any(|(status, path)| status == "Changed" && path.ends_with("/retries")));
+  assert!(changes.iter().any(|(status, path)| status == "Moved" && path.ends_with("/hosts/2")));
+
+  let diff: serde_json::Value =
+    client.get(diff_url("actual", "actual")).send().await?.error_for_status()?.json().await?;
+  assert_eq!(diff["payload"]["status"], "Unchanged");
+
+  // The observation panel loads the diff as a fragment
+  let actual = observation("actual");
+  let fragment_url = format!(
+    "{}/exe/{}/obs/{}/payload/{}/diff",
+    server.base_url(),
+    execution.id(),
+    actual.id,
+    actual.payloads[0].id
+  );
+  let fragment = client
+    .get(format!("{}?with={}", fragment_url, observation("expected").id))
+    .send()
+    .await?
+    .error_for_status()?
+    .text()
+    .await?;
+  assert!(fragment.contains("DiffJsonChange"));
+  assert!(fragment.contains("admin"));
+
+  let fragment = client
+    .get(format!("{}?with=invalid", fragment_url))
+    .send()
+    .await?
+    .error_for_status()?
+    .text()
+    .await?;
+  assert!(fragment.contains("PayloadDiffError"));
+  Ok(())
+}
@@ -0,0 +1,137 @@
+//! Integration tests for streaming new observations over Server-Sent Events
+
+mod common;
+
+use common::TestServer;
+use futures::StreamExt;
+use observation_tools::with_execution;
+use observation_tools::ObservationBuilder;
+use observation_tools_shared::LogLevel;
+use std::time::Duration;
+
+/// Read from an event stream until `done` returns true for everything read so
+/// far
+async fn read_events<S, E>(stream: &mut S, done: impl Fn(&str) -> bool) -> anyhow::Result<String>
+where
+  S: futures::Stream<Item = Result<bytes::Bytes, E>> + Unpin,
+  E: std::error::Error + Send + Sync + 'static,
+{
+  let mut text = String::new();
+  tokio::time::timeout(Duration::from_secs(10), async {
+    while !done(&text) {
+      let Some(chunk) = stream.next().await else {
+        anyhow::bail!("event stream ended, received: {}", text);
+      };
+      text.push_str(std::str::from_utf8(&chunk?)?);
+    }
+    Ok(())
+  })
+  .await
+  .map_err(|_| anyhow::anyhow!("timed out waiting for events, received: {}", text))??;
+  Ok(text)
+}
+
+#[test_log::test(tokio::test)]
+async fn test_execution_events_stream_new_observations() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let client = server.create_client()?;
+  let execution = client
+    .begin_execution("live-execution")?
+    .wait_for_upload()
+    .await?;
+  let (other_execution, _) = server
+    .with_execution("other-execution", async {
+      ObservationBuilder::new("before-subscribe").payload("old");
+    })
+    .await?;
+
+  let api_client = server.create_api_client()?;
+  let mut stream = api_client
+    .execution_events()
+    .id(execution.id().to_string())
+    .send()
+    .await?
+    .into_inner_stream();
+
+  // Observations for other executions are not sent to this stream
+  server
+    .with_execution("another-execution", async {
+      ObservationBuilder::new("wrong-execution").payload("ignored");
+    })
+    .await?;
+  with_execution(execution.clone(), async {
+    ObservationBuilder::new("first-live").payload("hello");
+    ObservationBuilder::new("second-live").payload("world");
+  })
+  .await;
+  client.shutdown().await?;
+
+  let text = read_events(&mut stream, |text| {
+    text.contains("second-live") && text.contains("first-live")
+  })
+  .await?;
+  assert!(text.contains("event: observation"));
+  assert!(!text.contains("wrong-execution"));
+  assert!(!text.contains(&other_execution.id().to_string()));
+  let observation = text
+    .lines()
+    .filter_map(|line| line.strip_prefix("data: "))
+    .map(serde_json::from_str::<serde_json::Value>)
+    .next()
+    .expect("an observation event")?;
+  assert_eq!(observation["execution_id"], execution.id().to_string());
+
+  Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_execution_events_invalid_id() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let api_client = server.create_api_client()?;
+  let result = api_client.execution_events().id("not-an-id").send().await;
+  let Err(err) = result else {
+    anyhow::bail!("invalid execution id should be rejected");
+  };
+  assert_eq!(err.status(), Some(reqwest::StatusCode::BAD_REQUEST));
+  Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_ui_streams_filtered_observation_rows() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let client = server.create_client()?;
+  let execution = client
+    .begin_execution("live-ui-execution")?
+    .wait_for_upload()
+    .await?;
+
+  let response = reqwest::get(format!(
+    "{}/exe/{}/events?level=warning",
+    server.base_url(),
+    execution.id()
+  ))
+  .await?
+  .error_for_status()?;
+  let mut stream = Box::pin(futures::stream::unfold(response, |mut response| async move {
+    let chunk = response.chunk().await.transpose()?;
+    Some((chunk, response))
+  }));
+
+  with_execution(execution.clone(), async {
+    ObservationBuilder::new("quiet-row")
+      .log_level(LogLevel::Debug)
+      .payload("debug");
+    ObservationBuilder::new("loud-row")
+      .log_level(LogLevel::Error)
+      .payload("error");
+  })
+  .await;
+  client.shutdown().await?;
+
+  let text = read_events(&mut stream, |text| text.contains("loud-row")).await?;
+  assert!(text.contains("event: observation"));
+  assert!(text.contains("data-testid=\"ObservationListItem\""));
+  assert!(text.contains("&amp;level=warning"));
+  assert!(!text.contains("quiet-row"));
+  Ok(())
+}
@@ -0,0 +1,90 @@
+//! Integration tests for searching and filtering listed executions
+
+mod common;
+
+use common::TestServer;
+use observation_tools::server_client::types::ListExecutionsResponse;
+
+fn names(response: &ListExecutionsResponse) -> Vec<&str> {
+  response.executions.iter().map(|e| e.name.as_str()).collect()
+}
+
+#[test_log::test(tokio::test)]
+async fn test_list_executions_by_name_and_time() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let client = server.create_client()?;
+  for name in ["nightly-build", "release-build", "Nightly-tests"] {
+    client.begin_execution(name)?.wait_for_upload().await?;
+  }
+
+  let api_client = server.create_api_client()?;
+  let response = api_client.list_executions().name("NIGHTLY").send().await?;
+  assert_eq!(names(&response), vec!["Nightly-tests", "nightly-build"]);
+  let response = api_client.list_executions().name("build").send().await?;
+  assert_eq!(names(&response), vec!["release-build", "nightly-build"]);
+
+  let all = api_client.list_executions().send().await?;
+  let middle = all.executions[1].created_at;
+  let response = api_client
+    .list_executions()
+    .created_after(middle)
+    .send()
+    .await?;
+  assert_eq!(names(&response), vec!["Nightly-tests", "release-build"]);
+  let response = api_client
+    .list_executions()
+    .created_before(middle)
+    .name("build")
+    .send()
+    .await?;
+  assert_eq!(names(&response), vec!["nightly-build"]);
+  let response = api_client
+    .list_executions()
+    .created_after(middle)
+    .limit(1)
+    .offset(1)
+    .send()
+    .await?;
+  assert_eq!(names(&response), vec!["release-build"]);
+  assert!(!response.has_next_page);
+
+  client.shutdown().await?;
+  Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_ui_filters_executions() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let client = server.create_client()?;
+  let nightly = client.begin_execution("nightly-build")?.wait_for_upload().await?;
+  let release = client.begin_execution("release-build")?.wait_for_upload().await?;
+
+  // Form values from `datetime-local` inputs are accepted as UTC
+  let html = reqwest::get(format!(
+    "{}/exe?name=nightly&created_after=2000-01-01T00:00&created_before=",
+    server.base_url()
+  ))
+  .await?
+  .error_for_status()?
+  .text()
+  .await?;
+  assert!(html.contains(&nightly.id().to_string()));
+  assert!(!html.contains(&release.id().to_string()));
+  assert!(html.contains("&amp;name=nightly&amp;created_after=2000-01-01T00%3A00"));
+
+  let html = reqwest::get(format!(
+    "{}/exe?created_before=2000-01-01T00:00",
+    server.base_url()
+  ))
+  .await?
+  .error_for_status()?
+  .text()
+  .await?;
+  assert!(html.contains("ExecutionsListEmpty"));
+
+  let response = reqwest::get(format!("{}/exe?created_after=yesterday", server.base_url())).await?;
+  assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+  client.shutdown().await?;
+  Ok(())
+}
@@ -0,0 +1,89 @@
+//! Integration tests for updating execution metadata and tags
+
+mod common;
+
+use common::TestServer;
+use std::collections::HashMap;
+
+#[test_log::test(tokio::test)]
+async fn test_set_metadata_and_tags_after_start() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let client = server.create_client()?;
+  let execution = client
+    .begin_execution_with_metadata(
+      "ci-run",
+      HashMap::from([("job".to_string(), "test".to_string())]),
+    )?
+    .wait_for_upload()
+    .await?;
+  execution.set_metadata("branch", "main")?;
+  execution.set_metadata("job", "lint")?;
+  execution.add_tag("ci")?;
+  execution.add_tag("ci")?.wait_for_upload().await?;
+  client.begin_execution("untagged")?.wait_for_upload().await?;
+
+  let api_client = server.create_api_client()?;
+  let response = api_client
+    .get_execution()
+    .id(execution.id().to_string())
+    .send()
+    .await?;
+  let stored = &response.execution;
+  assert_eq!(stored.metadata.get("branch").map(String::as_str), Some("main"));
+  assert_eq!(stored.metadata.get("job").map(String::as_str), Some("lint"));
+  assert_eq!(stored.tags, vec!["ci".to_string()]);
+
+  let response = api_client.list_executions().tag("ci").send().await?;
+  let names: Vec<&str> = response.executions.iter().map(|e| e.name.as_str()).collect();
+  assert_eq!(names, vec!["ci-run"]);
+  let response = api_client
+    .list_executions()
+    .metadata("branch=main,job=lint")
+    .send()
+    .await?;
+  assert_eq!(response.executions.len(), 1);
+  let response = api_client
+    .list_executions()
+    .metadata("branch=other")
+    .send()
+    .await?;
+  assert!(response.executions.is_empty());
+
+  let result = api_client.list_executions().metadata("not-a-pair").send().await;
+  let Err(err) = result else {
+    anyhow::bail!("invalid metadata filter should be rejected");
+  };
+  assert_eq!(err.status(), Some(reqwest::StatusCode::BAD_REQUEST));
+
+  client.shutdown().await?;
+  Ok(())
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 4))]
+async fn test_concurrent_updates_keep_all_tags() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let client = server.create_client()?;
+  let execution = client.begin_execution("concurrent")?.wait_for_upload().await?;
+  client.shutdown().await?;
+
+  let http = reqwest::Client::new();
+  let url = format!("{}/api/exe/{}", server.base_url(), execution.id());
+  let updates = (0..20).map(|i| {
+    http
+      .patch(&url)
+      .json(&serde_json::json!({ "add_tags": [format!("tag-{}", i)] }))
+      .send()
+  });
+  for response in futures::future::join_all(updates).await {
+    response?.error_for_status()?;
+  }
+
+  let api_client = server.create_api_client()?;
+  let response = api_client
+    .get_execution()
+    .id(execution.id().to_string())
+    .send()
+    .await?;
+  assert_eq!(response.execution.tags.len(), 20);
+  Ok(())
+}
@@ -0,0 +1,113 @@
+//! Integration tests for finishing executions and filtering by status
+
+mod common;
+
+use common::TestServer;
+use observation_tools::server_client::types::ExecutionStatus as ApiExecutionStatus;
+use observation_tools::with_execution;
+use observation_tools::ExecutionStatus;
+use observation_tools::ObservationBuilder;
+
+#[test_log::test(tokio::test)]
+async fn test_finish_execution() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let client = server.create_client()?;
+  let execution = client
+    .begin_execution("failing-job")?
+    .wait_for_upload()
+    .await?;
+  with_execution(execution.clone(), async {
+    ObservationBuilder::new("before-finish").payload("data");
+  })
+  .await;
+  execution
+    .finish(ExecutionStatus::Failure, Some("exit code 1".to_string()))?
+    .wait_for_upload()
+    .await?;
+
+  // Observations buffered before finishing are uploaded first
+  let observations = server.list_observations(&execution.id()).await?;
+  assert_eq!(observations.len(), 1);
+
+  let api_client = server.create_api_client()?;
+  let response = api_client
+    .get_execution()
+    .id(execution.id().to_string())
+    .send()
+    .await?;
+  assert_eq!(response.execution.status, Some(ApiExecutionStatus::Failure));
+  assert_eq!(response.execution.error.as_deref(), Some("exit code 1"));
+  let ended_at = response.execution.ended_at.expect("ended_at should be set");
+  assert!(ended_at >= response.execution.created_at);
+
+  // Going back to running clears the end time and error
+  let response = api_client
+    .update_execution()
+    .id(execution.id().to_string())
+    .body_map(|b| b.status(Some(ApiExecutionStatus::Running)))
+    .send()
+    .await?;
+  assert_eq!(response.execution.status, Some(ApiExecutionStatus::Running));
+  assert_eq!(response.execution.ended_at, None);
+  assert_eq!(response.execution.error, None);
+
+  client.shutdown().await?;
+  Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_list_executions_by_status() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let client = server.create_client()?;
+  let succeeded = client.begin_execution("succeeded")?.wait_for_upload().await?;
+  let cancelled = client.begin_execution("cancelled")?.wait_for_upload().await?;
+  client.begin_execution("running")?.wait_for_upload().await?;
+  succeeded
+    .finish(ExecutionStatus::Success, None)?
+    .wait_for_upload()
+    .await?;
+  cancelled
+    .finish(ExecutionStatus::Cancelled, None)?
+    .wait_for_upload()
+    .await?;
+
+  let api_client = server.create_api_client()?;
+  for (status, expected) in [
+    (ApiExecutionStatus::Success, "succeeded"),
+    (ApiExecutionStatus::Cancelled, "cancelled"),
+    (ApiExecutionStatus::Running, "running"),
+  ] {
+    let response = api_client.list_executions().status(status).send().await?;
+    let names: Vec<&str> = response.executions.iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(names, vec![expected]);
+  }
+  let response = api_client.list_executions().send().await?;
+  assert_eq!(response.executions.len(), 3);
+
+  // The UI filters by status too
+  let html = reqwest::get(format!("{}/exe?status=Success", server.base_url()))
+    .await?
+    .error_for_status()?
+    .text()
+    .await?;
+  assert!(html.contains(&succeeded.id().to_string()));
+  assert!(!html.contains(&cancelled.id().to_string()));
+
+  client.shutdown().await?;
+  Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_update_missing_execution() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let api_client = server.create_api_client()?;
+  let err = api_client
+    .update_execution()
+    .id(observation_tools_shared::ExecutionId::new().to_string())
+    .body_map(|b| b.status(Some(ApiExecutionStatus::Success)))
+    .send()
+    .await
+    .expect_err("missing execution should not be found");
+  assert_eq!(err.status(), Some(reqwest::StatusCode::NOT_FOUND));
+  Ok(())
+}
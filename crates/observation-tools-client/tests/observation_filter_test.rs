@@ -0,0 +1,176 @@
+//! Integration tests for filtering listed observations
+
+mod common;
+
+use common::TestServer;
+use observation_tools::server_client::types::LogLevel as ApiLogLevel;
+use observation_tools::GroupBuilder;
+use observation_tools::ObservationBuilder;
+use observation_tools_shared::LogLevel;
+use std::collections::HashSet;
+
+#[test_log::test(tokio::test)]
+async fn test_list_observations_with_filters() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let (execution, group_id) = server
+    .with_execution("filtered-execution", async {
+      ObservationBuilder::new("http.request")
+        .log_level(LogLevel::Info)
+        .metadata("env", "prod")
+        .source("crates/app/src/server.rs", 10)
+        .payload("GET /users");
+      ObservationBuilder::new("http.response")
+        .log_level(LogLevel::Error)
+        .metadata("env", "prod")
+        .metadata("status", "500")
+        .source("crates/app/src/server.rs", 20)
+        .payload("internal error");
+      ObservationBuilder::new("db.query")
+        .log_level(LogLevel::Warning)
+        .metadata("env", "staging")
+        .source("crates/app/src/db.rs", 5)
+        .payload("SELECT 1");
+      let group = GroupBuilder::new("batch").build().into_handle();
+      ObservationBuilder::new("batch.item")
+        .log_level(LogLevel::Debug)
+        .group(&group)
+        .payload("item");
+      group.id()
+    })
+    .await?;
+
+  let api_client = server.create_api_client()?;
+  let names = |response: &observation_tools::server_client::types::ListObservationsResponse| {
+    response
+      .observations
+      .iter()
+      .map(|o| o.name.clone())
+      .collect::<HashSet<_>>()
+  };
+
+  let response = api_client
+    .list_observations()
+    .execution_id(execution.id().to_string())
+    .level(ApiLogLevel::Warning)
+    .send()
+    .await?;
+  assert_eq!(
+    names(&response),
+    HashSet::from(["http.response".to_string(), "db.query".to_string()])
+  );
+
+  let response = api_client
+    .list_observations()
+    .execution_id(execution.id().to_string())
+    .name("http.")
+    .send()
+    .await?;
+  assert_eq!(
+    names(&response),
+    HashSet::from(["http.request".to_string(), "http.response".to_string()])
+  );
+
+  let response = api_client
+    .list_observations()
+    .execution_id(execution.id().to_string())
+    .name("*.query")
+    .send()
+    .await?;
+  assert_eq!(names(&response), HashSet::from(["db.query".to_string()]));
+
+  let response = api_client
+    .list_observations()
+    .execution_id(execution.id().to_string())
+    .metadata("env=prod,status=500")
+    .send()
+    .await?;
+  assert_eq!(names(&response), HashSet::from(["http.response".to_string()]));
+
+  let response = api_client
+    .list_observations()
+    .execution_id(execution.id().to_string())
+    .source_file("src/db.rs")
+    .send()
+    .await?;
+  assert_eq!(names(&response), HashSet::from(["db.query".to_string()]));
+
+  let response = api_client
+    .list_observations()
+    .execution_id(execution.id().to_string())
+    .group_id(group_id.as_str())
+    .send()
+    .await?;
+  assert_eq!(
+    names(&response),
+    HashSet::from(["batch".to_string(), "batch.item".to_string()])
+  );
+
+  // Criteria are combined
+  let response = api_client
+    .list_observations()
+    .execution_id(execution.id().to_string())
+    .metadata("env=prod")
+    .level(ApiLogLevel::Info)
+    .name("http.req")
+    .send()
+    .await?;
+  assert_eq!(names(&response), HashSet::from(["http.request".to_string()]));
+
+  Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_list_observations_created_range() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let (execution, _) = server
+    .with_execution("time-range-execution", async {
+      ObservationBuilder::new("first").payload("1");
+      ObservationBuilder::new("second").payload("2");
+    })
+    .await?;
+
+  let api_client = server.create_api_client()?;
+  let all = server.list_observations(&execution.id()).await?;
+  assert_eq!(all.len(), 2);
+  let first = all.iter().find(|o| o.name == "first").unwrap();
+  let second = all.iter().find(|o| o.name == "second").unwrap();
+
+  let response = api_client
+    .list_observations()
+    .execution_id(execution.id().to_string())
+    .created_before(second.created_at)
+    .send()
+    .await?;
+  assert!(response.observations.iter().all(|o| o.name != "second"));
+
+  let response = api_client
+    .list_observations()
+    .execution_id(execution.id().to_string())
+    .created_after(first.created_at)
+    .send()
+    .await?;
+  assert!(response.observations.iter().any(|o| o.name == "first"));
+  assert!(response.observations.iter().any(|o| o.name == "second"));
+
+  Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_list_observations_invalid_metadata_filter() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let (execution, _) = server
+    .with_execution("invalid-filter-execution", async {})
+    .await?;
+
+  let api_client = server.create_api_client()?;
+  let result = api_client
+    .list_observations()
+    .execution_id(execution.id().to_string())
+    .metadata("not-a-pair")
+    .send()
+    .await;
+  let err = result.expect_err("invalid metadata filter should be rejected");
+  assert_eq!(err.status(), Some(reqwest::StatusCode::BAD_REQUEST));
+
+  Ok(())
+}
@@ -0,0 +1,124 @@
+//! Integration tests for payload downloads: ranges, caching and file names
+
+mod common;
+
+use common::TestServer;
+use observation_tools::ObservationBuilder;
+use observation_tools::Payload;
+use reqwest::StatusCode;
+
+/// Upload a blob-sized and an inline payload, returning their content URLs
+async fn upload(server: &TestServer) -> anyhow::Result<(String, Vec<u8>, String)> {
+  let client = server.create_client()?;
+  let execution = client.begin_execution("downloads")?.wait_for_upload().await?;
+  let blob: Vec<u8> = (0..observation_tools::BLOB_THRESHOLD_BYTES * 2)
+    .map(|i| (i % 251) as u8)
+    .collect();
+  ObservationBuilder::new("recording")
+    .execution(&execution)
+    .stream_payload("video/mp4", std::io::Cursor::new(blob.clone()))
+    .wait_for_upload()
+    .await?;
+  ObservationBuilder::new("request")
+    .execution(&execution)
+    .named_payload("headers", Payload::text("0123456789"));
+  client.shutdown().await?;
+
+  let observations = server.list_observations(&execution.id()).await?;
+  let url = |name: &str| {
+    let obs = observations.iter().find(|o| o.name == name).unwrap();
+    format!(
+      "{}/api/exe/{}/obs/{}/payload/{}/content",
+      server.base_url(),
+      execution.id(),
+      obs.id,
+      obs.payloads[0].id
+    )
+  };
+  Ok((url("recording"), blob, url("request")))
+}
+
+#[test_log::test(tokio::test)]
+async fn test_range_requests() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let (blob_url, blob, inline_url) = upload(&server).await?;
+  let http = reqwest::Client::new();
+
+  let response = http.get(&blob_url).send().await?;
+  assert_eq!(response.status(), StatusCode::OK);
+  assert_eq!(response.headers()["accept-ranges"], "bytes");
+  assert_eq!(response.headers()["content-length"], blob.len().to_string());
+  assert_eq!(
+    response.headers()["content-disposition"],
+    "inline; filename=\"recording.mp4\"; filename*=UTF-8''recording.mp4"
+  );
+  assert_eq!(response.bytes().await?, blob);
+
+  let response = http.get(&blob_url).header("range", "bytes=100-199").send().await?;
+  assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+  assert_eq!(
+    response.headers()["content-range"],
+    format!("bytes 100-199/{}", blob.len())
+  );
+  assert_eq!(response.bytes().await?, blob[100..200]);
+
+  let response = http.get(&blob_url).header("range", "bytes=-10").send().await?;
+  assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+  assert_eq!(response.bytes().await?, blob[blob.len() - 10..]);
+
+  let response = http
+    .get(&blob_url)
+    .header("range", format!("bytes={}-", blob.len()))
+    .send()
+    .await?;
+  assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+  assert_eq!(
+    response.headers()["content-range"],
+    format!("bytes */{}", blob.len())
+  );
+
+  let response = http.get(&inline_url).header("range", "bytes=2-4").send().await?;
+  assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+  assert_eq!(response.headers()["content-range"], "bytes 2-4/10");
+  assert_eq!(
+    response.headers()["content-disposition"],
+    "inline; filename=\"headers.txt\"; filename*=UTF-8''headers.txt"
+  );
+  assert_eq!(response.bytes().await?, "234");
+  Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_conditional_requests() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let (blob_url, _, _) = upload(&server).await?;
+  let http = reqwest::Client::new();
+
+  let response = http.get(&blob_url).send().await?;
+  let etag = response.headers()["etag"].clone();
+  assert!(response.headers().contains_key("last-modified"));
+  assert!(response.headers()["cache-control"]
+    .to_str()?
+    .contains("immutable"));
+
+  let response = http.get(&blob_url).header("if-none-match", &etag).send().await?;
+  assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+  assert!(response.bytes().await?.is_empty());
+
+  // A stale If-Range precondition returns the whole payload
+  let response = http
+    .get(&blob_url)
+    .header("range", "bytes=0-9")
+    .header("if-range", "\"stale\"")
+    .send()
+    .await?;
+  assert_eq!(response.status(), StatusCode::OK);
+  let response = http
+    .get(&blob_url)
+    .header("range", "bytes=0-9")
+    .header("if-range", &etag)
+    .send()
+    .await?;
+  assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+  Ok(())
+}
@@ -0,0 +1,209 @@
+//! Integration tests for uploading large payloads with separate requests
+
+mod common;
+
+use common::TestServer;
+use observation_tools::server_client::types::PayloadOrPointerResponse;
+use observation_tools::ExecutionHandle;
+use observation_tools::ClientBuilder;
+use observation_tools::ObservationBuilder;
+use observation_tools::ProjectId;
+use observation_tools_shared::ObservationId;
+use observation_tools_shared::PayloadId;
+use observation_tools_server::config::RetentionConfig;
+use std::time::Duration;
+
+async fn fetch_content(
+  server: &TestServer,
+  execution: &ExecutionHandle,
+  name: &str,
+) -> anyhow::Result<bytes::Bytes> {
+  let observations = server.list_observations(&execution.id()).await?;
+  let obs = observations.iter().find(|o| o.name == name).unwrap();
+  assert!(matches!(obs.payload(), PayloadOrPointerResponse::Pointer { .. }));
+  let url = format!(
+    "{}/api/exe/{}/obs/{}/payload/{}/content",
+    server.base_url(),
+    execution.id(),
+    obs.id,
+    obs.payloads[0].id
+  );
+  Ok(reqwest::get(url).await?.error_for_status()?.bytes().await?)
+}
+
+#[test_log::test(tokio::test)]
+async fn test_large_and_streamed_payloads() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let client = server.create_client()?;
+  let execution = client.begin_execution("large")?.wait_for_upload().await?;
+
+  let large: String = (0..observation_tools::BLOB_THRESHOLD_BYTES * 3)
+    .map(|i| (b'a' + (i % 26) as u8) as char)
+    .collect();
+  ObservationBuilder::new("in-memory")
+    .execution(&execution)
+    .payload(large.clone())
+    .wait_for_upload()
+    .await?;
+
+  // Larger than the multipart batch limit, so it can only be sent streamed
+  let streamed: Vec<u8> = (0..observation_tools_shared::MAX_OBSERVATION_BATCH_SIZE + 1024)
+    .map(|i| (i % 127) as u8)
+    .collect();
+  ObservationBuilder::new("streamed")
+    .execution(&execution)
+    .stream_payload("application/octet-stream", std::io::Cursor::new(streamed.clone()))
+    .wait_for_upload()
+    .await?;
+  client.shutdown().await?;
+
+  assert_eq!(fetch_content(&server, &execution, "in-memory").await?, large.as_bytes());
+  assert_eq!(fetch_content(&server, &execution, "streamed").await?, streamed);
+  let observations = server.list_observations(&execution.id()).await?;
+  let obs = observations.iter().find(|o| o.name == "streamed").unwrap();
+  assert_eq!(obs.payloads[0].size as usize, streamed.len());
+  assert_eq!(obs.payloads[0].name, "default");
+  Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_put_payload_rejects_invalid_requests() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let client = server.create_client()?;
+  let execution = client.begin_execution("rejects")?.wait_for_upload().await?;
+  let observation = ObservationBuilder::new("existing")
+    .execution(&execution)
+    .payload("data")
+    .wait_for_upload()
+    .await?;
+  client.shutdown().await?;
+
+  let http = reqwest::Client::new();
+  let put = |observation_id: &ObservationId, payload_id: &str| {
+    http
+      .put(format!(
+        "{}/api/exe/{}/obs/{}/payload/{}",
+        server.base_url(),
+        execution.id(),
+        observation_id,
+        payload_id
+      ))
+      .body("content")
+  };
+
+  // Payloads of stored observations cannot be replaced
+  let response = put(observation.id(), PayloadId::new().as_str()).send().await?;
+  assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
+
+  let response = put(&ObservationId::new(), "..%2F..%2Fescape").send().await?;
+  assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+  let response = put(&ObservationId::new(), PayloadId::new().as_str())
+    .header("content-encoding", "br")
+    .send()
+    .await?;
+  assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+  Ok(())
+}
+
+/// Files stored under a directory of a local blob store
+fn stored_files(dir: &std::path::Path) -> Vec<std::path::PathBuf> {
+  let Ok(entries) = std::fs::read_dir(dir) else {
+    return Vec::new();
+  };
+  entries.map(|entry| entry.unwrap().path()).collect()
+}
+
+#[test_log::test(tokio::test)]
+async fn test_deduplicates_payload_content() -> anyhow::Result<()> {
+  let blob_dir = tempfile::tempdir()?;
+  let blob_url = format!("file://{}", blob_dir.path().display());
+  let server = TestServer::new_with_config(|config| {
+    config.with_blob_url(Some(blob_url)).with_retention(RetentionConfig {
+      sweep_interval: Duration::from_millis(100),
+      blob_grace_period: Duration::from_secs(2),
+      ..Default::default()
+    })
+  })
+  .await;
+  let content: String = (0..observation_tools::BLOB_THRESHOLD_BYTES * 2)
+    .map(|i| (b'a' + (i % 26) as u8) as char)
+    .collect();
+  let digest = observation_tools_shared::content_digest(content.as_bytes());
+
+  let client = server.create_client()?;
+  let first = client.begin_execution("first")?.wait_for_upload().await?;
+  ObservationBuilder::new("in-memory")
+    .execution(&first)
+    .payload(content.clone())
+    .wait_for_upload()
+    .await?;
+  let second = client.begin_execution("second")?.wait_for_upload().await?;
+  ObservationBuilder::new("in-memory")
+    .execution(&second)
+    .payload(content.clone())
+    .wait_for_upload()
+    .await?;
+  ObservationBuilder::new("streamed")
+    .execution(&second)
+    .stream_payload("text/plain", std::io::Cursor::new(content.clone().into_bytes()))
+    .wait_for_upload()
+    .await?;
+  client.shutdown().await?;
+
+  // All three payloads share one stored blob
+  let content_dir = blob_dir.path().join("sha256");
+  assert_eq!(stored_files(&content_dir), vec![content_dir.join(&digest)]);
+  assert!(stored_files(&blob_dir.path().join("uploads")).is_empty());
+  for (execution, name) in [(&first, "in-memory"), (&second, "in-memory"), (&second, "streamed")] {
+    assert_eq!(fetch_content(&server, execution, name).await?, content.as_bytes());
+  }
+
+  let http = reqwest::Client::new();
+  let head = |execution: &ExecutionHandle, digest: &str| {
+    http.head(format!("{}/api/exe/{}/blobs/{}", server.base_url(), execution.id(), digest))
+  };
+  assert_eq!(head(&second, &digest).send().await?.status(), reqwest::StatusCode::OK);
+  let unknown = observation_tools_shared::content_digest(b"unknown");
+  assert_eq!(head(&second, &unknown).send().await?.status(), reqwest::StatusCode::NOT_FOUND);
+  assert_eq!(
+    head(&second, "not-a-digest").send().await?.status(),
+    reqwest::StatusCode::BAD_REQUEST
+  );
+
+  // Executions of other projects cannot reference the content by its digest,
+  // so they upload it again
+  let other_client = ClientBuilder::new()
+    .base_url(server.base_url())
+    .project(ProjectId::parse("other")?)
+    .build()?;
+  let other = other_client.begin_execution("other")?.wait_for_upload().await?;
+  assert_eq!(head(&other, &digest).send().await?.status(), reqwest::StatusCode::NOT_FOUND);
+  ObservationBuilder::new("in-memory")
+    .execution(&other)
+    .payload(content.clone())
+    .wait_for_upload()
+    .await?;
+  other_client.shutdown().await?;
+  assert_eq!(fetch_content(&server, &other, "in-memory").await?, content.as_bytes());
+  assert_eq!(head(&other, &digest).send().await?.status(), reqwest::StatusCode::OK);
+  assert_eq!(stored_files(&content_dir), vec![content_dir.join(&digest)]);
+
+  // The blob outlives the first execution, and is collected after the grace
+  // period once the other ones are gone too
+  let api_client = server.create_api_client()?;
+  api_client.delete_execution().id(first.id().to_string()).send().await?;
+  tokio::time::sleep(Duration::from_secs(3)).await;
+  assert_eq!(fetch_content(&server, &second, "streamed").await?, content.as_bytes());
+  api_client.delete_execution().id(second.id().to_string()).send().await?;
+  api_client.delete_execution().id(other.id().to_string()).send().await?;
+  for _ in 0..50 {
+    if stored_files(&content_dir).is_empty() {
+      break;
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+  }
+  assert!(stored_files(&content_dir).is_empty());
+  assert_eq!(head(&second, &digest).send().await?.status(), reqwest::StatusCode::NOT_FOUND);
+  Ok(())
+}
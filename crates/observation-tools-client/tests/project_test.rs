@@ -0,0 +1,265 @@
+mod common;
+
+use common::post_observation;
+use common::TestServer;
+use observation_tools::ClientBuilder;
+use observation_tools::ProjectId;
+use observation_tools_server::auth::ApiKeyRecord;
+use observation_tools_server::auth::ApiKeyScope;
+use observation_tools_server::config::ReadAuthConfig;
+use observation_tools_shared::Execution;
+use reqwest::StatusCode;
+
+#[test_log::test(tokio::test)]
+async fn test_projects_group_executions() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let team_a = ClientBuilder::new()
+    .base_url(server.base_url())
+    .project(ProjectId::parse("team-a")?)
+    .build()?;
+  let default = server.create_client()?;
+  let a_execution = team_a.begin_execution("team-a-run")?.wait_for_upload().await?;
+  let default_execution = default.begin_execution("default-run")?.wait_for_upload().await?;
+  let client = reqwest::Client::new();
+
+  let projects: serde_json::Value = client
+    .get(format!("{}/api/projects", server.base_url()))
+    .send()
+    .await?
+    .json()
+    .await?;
+  let ids: Vec<_> = projects["projects"]
+    .as_array()
+    .unwrap()
+    .iter()
+    .map(|p| p["id"].as_str().unwrap().to_string())
+    .collect();
+  assert_eq!(ids, vec!["default", "team-a"]);
+
+  let executions: serde_json::Value = client
+    .get(format!("{}/api/exe?project=team-a", server.base_url()))
+    .send()
+    .await?
+    .json()
+    .await?;
+  let executions = executions["executions"].as_array().unwrap();
+  assert_eq!(executions.len(), 1);
+  assert_eq!(executions[0]["id"], a_execution.id().to_string());
+  assert_eq!(executions[0]["project"], "team-a");
+
+  // The UI lists each project separately, with a switcher in the nav bar
+  let page = client
+    .get(format!("{}/p/team-a/exe", server.base_url()))
+    .send()
+    .await?
+    .text()
+    .await?;
+  assert!(page.contains("team-a-run"));
+  assert!(!page.contains("default-run"));
+  assert!(page.contains("NavProject"));
+  let page = client
+    .get(format!("{}/exe", server.base_url()))
+    .send()
+    .await?
+    .text()
+    .await?;
+  assert!(page.contains("team-a-run") && page.contains("default-run"));
+
+  let response = client
+    .get(format!("{}/p/team-a/exe/{}", server.base_url(), a_execution.id()))
+    .send()
+    .await?;
+  assert_eq!(response.status(), StatusCode::OK);
+  let response = client
+    .get(format!("{}/p/team-a/exe/{}", server.base_url(), default_execution.id()))
+    .send()
+    .await?;
+  assert_eq!(response.status(), StatusCode::NOT_FOUND);
+  let response = client
+    .get(format!("{}/p/unknown/exe", server.base_url()))
+    .send()
+    .await?;
+  assert_eq!(response.status(), StatusCode::NOT_FOUND);
+  let response = client
+    .get(format!("{}/p/Not_A_Slug/exe", server.base_url()))
+    .send()
+    .await?;
+  assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+  team_a.shutdown().await?;
+  default.shutdown().await?;
+  Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_project_bound_keys() -> anyhow::Result<()> {
+  let team_a = ProjectId::parse("team-a")?;
+  let mut writer = ApiKeyRecord::new("team-a-ci", ApiKeyScope::WriteOnly);
+  writer.project = Some(team_a.clone());
+  let mut reader = ApiKeyRecord::new("team-a-dashboard", ApiKeyScope::ReadOnly);
+  reader.project = Some(team_a.clone());
+  let keys = [writer, reader, ApiKeyRecord::new("ops", ApiKeyScope::Admin)];
+  let server = TestServer::new_with_api_keys_and_config(&keys, |config| {
+    config.with_read_auth(Some(ReadAuthConfig::default()))
+  })
+  .await?;
+  let (writer, reader, admin) = (
+    server.registered_api_key(0),
+    server.registered_api_key(1),
+    server.registered_api_key(2),
+  );
+  let client = reqwest::Client::new();
+  let create = |key: &str, execution: &Execution| {
+    client
+      .post(format!("{}/api/exe", server.base_url()))
+      .bearer_auth(key)
+      .json(&serde_json::json!({ "execution": execution }))
+      .send()
+  };
+
+  // Bound keys only write to their project
+  let response = create(writer, &Execution::new("default")).await?;
+  assert_eq!(response.status(), StatusCode::FORBIDDEN);
+  let ours = Execution::new("ours").in_project(team_a.clone());
+  assert_eq!(create(writer, &ours).await?.status(), StatusCode::OK);
+  let theirs = Execution::new("theirs").in_project(ProjectId::parse("team-b")?);
+  assert_eq!(create(admin, &theirs).await?.status(), StatusCode::OK);
+  let response = client
+    .patch(format!("{}/api/exe/{}", server.base_url(), theirs.id))
+    .bearer_auth(writer)
+    .json(&serde_json::json!({ "add_tags": ["hijacked"] }))
+    .send()
+    .await?;
+  assert_eq!(response.status(), StatusCode::FORBIDDEN);
+  // Including through observations posted to an execution of their project
+  let status = post_observation(&server, writer, ours.id, theirs.id).await?;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+  let status = post_observation(&server, writer, theirs.id, theirs.id).await?;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+  let status = post_observation(&server, writer, ours.id, ours.id).await?;
+  assert_eq!(status, StatusCode::OK);
+  let observations: serde_json::Value = client
+    .get(format!("{}/api/exe/{}/obs", server.base_url(), theirs.id))
+    .bearer_auth(admin)
+    .send()
+    .await?
+    .json()
+    .await?;
+  assert_eq!(observations["observations"].as_array().unwrap().len(), 0);
+
+  // And only read from it
+  let get = |path: String| {
+    client
+      .get(format!("{}{}", server.base_url(), path))
+      .bearer_auth(reader)
+      .send()
+  };
+  let executions: serde_json::Value = get("/api/exe".to_string()).await?.json().await?;
+  let executions = executions["executions"].as_array().unwrap();
+  assert_eq!(executions.len(), 1);
+  assert_eq!(executions[0]["id"], ours.id.to_string());
+  let response = get("/api/exe?project=team-b".to_string()).await?;
+  assert_eq!(response.status(), StatusCode::FORBIDDEN);
+  let response = get(format!("/api/exe/{}", ours.id)).await?;
+  assert_eq!(response.status(), StatusCode::OK);
+  for path in [
+    format!("/api/exe/{}", theirs.id),
+    format!("/api/exe/{}/obs", theirs.id),
+    "/api/search?q=theirs".to_string(),
+  ] {
+    assert_eq!(get(path.clone()).await?.status(), StatusCode::FORBIDDEN, "{}", path);
+  }
+  let projects: serde_json::Value = get("/api/projects".to_string()).await?.json().await?;
+  assert_eq!(projects["projects"].as_array().unwrap().len(), 1);
+  assert_eq!(projects["projects"][0]["id"], "team-a");
+
+  // Projects are created by admins, or with the first execution in them
+  let response = client
+    .post(format!("{}/api/projects", server.base_url()))
+    .bearer_auth(writer)
+    .json(&serde_json::json!({ "id": "team-a", "name": "Team A" }))
+    .send()
+    .await?;
+  assert_eq!(response.status(), StatusCode::FORBIDDEN);
+  let response = client
+    .post(format!("{}/api/projects", server.base_url()))
+    .bearer_auth(admin)
+    .json(&serde_json::json!({ "id": "team-a", "name": "Team A" }))
+    .send()
+    .await?;
+  assert_eq!(response.status(), StatusCode::OK);
+  let projects: serde_json::Value = get("/api/projects".to_string()).await?.json().await?;
+  assert_eq!(projects["projects"][0]["name"], "Team A");
+  Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_project_bound_keys_in_ui() -> anyhow::Result<()> {
+  let mut reader = ApiKeyRecord::new("team-a-dashboard", ApiKeyScope::ReadOnly);
+  reader.project = Some(ProjectId::parse("team-a")?);
+  let keys = [reader, ApiKeyRecord::new("ops", ApiKeyScope::Admin)];
+  let server = TestServer::new_with_api_keys_and_config(&keys, |config| {
+    config.with_read_auth(Some(ReadAuthConfig::default()))
+  })
+  .await?;
+  let (reader, admin) = (server.registered_api_key(0), server.registered_api_key(1));
+  let client = reqwest::Client::new();
+  let ours = Execution::new("ours-run").in_project(ProjectId::parse("team-a")?);
+  let theirs = Execution::new("theirs-run").in_project(ProjectId::parse("team-b")?);
+  let mut observations = Vec::new();
+  for execution in [&ours, &theirs] {
+    client
+      .post(format!("{}/api/exe", server.base_url()))
+      .bearer_auth(admin)
+      .json(&serde_json::json!({ "execution": execution }))
+      .send()
+      .await?
+      .error_for_status()?;
+    let status = post_observation(&server, admin, execution.id, execution.id).await?;
+    assert_eq!(status, StatusCode::OK);
+    let listed: serde_json::Value = client
+      .get(format!("{}/api/exe/{}/obs", server.base_url(), execution.id))
+      .bearer_auth(admin)
+      .send()
+      .await?
+      .json()
+      .await?;
+    let obs = &listed["observations"][0];
+    observations.push((
+      obs["id"].as_str().unwrap().to_string(),
+      obs["payloads"][0]["id"].as_str().unwrap().to_string(),
+    ));
+  }
+  let ((our_obs, our_payload), (their_obs, their_payload)) =
+    (observations[0].clone(), observations[1].clone());
+  let get = |path: String| {
+    client
+      .get(format!("{}{}", server.base_url(), path))
+      .bearer_auth(reader)
+      .send()
+  };
+
+  // The list and the project switcher only show the key's project
+  let page = get("/exe".to_string()).await?.text().await?;
+  assert!(page.contains("ours-run"));
+  assert!(!page.contains("theirs-run") && !page.contains("team-b"));
+  let response = get(format!("/exe/{}", ours.id)).await?;
+  assert_eq!(response.status(), StatusCode::OK);
+  let response = get(format!("/exe/{}/obs/{}", ours.id, our_obs)).await?;
+  assert_eq!(response.status(), StatusCode::OK);
+
+  for path in [
+    "/p/team-b/exe".to_string(),
+    format!("/exe/{}", theirs.id),
+    format!("/exe/{}/tree", theirs.id),
+    format!("/exe/{}/events", theirs.id),
+    format!("/exe/{}?obs={}", ours.id, their_obs),
+    format!("/exe/{}/obs/{}", theirs.id, their_obs),
+    format!("/diff?left={}&right={}", ours.id, theirs.id),
+    format!("/exe/{}/obs/{}/payload/{}/diff?with={}", ours.id, our_obs, our_payload, their_obs),
+    format!("/exe/{}/obs/{}/payload/{}/diff?with={}", theirs.id, their_obs, their_payload, our_obs),
+  ] {
+    assert_eq!(get(path.clone()).await?.status(), StatusCode::FORBIDDEN, "{}", path);
+  }
+  Ok(())
+}
@@ -0,0 +1,166 @@
+//! Integration tests for the bounded upload queue and its drop policies
+
+mod common;
+
+use common::TestServer;
+use observation_tools::ClientBuilder;
+use observation_tools::Error;
+use observation_tools::ExecutionHandle;
+use observation_tools::ObservationBuilder;
+use observation_tools::QueuePolicy;
+use observation_tools::SendObservation;
+use observation_tools_shared::LogLevel;
+
+fn send(execution: &ExecutionHandle, name: &str, level: LogLevel) -> SendObservation {
+  ObservationBuilder::new(name)
+    .log_level(level)
+    .execution(execution)
+    .payload("data")
+}
+
+async fn uploaded_names(server: &TestServer, execution: &ExecutionHandle) -> Vec<String> {
+  let mut names: Vec<String> = server
+    .list_observations(&execution.id())
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|o| o.name)
+    .collect();
+  names.sort();
+  names
+}
+
+// On a current-thread runtime the uploader cannot run while the test sends,
+// so the queue fills up deterministically
+#[test_log::test(tokio::test)]
+async fn test_drop_newest_and_oldest() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+
+  let client = ClientBuilder::new()
+    .base_url(server.base_url())
+    .queue_capacity(2)
+    .queue_policy(QueuePolicy::DropNewest)
+    .build()?;
+  let execution = client.begin_execution("newest")?.wait_for_upload().await?;
+  let sends: Vec<_> = ["a", "b", "c", "d"]
+    .into_iter()
+    .map(|name| send(&execution, name, LogLevel::Info))
+    .collect();
+  assert_eq!(client.dropped_observations(), 2);
+  let mut results = Vec::new();
+  for mut send in sends {
+    results.push(send.wait_for_upload().await.is_ok());
+  }
+  assert_eq!(results, vec![true, true, false, false]);
+  client.shutdown().await?;
+  assert_eq!(uploaded_names(&server, &execution).await, vec!["a", "b"]);
+
+  let client = ClientBuilder::new()
+    .base_url(server.base_url())
+    .queue_capacity(2)
+    .queue_policy(QueuePolicy::DropOldest)
+    .build()?;
+  let execution = client.begin_execution("oldest")?.wait_for_upload().await?;
+  for name in ["a", "b", "c", "d"] {
+    send(&execution, name, LogLevel::Info);
+  }
+  assert_eq!(client.dropped_observations(), 2);
+  client.shutdown().await?;
+  assert_eq!(uploaded_names(&server, &execution).await, vec!["c", "d"]);
+  Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_drop_named_payloads() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let client = ClientBuilder::new()
+    .base_url(server.base_url())
+    .queue_capacity(2)
+    .queue_policy(QueuePolicy::DropNewest)
+    .build()?;
+  let execution = client.begin_execution("payloads")?.wait_for_upload().await?;
+  let handle = ObservationBuilder::new("multi")
+    .execution(&execution)
+    .named_payload("first", "data");
+  handle.payload("second", "data").payload("third", "data");
+  assert_eq!(handle.dropped_payloads(), 1);
+  assert_eq!(client.dropped_observations(), 1);
+  client.shutdown().await?;
+  Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_sample_by_level_keeps_important_observations() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let client = ClientBuilder::new()
+    .base_url(server.base_url())
+    .queue_capacity(4)
+    .queue_policy(QueuePolicy::SampleByLevel(LogLevel::Warning))
+    .build()?;
+  let execution = client.begin_execution("sampled")?.wait_for_upload().await?;
+  send(&execution, "info-1", LogLevel::Info);
+  send(&execution, "info-2", LogLevel::Info);
+  // The queue is half full, so only warnings and errors are kept
+  send(&execution, "info-3", LogLevel::Info);
+  send(&execution, "error-1", LogLevel::Error);
+  send(&execution, "warning-1", LogLevel::Warning);
+  // The queue is full
+  send(&execution, "error-2", LogLevel::Error);
+  assert_eq!(client.dropped_observations(), 2);
+  client.shutdown().await?;
+  assert_eq!(
+    uploaded_names(&server, &execution).await,
+    vec!["error-1", "info-1", "info-2", "warning-1"]
+  );
+  Ok(())
+}
+
+// Blocking on a current-thread runtime would stall the uploader
+#[test_log::test(tokio::test)]
+async fn test_block_drops_on_current_thread_runtime() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let client = ClientBuilder::new()
+    .base_url(server.base_url())
+    .queue_capacity(2)
+    .build()?;
+  let execution = client.begin_execution("blocking")?.wait_for_upload().await?;
+  let handle = ObservationBuilder::new("multi")
+    .execution(&execution)
+    .named_payload("first", "data");
+  let sends: Vec<_> = ["a", "b"]
+    .into_iter()
+    .map(|name| send(&execution, name, LogLevel::Info))
+    .collect();
+  handle.payload("second", "data");
+  assert_eq!(handle.dropped_payloads(), 1);
+  assert_eq!(client.dropped_observations(), 2);
+  let mut results = Vec::new();
+  for mut send in sends {
+    results.push(match send.wait_for_upload().await {
+      Ok(_) => "uploaded",
+      Err(Error::UploadFailed(message)) if message == Error::QueueFull.to_string() => "dropped",
+      Err(e) => panic!("unexpected error: {}", e),
+    });
+  }
+  assert_eq!(results, vec!["uploaded", "dropped"]);
+  client.shutdown().await?;
+  assert_eq!(uploaded_names(&server, &execution).await, vec!["a", "multi"]);
+  Ok(())
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread", worker_threads = 2))]
+async fn test_block_waits_for_room() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let client = ClientBuilder::new()
+    .base_url(server.base_url())
+    .queue_capacity(1)
+    .build()?;
+  let execution = client.begin_execution("blocking")?.wait_for_upload().await?;
+  for i in 0..20 {
+    send(&execution, &format!("obs-{:02}", i), LogLevel::Info);
+  }
+  assert_eq!(client.dropped_observations(), 0);
+  client.shutdown().await?;
+  assert_eq!(uploaded_names(&server, &execution).await.len(), 20);
+  Ok(())
+}
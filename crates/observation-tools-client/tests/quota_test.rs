@@ -0,0 +1,238 @@
+mod common;
+
+use common::TestServer;
+use observation_tools::Error;
+use observation_tools::ExecutionHandle;
+use observation_tools::ObservationBuilder;
+use observation_tools::Quota;
+use observation_tools_server::config::QuotaConfig;
+use observation_tools_shared::LogLevel;
+use observation_tools_shared::Observation;
+use observation_tools_shared::ObservationId;
+use observation_tools_shared::ObservationType;
+use observation_tools_shared::PayloadId;
+use reqwest::StatusCode;
+
+#[test_log::test(tokio::test)]
+async fn test_execution_observation_quota() -> anyhow::Result<()> {
+  let server = TestServer::new_with_config(|config| {
+    config.with_quotas(QuotaConfig {
+      max_observations_per_execution: Some(2),
+      ..Default::default()
+    })
+  })
+  .await;
+  let client = server.create_client()?;
+  let execution = client.begin_execution("quota")?.wait_for_upload().await?;
+
+  for name in ["first", "second"] {
+    ObservationBuilder::new(name)
+      .execution(&execution)
+      .payload("fits")
+      .wait_for_upload()
+      .await?;
+  }
+  let result = ObservationBuilder::new("third")
+    .execution(&execution)
+    .payload("over quota")
+    .wait_for_upload()
+    .await;
+
+  let Err(Error::QuotaExceeded(err)) = result else {
+    panic!("expected quota error, got {:?}", result);
+  };
+  assert_eq!(err.quota, Quota::ExecutionObservations);
+  assert_eq!((err.limit, err.used, err.requested), (2, 2, 1));
+  client.shutdown().await?;
+  assert_eq!(server.list_observations(&execution.id()).await?.len(), 2);
+  Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_execution_bytes_quota() -> anyhow::Result<()> {
+  let server = TestServer::new_with_config(|config| {
+    config.with_quotas(QuotaConfig {
+      max_bytes_per_execution: Some(1000),
+      ..Default::default()
+    })
+  })
+  .await;
+  let client = server.create_client()?;
+  let execution = client.begin_execution("quota")?.wait_for_upload().await?;
+  let other = client.begin_execution("quota-other")?.wait_for_upload().await?;
+
+  ObservationBuilder::new("first")
+    .execution(&execution)
+    .payload("a".repeat(600))
+    .wait_for_upload()
+    .await?;
+  let result = ObservationBuilder::new("second")
+    .execution(&execution)
+    .payload("b".repeat(600))
+    .wait_for_upload()
+    .await;
+
+  let Err(Error::QuotaExceeded(err)) = result else {
+    panic!("expected quota error, got {:?}", result);
+  };
+  assert_eq!(err.quota, Quota::ExecutionBytes);
+  assert_eq!(err.limit, 1000);
+  assert!(err.used >= 600 && err.requested >= 600);
+
+  // Each execution has its own quota
+  ObservationBuilder::new("second")
+    .execution(&other)
+    .payload("b".repeat(600))
+    .wait_for_upload()
+    .await?;
+  client.shutdown().await?;
+  assert_eq!(server.list_observations(&execution.id()).await?.len(), 1);
+  Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_api_key_daily_quota() -> anyhow::Result<()> {
+  let server = TestServer::new_with_auth_and_config(|config| {
+    config.with_quotas(QuotaConfig {
+      max_bytes_per_api_key_per_day: Some(1000),
+      ..Default::default()
+    })
+  })
+  .await?;
+  let client = server.create_client_with_api_key(&server.generate_api_key()?)?;
+  let execution = client.begin_execution("quota")?.wait_for_upload().await?;
+
+  ObservationBuilder::new("first")
+    .execution(&execution)
+    .payload("a".repeat(600))
+    .wait_for_upload()
+    .await?;
+  let result = ObservationBuilder::new("second")
+    .execution(&execution)
+    .payload("b".repeat(600))
+    .wait_for_upload()
+    .await;
+
+  let Err(Error::QuotaExceeded(err)) = result else {
+    panic!("expected quota error, got {:?}", result);
+  };
+  assert_eq!(err.quota, Quota::ApiKeyDailyBytes);
+  assert!(!Error::QuotaExceeded(err).is_transient());
+
+  // Other keys have their own budget
+  let other_client = server.create_client_with_api_key(&server.generate_api_key()?)?;
+  let other_execution = other_client
+    .begin_execution("quota-other-key")?
+    .wait_for_upload()
+    .await?;
+  ObservationBuilder::new("other")
+    .execution(&other_execution)
+    .payload("c".repeat(600))
+    .wait_for_upload()
+    .await?;
+
+  client.shutdown().await?;
+  other_client.shutdown().await?;
+  Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_uploaded_payload_quotas() -> anyhow::Result<()> {
+  let server = TestServer::new_with_auth_and_config(|config| {
+    config.with_quotas(QuotaConfig {
+      max_bytes_per_execution: Some(1000),
+      max_bytes_per_api_key_per_day: Some(2000),
+      ..Default::default()
+    })
+  })
+  .await?;
+  let api_key = server.generate_api_key()?;
+  let client = server.create_client_with_api_key(&api_key)?;
+  let execution = client.begin_execution("quota")?.wait_for_upload().await?;
+  let other = client.begin_execution("quota-other")?.wait_for_upload().await?;
+  let http = reqwest::Client::new();
+  let put = |execution: &ExecutionHandle, content: String| {
+    http
+      .put(format!(
+        "{}/api/exe/{}/obs/{}/payload/{}",
+        server.base_url(),
+        execution.id(),
+        ObservationId::new(),
+        PayloadId::new().as_str()
+      ))
+      .bearer_auth(&api_key)
+      .body(content)
+      .send()
+  };
+
+  let response = put(&execution, "a".repeat(1200)).await?;
+  assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+  // Uploaded content is charged to the key once, not again by its observation
+  ObservationBuilder::new("streamed")
+    .execution(&execution)
+    .stream_payload("text/plain", std::io::Cursor::new("b".repeat(900)))
+    .wait_for_upload()
+    .await?;
+  let response = put(&other, "c".repeat(1000)).await?;
+  assert_eq!(response.status(), StatusCode::OK);
+  let response = put(&other, "d".repeat(200)).await?;
+  assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+  client.shutdown().await?;
+  Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_observations_stored_again() -> anyhow::Result<()> {
+  let server = TestServer::new_with_config(|config| {
+    config.with_quotas(QuotaConfig {
+      max_observations_per_execution: Some(2),
+      max_bytes_per_execution: Some(1000),
+      ..Default::default()
+    })
+  })
+  .await;
+  let client = server.create_client()?;
+  let execution = client.begin_execution("quota")?.wait_for_upload().await?;
+  let http = reqwest::Client::new();
+  let post = |id: ObservationId, content: String| {
+    let observation = Observation {
+      id,
+      execution_id: execution.id(),
+      name: "retried".to_string(),
+      observation_type: ObservationType::Payload,
+      log_level: LogLevel::Info,
+      source: None,
+      metadata: Default::default(),
+      group_ids: Vec::new(),
+      parent_group_id: None,
+      parent_span_id: None,
+      created_at: chrono::Utc::now(),
+    };
+    let form = reqwest::multipart::Form::new()
+      .text("observations", serde_json::to_string(&[&observation]).unwrap())
+      .text(id.to_string(), content);
+    http
+      .post(format!("{}/api/exe/{}/obs", server.base_url(), execution.id()))
+      .multipart(form)
+      .send()
+  };
+
+  // Uploading the same observation again only counts the bytes it adds
+  let retried = ObservationId::new();
+  for _ in 0..3 {
+    let response = post(retried, "a".repeat(600)).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+  }
+  let response = post(ObservationId::new(), "b".repeat(500)).await?;
+  assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+  // Replacing it with less content frees the difference
+  let response = post(retried, "a".repeat(100)).await?;
+  assert_eq!(response.status(), StatusCode::OK);
+  let response = post(ObservationId::new(), "b".repeat(500)).await?;
+  assert_eq!(response.status(), StatusCode::OK);
+  client.shutdown().await?;
+  assert_eq!(server.list_observations(&execution.id()).await?.len(), 2);
+  Ok(())
+}
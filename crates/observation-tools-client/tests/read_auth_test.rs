@@ -0,0 +1,174 @@
+mod common;
+
+use common::set_cookie;
+use common::TestServer;
+use observation_tools_server::auth::ApiKeyRecord;
+use observation_tools_server::auth::ApiKeyScope;
+use observation_tools_server::config::ReadAuthConfig;
+use observation_tools_server::session::set_user_password;
+use observation_tools_shared::Execution;
+use reqwest::header::COOKIE;
+use reqwest::header::LOCATION;
+use reqwest::redirect::Policy;
+use reqwest::StatusCode;
+
+#[test_log::test(tokio::test)]
+async fn test_read_api_requires_key() -> anyhow::Result<()> {
+  let keys = [
+    ApiKeyRecord::new("reader", ApiKeyScope::ReadOnly),
+    ApiKeyRecord::new("writer", ApiKeyScope::WriteOnly),
+    ApiKeyRecord::new("admin", ApiKeyScope::Admin),
+  ];
+  let server = TestServer::new_with_api_keys_and_config(&keys, |config| {
+    config.with_read_auth(Some(ReadAuthConfig::default()))
+  })
+  .await?;
+  let (reader, writer, admin) = (
+    server.registered_api_key(0),
+    server.registered_api_key(1),
+    server.registered_api_key(2),
+  );
+  let client = reqwest::Client::new();
+
+  let execution = Execution::new("read-auth");
+  let response = client
+    .post(format!("{}/api/exe", server.base_url()))
+    .bearer_auth(writer)
+    .json(&serde_json::json!({ "execution": execution }))
+    .send()
+    .await?;
+  assert_eq!(response.status(), StatusCode::OK);
+
+  let list_url = format!("{}/api/exe", server.base_url());
+  let get_url = format!("{}/api/exe/{}", server.base_url(), execution.id);
+  let response = client.get(&list_url).send().await?;
+  assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+  let response = client.get(&get_url).bearer_auth("obs_invalid").send().await?;
+  assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+  let response = client.get(&list_url).bearer_auth(writer).send().await?;
+  assert_eq!(response.status(), StatusCode::FORBIDDEN);
+  for key in [reader, admin] {
+    let response = client.get(&list_url).bearer_auth(key).send().await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client.get(&get_url).bearer_auth(key).send().await?;
+    assert_eq!(response.status(), StatusCode::OK);
+  }
+
+  // Reading does not allow writing
+  let response = client
+    .post(&list_url)
+    .bearer_auth(reader)
+    .json(&serde_json::json!({ "execution": Execution::new("read-only") }))
+    .send()
+    .await?;
+  assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+  // The UI redirects to the login page instead
+  let response = reqwest::Client::builder()
+    .redirect(Policy::none())
+    .build()?
+    .get(format!("{}/exe", server.base_url()))
+    .send()
+    .await?;
+  assert_eq!(response.status(), StatusCode::SEE_OTHER);
+  assert_eq!(response.headers()[LOCATION], "/login?next=%2Fexe");
+  Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_ui_login() -> anyhow::Result<()> {
+  let users_dir = tempfile::tempdir()?;
+  let users_file = users_dir.path().join("users");
+  set_user_password(&users_file, "alice", "correct horse")?;
+  let server = TestServer::new_with_api_keys_and_config(&[], |config| {
+    config.with_read_auth(Some(ReadAuthConfig {
+      users_file: Some(users_file),
+      ..Default::default()
+    }))
+  })
+  .await?;
+  let client = reqwest::Client::builder().redirect(Policy::none()).build()?;
+  let login_url = format!("{}/login", server.base_url());
+
+  let response = client.get(&login_url).send().await?;
+  assert_eq!(response.status(), StatusCode::OK);
+  let csrf_cookie = set_cookie(&response, "csrf_token").expect("CSRF cookie");
+  let csrf_token = csrf_cookie.strip_prefix("csrf_token=").unwrap().to_string();
+  let login = |password: &'static str, csrf_token: String| {
+    client
+      .post(&login_url)
+      .header(COOKIE, &csrf_cookie)
+      .form(&[
+        ("username", "alice"),
+        ("password", password),
+        ("next", "/exe"),
+        ("csrf_token", &csrf_token),
+      ])
+      .send()
+  };
+
+  let response = login("wrong", csrf_token.clone()).await?;
+  assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+  assert!(set_cookie(&response, "obs_session").is_none());
+  let response = login("correct horse", "forged".to_string()).await?;
+  assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+  let response = login("correct horse", csrf_token.clone()).await?;
+  assert_eq!(response.status(), StatusCode::SEE_OTHER);
+  assert_eq!(response.headers()[LOCATION], "/exe");
+  let session = set_cookie(&response, "obs_session").expect("session cookie");
+
+  let response = client
+    .get(format!("{}/exe", server.base_url()))
+    .header(COOKIE, &session)
+    .send()
+    .await?;
+  assert_eq!(response.status(), StatusCode::OK);
+  let session_csrf_cookie = set_cookie(&response, "csrf_token").expect("CSRF cookie");
+  assert!(response.text().await?.contains("alice"));
+  let response = client
+    .get(format!("{}/api/exe", server.base_url()))
+    .header(COOKIE, &session)
+    .send()
+    .await?;
+  assert_eq!(response.status(), StatusCode::OK);
+
+  // A tampered session is rejected
+  let response = client
+    .get(format!("{}/api/exe", server.base_url()))
+    .header(COOKIE, format!("{}x", session))
+    .send()
+    .await?;
+  assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+  // Logging out requires the CSRF token bound to the session
+  let logout_url = format!("{}/logout", server.base_url());
+  let response = client
+    .post(&logout_url)
+    .header(COOKIE, &session)
+    .send()
+    .await?;
+  assert_eq!(response.status(), StatusCode::FORBIDDEN);
+  let response = client
+    .post(&logout_url)
+    .header(COOKIE, format!("{}; {}", session, csrf_cookie))
+    .header("x-csrf-token", &csrf_token)
+    .send()
+    .await?;
+  assert_eq!(response.status(), StatusCode::FORBIDDEN);
+  let response = client
+    .post(&logout_url)
+    .header(COOKIE, format!("{}; {}", session, session_csrf_cookie))
+    .header(
+      "x-csrf-token",
+      session_csrf_cookie.strip_prefix("csrf_token=").unwrap(),
+    )
+    .send()
+    .await?;
+  assert_eq!(response.status(), StatusCode::SEE_OTHER);
+  assert_eq!(
+    set_cookie(&response, "obs_session").as_deref(),
+    Some("obs_session=")
+  );
+  Ok(())
+}
@@ -0,0 +1,124 @@
+//! Integration tests for deleting executions and the retention sweeper
+
+mod common;
+
+use common::TestServer;
+use observation_tools::ObservationBuilder;
+use observation_tools_server::config::RetentionConfig;
+use std::time::Duration;
+
+#[test_log::test(tokio::test)]
+async fn test_delete_execution() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let large_payload = "x".repeat(observation_tools::BLOB_THRESHOLD_BYTES + 1);
+  let (execution, _) = server
+    .with_execution("execution-to-delete", async {
+      ObservationBuilder::new("small").payload("searchable needle");
+      ObservationBuilder::new("large").payload(large_payload);
+    })
+    .await?;
+  let (kept_execution, _) = server
+    .with_execution("execution-to-keep", async {
+      ObservationBuilder::new("kept").payload("needle");
+    })
+    .await?;
+
+  let observations = server.list_observations(&execution.id()).await?;
+  assert_eq!(observations.len(), 2);
+  let large = observations.iter().find(|o| o.name == "large").unwrap();
+
+  let api_client = server.create_api_client()?;
+  let response = api_client
+    .delete_execution()
+    .id(execution.id().to_string())
+    .send()
+    .await?;
+  assert_eq!(response.deleted_observations, 2);
+
+  // The execution, its observations, blobs and search entries are gone
+  let err = api_client
+    .get_execution()
+    .id(execution.id().to_string())
+    .send()
+    .await
+    .expect_err("deleted execution should not be found");
+  assert_eq!(err.status(), Some(reqwest::StatusCode::NOT_FOUND));
+  assert!(server.list_observations(&execution.id()).await?.is_empty());
+  let result = api_client
+    .get_observation_blob()
+    .execution_id(execution.id().to_string())
+    .observation_id(large.id.to_string())
+    .payload_id(large.payloads[0].id.to_string())
+    .send()
+    .await;
+  let Err(err) = result else {
+    anyhow::bail!("deleted blob should not be found");
+  };
+  assert_eq!(err.status(), Some(reqwest::StatusCode::NOT_FOUND));
+  let search = api_client.search_observations().q("needle").send().await?;
+  assert_eq!(search.observations.len(), 1);
+  assert_eq!(
+    search.observations[0].execution_id.to_string(),
+    kept_execution.id().to_string()
+  );
+
+  // Other executions are untouched
+  assert_eq!(server.list_observations(&kept_execution.id()).await?.len(), 1);
+
+  let err = api_client
+    .delete_execution()
+    .id(execution.id().to_string())
+    .send()
+    .await
+    .expect_err("deleting twice should fail");
+  assert_eq!(err.status(), Some(reqwest::StatusCode::NOT_FOUND));
+
+  Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_retention_sweeper_keeps_newest_executions() -> anyhow::Result<()> {
+  let server = TestServer::new_with_retention(RetentionConfig {
+    max_executions: Some(2),
+    sweep_interval: Duration::from_millis(100),
+    ..Default::default()
+  })
+  .await;
+
+  let mut execution_ids = Vec::new();
+  for i in 0..4 {
+    let (execution, _) = server
+      .with_execution(format!("execution-{}", i), async {
+        ObservationBuilder::new("obs").payload("data");
+      })
+      .await?;
+    execution_ids.push(execution.id().to_string());
+    // Make sure creation times are distinct
+    tokio::time::sleep(Duration::from_millis(5)).await;
+  }
+
+  let api_client = server.create_api_client()?;
+  let mut remaining = Vec::new();
+  for _ in 0..50 {
+    let response = api_client.list_executions().send().await?;
+    remaining = response
+      .executions
+      .iter()
+      .map(|e| e.id.to_string())
+      .collect();
+    if remaining.len() == 2 {
+      break;
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+  }
+  remaining.sort();
+  let mut newest = execution_ids[2..].to_vec();
+  newest.sort();
+  assert_eq!(remaining, newest);
+
+  for id in &execution_ids[..2] {
+    assert!(server.list_observations(id).await?.is_empty());
+  }
+
+  Ok(())
+}
@@ -0,0 +1,137 @@
+//! Integration tests for retrying failed uploads and the offline spool
+
+mod common;
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use axum::Router;
+use common::TestServer;
+use observation_tools::with_execution;
+use observation_tools::ClientBuilder;
+use observation_tools::ObservationBuilder;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Proxy in front of a test server that answers the first `failures`
+/// requests with 503 Service Unavailable
+struct FlakyProxy {
+  target: String,
+  failures: usize,
+  requests: AtomicUsize,
+}
+
+async fn proxy(State(proxy): State<Arc<FlakyProxy>>, request: Request) -> Response {
+  if proxy.requests.fetch_add(1, Ordering::SeqCst) < proxy.failures {
+    return StatusCode::SERVICE_UNAVAILABLE.into_response();
+  }
+  let (parts, body) = request.into_parts();
+  let body = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+  let response = reqwest::Client::new()
+    .request(parts.method, format!("{}{}", proxy.target, parts.uri))
+    .headers(parts.headers)
+    .body(body)
+    .send()
+    .await
+    .unwrap();
+  let status = response.status();
+  let body = response.bytes().await.unwrap();
+  (status, Body::from(body)).into_response()
+}
+
+async fn start_proxy(target: &str, failures: usize) -> anyhow::Result<(String, Arc<FlakyProxy>)> {
+  let state = Arc::new(FlakyProxy {
+    target: target.to_string(),
+    failures,
+    requests: AtomicUsize::new(0),
+  });
+  let app = Router::new().fallback(proxy).with_state(state.clone());
+  let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+  let url = format!("http://{}", listener.local_addr()?);
+  tokio::spawn(async move {
+    axum::serve(listener, app).await.expect("Proxy failed");
+  });
+  Ok((url, state))
+}
+
+#[test_log::test(tokio::test)]
+async fn test_retries_transient_failures() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let (proxy_url, proxy) = start_proxy(server.base_url(), 2).await?;
+  let client = ClientBuilder::new()
+    .base_url(&proxy_url)
+    .retry_backoff(Duration::from_millis(10))
+    .build()?;
+  let execution = client.begin_execution("flaky")?.wait_for_upload().await?;
+  // Two 503 responses followed by the successful attempt
+  assert_eq!(proxy.requests.load(Ordering::SeqCst), 3);
+  client.shutdown().await?;
+
+  let api_client = server.create_api_client()?;
+  let response = api_client
+    .get_execution()
+    .id(execution.id().to_string())
+    .send()
+    .await?;
+  assert_eq!(response.execution.name, "flaky");
+
+  // Giving up once the retries are exhausted
+  let (proxy_url, proxy) = start_proxy(server.base_url(), 2).await?;
+  let client = ClientBuilder::new()
+    .base_url(&proxy_url)
+    .max_retries(1)
+    .retry_backoff(Duration::from_millis(10))
+    .build()?;
+  let result = client.begin_execution("gave-up")?.wait_for_upload().await;
+  assert!(result.is_err(), "upload should fail after one retry");
+  assert_eq!(proxy.requests.load(Ordering::SeqCst), 2);
+  client.shutdown().await?;
+  Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_spool_replays_uploads_after_outage() -> anyhow::Result<()> {
+  let spool_dir = tempfile::tempdir()?;
+
+  // Nothing listens on this address, so every upload fails
+  let unused = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+  let offline_url = format!("http://{}", unused.local_addr()?);
+  drop(unused);
+  let client = ClientBuilder::new()
+    .base_url(&offline_url)
+    .max_retries(0)
+    .spool_dir(spool_dir.path())
+    .build()?;
+  let begin = client.begin_execution("offline")?;
+  let execution = begin.handle().clone();
+  assert!(begin.wait_for_upload().await.is_err());
+  let mut send = with_execution(execution.clone(), async {
+    ObservationBuilder::new("spooled").payload("kept on disk")
+  })
+  .await;
+  assert!(send.wait_for_upload().await.is_err());
+  client.shutdown().await?;
+  assert!(std::fs::read_dir(spool_dir.path())?.count() >= 2);
+
+  // A new client with the same spool sends the uploads left behind
+  let server = TestServer::new().await;
+  let client = ClientBuilder::new()
+    .base_url(server.base_url())
+    .spool_dir(spool_dir.path())
+    .build()?;
+  client.shutdown().await?;
+  assert_eq!(std::fs::read_dir(spool_dir.path())?.count(), 0);
+
+  let observations = server.list_observations(&execution.id()).await?;
+  assert_eq!(observations.len(), 1);
+  let observation = server
+    .get_observation(&execution.id(), &observations[0].id)
+    .await?;
+  assert_eq!(observation.payload().as_str(), Some("kept on disk"));
+  Ok(())
+}
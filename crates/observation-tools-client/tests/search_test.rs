@@ -0,0 +1,153 @@
+//! Integration tests for full-text search over observations
+
+mod common;
+
+use common::TestServer;
+use observation_tools::ObservationBuilder;
+use observation_tools_shared::Payload;
+use serde_json::json;
+use std::collections::HashSet;
+
+#[test_log::test(tokio::test)]
+async fn test_search_observations() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let (execution, _) = server
+    .with_execution("search-execution", async {
+      ObservationBuilder::new("login-attempt")
+        .metadata("user_id", "42")
+        .payload(Payload::text("invalid password for account"));
+      ObservationBuilder::new("login-attempt")
+        .metadata("user_id", "7")
+        .payload(Payload::text("login succeeded"));
+      ObservationBuilder::new("request-body")
+        .serde(&json!({"customer": "Acme Corporation", "items": 3}));
+    })
+    .await?;
+  let (other_execution, _) = server
+    .with_execution("other-execution", async {
+      ObservationBuilder::new("login-attempt")
+        .metadata("user_id", "42")
+        .payload(Payload::text("login succeeded"));
+    })
+    .await?;
+
+  let api_client = server.create_api_client()?;
+
+  // Exact metadata pairs match across executions
+  let response = api_client.search_observations().q("user_id=42").send().await?;
+  assert_eq!(response.observations.len(), 2);
+  let execution_ids: HashSet<String> = response
+    .observations
+    .iter()
+    .map(|o| o.execution_id.to_string())
+    .collect();
+  assert_eq!(
+    execution_ids,
+    HashSet::from([execution.id().to_string(), other_execution.id().to_string()])
+  );
+
+  // Words in text and JSON payloads are searchable, case-insensitively
+  let response = api_client.search_observations().q("PASSWORD").send().await?;
+  assert_eq!(response.observations.len(), 1);
+  assert_eq!(response.observations[0].metadata.get("user_id"), Some(&"42".to_string()));
+
+  let response = api_client.search_observations().q("acme").send().await?;
+  assert_eq!(response.observations.len(), 1);
+  assert_eq!(response.observations[0].name, "request-body");
+
+  // Prefix terms
+  let response = api_client.search_observations().q("corp*").send().await?;
+  assert_eq!(response.observations.len(), 1);
+
+  // Terms are AND'ed together and can be scoped to one execution
+  let response = api_client
+    .search_execution_observations()
+    .execution_id(execution.id().to_string())
+    .q("login succeeded")
+    .send()
+    .await?;
+  assert_eq!(response.observations.len(), 1);
+  assert_eq!(
+    response.observations[0].execution_id.to_string(),
+    execution.id().to_string()
+  );
+  assert_eq!(response.observations[0].metadata.get("user_id"), Some(&"7".to_string()));
+
+  let response = api_client.search_observations().q("nonexistent").send().await?;
+  assert!(response.observations.is_empty());
+
+  Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_search_pagination() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let (execution, _) = server
+    .with_execution("search-pagination", async {
+      for i in 0..5 {
+        ObservationBuilder::new(format!("item-{}", i)).payload("needle");
+      }
+    })
+    .await?;
+
+  let api_client = server.create_api_client()?;
+  let first_page = api_client
+    .search_execution_observations()
+    .execution_id(execution.id().to_string())
+    .q("needle")
+    .limit(3)
+    .send()
+    .await?;
+  let names: Vec<_> = first_page.observations.iter().map(|o| o.name.as_str()).collect();
+  assert_eq!(names, vec!["item-4", "item-3", "item-2"]);
+  assert!(first_page.has_next_page);
+
+  let second_page = api_client
+    .search_execution_observations()
+    .execution_id(execution.id().to_string())
+    .q("needle")
+    .limit(3)
+    .offset(3)
+    .send()
+    .await?;
+  assert_eq!(second_page.observations.len(), 2);
+  assert!(!second_page.has_next_page);
+
+  // Prefix terms page through the same hits
+  let prefix_page = api_client
+    .search_execution_observations()
+    .execution_id(execution.id().to_string())
+    .q("need*")
+    .limit(4)
+    .send()
+    .await?;
+  let names: Vec<_> = prefix_page.observations.iter().map(|o| o.name.as_str()).collect();
+  assert_eq!(names, vec!["item-4", "item-3", "item-2", "item-1"]);
+  assert!(prefix_page.has_next_page);
+
+  // The UI pages through search results without counting them
+  let html = reqwest::get(format!(
+    "{}/exe/{}/payload?q=needle&limit=3",
+    server.base_url(),
+    execution.id()
+  ))
+  .await?
+  .text()
+  .await?;
+  assert!(html.contains("showing 1-3"));
+  assert!(!html.contains("showing 1-3 of"));
+
+  Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_search_empty_query_is_rejected() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let api_client = server.create_api_client()?;
+
+  let result = api_client.search_observations().q("  ").send().await;
+  let err = result.expect_err("empty query should be rejected");
+  assert_eq!(err.status(), Some(reqwest::StatusCode::BAD_REQUEST));
+
+  Ok(())
+}
@@ -0,0 +1,118 @@
+//! Integration tests for snapshots compared against baselines
+
+mod common;
+
+use common::set_cookie;
+use common::TestServer;
+use observation_tools::observe;
+use observation_tools::Error;
+use observation_tools::ACCEPT_SNAPSHOTS_ENV;
+use reqwest::header::COOKIE;
+use reqwest::StatusCode;
+use serde_json::json;
+
+#[test_log::test(tokio::test)]
+async fn test_snapshots() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let report = json!({"passed": 10, "failed": 0, "slowest": ["a", "b"]});
+
+  // The first snapshot becomes the baseline, later ones are compared with it
+  let (first, result) = server
+    .with_execution("suite", async { observe!("report").snapshot(&report).await })
+    .await?;
+  let baseline = result?;
+  let (_, result) = server
+    .with_execution("suite", async { observe!("report").snapshot(&report).await })
+    .await?;
+  result?;
+  // Executions with other names have their own baselines
+  let changed = json!({"passed": 9, "failed": 1, "slowest": ["b", "a"]});
+  let (_, result) = server
+    .with_execution("other-suite", async { observe!("report").snapshot(&changed).await })
+    .await?;
+  result?;
+
+  let (_, result) = server
+    .with_execution("suite", async { observe!("report").snapshot(&changed).await })
+    .await?;
+  let Err(Error::SnapshotMismatch { name, url }) = result else {
+    panic!("expected a snapshot mismatch, got {:?}", result);
+  };
+  assert_eq!(name, "report");
+  let client = reqwest::Client::new();
+  let response = client.get(&url).send().await?.error_for_status()?;
+  let cookie = set_cookie(&response, "csrf_token").expect("CSRF cookie");
+  let page = response.text().await?;
+  assert!(page.contains(r#"data-status="Mismatched""#));
+  assert!(page.contains("SnapshotAccept"));
+  assert!(page.contains(&baseline.id().to_string()));
+  assert!(page.contains("DiffJsonChange"));
+
+  // Accepting the snapshot in the UI makes it the baseline
+  let (_, observation_path) = url.split_once("/exe/").unwrap();
+  let response = client
+    .post(format!("{}/exe/{}/snapshot/accept", server.base_url(), observation_path))
+    .header(COOKIE, &cookie)
+    .header("x-csrf-token", cookie.strip_prefix("csrf_token=").unwrap())
+    .send()
+    .await?
+    .error_for_status()?;
+  assert!(response.text().await?.contains("SnapshotIsBaseline"));
+  let (_, result) = server
+    .with_execution("suite", async { observe!("report").snapshot(&changed).await })
+    .await?;
+  result?;
+
+  // The old baseline now differs from the new one
+  let page = client
+    .get(format!("{}/exe/{}?obs={}", server.base_url(), first.id(), baseline.id()))
+    .send()
+    .await?
+    .text()
+    .await?;
+  assert!(page.contains(r#"data-status="Mismatched""#));
+
+  // Snapshots can also be accepted when running the tests
+  std::env::set_var(ACCEPT_SNAPSHOTS_ENV, "1");
+  let (_, result) = server
+    .with_execution("suite", async { observe!("report").snapshot(&report).await })
+    .await?;
+  std::env::remove_var(ACCEPT_SNAPSHOTS_ENV);
+  result?;
+  let (_, result) = server
+    .with_execution("suite", async { observe!("report").snapshot(&report).await })
+    .await?;
+  result?;
+  Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_delete_baseline_execution() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let report = json!({"passed": 10});
+  let (baseline, result) = server
+    .with_execution("kept", async { observe!("report").snapshot(&report).await })
+    .await?;
+  result?;
+  let (compared, result) = server
+    .with_execution("kept", async { observe!("report").snapshot(&report).await })
+    .await?;
+  result?;
+
+  // The execution holding the baseline can't be deleted, others can
+  let client = reqwest::Client::new();
+  let delete = |id: String| {
+    client
+      .delete(format!("{}/api/exe/{}", server.base_url(), id))
+      .send()
+  };
+  let response = delete(baseline.id().to_string()).await?;
+  assert_eq!(response.status(), StatusCode::CONFLICT);
+  let response = delete(compared.id().to_string()).await?;
+  assert_eq!(response.status(), StatusCode::OK);
+  let (_, result) = server
+    .with_execution("kept", async { observe!("report").snapshot(&report).await })
+    .await?;
+  result?;
+  Ok(())
+}
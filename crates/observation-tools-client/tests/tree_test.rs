@@ -0,0 +1,80 @@
+//! Integration tests for the tree of groups of an execution
+
+mod common;
+
+use common::TestServer;
+use observation_tools::GroupBuilder;
+use observation_tools::ObservationBuilder;
+use observation_tools_shared::Payload;
+
+#[test_log::test(tokio::test)]
+async fn test_execution_tree() -> anyhow::Result<()> {
+  let server = TestServer::new().await;
+  let (execution, _) = server
+    .with_execution("nested", async {
+      ObservationBuilder::new("start").payload(Payload::text("ungrouped"));
+      let request = GroupBuilder::new("request").build().into_handle();
+      let handler = request.child("handler").build().into_handle();
+      ObservationBuilder::new("headers")
+        .group(&request)
+        .payload(Payload::text("accept: */*"));
+      ObservationBuilder::new("query")
+        .group(&handler)
+        .payload(Payload::text("select 1"));
+      ObservationBuilder::new("result")
+        .group(&handler)
+        .payload(Payload::text("1"));
+    })
+    .await?;
+
+  let api_client = server.create_api_client()?;
+  let response = api_client
+    .get_execution_tree()
+    .id(execution.id().to_string())
+    .send()
+    .await?;
+  let tree = &response.tree;
+  let names = |observations: &[observation_tools::server_client::types::Observation]| {
+    observations
+      .iter()
+      .map(|o| o.name.clone())
+      .collect::<Vec<_>>()
+  };
+  assert_eq!(names(&tree.observations), vec!["start"]);
+  assert_eq!(tree.groups.len(), 1);
+  let request = &tree.groups[0];
+  assert_eq!(request.name, "request");
+  assert_eq!(request.observation_count, 3);
+  assert_eq!(names(&request.observations), vec!["headers"]);
+  assert_eq!(request.children.len(), 1);
+  let handler = &request.children[0];
+  assert_eq!(handler.name, "handler");
+  assert_eq!(handler.observation_count, 2);
+  assert_eq!(names(&handler.observations), vec!["query", "result"]);
+
+  let page = reqwest::get(format!("{}/exe/{}/tree", server.base_url(), execution.id()))
+    .await?
+    .error_for_status()?
+    .text()
+    .await?;
+  assert!(page.contains("ExecutionTree"));
+  assert_eq!(page.matches(r#"data-testid="TreeGroup""#).count(), 2);
+  assert_eq!(page.matches(r#"data-testid="TreeObservation""#).count(), 4);
+  let group_id = handler.observation_id.clone().expect("handler group observation");
+  assert!(page.contains(&group_id.to_string()));
+
+  // Selecting a group shows it in the side panel next to the tree
+  let page = reqwest::get(format!(
+    "{}/exe/{}/tree?obs={}",
+    server.base_url(),
+    execution.id(),
+    group_id
+  ))
+  .await?
+  .error_for_status()?
+  .text()
+  .await?;
+  assert!(page.contains("side-panel"));
+  assert!(page.contains("ExecutionTree"));
+  Ok(())
+}
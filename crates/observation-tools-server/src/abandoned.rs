@@ -0,0 +1,160 @@
+//! Detection of executions that stopped without finishing
+//!
+//! A client that crashes or is killed never reports how its execution ended,
+//! so it would stay `Running` forever. Running executions that have not
+//! received any data for the configured time are marked `Abandoned`.
+//!
+//! Data received for an execution refreshes its `updated_at` with the
+//! server's clock, so that the check neither depends on client clocks nor
+//! needs to read observations.
+
+use crate::storage::ExecutionFilter;
+use crate::storage::MetadataStorage;
+use crate::storage::StorageError;
+use crate::storage::StorageResult;
+use chrono::DateTime;
+use chrono::Utc;
+use observation_tools_shared::Execution;
+use observation_tools_shared::ExecutionId;
+use observation_tools_shared::ExecutionStatus;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::info;
+use tracing::warn;
+
+/// Upper bound on the time between checks for abandoned executions
+const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Record that data was received for an execution at `now`. Executions that
+/// are not stored yet are skipped.
+pub async fn record_activity(
+  metadata: &dyn MetadataStorage,
+  execution_id: ExecutionId,
+  now: DateTime<Utc>,
+) -> StorageResult<()> {
+  let touch = move |execution: &mut Execution| {
+    if execution.updated_at >= now {
+      return false;
+    }
+    execution.updated_at = now;
+    true
+  };
+  match metadata.update_execution(execution_id, &touch).await {
+    Ok(_) | Err(StorageError::NotFound(_)) => Ok(()),
+    Err(e) => Err(e),
+  }
+}
+
+/// Mark running executions without activity for longer than `abandoned_after`
+/// as abandoned. Returns the number of executions that were marked.
+pub async fn mark_abandoned(
+  metadata: &dyn MetadataStorage,
+  abandoned_after: Duration,
+  now: DateTime<Utc>,
+) -> StorageResult<usize> {
+  let Ok(abandoned_after) = chrono::Duration::from_std(abandoned_after) else {
+    return Ok(0);
+  };
+  let running = ExecutionFilter {
+    status: Some(ExecutionStatus::Running),
+    ..Default::default()
+  };
+  let count = metadata.count_executions(&running).await?;
+  let executions = metadata.list_executions(Some(count), None, &running).await?;
+  // Executions may have finished or received data since they were listed
+  let abandon = move |execution: &mut Execution| {
+    if execution.status != ExecutionStatus::Running
+      || now - execution.updated_at <= abandoned_after
+    {
+      return false;
+    }
+    execution.status = ExecutionStatus::Abandoned;
+    execution.ended_at = Some(execution.updated_at);
+    execution.updated_at = now;
+    true
+  };
+  let mut marked = 0;
+  for execution in executions {
+    if now - execution.updated_at <= abandoned_after {
+      continue;
+    }
+    match metadata.update_execution(execution.id, &abandon).await {
+      Ok(Some(_)) => marked += 1,
+      Ok(None) | Err(StorageError::NotFound(_)) => {}
+      Err(e) => return Err(e),
+    }
+  }
+  Ok(marked)
+}
+
+/// Spawn a task that periodically runs [`mark_abandoned`]
+pub fn spawn_detector(
+  metadata: Arc<dyn MetadataStorage>,
+  abandoned_after: Duration,
+) -> tokio::task::JoinHandle<()> {
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(abandoned_after.min(MAX_CHECK_INTERVAL));
+    loop {
+      interval.tick().await;
+      match mark_abandoned(metadata.as_ref(), abandoned_after, Utc::now()).await {
+        Ok(marked) if marked > 0 => {
+          info!(executions = marked, "Marked executions as abandoned");
+        }
+        Ok(_) => {}
+        Err(e) => warn!(error = %e, "Abandoned execution check failed"),
+      }
+    }
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::storage::SledStorage;
+
+  fn execution(name: &str, updated_at: DateTime<Utc>) -> Execution {
+    let mut execution = Execution::new(name);
+    execution.created_at = updated_at;
+    execution.updated_at = updated_at;
+    execution
+  }
+
+  #[tokio::test]
+  async fn test_mark_abandoned() {
+    let dir = tempfile::tempdir().unwrap();
+    let metadata = SledStorage::new(dir.path()).unwrap();
+    let now = Utc::now();
+    let hours_ago = |hours| now - chrono::Duration::hours(hours);
+
+    let stale = execution("stale", hours_ago(3));
+    let recent = execution("recent", hours_ago(3));
+    let mut finished = execution("finished", hours_ago(3));
+    finished.status = ExecutionStatus::Success;
+    for execution in [&stale, &recent, &finished] {
+      metadata.store_execution(execution).await.unwrap();
+    }
+    // Data received for the recent execution keeps it alive, and data for
+    // executions that are not stored yet is ignored
+    record_activity(&metadata, recent.id, hours_ago(0)).await.unwrap();
+    record_activity(&metadata, ExecutionId::new(), hours_ago(0)).await.unwrap();
+    assert_eq!(
+      metadata.get_execution(recent.id).await.unwrap().updated_at,
+      hours_ago(0)
+    );
+
+    let abandoned_after = Duration::from_secs(60 * 60);
+    let marked = mark_abandoned(&metadata, abandoned_after, now).await.unwrap();
+    assert_eq!(marked, 1);
+    let stale = metadata.get_execution(stale.id).await.unwrap();
+    assert_eq!(stale.status, ExecutionStatus::Abandoned);
+    assert_eq!(stale.ended_at, Some(hours_ago(3)));
+    let recent = metadata.get_execution(recent.id).await.unwrap();
+    assert_eq!(recent.status, ExecutionStatus::Running);
+    let finished = metadata.get_execution(finished.id).await.unwrap();
+    assert_eq!(finished.status, ExecutionStatus::Success);
+
+    // Already abandoned executions are not marked again
+    let marked = mark_abandoned(&metadata, abandoned_after, now).await.unwrap();
+    assert_eq!(marked, 0);
+  }
+}
@@ -0,0 +1,68 @@
+//! Content-addressed blob handlers
+
+use crate::api::authorize_execution;
+use crate::api::AppError;
+use crate::auth::ApiKeyRecord;
+use crate::storage::content::execution_scopes;
+use crate::storage::content::in_any_scope;
+use crate::storage::MetadataStorage;
+use crate::storage::StorageError;
+use axum::extract::Path;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Extension;
+use observation_tools_shared::is_content_digest;
+use observation_tools_shared::ExecutionId;
+use std::sync::Arc;
+
+/// Check whether payloads of an execution may reference stored content by
+/// its digest
+///
+/// Clients use this to skip uploading large payloads whose content was
+/// uploaded before, declaring only the digest in the payload manifest. It
+/// requires the same authentication as uploads, and only reports content
+/// uploaded to the same execution or to the execution's project, so that
+/// content cannot be probed by anyone who merely knows its digest.
+#[utoipa::path(
+    head,
+    path = "/api/exe/{execution_id}/blobs/{digest}",
+    params(
+        ("execution_id" = String, Path, description = "Execution ID"),
+        ("digest" = String, Path, description = "SHA-256 of the uncompressed content, as lowercase hex")
+    ),
+    responses(
+        (status = 200, description = "Content is stored"),
+        (status = 404, description = "Content is not stored"),
+        (status = 400, description = "Invalid digest"),
+        (status = 403, description = "API key not allowed to access the execution")
+    ),
+    tag = "observations"
+)]
+#[tracing::instrument(skip(metadata, api_key))]
+pub async fn head_blob(
+  State(metadata): State<Arc<dyn MetadataStorage>>,
+  api_key: Option<Extension<ApiKeyRecord>>,
+  Path((execution_id, digest)): Path<(String, String)>,
+) -> Result<StatusCode, AppError> {
+  let execution_id = ExecutionId::parse(&execution_id)?;
+  authorize_execution(
+    metadata.as_ref(),
+    api_key.as_ref().map(|Extension(key)| key),
+    execution_id,
+  )
+  .await?;
+  if !is_content_digest(&digest) {
+    return Err(AppError::BadRequest(format!("Invalid digest: {}", digest)));
+  }
+  let scopes = execution_scopes(metadata.as_ref(), execution_id).await?;
+  if !in_any_scope(metadata.as_ref(), &digest, &scopes).await? {
+    return Err(StorageError::NotFound(format!("Content {} not found", digest)).into());
+  }
+  let record = metadata.get_blob_record(&digest).await?;
+  if record.refcount <= 0 {
+    // Restart the grace period, so the content is not collected before the
+    // observation referencing it is stored
+    metadata.register_blob(&record).await?;
+  }
+  Ok(StatusCode::OK)
+}
@@ -0,0 +1,459 @@
+//! Execution and payload diff handlers
+
+use crate::api::authorize_read;
+use crate::api::types::DiffExecutionsQuery;
+use crate::api::types::PayloadDiffQuery;
+use crate::api::AppError;
+use crate::auth::ApiKeyRecord;
+use crate::diff::align_observations;
+use crate::diff::diff_payload_data;
+use crate::diff::DiffStatus;
+use crate::diff::ObservationKey;
+use crate::diff::PayloadDiff;
+use crate::storage::BlobStorage;
+use crate::storage::MetadataStorage;
+use crate::storage::ObservationFilter;
+use crate::storage::ObservationWithPayloads;
+use crate::storage::PayloadData;
+use crate::storage::StorageError;
+use crate::storage::StorageResult;
+use crate::storage::StoredPayload;
+use axum::extract::Path;
+use axum::extract::Query;
+use axum::extract::State;
+use axum::Extension;
+use axum::Json;
+use observation_tools_shared::models::Execution;
+use observation_tools_shared::models::ExecutionId;
+use observation_tools_shared::ObservationId;
+use observation_tools_shared::ObservationType;
+use observation_tools_shared::PayloadId;
+use observation_tools_shared::DISPLAY_THRESHOLD_BYTES;
+use serde::Deserialize;
+use serde::Serialize;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+/// Most observations per execution that can be compared
+pub const MAX_DIFF_OBSERVATIONS: usize = 10_000;
+
+/// Response for comparing two executions
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ExecutionDiffResponse {
+  /// The execution compared from
+  pub left: Execution,
+
+  /// The execution compared to
+  pub right: Execution,
+
+  /// Observations of both executions, aligned by their keys
+  pub observations: Vec<ObservationDiff>,
+
+  /// Number of observations by status
+  pub summary: DiffSummary,
+}
+
+/// Number of aligned observations by status
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct DiffSummary {
+  pub added: usize,
+  pub removed: usize,
+  pub changed: usize,
+  pub unchanged: usize,
+}
+
+/// An observation of either execution, and how it changed
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ObservationDiff {
+  /// What the observation was aligned by
+  pub key: ObservationKey,
+
+  /// Changed if any of its payloads changed
+  pub status: DiffStatus,
+
+  /// Type of the observation
+  pub observation_type: ObservationType,
+
+  /// ID of the observation in the left execution
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub left: Option<ObservationId>,
+
+  /// ID of the observation in the right execution
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub right: Option<ObservationId>,
+
+  /// Payloads of the observation, aligned by name
+  pub payloads: Vec<PayloadChange>,
+}
+
+/// A payload of either observation, and how it changed
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PayloadChange {
+  /// Payload name
+  pub name: String,
+
+  /// Whether the payload was added, removed or changed
+  pub status: DiffStatus,
+
+  /// ID of the payload on the left side
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub left: Option<PayloadId>,
+
+  /// ID of the payload on the right side
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub right: Option<PayloadId>,
+
+  /// Differences between the contents of changed payloads
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub diff: Option<PayloadDiff>,
+}
+
+/// Response for comparing two payloads
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PayloadDiffResponse {
+  /// The compared payloads, named after the right one
+  pub payload: PayloadChange,
+}
+
+/// Compare the observations of two executions
+#[utoipa::path(
+    get,
+    path = "/api/diff",
+    params(DiffExecutionsQuery),
+    responses(
+        (status = 200, description = "Differences between the executions", body = ExecutionDiffResponse),
+        (status = 400, description = "Bad request"),
+        (status = 403, description = "API key not allowed to access the project"),
+        (status = 404, description = "Execution not found")
+    ),
+    tag = "executions"
+)]
+#[tracing::instrument(skip(metadata, blobs, api_key))]
+pub async fn diff_executions(
+  State(metadata): State<Arc<dyn MetadataStorage>>,
+  State(blobs): State<Arc<dyn BlobStorage>>,
+  api_key: Option<Extension<ApiKeyRecord>>,
+  Query(query): Query<DiffExecutionsQuery>,
+) -> Result<Json<ExecutionDiffResponse>, AppError> {
+  let left = ExecutionId::parse(&query.left)?;
+  let right = ExecutionId::parse(&query.right)?;
+  let api_key = api_key.as_ref().map(|Extension(key)| key);
+  authorize_read(metadata.as_ref(), api_key, left).await?;
+  authorize_read(metadata.as_ref(), api_key, right).await?;
+  let diff = execution_diff(metadata.as_ref(), blobs.as_ref(), left, right).await?;
+  Ok(Json(diff))
+}
+
+/// Compare two executions. Payloads are only loaded for observations on both
+/// sides whose payload digests don't tell them apart.
+pub async fn execution_diff(
+  metadata: &dyn MetadataStorage,
+  blobs: &dyn BlobStorage,
+  left: ExecutionId,
+  right: ExecutionId,
+) -> Result<ExecutionDiffResponse, AppError> {
+  let left_execution = metadata.get_execution(left).await?;
+  let right_execution = metadata.get_execution(right).await?;
+  let left_observations = all_observations(metadata, left).await?;
+  let right_observations = all_observations(metadata, right).await?;
+  tracing::debug!(
+    left = left_observations.len(),
+    right = right_observations.len(),
+    "Comparing executions"
+  );
+
+  let aligned = align_observations(
+    &left_observations
+      .iter()
+      .map(|o| o.observation.clone())
+      .collect::<Vec<_>>(),
+    &right_observations
+      .iter()
+      .map(|o| o.observation.clone())
+      .collect::<Vec<_>>(),
+  );
+  let mut summary = DiffSummary::default();
+  let mut observations = Vec::with_capacity(aligned.len());
+  for pair in aligned {
+    let left = pair.left.map(|i| &left_observations[i]);
+    let right = pair.right.map(|i| &right_observations[i]);
+    let (observation_type, payloads) = match (left, right) {
+      (Some(left), Some(right)) => (
+        right.observation.observation_type,
+        diff_payloads(metadata, blobs, left, right).await?,
+      ),
+      (Some(only), None) | (None, Some(only)) => {
+        let status = if left.is_some() {
+          DiffStatus::Removed
+        } else {
+          DiffStatus::Added
+        };
+        let payloads = only
+          .payloads
+          .iter()
+          .map(|p| PayloadChange {
+            name: p.name.clone(),
+            status,
+            left: left.map(|_| p.id.clone()),
+            right: right.map(|_| p.id.clone()),
+            diff: None,
+          })
+          .collect();
+        (only.observation.observation_type, payloads)
+      }
+      (None, None) => unreachable!("aligned observations are on at least one side"),
+    };
+    let status = match (left, right) {
+      (None, _) => DiffStatus::Added,
+      (_, None) => DiffStatus::Removed,
+      _ if payloads.iter().any(|p| p.status != DiffStatus::Unchanged) => DiffStatus::Changed,
+      _ => DiffStatus::Unchanged,
+    };
+    match status {
+      DiffStatus::Added => summary.added += 1,
+      DiffStatus::Removed => summary.removed += 1,
+      DiffStatus::Changed | DiffStatus::Moved => summary.changed += 1,
+      DiffStatus::Unchanged => summary.unchanged += 1,
+    }
+    observations.push(ObservationDiff {
+      key: pair.key,
+      status,
+      observation_type,
+      left: left.map(|o| o.observation.id),
+      right: right.map(|o| o.observation.id),
+      payloads,
+    });
+  }
+
+  Ok(ExecutionDiffResponse {
+    left: left_execution,
+    right: right_execution,
+    observations,
+    summary,
+  })
+}
+
+/// Load every observation of an execution, without payload data
+async fn all_observations(
+  metadata: &dyn MetadataStorage,
+  execution_id: ExecutionId,
+) -> Result<Vec<ObservationWithPayloads>, AppError> {
+  let filter = ObservationFilter::default();
+  let observations = metadata
+    .list_observations(execution_id, Some(MAX_DIFF_OBSERVATIONS + 1), None, &filter)
+    .await?;
+  if observations.len() > MAX_DIFF_OBSERVATIONS {
+    return Err(AppError::BadRequest(format!(
+      "Execution {} has too many observations to compare, at most {} are supported",
+      execution_id, MAX_DIFF_OBSERVATIONS
+    )));
+  }
+  Ok(observations)
+}
+
+/// Align the payloads of two observations by name and compare them
+async fn diff_payloads(
+  metadata: &dyn MetadataStorage,
+  blobs: &dyn BlobStorage,
+  left: &ObservationWithPayloads,
+  right: &ObservationWithPayloads,
+) -> StorageResult<Vec<PayloadChange>> {
+  // Listed observations carry no payload data, so the full observations are
+  // loaded once a payload needs to be read
+  let mut loaded: Option<(ObservationWithPayloads, ObservationWithPayloads)> = None;
+  let mut unmatched: Vec<_> = right.payloads.iter().map(Some).collect();
+  let mut changes = Vec::new();
+  for left_payload in &left.payloads {
+    let right_payload = unmatched
+      .iter_mut()
+      .find(|p| p.is_some_and(|p| p.name == left_payload.name))
+      .and_then(Option::take);
+    let Some(right_payload) = right_payload else {
+      changes.push(PayloadChange {
+        name: left_payload.name.clone(),
+        status: DiffStatus::Removed,
+        left: Some(left_payload.id.clone()),
+        right: None,
+        diff: None,
+      });
+      continue;
+    };
+    let same_digest = left_payload.digest.is_some() && left_payload.digest == right_payload.digest;
+    let (status, diff) = if same_digest {
+      (DiffStatus::Unchanged, None)
+    } else {
+      if loaded.is_none() {
+        loaded = Some((
+          metadata.get_observation(left.observation.id).await?,
+          metadata.get_observation(right.observation.id).await?,
+        ));
+      }
+      let (left_full, right_full) = loaded.as_ref().expect("loaded above");
+      compare_payloads(blobs, left_full, left_payload, right_full, right_payload).await?
+    };
+    changes.push(PayloadChange {
+      name: left_payload.name.clone(),
+      status,
+      left: Some(left_payload.id.clone()),
+      right: Some(right_payload.id.clone()),
+      diff,
+    });
+  }
+  for right_payload in unmatched.into_iter().flatten() {
+    changes.push(PayloadChange {
+      name: right_payload.name.clone(),
+      status: DiffStatus::Added,
+      left: None,
+      right: Some(right_payload.id.clone()),
+      diff: None,
+    });
+  }
+  Ok(changes)
+}
+
+/// Compare one payload of each of two fully loaded observations
+async fn compare_payloads(
+  blobs: &dyn BlobStorage,
+  left: &ObservationWithPayloads,
+  left_payload: &StoredPayload,
+  right: &ObservationWithPayloads,
+  right_payload: &StoredPayload,
+) -> StorageResult<(DiffStatus, Option<PayloadDiff>)> {
+  if left_payload.digest.is_some() && left_payload.digest == right_payload.digest {
+    return Ok((DiffStatus::Unchanged, None));
+  }
+  let left_data = payload_data(blobs, left, &left_payload.id).await?;
+  let right_data = payload_data(blobs, right, &right_payload.id).await?;
+  if left_data == right_data && left_payload.mime_type == right_payload.mime_type {
+    return Ok((DiffStatus::Unchanged, None));
+  }
+  if left_data.len().max(right_data.len()) > DISPLAY_THRESHOLD_BYTES {
+    return Ok((DiffStatus::Changed, Some(PayloadDiff::Binary)));
+  }
+  let diff = diff_payload_data(
+    &left_payload.mime_type,
+    &left_data,
+    &right_payload.mime_type,
+    &right_data,
+  );
+  Ok((DiffStatus::Changed, Some(diff)))
+}
+
+/// Compare a payload of `right` against one of `left`: the payload with
+/// `left_payload_id` if given, otherwise the one with the same name, or the
+/// only one
+pub async fn payload_diff(
+  blobs: &dyn BlobStorage,
+  left: &ObservationWithPayloads,
+  left_payload_id: Option<&PayloadId>,
+  right: &ObservationWithPayloads,
+  right_payload_id: &PayloadId,
+) -> Result<PayloadChange, AppError> {
+  let right_payload = find_payload(right, right_payload_id)?;
+  let left_payload = match left_payload_id {
+    Some(id) => find_payload(left, id)?,
+    None => left
+      .payloads
+      .iter()
+      .find(|p| p.name == right_payload.name)
+      .or(match left.payloads.as_slice() {
+        [only] => Some(only),
+        _ => None,
+      })
+      .ok_or_else(|| {
+        AppError::BadRequest(format!(
+          "Observation {} has no payload named '{}', choose one to compare with",
+          left.observation.id, right_payload.name
+        ))
+      })?,
+  };
+  let (status, diff) = compare_payloads(blobs, left, left_payload, right, right_payload).await?;
+  Ok(PayloadChange {
+    name: right_payload.name.clone(),
+    status,
+    left: Some(left_payload.id.clone()),
+    right: Some(right_payload.id.clone()),
+    diff,
+  })
+}
+
+/// Compare a payload against a payload of another observation
+///
+/// The other observation may belong to a different execution. JSON and Rust
+/// Debug payloads are compared structurally, other text payloads line by
+/// line.
+#[utoipa::path(
+    get,
+    path = "/api/exe/{execution_id}/obs/{observation_id}/payload/{payload_id}/diff",
+    params(
+        ("execution_id" = String, Path, description = "Execution ID"),
+        ("observation_id" = String, Path, description = "Observation ID"),
+        ("payload_id" = String, Path, description = "Payload ID"),
+        PayloadDiffQuery
+    ),
+    responses(
+        (status = 200, description = "Differences between the payloads", body = PayloadDiffResponse),
+        (status = 400, description = "Bad request"),
+        (status = 403, description = "API key not allowed to access the project"),
+        (status = 404, description = "Observation or payload not found")
+    ),
+    tag = "observations"
+)]
+#[tracing::instrument(skip(metadata, blobs, api_key))]
+pub async fn diff_payload(
+  State(metadata): State<Arc<dyn MetadataStorage>>,
+  State(blobs): State<Arc<dyn BlobStorage>>,
+  api_key: Option<Extension<ApiKeyRecord>>,
+  Path((_execution_id, observation_id, payload_id)): Path<(String, String, String)>,
+  Query(query): Query<PayloadDiffQuery>,
+) -> Result<Json<PayloadDiffResponse>, AppError> {
+  let right = metadata.get_observation(ObservationId::parse(&observation_id)?).await?;
+  let left = metadata.get_observation(ObservationId::parse(&query.with)?).await?;
+  let api_key = api_key.as_ref().map(|Extension(key)| key);
+  authorize_read(metadata.as_ref(), api_key, left.observation.execution_id).await?;
+  authorize_read(metadata.as_ref(), api_key, right.observation.execution_id).await?;
+  let left_payload_id = query.with_payload.map(PayloadId::from);
+  let payload = payload_diff(
+    blobs.as_ref(),
+    &left,
+    left_payload_id.as_ref(),
+    &right,
+    &PayloadId::from(payload_id),
+  )
+  .await?;
+  Ok(Json(PayloadDiffResponse { payload }))
+}
+
+fn find_payload<'a>(
+  observation: &'a ObservationWithPayloads,
+  payload_id: &PayloadId,
+) -> StorageResult<&'a StoredPayload> {
+  observation
+    .payloads
+    .iter()
+    .find(|p| p.id == *payload_id)
+    .ok_or_else(|| {
+      StorageError::NotFound(format!(
+        "Payload {} not found for observation {}",
+        payload_id.as_str(),
+        observation.observation.id
+      ))
+    })
+}
+
+/// Read the decoded contents of one of an observation's payloads, from the
+/// observation itself or from blob storage
+pub(crate) async fn payload_data(
+  blobs: &dyn BlobStorage,
+  observation: &ObservationWithPayloads,
+  payload_id: &PayloadId,
+) -> StorageResult<Vec<u8>> {
+  let payload = find_payload(observation, payload_id)?;
+  match &payload.data {
+    PayloadData::Inline(data) => payload.decode(data),
+    PayloadData::Blob => {
+      let stored = blobs.get_blob(&payload.blob_key(observation.observation.id)).await?;
+      payload.decode(&stored)
+    }
+  }
+}
@@ -2,9 +2,15 @@
 
 use crate::api::types::CreateExecutionRequest;
 use crate::api::types::CreateExecutionResponse;
+use crate::api::authorize_execution_name;
+use crate::api::authorize_project;
+use crate::api::projects::ensure_project;
 use crate::api::AppError;
+use crate::auth::ApiKeyRecord;
 use crate::storage::MetadataStorage;
+use crate::storage::StorageError;
 use axum::extract::State;
+use axum::Extension;
 use axum::Json;
 use std::sync::Arc;
 
@@ -15,13 +21,15 @@ use std::sync::Arc;
     request_body = CreateExecutionRequest,
     responses(
         (status = 200, description = "Execution created successfully", body = CreateExecutionResponse),
-        (status = 400, description = "Bad request")
+        (status = 400, description = "Bad request"),
+        (status = 403, description = "API key not allowed to access the execution or its project")
     ),
     tag = "executions"
 )]
-#[tracing::instrument(skip(metadata), fields(execution_id))]
+#[tracing::instrument(skip(metadata, api_key), fields(execution_id))]
 pub async fn create_execution(
   State(metadata): State<Arc<dyn MetadataStorage>>,
+  api_key: Option<Extension<ApiKeyRecord>>,
   Json(req): Json<CreateExecutionRequest>,
 ) -> Result<Json<CreateExecutionResponse>, AppError> {
   let execution = req.execution;
@@ -29,6 +37,23 @@ pub async fn create_execution(
 
   tracing::Span::current().record("execution_id", tracing::field::display(&execution.id));
 
+  let api_key = api_key.as_ref().map(|Extension(key)| key);
+  authorize_project(api_key, &execution.project)?;
+  authorize_execution_name(api_key, &execution.name)?;
+  // Storing replaces an execution with the same ID, which must be accessible
+  // as well
+  if api_key.is_some_and(|key| !key.execution_prefixes.is_empty() || key.project.is_some()) {
+    match metadata.get_execution(execution.id).await {
+      Ok(existing) => {
+        authorize_project(api_key, &existing.project)?;
+        authorize_execution_name(api_key, &existing.name)?;
+      }
+      Err(StorageError::NotFound(_)) => {}
+      Err(e) => return Err(e.into()),
+    }
+  }
+
+  ensure_project(metadata.as_ref(), &execution.project).await?;
   metadata.store_execution(&execution).await?;
 
   tracing::info!(execution_id = %execution.id, "Execution created successfully");
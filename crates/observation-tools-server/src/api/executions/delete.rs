@@ -0,0 +1,76 @@
+//! Delete execution handler
+
+use crate::api::types::DeleteExecutionResponse;
+use crate::api::authorize_execution;
+use crate::api::require_scope;
+use crate::api::AppError;
+use crate::auth::ApiKeyRecord;
+use crate::auth::ApiKeyScope;
+use crate::retention;
+use crate::storage::BlobStorage;
+use crate::storage::MetadataStorage;
+use crate::storage::SearchIndex;
+use axum::extract::Path;
+use axum::extract::State;
+use axum::Extension;
+use axum::Json;
+use observation_tools_shared::models::ExecutionId;
+use std::sync::Arc;
+
+/// Delete an execution with all of its observations and payloads
+///
+/// Requires an admin API key when authentication is enabled. Executions
+/// holding a snapshot baseline are kept until the baseline is replaced.
+#[utoipa::path(
+    delete,
+    path = "/api/exe/{id}",
+    params(
+        ("id" = String, Path, description = "Execution ID")
+    ),
+    responses(
+        (status = 200, description = "Execution deleted", body = DeleteExecutionResponse),
+        (status = 404, description = "Execution not found"),
+        (status = 400, description = "Bad request"),
+        (status = 403, description = "API key not allowed to delete the execution"),
+        (status = 409, description = "Execution holds a snapshot baseline")
+    ),
+    tag = "executions"
+)]
+#[tracing::instrument(skip(metadata, blobs, search, api_key))]
+pub async fn delete_execution(
+  State(metadata): State<Arc<dyn MetadataStorage>>,
+  State(blobs): State<Arc<dyn BlobStorage>>,
+  State(search): State<Arc<dyn SearchIndex>>,
+  api_key: Option<Extension<ApiKeyRecord>>,
+  Path(id): Path<String>,
+) -> Result<Json<DeleteExecutionResponse>, AppError> {
+  let execution_id = ExecutionId::parse(&id)?;
+  let api_key = api_key.as_ref().map(|Extension(key)| key);
+  require_scope(api_key, ApiKeyScope::Admin)?;
+  authorize_execution(metadata.as_ref(), api_key, execution_id).await?;
+  let baselines = metadata.list_snapshot_baselines().await?;
+  if let Some(baseline) = baselines.iter().find(|b| b.execution_id == execution_id) {
+    return Err(AppError::Conflict(format!(
+      "Execution {} holds the snapshot baseline of {}",
+      execution_id, baseline.name
+    )));
+  }
+  let stats = retention::delete_execution(
+    metadata.as_ref(),
+    blobs.as_ref(),
+    search.as_ref(),
+    execution_id,
+  )
+  .await?;
+
+  tracing::info!(
+    execution_id = %execution_id,
+    observations = stats.observations,
+    blobs = stats.blobs,
+    "Execution deleted"
+  );
+
+  Ok(Json(DeleteExecutionResponse {
+    deleted_observations: stats.observations,
+  }))
+}
@@ -0,0 +1,69 @@
+//! Execution event stream handler
+
+use crate::api::observations::GetObservation;
+use crate::api::authorize_read;
+use crate::api::AppError;
+use crate::auth::ApiKeyRecord;
+use crate::events::ExecutionEvent;
+use crate::events::ObservationEvents;
+use crate::storage::MetadataStorage;
+use axum::extract::Path;
+use axum::extract::State;
+use axum::response::sse::Event;
+use axum::response::sse::KeepAlive;
+use axum::response::sse::Sse;
+use futures::Stream;
+use axum::Extension;
+use futures::StreamExt;
+use observation_tools_shared::models::ExecutionId;
+use std::sync::Arc;
+
+/// Stream observations as they are stored for an execution
+///
+/// Server-Sent Events stream. Each stored observation is sent as an
+/// `observation` event whose data is the observation as JSON. If the client
+/// falls behind, a `lagged` event with the number of missed batches is sent
+/// and it should reload the observation list.
+#[utoipa::path(
+    get,
+    path = "/api/exe/{id}/events",
+    params(
+        ("id" = String, Path, description = "Execution ID")
+    ),
+    responses(
+        (status = 200, description = "Stream of observation events", content_type = "text/event-stream", body = String),
+        (status = 400, description = "Bad request"),
+        (status = 403, description = "API key not allowed to access the project")
+    ),
+    tag = "executions"
+)]
+#[tracing::instrument(skip(metadata, events, api_key))]
+pub async fn execution_events(
+  State(metadata): State<Arc<dyn MetadataStorage>>,
+  State(events): State<ObservationEvents>,
+  api_key: Option<Extension<ApiKeyRecord>>,
+  Path(id): Path<String>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, AppError> {
+  let execution_id = ExecutionId::parse(&id)?;
+  let api_key = api_key.as_ref().map(|Extension(key)| key);
+  authorize_read(metadata.as_ref(), api_key, execution_id).await?;
+  tracing::debug!(execution_id = %execution_id, "Client subscribed to execution events");
+  let stream = events.subscribe(execution_id).flat_map(|event| {
+    let events: Vec<Result<Event, axum::Error>> = match event {
+      ExecutionEvent::Observations(batch) => batch
+        .observations
+        .iter()
+        .map(|obs| {
+          Event::default()
+            .event("observation")
+            .json_data(GetObservation::new(obs.clone()))
+        })
+        .collect(),
+      ExecutionEvent::Lagged(missed) => {
+        vec![Ok(Event::default().event("lagged").data(missed.to_string()))]
+      }
+    };
+    futures::stream::iter(events)
+  });
+  Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
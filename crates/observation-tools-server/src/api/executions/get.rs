@@ -1,10 +1,13 @@
 //! Get execution handler
 
 use crate::api::types::GetExecutionResponse;
+use crate::api::authorize_project;
 use crate::api::AppError;
+use crate::auth::ApiKeyRecord;
 use crate::storage::MetadataStorage;
 use axum::extract::Path;
 use axum::extract::State;
+use axum::Extension;
 use axum::Json;
 use observation_tools_shared::models::ExecutionId;
 use std::sync::Arc;
@@ -19,19 +22,23 @@ use std::sync::Arc;
     responses(
         (status = 200, description = "Execution details", body = GetExecutionResponse),
         (status = 404, description = "Execution not found"),
-        (status = 400, description = "Bad request")
+        (status = 400, description = "Bad request"),
+        (status = 403, description = "API key not allowed to access the project")
     ),
     tag = "executions"
 )]
-#[tracing::instrument(skip(metadata))]
+#[tracing::instrument(skip(metadata, api_key))]
 pub async fn get_execution(
   State(metadata): State<Arc<dyn MetadataStorage>>,
+  api_key: Option<Extension<ApiKeyRecord>>,
   Path(id): Path<String>,
 ) -> Result<Json<GetExecutionResponse>, AppError> {
   tracing::debug!(id = %id, "Getting execution");
 
   let execution_id = ExecutionId::parse(&id)?;
   let execution = metadata.get_execution(execution_id).await?;
+  let api_key = api_key.as_ref().map(|Extension(key)| key);
+  authorize_project(api_key, &execution.project)?;
 
   tracing::debug!(name = %execution.name, "Execution retrieved");
 
@@ -2,35 +2,49 @@
 
 use crate::api::types::ListExecutionsQuery;
 use crate::api::types::ListExecutionsResponse;
+use crate::api::authorize_project;
 use crate::api::AppError;
+use crate::auth::ApiKeyRecord;
 use crate::storage::MetadataStorage;
 use axum::extract::Query;
 use axum::extract::State;
+use axum::Extension;
 use axum::Json;
 use std::sync::Arc;
 
-/// List all executions
+/// List all executions. Keys bound to a project only see executions in that
+/// project.
 #[utoipa::path(
     get,
     path = "/api/exe",
     params(ListExecutionsQuery),
     responses(
         (status = 200, description = "List of executions", body = ListExecutionsResponse),
-        (status = 400, description = "Bad request")
+        (status = 400, description = "Bad request"),
+        (status = 403, description = "API key not allowed to access the project")
     ),
     tag = "executions"
 )]
-#[tracing::instrument(skip(metadata))]
+#[tracing::instrument(skip(metadata, api_key))]
 pub async fn list_executions(
   State(metadata): State<Arc<dyn MetadataStorage>>,
+  api_key: Option<Extension<ApiKeyRecord>>,
   Query(query): Query<ListExecutionsQuery>,
 ) -> Result<Json<ListExecutionsResponse>, AppError> {
   let limit = query.limit.unwrap_or(100);
   tracing::debug!(limit = limit, offset = ?query.offset, "Listing executions");
 
+  let mut filter = query.execution_filter()?;
+  if let Some(Extension(key)) = &api_key {
+    match &filter.project {
+      Some(project) => authorize_project(Some(key), project)?,
+      None => filter.project = key.project.clone(),
+    }
+  }
+
   // Fetch one extra to determine if there are more pages
   let mut executions = metadata
-    .list_executions(Some(limit + 1), query.offset)
+    .list_executions(Some(limit + 1), query.offset, &filter)
     .await?;
 
   let has_next_page = executions.len() > limit;
@@ -1,12 +1,24 @@
 //! Execution API handlers
 
 mod create;
+mod delete;
+mod events;
 mod get;
 mod list;
+mod tree;
+mod update;
 
 pub use create::__path_create_execution;
 pub use create::create_execution;
+pub use delete::__path_delete_execution;
+pub use delete::delete_execution;
+pub use events::__path_execution_events;
+pub use events::execution_events;
 pub use get::__path_get_execution;
 pub use get::get_execution;
 pub use list::__path_list_executions;
 pub use list::list_executions;
+pub use tree::__path_get_execution_tree;
+pub use tree::get_execution_tree;
+pub use update::__path_update_execution;
+pub use update::update_execution;
@@ -0,0 +1,50 @@
+//! Execution tree handler
+
+use crate::api::types::GetExecutionTreeResponse;
+use crate::api::authorize_project;
+use crate::api::AppError;
+use crate::auth::ApiKeyRecord;
+use crate::storage::MetadataStorage;
+use crate::tree::execution_tree;
+use axum::extract::Path;
+use axum::extract::State;
+use axum::Extension;
+use axum::Json;
+use observation_tools_shared::models::ExecutionId;
+use std::sync::Arc;
+
+/// Get the groups and observations of an execution, arranged by nesting
+#[utoipa::path(
+    get,
+    path = "/api/exe/{id}/tree",
+    params(
+        ("id" = String, Path, description = "Execution ID")
+    ),
+    responses(
+        (status = 200, description = "Tree of groups and observations", body = GetExecutionTreeResponse),
+        (status = 404, description = "Execution not found"),
+        (status = 400, description = "Bad request, or too many observations"),
+        (status = 403, description = "API key not allowed to access the project")
+    ),
+    tag = "executions"
+)]
+#[tracing::instrument(skip(metadata, api_key))]
+pub async fn get_execution_tree(
+  State(metadata): State<Arc<dyn MetadataStorage>>,
+  api_key: Option<Extension<ApiKeyRecord>>,
+  Path(id): Path<String>,
+) -> Result<Json<GetExecutionTreeResponse>, AppError> {
+  let execution_id = ExecutionId::parse(&id)?;
+  let execution = metadata.get_execution(execution_id).await?;
+  let api_key = api_key.as_ref().map(|Extension(key)| key);
+  authorize_project(api_key, &execution.project)?;
+
+  let tree = execution_tree(metadata.as_ref(), execution_id).await?;
+  tracing::debug!(
+    groups = tree.groups.len(),
+    observations = tree.observation_count(),
+    "Execution tree built"
+  );
+
+  Ok(Json(GetExecutionTreeResponse { tree }))
+}
@@ -0,0 +1,94 @@
+//! Update execution handler
+
+use crate::api::types::UpdateExecutionRequest;
+use crate::api::types::UpdateExecutionResponse;
+use crate::api::authorize_execution_name;
+use crate::api::authorize_project;
+use crate::api::AppError;
+use crate::auth::ApiKeyRecord;
+use crate::storage::MetadataStorage;
+use axum::extract::Path;
+use axum::extract::State;
+use axum::Extension;
+use axum::Json;
+use chrono::DateTime;
+use chrono::Utc;
+use observation_tools_shared::models::ExecutionId;
+use observation_tools_shared::Execution;
+use observation_tools_shared::ExecutionStatus;
+use std::sync::Arc;
+
+/// Update an execution, e.g. to record that it finished or to add metadata
+/// and tags after it started
+#[utoipa::path(
+    patch,
+    path = "/api/exe/{id}",
+    params(
+        ("id" = String, Path, description = "Execution ID")
+    ),
+    request_body = UpdateExecutionRequest,
+    responses(
+        (status = 200, description = "Execution updated", body = UpdateExecutionResponse),
+        (status = 404, description = "Execution not found"),
+        (status = 400, description = "Bad request"),
+        (status = 403, description = "API key not allowed to access the execution")
+    ),
+    tag = "executions"
+)]
+#[tracing::instrument(skip(metadata, api_key, req))]
+pub async fn update_execution(
+  State(metadata): State<Arc<dyn MetadataStorage>>,
+  api_key: Option<Extension<ApiKeyRecord>>,
+  Path(id): Path<String>,
+  Json(req): Json<UpdateExecutionRequest>,
+) -> Result<Json<UpdateExecutionResponse>, AppError> {
+  let execution_id = ExecutionId::parse(&id)?;
+  let stored = metadata.get_execution(execution_id).await?;
+  let api_key = api_key.as_ref().map(|Extension(key)| key);
+  authorize_project(api_key, &stored.project)?;
+  authorize_execution_name(api_key, &stored.name)?;
+  // Applied to the latest stored execution, so that concurrent updates and
+  // activity recorded for it are kept
+  let now = Utc::now();
+  let update = move |execution: &mut Execution| {
+    apply_update(execution, &req, now);
+    true
+  };
+  let execution = metadata
+    .update_execution(execution_id, &update)
+    .await?
+    .expect("update is always applied");
+
+  tracing::info!(
+    execution_id = %execution.id,
+    status = ?execution.status,
+    "Execution updated"
+  );
+
+  Ok(Json(UpdateExecutionResponse { execution }))
+}
+
+fn apply_update(execution: &mut Execution, req: &UpdateExecutionRequest, now: DateTime<Utc>) {
+  if let Some(status) = req.status {
+    execution.status = status;
+    if status == ExecutionStatus::Running {
+      execution.ended_at = None;
+      execution.error = None;
+    } else if execution.ended_at.is_none() {
+      execution.ended_at = Some(now);
+    }
+  }
+  if let Some(ended_at) = req.ended_at {
+    execution.ended_at = Some(ended_at);
+  }
+  if let Some(error) = &req.error {
+    execution.error = Some(error.clone());
+  }
+  execution.metadata.extend(req.metadata.clone());
+  for tag in &req.add_tags {
+    if !execution.tags.contains(tag) {
+      execution.tags.push(tag.clone());
+    }
+  }
+  execution.updated_at = execution.updated_at.max(now);
+}
@@ -0,0 +1,97 @@
+//! API key handlers
+
+use crate::api::require_scope;
+use crate::api::AppError;
+use crate::auth;
+use crate::auth::api_key_id;
+use crate::auth::ApiKeyRecord;
+use crate::auth::ApiKeyScope;
+use crate::auth::AuthError;
+use crate::storage::MetadataStorage;
+use crate::storage::StorageError;
+use axum::extract::Path;
+use axum::extract::State;
+use axum::Extension;
+use axum::Json;
+use chrono::DateTime;
+use chrono::Utc;
+use serde::Deserialize;
+use serde::Serialize;
+use std::sync::Arc;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Response after revoking an API key
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RevokeApiKeyResponse {
+  /// ID of the revoked key
+  #[schema(value_type = String, example = "018e9a3a2c1b7e3f8d2a4b5c6d7e8f9c")]
+  pub id: Uuid,
+
+  /// Name of the key, `unregistered` for keys missing from the registry
+  pub name: String,
+
+  /// When the key was revoked
+  pub revoked_at: DateTime<Utc>,
+}
+
+/// Revoke an API key, given its ID or the key itself
+///
+/// Requires an admin API key when authentication is enabled. Keys bound to a
+/// project may only revoke keys bound to the same project. Keys missing from
+/// the registry are added to it as revoked.
+#[utoipa::path(
+    delete,
+    path = "/api/keys/{id}",
+    params(
+        ("id" = String, Path, description = "Key ID or API key")
+    ),
+    responses(
+        (status = 200, description = "API key revoked", body = RevokeApiKeyResponse),
+        (status = 400, description = "Bad request"),
+        (status = 403, description = "API key not allowed to revoke the key")
+    ),
+    tag = "keys"
+)]
+#[tracing::instrument(skip(metadata, api_key, id))]
+pub async fn revoke_api_key(
+  State(metadata): State<Arc<dyn MetadataStorage>>,
+  api_key: Option<Extension<ApiKeyRecord>>,
+  Path(id): Path<String>,
+) -> Result<Json<RevokeApiKeyResponse>, AppError> {
+  let api_key = api_key.as_ref().map(|Extension(key)| key);
+  require_scope(api_key, ApiKeyScope::Admin)?;
+  let id = match Uuid::parse_str(&id) {
+    Ok(id) => id,
+    Err(_) => {
+      api_key_id(&id).map_err(|_| AppError::BadRequest("Invalid key ID or API key".to_string()))?
+    }
+  };
+  if let Some(project) = api_key.and_then(|key| key.project.as_ref()) {
+    let revoked_project = match metadata.get_api_key(id).await {
+      Ok(key) => key.project,
+      Err(StorageError::NotFound(_)) => None,
+      Err(e) => return Err(e.into()),
+    };
+    if revoked_project.as_ref() != Some(project) {
+      return Err(AppError::Forbidden(format!(
+        "API key bound to project {} can only revoke keys of that project",
+        project
+      )));
+    }
+  }
+  let record = auth::revoke_api_key(id, metadata.as_ref())
+    .await
+    .map_err(|e| match e {
+      AuthError::Registry(e) => AppError::Storage(e),
+      e => AppError::Storage(StorageError::Internal(e.to_string())),
+    })?;
+  tracing::info!(key_id = %record.id, name = %record.name, "API key revoked");
+  Ok(Json(RevokeApiKeyResponse {
+    id: record.id,
+    name: record.name,
+    revoked_at: record
+      .revoked_at
+      .expect("revoked key has a revocation time"),
+  }))
+}
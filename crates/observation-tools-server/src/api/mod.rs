@@ -1,19 +1,39 @@
 //! API handlers
 
+pub mod blobs;
+pub mod diff;
 pub mod executions;
+pub mod keys;
 pub mod observations;
+pub mod projects;
+pub mod search;
+pub mod snapshots;
 pub mod types;
 
+use crate::auth::ApiKeyRecord;
+use crate::auth::ApiKeyScope;
+use crate::csrf::CsrfSecret;
+use crate::events::ObservationEvents;
+use crate::quota::Quotas;
+use crate::session::ReadAuth;
 use crate::storage::BlobStorage;
 use crate::storage::MetadataStorage;
+use crate::storage::SearchIndex;
+use crate::storage::StorageError;
 use axum::extract::DefaultBodyLimit;
 use axum::extract::FromRef;
+use axum::http::header::RETRY_AFTER;
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::response::Response;
 use axum::Json;
 use axum::Router;
 use minijinja_autoreload::AutoReloader;
+use observation_tools_shared::ContentEncoding;
+use observation_tools_shared::ExecutionId;
+use observation_tools_shared::ProjectId;
+use observation_tools_shared::Quota;
+use observation_tools_shared::QuotaExceeded;
 use std::sync::Arc;
 use tracing::error;
 use tracing::warn;
@@ -26,7 +46,15 @@ use utoipa_axum::routes;
 pub struct AppState {
   pub metadata: Arc<dyn MetadataStorage>,
   pub blobs: Arc<dyn BlobStorage>,
+  pub search: Arc<dyn SearchIndex>,
+  pub events: ObservationEvents,
   pub templates: Arc<AutoReloader>,
+  /// Compression for newly stored payloads
+  pub payload_encoding: ContentEncoding,
+  pub quotas: Quotas,
+  /// Set when reads require authentication
+  pub read_auth: Option<ReadAuth>,
+  pub csrf_secret: CsrfSecret,
 }
 
 impl FromRef<AppState> for Arc<dyn MetadataStorage> {
@@ -41,6 +69,42 @@ impl FromRef<AppState> for Arc<dyn BlobStorage> {
   }
 }
 
+impl FromRef<AppState> for Arc<dyn SearchIndex> {
+  fn from_ref(state: &AppState) -> Self {
+    state.search.clone()
+  }
+}
+
+impl FromRef<AppState> for ObservationEvents {
+  fn from_ref(state: &AppState) -> Self {
+    state.events.clone()
+  }
+}
+
+impl FromRef<AppState> for Option<ReadAuth> {
+  fn from_ref(state: &AppState) -> Self {
+    state.read_auth.clone()
+  }
+}
+
+impl FromRef<AppState> for CsrfSecret {
+  fn from_ref(state: &AppState) -> Self {
+    state.csrf_secret.clone()
+  }
+}
+
+impl FromRef<AppState> for ContentEncoding {
+  fn from_ref(state: &AppState) -> Self {
+    state.payload_encoding
+  }
+}
+
+impl FromRef<AppState> for Quotas {
+  fn from_ref(state: &AppState) -> Self {
+    state.quotas.clone()
+  }
+}
+
 impl FromRef<AppState> for Arc<AutoReloader> {
   fn from_ref(state: &AppState) -> Self {
     state.templates.clone()
@@ -53,6 +117,13 @@ pub enum AppError {
   Storage(crate::storage::StorageError),
   Shared(observation_tools_shared::Error),
   BadRequest(String),
+  /// The API key does not allow the request
+  Forbidden(String),
+  /// The request conflicts with the current state of a resource
+  Conflict(String),
+  /// Request body larger than the given number of bytes
+  PayloadTooLarge(usize),
+  QuotaExceeded(QuotaExceeded),
   Template(minijinja::Error),
 }
 
@@ -68,6 +139,12 @@ impl From<observation_tools_shared::Error> for AppError {
   }
 }
 
+impl From<QuotaExceeded> for AppError {
+  fn from(err: QuotaExceeded) -> Self {
+    AppError::QuotaExceeded(err)
+  }
+}
+
 impl From<minijinja::Error> for AppError {
   fn from(err: minijinja::Error) -> Self {
     AppError::Template(err)
@@ -81,6 +158,14 @@ impl IntoResponse for AppError {
         tracing::debug!(error = %msg, "Resource not found");
         (StatusCode::NOT_FOUND, msg.clone())
       }
+      AppError::Storage(crate::storage::StorageError::AlreadyExists(msg)) => {
+        warn!(error = %msg, "Resource already exists");
+        (StatusCode::CONFLICT, msg.clone())
+      }
+      AppError::Storage(crate::storage::StorageError::Search(msg)) => {
+        warn!(error = %msg, "Invalid search query");
+        (StatusCode::BAD_REQUEST, msg.clone())
+      }
       AppError::Storage(err) => {
         tracing::error!(error = %err, "Storage error");
         (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
@@ -93,6 +178,20 @@ impl IntoResponse for AppError {
         warn!(error = %msg, "Bad request");
         (StatusCode::BAD_REQUEST, msg.clone())
       }
+      AppError::Forbidden(msg) => {
+        warn!(error = %msg, "Forbidden");
+        (StatusCode::FORBIDDEN, msg.clone())
+      }
+      AppError::Conflict(msg) => {
+        warn!(error = %msg, "Conflict");
+        (StatusCode::CONFLICT, msg.clone())
+      }
+      AppError::PayloadTooLarge(max) => {
+        let msg = format!("Request body exceeds the limit of {} bytes", max);
+        warn!(error = %msg, "Payload too large");
+        (StatusCode::PAYLOAD_TOO_LARGE, msg)
+      }
+      AppError::QuotaExceeded(err) => return quota_exceeded_response(err.clone()),
       AppError::Template(err) => {
         let mut full_error_text = format!("Template rendering error: {:#}", err);
         let mut e = &err as &dyn std::error::Error;
@@ -113,6 +212,105 @@ impl IntoResponse for AppError {
   }
 }
 
+/// Check that the request's API key has `scope`. Requests without a key are
+/// only let through when authentication is disabled.
+pub(crate) fn require_scope(key: Option<&ApiKeyRecord>, scope: ApiKeyScope) -> Result<(), AppError> {
+  match key {
+    Some(key) if key.scope != scope && key.scope != ApiKeyScope::Admin => Err(AppError::Forbidden(
+      format!("API key scope {} does not allow this request, {} is required", key.scope, scope),
+    )),
+    _ => Ok(()),
+  }
+}
+
+/// Check that the request's API key may access executions named `name`
+pub(crate) fn authorize_execution_name(
+  key: Option<&ApiKeyRecord>,
+  name: &str,
+) -> Result<(), AppError> {
+  match key {
+    Some(key) if !key.allows_execution(name) => Err(AppError::Forbidden(format!(
+      "API key is not allowed to access execution '{}'",
+      name
+    ))),
+    _ => Ok(()),
+  }
+}
+
+/// Check that the request's API key may access executions in `project`
+pub(crate) fn authorize_project(
+  key: Option<&ApiKeyRecord>,
+  project: &ProjectId,
+) -> Result<(), AppError> {
+  match key {
+    Some(key) if !key.allows_project(project) => Err(AppError::Forbidden(format!(
+      "API key is not allowed to access project '{}'",
+      project
+    ))),
+    _ => Ok(()),
+  }
+}
+
+/// Check that the request's API key may write to a stored execution. Keys
+/// restricted to execution name prefixes or to a project may not access
+/// executions that do not exist yet.
+pub(crate) async fn authorize_execution(
+  metadata: &dyn MetadataStorage,
+  key: Option<&ApiKeyRecord>,
+  execution_id: ExecutionId,
+) -> Result<(), AppError> {
+  let Some(key) = key.filter(|key| !key.execution_prefixes.is_empty() || key.project.is_some())
+  else {
+    return Ok(());
+  };
+  match metadata.get_execution(execution_id).await {
+    Ok(execution) => {
+      authorize_project(Some(key), &execution.project)?;
+      authorize_execution_name(Some(key), &execution.name)
+    }
+    Err(StorageError::NotFound(_)) => Err(AppError::Forbidden(format!(
+      "API key is not allowed to access execution {}",
+      execution_id
+    ))),
+    Err(e) => Err(e.into()),
+  }
+}
+
+/// Check that the request's API key may read a stored execution. Only keys
+/// bound to a project are restricted, and executions that do not exist yet
+/// are reported as not found.
+pub(crate) async fn authorize_read(
+  metadata: &dyn MetadataStorage,
+  key: Option<&ApiKeyRecord>,
+  execution_id: ExecutionId,
+) -> Result<(), AppError> {
+  let Some(key) = key.filter(|key| key.project.is_some()) else {
+    return Ok(());
+  };
+  let execution = metadata.get_execution(execution_id).await?;
+  authorize_project(Some(key), &execution.project)
+}
+
+/// Per-execution quotas cannot be met by retrying, so they are reported as
+/// 413. Daily quotas are reported as 429 with the time until they start over.
+fn quota_exceeded_response(err: QuotaExceeded) -> Response {
+  warn!(error = %err, "Quota exceeded");
+  match err.quota {
+    Quota::ExecutionObservations | Quota::ExecutionBytes => {
+      (StatusCode::PAYLOAD_TOO_LARGE, Json(err)).into_response()
+    }
+    Quota::ApiKeyDailyBytes => {
+      let retry_after = crate::quota::until_next_day(chrono::Utc::now()).as_secs().max(1);
+      (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(RETRY_AFTER, retry_after.to_string())],
+        Json(err),
+      )
+        .into_response()
+    }
+  }
+}
+
 /// Build the complete API router and OpenAPI spec
 /// Returns the router (split into mutating and readonly) and the OpenAPI spec
 pub fn build_api() -> (Router<AppState>, Router<AppState>, OpenApi) {
@@ -123,6 +321,13 @@ pub fn build_api() -> (Router<AppState>, Router<AppState>, OpenApi) {
 
   let (mutation_router, mutation_openapi) = OpenApiRouter::<AppState>::new()
     .routes(routes!(executions::create_execution))
+    .routes(routes!(executions::delete_execution))
+    .routes(routes!(executions::update_execution))
+    .routes(routes!(projects::create_project))
+    .routes(routes!(keys::revoke_api_key))
+    .routes(routes!(observations::put_observation_payload))
+    .routes(routes!(snapshots::check_snapshot))
+    .routes(routes!(blobs::head_blob))
     .split_for_parts();
 
   // create_observations uses multipart form which isn't supported by OpenAPI
@@ -141,10 +346,17 @@ pub fn build_api() -> (Router<AppState>, Router<AppState>, OpenApi) {
   let (read_only_router, read_only_openapi) = OpenApiRouter::<AppState>::new()
     .routes(routes!(executions::list_executions))
     .routes(routes!(executions::get_execution))
+    .routes(routes!(executions::get_execution_tree))
+    .routes(routes!(executions::execution_events))
+    .routes(routes!(projects::list_projects))
     .routes(routes!(observations::list_observations))
     .routes(routes!(observations::get_observation))
     .routes(routes!(observations::get_observation_blob))
     .routes(routes!(observations::get_observation_blob_legacy))
+    .routes(routes!(search::search_observations))
+    .routes(routes!(search::search_execution_observations))
+    .routes(routes!(diff::diff_executions))
+    .routes(routes!(diff::diff_payload))
     .split_for_parts();
 
   let mut openapi = OpenApi::default();
@@ -168,3 +380,22 @@ pub fn build_api() -> (Router<AppState>, Router<AppState>, OpenApi) {
 
   (mutation_router, read_only_router, openapi)
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_quota_exceeded_response() {
+    let execution = AppError::from(QuotaExceeded::new(Quota::ExecutionBytes, 10, 8, 4));
+    let response = execution.into_response();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert!(response.headers().get(RETRY_AFTER).is_none());
+
+    let daily = AppError::from(QuotaExceeded::new(Quota::ApiKeyDailyBytes, 10, 8, 4));
+    let response = daily.into_response();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let retry_after: u64 = response.headers()[RETRY_AFTER].to_str().unwrap().parse().unwrap();
+    assert!((1..=24 * 60 * 60).contains(&retry_after));
+  }
+}
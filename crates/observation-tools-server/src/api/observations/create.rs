@@ -1,20 +1,44 @@
 //! Create observations handler
 
+use crate::abandoned::record_activity;
 use crate::api::types::CreateObservationsResponse;
+use crate::api::authorize_execution;
 use crate::api::AppError;
+use crate::auth::ApiKeyId;
+use crate::auth::ApiKeyRecord;
+use crate::events::ObservationEvents;
+use crate::quota::ExecutionUsage;
+use crate::quota::Quotas;
+use crate::storage::content::add_scopes;
+use crate::storage::content::execution_scopes;
+use crate::storage::content::in_any_scope;
+use crate::storage::content::store_content;
+use crate::storage::BlobRecord;
+use crate::storage::BlobScope;
 use crate::storage::BlobStorage;
+use crate::storage::search::SearchDocument;
 use crate::storage::MetadataStorage;
 use crate::storage::ObservationWithPayloads;
 use crate::storage::PayloadData;
+use crate::storage::SearchIndex;
+use crate::storage::StorageError;
 use crate::storage::StoredPayload;
 use axum::extract::Multipart;
 use axum::extract::Path;
 use axum::extract::State;
+use axum::Extension;
 use axum::Json;
+use bytes::Bytes;
+use chrono::Utc;
+use observation_tools_shared::is_content_digest;
 use observation_tools_shared::models::ExecutionId;
+use observation_tools_shared::content_digest;
+use observation_tools_shared::ContentEncoding;
 use observation_tools_shared::Observation;
 use observation_tools_shared::PayloadId;
 use observation_tools_shared::BLOB_THRESHOLD_BYTES;
+use observation_tools_shared::COMPRESSION_MIN_BYTES;
+use observation_tools_shared::MAX_BLOB_SIZE;
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -24,11 +48,28 @@ use std::sync::Arc;
 struct PayloadManifestEntry {
   observation_id: String,
   payload_id: String,
-  #[allow(dead_code)]
   name: String,
   mime_type: String,
-  #[allow(dead_code)]
+  /// Size before compression
   size: usize,
+  /// Compression of the payload part, absent if not compressed
+  #[serde(default)]
+  content_encoding: Option<String>,
+  /// Content is already stored, uploaded with a separate PUT request or
+  /// earlier for another payload, so there is no payload part
+  #[serde(default)]
+  uploaded: bool,
+  /// Content digest of an `uploaded` payload
+  #[serde(default)]
+  digest: Option<String>,
+}
+
+/// Payload content to store as a blob once the batch passed the quota checks
+struct PendingBlob {
+  observation_index: usize,
+  payload_index: usize,
+  record: BlobRecord,
+  data: Bytes,
 }
 
 /// Create observations (batch) via multipart form
@@ -37,14 +78,34 @@ struct PayloadManifestEntry {
 /// - "observations": JSON array of observation metadata
 /// - "{obs_id}:{payload_id}:{name}": Binary payload data for each payload
 /// - Legacy: "{obs_id}:{name}" or "{obs_id}" formats are also supported
-#[tracing::instrument(skip(metadata, blobs, multipart))]
+///
+/// Payload parts may be compressed, as declared by `content_encoding` in
+/// their manifest entry. Large payloads whose content is already stored, e.g.
+/// uploaded beforehand through `PUT .../payload/{payload_id}`, are marked
+/// `uploaded` with the content digest in the manifest and are not indexed for
+/// search. Large payloads are stored once per content digest, and may only
+/// be referenced by their digest from the execution or project the content
+/// was received in.
+///
+/// Batches exceeding a configured quota are rejected as a whole, with status
+/// 413 for per-execution quotas and 429 for the daily per-API-key quota.
+/// Observations that are already stored only count with the bytes they add.
+#[tracing::instrument(skip(metadata, blobs, search, events, quotas, multipart))]
+#[allow(clippy::too_many_arguments)]
 pub async fn create_observations(
   State(metadata): State<Arc<dyn MetadataStorage>>,
   State(blobs): State<Arc<dyn BlobStorage>>,
+  State(search): State<Arc<dyn SearchIndex>>,
+  State(events): State<ObservationEvents>,
+  State(payload_encoding): State<ContentEncoding>,
+  State(quotas): State<Quotas>,
+  api_key: Option<Extension<ApiKeyRecord>>,
   Path(execution_id): Path<String>,
   mut multipart: Multipart,
 ) -> Result<Json<CreateObservationsResponse>, AppError> {
-  let _execution_id = ExecutionId::parse(&execution_id)?;
+  let parsed_execution_id = ExecutionId::parse(&execution_id)?;
+  let api_key = api_key.map(|Extension(key)| key);
+  authorize_execution(metadata.as_ref(), api_key.as_ref(), parsed_execution_id).await?;
 
   let mut observations: Option<Vec<Observation>> = None;
   let mut payload_manifest: Option<Vec<PayloadManifestEntry>> = None;
@@ -88,6 +149,16 @@ pub async fn create_observations(
   let observations = observations.ok_or_else(|| {
     AppError::BadRequest("Missing 'observations' field in multipart form".to_string())
   })?;
+  // The API key was only authorized for the execution in the path
+  if let Some(obs) = observations
+    .iter()
+    .find(|obs| obs.execution_id != parsed_execution_id)
+  {
+    return Err(AppError::BadRequest(format!(
+      "Observation {} belongs to execution {}, not {}",
+      obs.id, obs.execution_id, parsed_execution_id
+    )));
+  }
 
   tracing::debug!(
     execution_id = %execution_id,
@@ -109,10 +180,22 @@ pub async fn create_observations(
 
   // Build ObservationWithPayloads for each observation by collecting all matching payloads
   let mut observations_with_payloads = Vec::with_capacity(observations.len());
+  let mut search_documents = Vec::with_capacity(observations.len());
+  let mut pending_blobs = Vec::new();
+  let mut received_bytes = 0;
+  let scopes = execution_scopes(metadata.as_ref(), parsed_execution_id).await?;
+  // Observations stored again, e.g. by a retried upload, only count with
+  // the bytes they add to the stored ones
+  let ids: Vec<_> = observations.iter().map(|obs| obs.id).collect();
+  let stored_sizes = metadata.observation_sizes(&ids).await?;
+  let mut requested = ExecutionUsage::default();
+  let mut replaced = ExecutionUsage::default();
 
   for obs in &observations {
+    let mut obs_received = 0;
     let obs_id_str = obs.id.to_string();
     let mut obs_payloads: Vec<StoredPayload> = Vec::new();
+    let mut payload_text: Vec<String> = Vec::new();
 
     // Collect all payload keys that belong to this observation
     let matching_keys: Vec<String> = payloads
@@ -125,11 +208,26 @@ pub async fn create_observations(
       .collect();
 
     for key in matching_keys {
-      let data = payloads.remove(&key).expect("key was just found");
+      let received = payloads.remove(&key).expect("key was just found");
       let (payload_id, name) = parse_payload_key(&key, &obs_id_str)?;
+      let entry = manifest_lookup.get(&(obs_id_str.clone(), payload_id.as_str().to_string()));
+
+      let received_encoding: ContentEncoding = entry
+        .and_then(|e| e.content_encoding.as_deref())
+        .unwrap_or_default()
+        .parse()?;
+      let data = if received_encoding.is_identity() {
+        received.clone()
+      } else {
+        let max_size = entry.map_or(MAX_BLOB_SIZE, |e| e.size.min(MAX_BLOB_SIZE));
+        let decoded = received_encoding.decode(&received, max_size).map_err(|e| {
+          AppError::BadRequest(format!("Failed to decompress payload {}: {}", key, e))
+        })?;
+        Bytes::from(decoded)
+      };
 
       // Determine MIME type: check manifest first, fall back to heuristic for old clients
-      let mime_type = if let Some(entry) = manifest_lookup.get(&(obs_id_str.clone(), payload_id.as_str().to_string())) {
+      let mime_type = if let Some(entry) = entry {
         entry.mime_type.clone()
       } else if serde_json::from_slice::<serde_json::Value>(&data).is_ok() {
         "application/json".to_string()
@@ -139,14 +237,24 @@ pub async fn create_observations(
         "application/octet-stream".to_string()
       };
 
+      payload_text.extend(crate::storage::search::payload_text(&mime_type, &data));
+
       let size = data.len();
-      let payload_data = if size >= BLOB_THRESHOLD_BYTES {
-        blobs
-          .store_blob(obs.id, payload_id.clone(), data.clone())
-          .await?;
-        PayloadData::Blob
-      } else {
-        PayloadData::Inline(data.to_vec())
+      obs_received += size as u64;
+      let digest = (size >= BLOB_THRESHOLD_BYTES).then(|| content_digest(&data));
+      let (encoding, stored) =
+        encode_for_storage(payload_encoding, data, received_encoding, received)?;
+      let payload_data = match &digest {
+        Some(digest) => {
+          pending_blobs.push(PendingBlob {
+            observation_index: observations_with_payloads.len(),
+            payload_index: obs_payloads.len(),
+            record: BlobRecord::new(digest.clone(), size as u64, encoding),
+            data: stored,
+          });
+          PayloadData::Blob
+        }
+        None => PayloadData::Inline(stored.to_vec()),
       };
 
       obs_payloads.push(StoredPayload {
@@ -154,10 +262,54 @@ pub async fn create_observations(
         name,
         mime_type,
         size,
+        encoding,
+        digest,
         data: payload_data,
       });
     }
 
+    for entry in payload_manifest.iter().flatten() {
+      if !entry.uploaded || entry.observation_id != obs_id_str {
+        continue;
+      }
+      let digest = entry
+        .digest
+        .as_deref()
+        .filter(|digest| is_content_digest(digest))
+        .ok_or_else(|| {
+          AppError::BadRequest(format!(
+            "Missing or invalid digest for uploaded payload {}",
+            entry.payload_id
+          ))
+        })?;
+      // Content received elsewhere is reported as not stored, like content
+      // that does not exist
+      let not_stored = || {
+        AppError::BadRequest(format!(
+          "Content {} of payload {} is not stored",
+          digest, entry.payload_id
+        ))
+      };
+      if !in_any_scope(metadata.as_ref(), digest, &scopes).await? {
+        return Err(not_stored());
+      }
+      // The record is authoritative for how the content is stored
+      let record = match metadata.get_blob_record(digest).await {
+        Ok(record) => record,
+        Err(StorageError::NotFound(_)) => return Err(not_stored()),
+        Err(e) => return Err(e.into()),
+      };
+      obs_payloads.push(StoredPayload {
+        id: PayloadId::from(entry.payload_id.clone()),
+        name: entry.name.clone(),
+        mime_type: entry.mime_type.clone(),
+        size: record.size as usize,
+        encoding: record.encoding,
+        digest: Some(record.digest),
+        data: PayloadData::Blob,
+      });
+    }
+
     if obs_payloads.is_empty() {
       return Err(AppError::BadRequest(format!(
         "Missing payload data for observation ID {}",
@@ -165,10 +317,24 @@ pub async fn create_observations(
       )));
     }
 
+    let size: u64 = obs_payloads.iter().map(|payload| payload.size as u64).sum();
+    let stored_size = stored_sizes.get(&obs.id).copied();
+    if stored_size.is_none() {
+      requested.observations += 1;
+    }
+    let stored_size = stored_size.unwrap_or_default();
+    requested.bytes += size.saturating_sub(stored_size);
+    replaced.bytes += stored_size.saturating_sub(size);
+    received_bytes += obs_received.saturating_sub(stored_size);
+
     observations_with_payloads.push(ObservationWithPayloads {
       observation: obs.clone(),
       payloads: obs_payloads,
     });
+    search_documents.push(SearchDocument {
+      observation: obs.clone(),
+      payload_text,
+    });
   }
 
   // Warn about any orphaned payloads
@@ -179,9 +345,42 @@ pub async fn create_observations(
     );
   }
 
-  metadata
-    .store_observations(observations_with_payloads)
+  quotas
+    .reserve_execution(metadata.as_ref(), parsed_execution_id, requested)
     .await?;
+  // Content uploaded separately was charged when it was received
+  let api_key = api_key.map(|key| ApiKeyId(key.id));
+  let now = Utc::now();
+  if let Err(e) = quotas.charge_api_key(api_key, received_bytes, now) {
+    quotas
+      .release_execution(metadata.as_ref(), parsed_execution_id, requested)
+      .await?;
+    return Err(e.into());
+  }
+  let stored = store_batch(
+    metadata.as_ref(),
+    blobs.as_ref(),
+    pending_blobs,
+    &mut observations_with_payloads,
+    &scopes,
+  )
+  .await;
+  if let Err(e) = stored {
+    quotas.refund_api_key(api_key, received_bytes, now);
+    quotas
+      .release_execution(metadata.as_ref(), parsed_execution_id, requested)
+      .await?;
+    return Err(e);
+  }
+  // Replaced observations that were larger free their remaining bytes
+  if replaced != ExecutionUsage::default() {
+    quotas
+      .release_execution(metadata.as_ref(), parsed_execution_id, replaced)
+      .await?;
+  }
+  record_activity(metadata.as_ref(), parsed_execution_id, now).await?;
+  search.index_observations(search_documents).await?;
+  events.publish(parsed_execution_id, observations_with_payloads);
 
   tracing::info!(
     execution_id = %execution_id,
@@ -192,6 +391,53 @@ pub async fn create_observations(
   Ok(Json(CreateObservationsResponse {}))
 }
 
+/// Store the blobs of a batch, then its observations. All content of the
+/// batch may be referenced by its digest in `scopes` afterwards.
+async fn store_batch(
+  metadata: &dyn MetadataStorage,
+  blobs: &dyn BlobStorage,
+  pending_blobs: Vec<PendingBlob>,
+  observations: &mut [ObservationWithPayloads],
+  scopes: &[BlobScope],
+) -> Result<(), AppError> {
+  for pending in pending_blobs {
+    let record = store_content(metadata, blobs, pending.record, pending.data).await?;
+    // Content stored earlier keeps its encoding
+    observations[pending.observation_index].payloads[pending.payload_index].encoding =
+      record.encoding;
+  }
+  metadata.store_observations(observations.to_vec()).await?;
+  let digests = observations
+    .iter()
+    .flat_map(|obs| &obs.payloads)
+    .filter_map(|payload| payload.digest.as_deref());
+  for digest in digests {
+    add_scopes(metadata, digest, scopes).await?;
+  }
+  Ok(())
+}
+
+/// Compress payload data for storage with `encoding`, if that makes it
+/// smaller. Data the client sent with the same encoding is stored as received.
+pub(super) fn encode_for_storage(
+  encoding: ContentEncoding,
+  data: Bytes,
+  received_encoding: ContentEncoding,
+  received: Bytes,
+) -> Result<(ContentEncoding, Bytes), AppError> {
+  if encoding.is_identity() || data.len() < COMPRESSION_MIN_BYTES {
+    return Ok((ContentEncoding::Identity, data));
+  }
+  if received_encoding == encoding {
+    return Ok((encoding, received));
+  }
+  let encoded = encoding.encode(&data).map_err(StorageError::from)?;
+  if encoded.len() >= data.len() {
+    return Ok((ContentEncoding::Identity, data));
+  }
+  Ok((encoding, Bytes::from(encoded)))
+}
+
 /// Parse a payload key into (PayloadId, name).
 /// Supports formats:
 /// - "{obs_id}:{payload_id}:{name}" (new format)
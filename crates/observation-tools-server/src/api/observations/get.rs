@@ -1,12 +1,15 @@
 //! Get observation handler
 
+use crate::api::authorize_read;
 use crate::api::AppError;
+use crate::auth::ApiKeyRecord;
 use crate::storage::MetadataStorage;
 use crate::storage::ObservationWithPayloads;
 use crate::storage::PayloadData;
 use crate::storage::StoredPayload;
 use axum::extract::Path;
 use axum::extract::State;
+use axum::Extension;
 use axum::Json;
 use observation_tools_shared::models::ExecutionId;
 use observation_tools_shared::Observation;
@@ -82,8 +85,14 @@ impl PayloadOrPointerResponse {
     exec_id: ExecutionId,
     obs_id: ObservationId,
   ) -> Self {
-    let data = match payload.data {
-      PayloadData::Inline(data) => data,
+    let data = match &payload.data {
+      PayloadData::Inline(data) => match payload.decode(data) {
+        Ok(data) => data,
+        Err(e) => {
+          tracing::error!(payload_id = %payload.id.as_str(), error = %e, "Invalid payload data");
+          return PayloadOrPointerResponse::InlineBinary(data.clone());
+        }
+      },
       PayloadData::Blob => {
         return PayloadOrPointerResponse::Pointer {
           url: format!(
@@ -136,18 +145,22 @@ impl PayloadOrPointerResponse {
     responses(
         (status = 200, description = "Observation details", body = GetObservationResponse),
         (status = 404, description = "Observation not found"),
-        (status = 400, description = "Bad request")
+        (status = 400, description = "Bad request"),
+        (status = 403, description = "API key not allowed to access the project")
     ),
     tag = "observations"
 )]
-#[tracing::instrument(skip(metadata))]
+#[tracing::instrument(skip(metadata, api_key))]
 pub async fn get_observation(
   State(metadata): State<Arc<dyn MetadataStorage>>,
+  api_key: Option<Extension<ApiKeyRecord>>,
   Path((execution_id, observation_id)): Path<(String, String)>,
 ) -> Result<Json<GetObservationResponse>, AppError> {
   let _execution_id = ExecutionId::parse(&execution_id)?;
   let observation_id = ObservationId::parse(&observation_id)?;
   let observation = metadata.get_observation(observation_id).await?;
+  let api_key = api_key.as_ref().map(|Extension(key)| key);
+  authorize_read(metadata.as_ref(), api_key, observation.observation.execution_id).await?;
   Ok(Json(GetObservationResponse {
     observation: GetObservation::new(observation),
   }))
@@ -1,21 +1,36 @@
 //! Get observation blob handler
 
+use crate::api::authorize_read;
 use crate::api::AppError;
+use crate::auth::ApiKeyRecord;
 use crate::storage::BlobStorage;
 use crate::storage::MetadataStorage;
 use crate::storage::PayloadData;
 use crate::storage::StorageError;
+use crate::storage::StoredPayload;
+use axum::body::Body;
 use axum::extract::Path;
 use axum::extract::State;
 use axum::http::header;
 use axum::http::HeaderValue;
+use axum::http::HeaderMap;
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
+use axum::response::Response;
+use axum::Extension;
+use bytes::Bytes;
+use observation_tools_shared::ContentEncoding;
+use observation_tools_shared::Observation;
 use observation_tools_shared::ObservationId;
 use observation_tools_shared::PayloadId;
+use std::ops::Range;
 use std::sync::Arc;
 
 /// Get observation payload content
+///
+/// Payloads stored compressed are sent with a `Content-Encoding` header when
+/// the request's `Accept-Encoding` allows it. Supports single `Range`
+/// requests and conditional requests with `If-None-Match`.
 #[utoipa::path(
     get,
     path = "/api/exe/{execution_id}/obs/{observation_id}/payload/{payload_id}/content",
@@ -26,20 +41,28 @@ use std::sync::Arc;
     ),
     responses(
         (status = 200, description = "Payload content", body = Vec<u8>, content_type = "application/octet-stream"),
+        (status = 206, description = "Requested range of the payload content", body = Vec<u8>, content_type = "application/octet-stream"),
+        (status = 304, description = "Payload not modified"),
         (status = 404, description = "Payload not found"),
-        (status = 400, description = "Bad request")
+        (status = 416, description = "Requested range not satisfiable"),
+        (status = 400, description = "Bad request"),
+        (status = 403, description = "API key not allowed to access the project")
     ),
     tag = "observations"
 )]
-#[tracing::instrument(skip(metadata, blobs))]
+#[tracing::instrument(skip(metadata, blobs, headers, api_key))]
 pub async fn get_observation_blob(
   State(metadata): State<Arc<dyn MetadataStorage>>,
   State(blobs): State<Arc<dyn BlobStorage>>,
+  api_key: Option<Extension<ApiKeyRecord>>,
   Path((_execution_id, observation_id, payload_id)): Path<(String, String, String)>,
-) -> Result<impl IntoResponse, AppError> {
+  headers: HeaderMap,
+) -> Result<Response, AppError> {
   let observation_id = ObservationId::parse(&observation_id)?;
   let payload_id = PayloadId::from(payload_id);
   let observation = metadata.get_observation(observation_id).await?;
+  let api_key = api_key.as_ref().map(|Extension(key)| key);
+  authorize_read(metadata.as_ref(), api_key, observation.observation.execution_id).await?;
 
   // Find the payload in the manifest
   let payload = observation
@@ -53,25 +76,7 @@ pub async fn get_observation_blob(
       ))
     })?;
 
-  let content_type = HeaderValue::from_str(&payload.mime_type)
-    .unwrap_or_else(|_| HeaderValue::from_static("application/octet-stream"));
-
-  // If the payload data is inline (from prefix scan), return it directly
-  if let PayloadData::Inline(ref data) = payload.data {
-    return Ok((
-      StatusCode::OK,
-      [(header::CONTENT_TYPE, content_type)],
-      data.clone(),
-    ));
-  }
-
-  // Otherwise fetch from blob storage
-  let blob = blobs.get_blob(observation_id, payload_id.clone()).await?;
-  Ok((
-    StatusCode::OK,
-    [(header::CONTENT_TYPE, content_type)],
-    blob.to_vec(),
-  ))
+  payload_response(blobs.as_ref(), &observation.observation, payload, &headers).await
 }
 
 /// Get observation blob content (legacy route for backward compat)
@@ -86,18 +91,23 @@ pub async fn get_observation_blob(
     responses(
         (status = 200, description = "Observation blob content", body = Vec<u8>, content_type = "application/octet-stream"),
         (status = 404, description = "Observation blob not found"),
-        (status = 400, description = "Bad request")
+        (status = 400, description = "Bad request"),
+        (status = 403, description = "API key not allowed to access the project")
     ),
     tag = "observations"
 )]
-#[tracing::instrument(skip(metadata, blobs))]
+#[tracing::instrument(skip(metadata, blobs, headers, api_key))]
 pub async fn get_observation_blob_legacy(
   State(metadata): State<Arc<dyn MetadataStorage>>,
   State(blobs): State<Arc<dyn BlobStorage>>,
+  api_key: Option<Extension<ApiKeyRecord>>,
   Path((_execution_id, observation_id)): Path<(String, String)>,
-) -> Result<impl IntoResponse, AppError> {
+  headers: HeaderMap,
+) -> Result<Response, AppError> {
   let observation_id = ObservationId::parse(&observation_id)?;
   let observation = metadata.get_observation(observation_id).await?;
+  let api_key = api_key.as_ref().map(|Extension(key)| key);
+  authorize_read(metadata.as_ref(), api_key, observation.observation.execution_id).await?;
 
   // Use the first payload
   let payload = observation.payloads.first().ok_or_else(|| {
@@ -107,21 +117,335 @@ pub async fn get_observation_blob_legacy(
     ))
   })?;
 
+  payload_response(blobs.as_ref(), &observation.observation, payload, &headers).await
+}
+
+/// Respond with the payload data. Compressed payloads are sent as stored,
+/// with a `Content-Encoding` header, if the client accepts the encoding and
+/// decompressed otherwise.
+///
+/// Payloads never change, so responses carry an `ETag` derived from the
+/// payload ID and may be cached indefinitely. Single `Range` requests are
+/// answered with the requested part, streamed from blob storage where
+/// possible.
+async fn payload_response(
+  blobs: &dyn BlobStorage,
+  observation: &Observation,
+  payload: &StoredPayload,
+  headers: &HeaderMap,
+) -> Result<Response, AppError> {
+  let send_encoded =
+    !payload.encoding.is_identity() && accepts_encoding(headers, payload.encoding);
+  let etag = if send_encoded {
+    format!("\"{}-{}\"", payload.id.as_str(), payload.encoding)
+  } else {
+    format!("\"{}\"", payload.id.as_str())
+  };
+
+  let mut response_headers = HeaderMap::new();
   let content_type = HeaderValue::from_str(&payload.mime_type)
     .unwrap_or_else(|_| HeaderValue::from_static("application/octet-stream"));
+  response_headers.insert(header::CONTENT_TYPE, content_type);
+  response_headers.insert(header::ETAG, header_value(&etag));
+  response_headers.insert(
+    header::LAST_MODIFIED,
+    header_value(
+      &observation
+        .created_at
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string(),
+    ),
+  );
+  response_headers.insert(
+    header::CACHE_CONTROL,
+    HeaderValue::from_static("private, max-age=31536000, immutable"),
+  );
+  response_headers.insert(
+    header::CONTENT_DISPOSITION,
+    content_disposition(&download_name(observation, payload)),
+  );
+  response_headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+  if !payload.encoding.is_identity() {
+    response_headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+  }
+  if send_encoded {
+    response_headers.insert(
+      header::CONTENT_ENCODING,
+      HeaderValue::from_static(payload.encoding.as_str()),
+    );
+  }
 
-  if let PayloadData::Inline(ref data) = payload.data {
-    return Ok((
-      StatusCode::OK,
-      [(header::CONTENT_TYPE, content_type)],
-      data.clone(),
-    ));
+  if matches_etag(headers.get(header::IF_NONE_MATCH), &etag) {
+    return Ok((StatusCode::NOT_MODIFIED, response_headers).into_response());
   }
 
-  let blob = blobs.get_blob(observation_id, payload.id.clone()).await?;
-  Ok((
-    StatusCode::OK,
-    [(header::CONTENT_TYPE, content_type)],
-    blob.to_vec(),
+  // Blobs sent as stored are streamed, everything else is built in memory
+  let stream_blob =
+    matches!(payload.data, PayloadData::Blob) && (send_encoded || payload.encoding.is_identity());
+  let key = payload.blob_key(observation.id);
+  let (size, data) = if stream_blob {
+    let size = blobs.blob_size(&key).await?;
+    (size, None)
+  } else {
+    let stored = match &payload.data {
+      PayloadData::Inline(data) => Bytes::from(data.clone()),
+      PayloadData::Blob => blobs.get_blob(&key).await?,
+    };
+    let data = if send_encoded || payload.encoding.is_identity() {
+      stored
+    } else {
+      Bytes::from(payload.decode(&stored)?)
+    };
+    (data.len() as u64, Some(data))
+  };
+
+  let (status, range) = match range_request(headers, &etag, size) {
+    RangeRequest::Full => (StatusCode::OK, 0..size),
+    RangeRequest::Partial(range) => {
+      response_headers.insert(
+        header::CONTENT_RANGE,
+        header_value(&format!("bytes {}-{}/{}", range.start, range.end - 1, size)),
+      );
+      (StatusCode::PARTIAL_CONTENT, range)
+    }
+    RangeRequest::Unsatisfiable => {
+      response_headers.insert(header::CONTENT_RANGE, header_value(&format!("bytes */{}", size)));
+      return Ok((StatusCode::RANGE_NOT_SATISFIABLE, response_headers).into_response());
+    }
+  };
+  response_headers.insert(header::CONTENT_LENGTH, HeaderValue::from(range.end - range.start));
+
+  let body = match data {
+    Some(data) => Body::from(data.slice(range.start as usize..range.end as usize)),
+    None => {
+      let range = (status == StatusCode::PARTIAL_CONTENT).then_some(range);
+      Body::from_stream(
+        blobs
+          .get_blob_stream(&key, range)
+          .await?,
+      )
+    }
+  };
+  Ok((status, response_headers, body).into_response())
+}
+
+fn header_value(value: &str) -> HeaderValue {
+  HeaderValue::from_str(value).expect("header value is visible ASCII")
+}
+
+/// Whether an `If-None-Match` or `If-Range` header value matches `etag`
+fn matches_etag(value: Option<&HeaderValue>, etag: &str) -> bool {
+  value
+    .and_then(|value| value.to_str().ok())
+    .is_some_and(|value| {
+      value
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+    })
+}
+
+/// Outcome of a request's `Range` header
+#[derive(Debug, PartialEq)]
+enum RangeRequest {
+  /// No usable range, send everything
+  Full,
+  Partial(Range<u64>),
+  Unsatisfiable,
+}
+
+/// Parse a single `bytes` range for content of `size` bytes. Multiple ranges,
+/// other units and malformed headers are ignored, as is a range whose
+/// `If-Range` precondition doesn't match `etag`.
+fn range_request(headers: &HeaderMap, etag: &str, size: u64) -> RangeRequest {
+  let Some(value) = headers.get(header::RANGE).and_then(|v| v.to_str().ok()) else {
+    return RangeRequest::Full;
+  };
+  if headers.contains_key(header::IF_RANGE)
+    && !matches_etag(headers.get(header::IF_RANGE), etag)
+  {
+    return RangeRequest::Full;
+  }
+  let Some(spec) = value.trim().strip_prefix("bytes=") else {
+    return RangeRequest::Full;
+  };
+  let Some((start, end)) = spec.trim().split_once('-') else {
+    return RangeRequest::Full;
+  };
+  if spec.contains(',') {
+    return RangeRequest::Full;
+  }
+  let (start, end) = (start.trim(), end.trim());
+
+  if start.is_empty() {
+    // Suffix range, the last `end` bytes
+    return match end.parse::<u64>() {
+      Ok(0) => RangeRequest::Unsatisfiable,
+      Ok(_) if size == 0 => RangeRequest::Unsatisfiable,
+      Ok(len) => RangeRequest::Partial(size.saturating_sub(len)..size),
+      Err(_) => RangeRequest::Full,
+    };
+  }
+  let Ok(start) = start.parse::<u64>() else {
+    return RangeRequest::Full;
+  };
+  let end = if end.is_empty() {
+    size
+  } else {
+    match end.parse::<u64>() {
+      Ok(end) if end >= start => end.saturating_add(1).min(size),
+      _ => return RangeRequest::Full,
+    }
+  };
+  if start >= size {
+    return RangeRequest::Unsatisfiable;
+  }
+  RangeRequest::Partial(start..end)
+}
+
+/// File name for saving a payload, the payload name or, for the default
+/// payload, the observation name, with an extension matching the MIME type
+fn download_name(observation: &Observation, payload: &StoredPayload) -> String {
+  let name = if payload.name == "default" {
+    &observation.name
+  } else {
+    &payload.name
+  };
+  let name = name.trim();
+  let name = if name.is_empty() { "payload" } else { name };
+  let essence = payload.mime_type.split(';').next().unwrap_or_default().trim();
+  match extension_for_mime_type(essence) {
+    Some(extension) if !name.contains('.') => format!("{}.{}", name, extension),
+    _ => name.to_string(),
+  }
+}
+
+fn extension_for_mime_type(mime_type: &str) -> Option<&'static str> {
+  let extension = match mime_type {
+    "application/json" => "json",
+    "application/pdf" => "pdf",
+    "application/xml" | "text/xml" => "xml",
+    "text/plain" | "text/x-rust-debug" => "txt",
+    "text/html" => "html",
+    "text/markdown" => "md",
+    "text/csv" => "csv",
+    "image/png" => "png",
+    "image/jpeg" => "jpg",
+    "image/gif" => "gif",
+    "image/webp" => "webp",
+    "image/svg+xml" => "svg",
+    "audio/mpeg" => "mp3",
+    "audio/wav" => "wav",
+    "audio/ogg" => "ogg",
+    "video/mp4" => "mp4",
+    "video/webm" => "webm",
+    _ => return None,
+  };
+  Some(extension)
+}
+
+/// `Content-Disposition` header showing the payload in the browser, with
+/// `name` used when saving it. Non-ASCII names are sent in the RFC 6266
+/// `filename*` parameter, with an ASCII approximation for older clients.
+fn content_disposition(name: &str) -> HeaderValue {
+  let fallback: String = name
+    .chars()
+    .map(|c| match c {
+      c if c.is_ascii_alphanumeric() || " .-_()[]".contains(c) => c,
+      _ => '_',
+    })
+    .collect();
+  let mut encoded = String::new();
+  for byte in name.bytes() {
+    if byte.is_ascii_alphanumeric() || b".-_~".contains(&byte) {
+      encoded.push(byte as char);
+    } else {
+      encoded.push_str(&format!("%{:02X}", byte));
+    }
+  }
+  header_value(&format!(
+    "inline; filename=\"{}\"; filename*=UTF-8''{}",
+    fallback, encoded
   ))
 }
+
+/// Whether the `Accept-Encoding` request header allows `encoding`
+fn accepts_encoding(headers: &HeaderMap, encoding: ContentEncoding) -> bool {
+  headers
+    .get_all(header::ACCEPT_ENCODING)
+    .iter()
+    .filter_map(|value| value.to_str().ok())
+    .flat_map(|value| value.split(','))
+    .any(|item| {
+      let mut parts = item.split(';');
+      let name = parts.next().unwrap_or_default().trim();
+      let rejected = parts.any(|param| {
+        param
+          .trim()
+          .strip_prefix("q=")
+          .and_then(|q| q.parse::<f32>().ok())
+          .is_some_and(|q| q == 0.0)
+      });
+      !rejected && (name == "*" || name.eq_ignore_ascii_case(encoding.as_str()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_accepts_encoding() {
+    let headers = |value: &str| {
+      let mut headers = HeaderMap::new();
+      headers.insert(header::ACCEPT_ENCODING, HeaderValue::from_str(value).unwrap());
+      headers
+    };
+    assert!(accepts_encoding(&headers("gzip, deflate, br, zstd"), ContentEncoding::Zstd));
+    assert!(accepts_encoding(&headers("GZIP;q=0.5"), ContentEncoding::Gzip));
+    assert!(accepts_encoding(&headers("*"), ContentEncoding::Zstd));
+    assert!(!accepts_encoding(&headers("gzip"), ContentEncoding::Zstd));
+    assert!(!accepts_encoding(&headers("zstd;q=0"), ContentEncoding::Zstd));
+    assert!(!accepts_encoding(&HeaderMap::new(), ContentEncoding::Gzip));
+  }
+
+  #[test]
+  fn test_range_request() {
+    let etag = "\"etag\"";
+    let range = |value: &str, size| {
+      let mut headers = HeaderMap::new();
+      headers.insert(header::RANGE, HeaderValue::from_str(value).unwrap());
+      range_request(&headers, etag, size)
+    };
+    assert_eq!(range_request(&HeaderMap::new(), etag, 10), RangeRequest::Full);
+    assert_eq!(range("bytes=2-4", 10), RangeRequest::Partial(2..5));
+    assert_eq!(range("bytes=2-", 10), RangeRequest::Partial(2..10));
+    assert_eq!(range("bytes=5-100", 10), RangeRequest::Partial(5..10));
+    assert_eq!(range("bytes=-3", 10), RangeRequest::Partial(7..10));
+    assert_eq!(range("bytes=-30", 10), RangeRequest::Partial(0..10));
+    assert_eq!(range("bytes=10-", 10), RangeRequest::Unsatisfiable);
+    assert_eq!(range("bytes=-0", 10), RangeRequest::Unsatisfiable);
+    assert_eq!(range("bytes=0-1,4-5", 10), RangeRequest::Full);
+    assert_eq!(range("bytes=4-2", 10), RangeRequest::Full);
+    assert_eq!(range("items=0-1", 10), RangeRequest::Full);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::RANGE, HeaderValue::from_static("bytes=0-1"));
+    headers.insert(header::IF_RANGE, HeaderValue::from_static("\"other\""));
+    assert_eq!(range_request(&headers, etag, 10), RangeRequest::Full);
+    headers.insert(header::IF_RANGE, HeaderValue::from_static("\"etag\""));
+    assert_eq!(range_request(&headers, etag, 10), RangeRequest::Partial(0..2));
+  }
+
+  #[test]
+  fn test_content_disposition() {
+    assert_eq!(
+      content_disposition("report.json"),
+      "inline; filename=\"report.json\"; filename*=UTF-8''report.json"
+    );
+    assert_eq!(
+      content_disposition("naïve \"plan\".txt"),
+      "inline; filename=\"na_ve _plan_.txt\"; filename*=UTF-8''na%C3%AFve%20%22plan%22.txt"
+    );
+  }
+}
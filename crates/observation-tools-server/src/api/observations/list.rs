@@ -2,13 +2,19 @@
 
 use crate::api::observations::get::GetObservation;
 use crate::api::types::ListObservationsQuery;
+use crate::api::authorize_read;
 use crate::api::AppError;
+use crate::auth::ApiKeyRecord;
+use crate::storage::filter::parse_metadata_pairs;
 use crate::storage::MetadataStorage;
+use crate::storage::ObservationFilter;
 use axum::extract::Path;
 use axum::extract::Query;
 use axum::extract::State;
+use axum::Extension;
 use axum::Json;
 use observation_tools_shared::models::ExecutionId;
+use observation_tools_shared::GroupId;
 use serde::Deserialize;
 use serde::Serialize;
 use std::sync::Arc;
@@ -34,21 +40,26 @@ pub struct ListObservationsResponse {
     ),
     responses(
         (status = 200, description = "List of observations", body = ListObservationsResponse),
-        (status = 400, description = "Bad request")
+        (status = 400, description = "Bad request"),
+        (status = 403, description = "API key not allowed to access the project")
     ),
     tag = "observations"
 )]
-#[tracing::instrument(skip(metadata))]
+#[tracing::instrument(skip(metadata, api_key))]
 pub async fn list_observations(
   State(metadata): State<Arc<dyn MetadataStorage>>,
+  api_key: Option<Extension<ApiKeyRecord>>,
   Path(execution_id): Path<String>,
   Query(query): Query<ListObservationsQuery>,
 ) -> Result<Json<ListObservationsResponse>, AppError> {
   let execution_id = ExecutionId::parse(&execution_id)?;
+  let api_key = api_key.as_ref().map(|Extension(key)| key);
+  authorize_read(metadata.as_ref(), api_key, execution_id).await?;
   let limit = query.limit.unwrap_or(100);
+  let filter = observation_filter(&query)?;
   let mut observations = metadata
     // Fetch one extra to see if there's a next page
-    .list_observations(execution_id, Some(limit + 1), query.offset, None)
+    .list_observations(execution_id, Some(limit + 1), query.offset, &filter)
     .await?;
   let has_next_page = observations.len() > limit;
   if has_next_page {
@@ -63,3 +74,20 @@ pub async fn list_observations(
     has_next_page,
   }))
 }
+
+fn observation_filter(query: &ListObservationsQuery) -> Result<ObservationFilter, AppError> {
+  let metadata = match &query.metadata {
+    Some(pairs) => parse_metadata_pairs(pairs).map_err(AppError::BadRequest)?,
+    None => Default::default(),
+  };
+  Ok(ObservationFilter {
+    observation_type: query.observation_type,
+    min_log_level: query.level,
+    name: query.name.clone(),
+    metadata,
+    created_after: query.created_after,
+    created_before: query.created_before,
+    group_id: query.group_id.clone().map(GroupId::from),
+    source_file: query.source_file.clone(),
+  })
+}
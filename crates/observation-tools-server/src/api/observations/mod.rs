@@ -4,6 +4,7 @@ mod create;
 mod get;
 mod get_blob;
 mod list;
+mod put_payload;
 
 pub use create::create_observations;
 pub use get::__path_get_observation;
@@ -16,3 +17,5 @@ pub use get_blob::get_observation_blob;
 pub use get_blob::get_observation_blob_legacy;
 pub use list::__path_list_observations;
 pub use list::list_observations;
+pub use put_payload::__path_put_observation_payload;
+pub use put_payload::put_observation_payload;
@@ -0,0 +1,236 @@
+//! Streaming payload upload handler
+
+use crate::abandoned::record_activity;
+use crate::api::types::PayloadContent;
+use crate::api::types::PutPayloadResponse;
+use crate::api::observations::create::encode_for_storage;
+use crate::api::authorize_execution;
+use crate::api::AppError;
+use crate::auth::ApiKeyId;
+use crate::auth::ApiKeyRecord;
+use crate::quota::ExecutionUsage;
+use crate::quota::Quotas;
+use crate::storage::content::add_scopes;
+use crate::storage::content::execution_scopes;
+use crate::storage::content::store_content;
+use crate::storage::content::store_content_stream;
+use crate::storage::BlobRecord;
+use crate::storage::BlobStorage;
+use crate::storage::MetadataStorage;
+use crate::storage::StorageError;
+use axum::body::Body;
+use axum::extract::Path;
+use axum::extract::State;
+use axum::Extension;
+use axum::http::header;
+use axum::http::HeaderMap;
+use axum::Json;
+use bytes::Bytes;
+use bytes::BytesMut;
+use chrono::Utc;
+use futures::StreamExt;
+use observation_tools_shared::content_digest;
+use observation_tools_shared::ContentEncoding;
+use observation_tools_shared::ExecutionId;
+use observation_tools_shared::ObservationId;
+use observation_tools_shared::PayloadId;
+use observation_tools_shared::MAX_BLOB_SIZE;
+use std::io;
+use std::sync::Arc;
+
+/// Upload the content of a large payload before creating its observation
+///
+/// The body is streamed to blob storage as received, without buffering it in
+/// memory. The observation is then created as usual, with the payload's
+/// manifest entry marked as `uploaded` instead of sending a payload part.
+///
+/// Content is stored once per SHA-256 digest of the uncompressed data, which
+/// the response reports along with the stored encoding to declare in the
+/// manifest entry. Uncompressed bodies are hashed and stored as they stream
+/// in. Compressed bodies are decompressed in memory to hash them, and
+/// recompressed for storage if needed.
+///
+/// The uncompressed content counts against the daily per-API-key quota when
+/// it is received. Uploads that would exceed it, or the execution's byte
+/// quota, are rejected, by their declared length before storing them.
+#[utoipa::path(
+    put,
+    path = "/api/exe/{execution_id}/obs/{observation_id}/payload/{payload_id}",
+    params(
+        ("execution_id" = String, Path, description = "Execution ID"),
+        ("observation_id" = String, Path, description = "Observation ID"),
+        ("payload_id" = String, Path, description = "Payload ID")
+    ),
+    request_body(content = PayloadContent, content_type = "application/octet-stream"),
+    responses(
+        (status = 200, description = "Payload stored", body = PutPayloadResponse),
+        (status = 400, description = "Bad request"),
+        (status = 403, description = "API key not allowed to access the execution"),
+        (status = 409, description = "Observation already exists"),
+        (status = 413, description = "Payload too large or execution quota exceeded"),
+        (status = 429, description = "Daily API key quota exceeded")
+    ),
+    tag = "observations"
+)]
+#[tracing::instrument(skip(metadata, blobs, quotas, api_key, headers, body))]
+#[allow(clippy::too_many_arguments)]
+pub async fn put_observation_payload(
+  State(metadata): State<Arc<dyn MetadataStorage>>,
+  State(blobs): State<Arc<dyn BlobStorage>>,
+  State(payload_encoding): State<ContentEncoding>,
+  State(quotas): State<Quotas>,
+  api_key: Option<Extension<ApiKeyRecord>>,
+  Path((execution_id, observation_id, payload_id)): Path<(String, String, String)>,
+  headers: HeaderMap,
+  body: Body,
+) -> Result<Json<PutPayloadResponse>, AppError> {
+  let execution_id = ExecutionId::parse(&execution_id)?;
+  authorize_execution(
+    metadata.as_ref(),
+    api_key.as_ref().map(|Extension(key)| key),
+    execution_id,
+  )
+  .await?;
+  let observation_id = ObservationId::parse(&observation_id)?;
+  // Payload IDs are echoed into the manifest, so reject anything unexpected
+  if uuid::Uuid::parse_str(&payload_id).is_err() {
+    return Err(AppError::BadRequest(format!("Invalid payload ID: {}", payload_id)));
+  }
+  let payload_id = PayloadId::from(payload_id);
+
+  // Payloads of stored observations are immutable
+  match metadata.get_observation(observation_id).await {
+    Ok(_) => {
+      return Err(
+        StorageError::AlreadyExists(format!("Observation {} already exists", observation_id))
+          .into(),
+      )
+    }
+    Err(StorageError::NotFound(_)) => {}
+    Err(e) => return Err(e.into()),
+  }
+
+  let content_length = headers
+    .get(header::CONTENT_LENGTH)
+    .and_then(|v| v.to_str().ok())
+    .and_then(|v| v.parse::<u64>().ok());
+  if content_length.is_some_and(|len| len > MAX_BLOB_SIZE as u64) {
+    return Err(AppError::PayloadTooLarge(MAX_BLOB_SIZE));
+  }
+
+  // Quotas count uncompressed bytes, so the declared length is settled once
+  // the content is stored
+  let api_key_id = api_key.as_ref().map(|Extension(key)| ApiKeyId(key.id));
+  let now = Utc::now();
+  let declared = content_length.unwrap_or_default();
+  let check_execution = |bytes| {
+    quotas.check_execution_usage(
+      metadata.as_ref(),
+      execution_id,
+      ExecutionUsage {
+        observations: 0,
+        bytes,
+      },
+    )
+  };
+  quotas.charge_api_key(api_key_id, declared, now)?;
+  if let Err(e) = check_execution(declared).await {
+    quotas.refund_api_key(api_key_id, declared, now);
+    return Err(e);
+  }
+  let record = match store_body(metadata.as_ref(), blobs.as_ref(), payload_encoding, &headers, body)
+    .await
+  {
+    Ok(record) => record,
+    Err(e) => {
+      quotas.refund_api_key(api_key_id, declared, now);
+      return Err(e);
+    }
+  };
+  quotas.refund_api_key(api_key_id, declared, now);
+  quotas.charge_api_key(api_key_id, record.size, now)?;
+  if let Err(e) = check_execution(record.size).await {
+    quotas.refund_api_key(api_key_id, record.size, now);
+    return Err(e);
+  }
+  let scopes = execution_scopes(metadata.as_ref(), execution_id).await?;
+  add_scopes(metadata.as_ref(), &record.digest, &scopes).await?;
+  record_activity(metadata.as_ref(), execution_id, Utc::now()).await?;
+
+  tracing::debug!(
+    observation_id = %observation_id,
+    payload_id = %payload_id.as_str(),
+    digest = %record.digest,
+    size = record.size,
+    encoding = %record.encoding,
+    "Payload uploaded"
+  );
+
+  Ok(Json(PutPayloadResponse {
+    size: record.size,
+    content_encoding: record.encoding.to_string(),
+    digest: record.digest,
+  }))
+}
+
+/// Store the content of a request body, decompressing it if needed
+async fn store_body(
+  metadata: &dyn MetadataStorage,
+  blobs: &dyn BlobStorage,
+  payload_encoding: ContentEncoding,
+  headers: &HeaderMap,
+  body: Body,
+) -> Result<BlobRecord, AppError> {
+  let mut received = 0;
+  let stream = body
+    .into_data_stream()
+    .map(move |chunk| {
+      let chunk = chunk.map_err(io::Error::other)?;
+      received += chunk.len();
+      if received > MAX_BLOB_SIZE {
+        return Err(io::Error::new(
+          io::ErrorKind::FileTooLarge,
+          format!("payload exceeds {} bytes", MAX_BLOB_SIZE),
+        ));
+      }
+      Ok(chunk)
+    })
+    .boxed();
+
+  let received_encoding: ContentEncoding = headers
+    .get(header::CONTENT_ENCODING)
+    .and_then(|v| v.to_str().ok())
+    .unwrap_or_default()
+    .parse()?;
+  let record = if received_encoding.is_identity() {
+    store_content_stream(metadata, blobs, stream)
+      .await
+      .map_err(body_error)?
+  } else {
+    let mut stream = stream;
+    let mut received = BytesMut::new();
+    while let Some(chunk) = stream.next().await {
+      received.extend_from_slice(&chunk.map_err(|e| body_error(e.into()))?);
+    }
+    let received = received.freeze();
+    let data = received_encoding
+      .decode(&received, MAX_BLOB_SIZE)
+      .map_err(|e| AppError::BadRequest(format!("Failed to decompress payload: {}", e)))?;
+    let digest = content_digest(&data);
+    let size = data.len() as u64;
+    let (encoding, stored) =
+      encode_for_storage(payload_encoding, Bytes::from(data), received_encoding, received)?;
+    let record = BlobRecord::new(digest, size, encoding);
+    store_content(metadata, blobs, record, stored).await?
+  };
+  Ok(record)
+}
+
+fn body_error(err: StorageError) -> AppError {
+  match err {
+    StorageError::Io(e) if e.kind() == io::ErrorKind::FileTooLarge => {
+      AppError::PayloadTooLarge(MAX_BLOB_SIZE)
+    }
+    e => e.into(),
+  }
+}
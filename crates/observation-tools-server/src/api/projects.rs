@@ -0,0 +1,123 @@
+//! Project handlers
+
+use crate::api::authorize_project;
+use crate::api::require_scope;
+use crate::api::AppError;
+use crate::auth::ApiKeyRecord;
+use crate::auth::ApiKeyScope;
+use crate::storage::MetadataStorage;
+use crate::storage::StorageError;
+use crate::storage::StorageResult;
+use axum::extract::State;
+use axum::Extension;
+use axum::Json;
+use observation_tools_shared::Project;
+use observation_tools_shared::ProjectId;
+use serde::Deserialize;
+use serde::Serialize;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+/// Request to create or rename a project
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CreateProjectRequest {
+  /// Project ID, used in URLs
+  pub id: ProjectId,
+
+  /// Display name, the ID if not given
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub name: Option<String>,
+}
+
+/// Response after creating a project
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CreateProjectResponse {
+  /// The created or renamed project
+  pub project: Project,
+}
+
+/// Response for listing projects
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ListProjectsResponse {
+  /// Projects ordered by ID
+  pub projects: Vec<Project>,
+}
+
+/// Make sure a project exists, creating it named after its ID if it does not
+pub async fn ensure_project(metadata: &dyn MetadataStorage, id: &ProjectId) -> StorageResult<()> {
+  match metadata.get_project(id).await {
+    Ok(_) => Ok(()),
+    Err(StorageError::NotFound(_)) => {
+      tracing::info!(project = %id, "Creating project");
+      metadata.store_project(&Project::new(id.clone(), id.as_str())).await
+    }
+    Err(e) => Err(e),
+  }
+}
+
+/// Store a project named `name`, or its ID if not given. An existing project
+/// with the same ID is renamed.
+pub async fn upsert_project(
+  metadata: &dyn MetadataStorage,
+  id: ProjectId,
+  name: Option<String>,
+) -> StorageResult<Project> {
+  let name = name.unwrap_or_else(|| id.to_string());
+  let project = match metadata.get_project(&id).await {
+    Ok(existing) => Project { name, ..existing },
+    Err(StorageError::NotFound(_)) => Project::new(id, name),
+    Err(e) => return Err(e),
+  };
+  metadata.store_project(&project).await?;
+  Ok(project)
+}
+
+/// Create a project, or rename an existing one
+///
+/// Requires an admin API key when authentication is enabled. Projects are
+/// also created when the first execution in them is.
+#[utoipa::path(
+    post,
+    path = "/api/projects",
+    request_body = CreateProjectRequest,
+    responses(
+        (status = 200, description = "Project created", body = CreateProjectResponse),
+        (status = 400, description = "Bad request"),
+        (status = 403, description = "API key not allowed to create projects")
+    ),
+    tag = "projects"
+)]
+#[tracing::instrument(skip(metadata, api_key))]
+pub async fn create_project(
+  State(metadata): State<Arc<dyn MetadataStorage>>,
+  api_key: Option<Extension<ApiKeyRecord>>,
+  Json(req): Json<CreateProjectRequest>,
+) -> Result<Json<CreateProjectResponse>, AppError> {
+  let api_key = api_key.as_ref().map(|Extension(key)| key);
+  require_scope(api_key, ApiKeyScope::Admin)?;
+  authorize_project(api_key, &req.id)?;
+  let project = upsert_project(metadata.as_ref(), req.id, req.name).await?;
+  tracing::info!(project = %project.id, "Project stored");
+  Ok(Json(CreateProjectResponse { project }))
+}
+
+/// List projects. Keys bound to a project only see that project.
+#[utoipa::path(
+    get,
+    path = "/api/projects",
+    responses(
+        (status = 200, description = "List of projects", body = ListProjectsResponse)
+    ),
+    tag = "projects"
+)]
+#[tracing::instrument(skip(metadata, api_key))]
+pub async fn list_projects(
+  State(metadata): State<Arc<dyn MetadataStorage>>,
+  api_key: Option<Extension<ApiKeyRecord>>,
+) -> Result<Json<ListProjectsResponse>, AppError> {
+  let mut projects = metadata.list_projects().await?;
+  if let Some(Extension(key)) = &api_key {
+    projects.retain(|project| key.allows_project(&project.id));
+  }
+  Ok(Json(ListProjectsResponse { projects }))
+}
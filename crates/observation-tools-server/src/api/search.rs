@@ -0,0 +1,164 @@
+//! Search handlers
+
+use crate::api::observations::GetObservation;
+use crate::api::types::SearchObservationsQuery;
+use crate::api::authorize_read;
+use crate::api::AppError;
+use crate::auth::ApiKeyRecord;
+use crate::storage::search::SearchQuery;
+use crate::storage::MetadataStorage;
+use crate::storage::ObservationFilter;
+use crate::storage::SearchIndex;
+use crate::storage::StorageError;
+use axum::extract::Path;
+use axum::extract::Query;
+use axum::extract::State;
+use axum::Extension;
+use axum::Json;
+use observation_tools_shared::models::ExecutionId;
+use serde::Deserialize;
+use serde::Serialize;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+/// Response for searching observations
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SearchObservationsResponse {
+  /// Matching observations, most recent first
+  pub observations: Vec<GetObservation>,
+
+  /// Whether there are more results available
+  pub has_next_page: bool,
+}
+
+/// Run a search and load one page of matching observations.
+///
+/// Hits are further narrowed down by `filter`. Hits are read from the index
+/// until the page and one more match are found. Returns the page of
+/// observations and whether there are more matches.
+pub async fn search_page(
+  metadata: &dyn MetadataStorage,
+  search: &dyn SearchIndex,
+  text: &str,
+  execution_id: Option<ExecutionId>,
+  filter: &ObservationFilter,
+  limit: usize,
+  offset: usize,
+) -> Result<(Vec<GetObservation>, bool), AppError> {
+  let hits = search
+    .search(&SearchQuery {
+      text: text.to_string(),
+      execution_id,
+    })
+    .await?;
+
+  let mut skipped = 0;
+  let mut observations = Vec::new();
+  for hit in hits {
+    let hit = hit?;
+    match metadata.get_observation(hit.observation_id).await {
+      Ok(obs) if filter.matches(&obs.observation) => {
+        if skipped < offset {
+          skipped += 1;
+        } else if observations.len() < limit {
+          observations.push(GetObservation::new(obs));
+        } else {
+          return Ok((observations, true));
+        }
+      }
+      Ok(_) => {}
+      // The index may reference observations that were since removed
+      Err(StorageError::NotFound(_)) => {
+        tracing::debug!(observation_id = %hit.observation_id, "Skipping stale search hit");
+      }
+      Err(e) => return Err(e.into()),
+    }
+  }
+  Ok((observations, false))
+}
+
+/// Search observations across all executions
+///
+/// Not available to keys bound to a project, which have to search each
+/// execution instead.
+#[utoipa::path(
+    get,
+    path = "/api/search",
+    params(SearchObservationsQuery),
+    responses(
+        (status = 200, description = "Matching observations", body = SearchObservationsResponse),
+        (status = 400, description = "Bad request"),
+        (status = 403, description = "API key bound to a project")
+    ),
+    tag = "search"
+)]
+#[tracing::instrument(skip(metadata, search, api_key))]
+pub async fn search_observations(
+  State(metadata): State<Arc<dyn MetadataStorage>>,
+  State(search): State<Arc<dyn SearchIndex>>,
+  api_key: Option<Extension<ApiKeyRecord>>,
+  Query(query): Query<SearchObservationsQuery>,
+) -> Result<Json<SearchObservationsResponse>, AppError> {
+  if api_key.is_some_and(|Extension(key)| key.project.is_some()) {
+    return Err(AppError::Forbidden(
+      "API keys bound to a project can only search within an execution".to_string(),
+    ));
+  }
+  search_response(metadata, search, query, None).await
+}
+
+/// Search observations within a single execution
+#[utoipa::path(
+    get,
+    path = "/api/exe/{execution_id}/search",
+    params(
+        ("execution_id" = String, Path, description = "Execution ID"),
+        SearchObservationsQuery
+    ),
+    responses(
+        (status = 200, description = "Matching observations", body = SearchObservationsResponse),
+        (status = 400, description = "Bad request"),
+        (status = 403, description = "API key not allowed to access the project")
+    ),
+    tag = "search"
+)]
+#[tracing::instrument(skip(metadata, search, api_key))]
+pub async fn search_execution_observations(
+  State(metadata): State<Arc<dyn MetadataStorage>>,
+  State(search): State<Arc<dyn SearchIndex>>,
+  api_key: Option<Extension<ApiKeyRecord>>,
+  Path(execution_id): Path<String>,
+  Query(query): Query<SearchObservationsQuery>,
+) -> Result<Json<SearchObservationsResponse>, AppError> {
+  let execution_id = ExecutionId::parse(&execution_id)?;
+  let api_key = api_key.as_ref().map(|Extension(key)| key);
+  authorize_read(metadata.as_ref(), api_key, execution_id).await?;
+  search_response(metadata, search, query, Some(execution_id)).await
+}
+
+async fn search_response(
+  metadata: Arc<dyn MetadataStorage>,
+  search: Arc<dyn SearchIndex>,
+  query: SearchObservationsQuery,
+  execution_id: Option<ExecutionId>,
+) -> Result<Json<SearchObservationsResponse>, AppError> {
+  let limit = query.limit.unwrap_or(100);
+  let offset = query.offset.unwrap_or(0);
+  tracing::debug!(q = %query.q, ?execution_id, "Searching observations");
+
+  let (observations, has_next_page) = search_page(
+    metadata.as_ref(),
+    search.as_ref(),
+    &query.q,
+    execution_id,
+    &ObservationFilter::default(),
+    limit,
+    offset,
+  )
+  .await?;
+
+  Ok(Json(SearchObservationsResponse {
+    observations,
+    has_next_page,
+  }))
+}
@@ -0,0 +1,84 @@
+//! Snapshot handlers
+
+use crate::api::authorize_execution_name;
+use crate::api::authorize_project;
+use crate::api::AppError;
+use crate::auth::ApiKeyRecord;
+use crate::snapshot::SnapshotCheck;
+use crate::storage::BlobStorage;
+use crate::storage::MetadataStorage;
+use axum::extract::Path;
+use axum::extract::State;
+use axum::Extension;
+use axum::Json;
+use observation_tools_shared::ExecutionId;
+use observation_tools_shared::ObservationId;
+use serde::Deserialize;
+use serde::Serialize;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+/// Request to check a snapshot against its baseline
+#[derive(Debug, Clone, Serialize, Deserialize, Default, ToSchema)]
+pub struct CheckSnapshotRequest {
+  /// Make the snapshot the baseline if it differs from it
+  #[serde(default)]
+  pub accept: bool,
+}
+
+/// Response after checking a snapshot
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CheckSnapshotResponse {
+  pub snapshot: SnapshotCheck,
+}
+
+/// Compare an observation with the baseline of the same observation name in
+/// executions with the same name and project
+///
+/// The observation becomes the baseline if there is none yet, or if it
+/// differs and `accept` is set.
+#[utoipa::path(
+    post,
+    path = "/api/exe/{execution_id}/obs/{observation_id}/snapshot",
+    params(
+        ("execution_id" = String, Path, description = "Execution ID"),
+        ("observation_id" = String, Path, description = "Observation ID")
+    ),
+    request_body = CheckSnapshotRequest,
+    responses(
+        (status = 200, description = "Snapshot compared with its baseline", body = CheckSnapshotResponse),
+        (status = 400, description = "Bad request"),
+        (status = 403, description = "API key not allowed to access the execution"),
+        (status = 404, description = "Execution or observation not found")
+    ),
+    tag = "observations"
+)]
+#[tracing::instrument(skip(metadata, blobs, api_key, req))]
+pub async fn check_snapshot(
+  State(metadata): State<Arc<dyn MetadataStorage>>,
+  State(blobs): State<Arc<dyn BlobStorage>>,
+  api_key: Option<Extension<ApiKeyRecord>>,
+  Path((execution_id, observation_id)): Path<(String, String)>,
+  Json(req): Json<CheckSnapshotRequest>,
+) -> Result<Json<CheckSnapshotResponse>, AppError> {
+  let execution = metadata.get_execution(ExecutionId::parse(&execution_id)?).await?;
+  let api_key = api_key.as_ref().map(|Extension(key)| key);
+  authorize_project(api_key, &execution.project)?;
+  authorize_execution_name(api_key, &execution.name)?;
+  let observation = metadata.get_observation(ObservationId::parse(&observation_id)?).await?;
+  if observation.observation.execution_id != execution.id {
+    return Err(AppError::BadRequest(format!(
+      "Observation {} does not belong to execution {}",
+      observation.observation.id, execution.id
+    )));
+  }
+  let snapshot = crate::snapshot::check_snapshot(
+    metadata.as_ref(),
+    blobs.as_ref(),
+    &execution,
+    &observation,
+    req.accept,
+  )
+  .await?;
+  Ok(Json(CheckSnapshotResponse { snapshot }))
+}
@@ -1,9 +1,20 @@
 //! API request and response types
 
+use crate::api::AppError;
+use crate::storage::filter::parse_metadata_pairs;
+use crate::storage::ExecutionFilter;
+use crate::tree::ExecutionTree;
+use chrono::DateTime;
+use chrono::Utc;
 use observation_tools_shared::models::Execution;
+use observation_tools_shared::ExecutionStatus;
+use observation_tools_shared::LogLevel;
 use observation_tools_shared::Observation;
+use observation_tools_shared::ObservationType;
+use observation_tools_shared::ProjectId;
 use serde::Deserialize;
 use serde::Serialize;
+use std::collections::HashMap;
 use utoipa::IntoParams;
 use utoipa::ToSchema;
 
@@ -22,6 +33,47 @@ pub struct CreateExecutionRequest {
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct CreateExecutionResponse {}
 
+/// Request to update an execution, e.g. to record that it finished. Fields
+/// that are not set are left unchanged.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, ToSchema)]
+pub struct UpdateExecutionRequest {
+  /// New status. Finishing sets `ended_at` if it is not given, and going back
+  /// to `Running` clears `ended_at` and `error`.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub status: Option<ExecutionStatus>,
+
+  /// When the execution finished
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub ended_at: Option<DateTime<Utc>>,
+
+  /// Error message for a failed execution
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub error: Option<String>,
+
+  /// Metadata to merge into the execution's metadata, replacing the values
+  /// of existing keys
+  #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+  pub metadata: HashMap<String, String>,
+
+  /// Tags to add to the execution
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub add_tags: Vec<String>,
+}
+
+/// Response after updating an execution
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UpdateExecutionResponse {
+  /// The updated execution
+  pub execution: Execution,
+}
+
+/// Response after deleting an execution
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DeleteExecutionResponse {
+  /// Number of observations that were deleted with the execution
+  pub deleted_observations: usize,
+}
+
 /// Query parameters for listing executions
 #[derive(Debug, Clone, Serialize, Deserialize, Default, ToSchema, IntoParams)]
 pub struct ListExecutionsQuery {
@@ -32,6 +84,62 @@ pub struct ListExecutionsQuery {
   /// Number of results to skip (for pagination)
   #[serde(skip_serializing_if = "Option::is_none")]
   pub offset: Option<usize>,
+
+  /// Only include executions in this project
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub project: Option<ProjectId>,
+
+  /// Case-insensitive substring of the execution name
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub name: Option<String>,
+
+  /// Only include executions with this status
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub status: Option<ExecutionStatus>,
+
+  /// Only include executions with this tag
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub tag: Option<String>,
+
+  /// Comma separated `key=value` metadata pairs that must all match
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub metadata: Option<String>,
+
+  /// Only include executions created at or after this time
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub created_after: Option<DateTime<Utc>>,
+
+  /// Only include executions created before this time
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub created_before: Option<DateTime<Utc>>,
+}
+
+impl ListExecutionsQuery {
+  /// The storage filter for this query. Empty parameters are ignored.
+  pub fn execution_filter(&self) -> Result<ExecutionFilter, AppError> {
+    let metadata = match self.metadata.as_deref().map(str::trim) {
+      Some(pairs) if !pairs.is_empty() => {
+        parse_metadata_pairs(pairs).map_err(AppError::BadRequest)?
+      }
+      _ => Default::default(),
+    };
+    let non_empty = |value: &Option<String>| {
+      value
+        .as_deref()
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(str::to_string)
+    };
+    Ok(ExecutionFilter {
+      project: self.project.clone(),
+      name: non_empty(&self.name),
+      status: self.status,
+      tag: non_empty(&self.tag),
+      metadata,
+      created_after: self.created_after,
+      created_before: self.created_before,
+    })
+  }
 }
 
 /// Response for listing executions
@@ -51,6 +159,13 @@ pub struct GetExecutionResponse {
   pub execution: Execution,
 }
 
+/// Response for getting the tree of an execution
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct GetExecutionTreeResponse {
+  /// Groups and observations of the execution, arranged by nesting
+  pub tree: ExecutionTree,
+}
+
 // ============================================================================
 // Observation API types
 // ============================================================================
@@ -66,6 +181,23 @@ pub struct CreateObservationsRequest {
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct CreateObservationsResponse {}
 
+/// Raw payload content, as sent in a request body
+#[derive(Debug, Clone, ToSchema)]
+#[schema(value_type = String, format = Binary)]
+pub struct PayloadContent(pub Vec<u8>);
+
+/// Response after uploading a payload's content
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PutPayloadResponse {
+  /// Size of the content before compression
+  pub size: u64,
+  /// Compression of the stored content, to declare in the manifest entry
+  pub content_encoding: String,
+  /// SHA-256 of the content before compression, to declare in the manifest
+  /// entry
+  pub digest: String,
+}
+
 /// Query parameters for listing observations
 #[derive(Debug, Clone, Serialize, Deserialize, Default, ToSchema, IntoParams)]
 pub struct ListObservationsQuery {
@@ -76,6 +208,78 @@ pub struct ListObservationsQuery {
   /// Number of results to skip (for pagination)
   #[serde(skip_serializing_if = "Option::is_none")]
   pub offset: Option<usize>,
+
+  /// Only include observations of this type
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub observation_type: Option<ObservationType>,
+
+  /// Only include observations at or above this log level
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub level: Option<LogLevel>,
+
+  /// Name prefix, or a glob pattern if it contains `*` or `?`
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub name: Option<String>,
+
+  /// Comma separated `key=value` metadata pairs that must all match
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub metadata: Option<String>,
+
+  /// Only include observations created at or after this time
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub created_after: Option<DateTime<Utc>>,
+
+  /// Only include observations created before this time
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub created_before: Option<DateTime<Utc>>,
+
+  /// Only include observations belonging to this group
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub group_id: Option<String>,
+
+  /// Only include observations from this source file, e.g. `src/main.rs`
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub source_file: Option<String>,
+}
+
+// ============================================================================
+// Search API types
+// ============================================================================
+
+/// Query parameters for searching observations
+#[derive(Debug, Clone, Serialize, Deserialize, Default, ToSchema, IntoParams)]
+pub struct SearchObservationsQuery {
+  /// Search terms, e.g. `timeout user_id=42 conn*`
+  pub q: String,
+
+  /// Maximum number of results to return
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub limit: Option<usize>,
+
+  /// Number of results to skip (for pagination)
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub offset: Option<usize>,
+}
+
+/// Query parameters for comparing two executions
+#[derive(Debug, Clone, Serialize, Deserialize, Default, ToSchema, IntoParams)]
+pub struct DiffExecutionsQuery {
+  /// ID of the execution to compare from, e.g. a passing run
+  pub left: String,
+
+  /// ID of the execution to compare to, e.g. a failing run
+  pub right: String,
+}
+
+/// Query parameters for comparing a payload against another one
+#[derive(Debug, Clone, Serialize, Deserialize, Default, ToSchema, IntoParams)]
+pub struct PayloadDiffQuery {
+  /// ID of the observation with the payload to compare against
+  pub with: String,
+
+  /// ID of the payload to compare against, by default the one with the same name
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub with_payload: Option<String>,
 }
 
 // ============================================================================
@@ -1,20 +1,169 @@
+//! API key authentication
+//!
+//! API keys are signed with the server's secret, so that they can be
+//! validated without a lookup. Keys created with `keys create` are also
+//! recorded in a key registry in the metadata store, which holds their scope,
+//! expiry and whether they were revoked. Keys missing from the registry were
+//! generated before it existed, or by `generate-key`, and keep the write
+//! access such keys always had until they are revoked.
+//!
+//! Reads only require a key when read authentication is enabled, see
+//! [`session`](crate::session). Execution prefixes of keys restrict writes
+//! only, while a key bound to a project may neither read nor write executions
+//! of other projects.
+
+use crate::storage::MetadataStorage;
+use crate::storage::StorageError;
 use axum::extract::Request;
 use axum::http::header::AUTHORIZATION;
+use axum::http::HeaderMap;
 use axum::http::StatusCode;
 use axum::middleware::Next;
 use axum::response::IntoResponse;
 use axum::response::Response;
 use base64::engine::general_purpose::URL_SAFE_NO_PAD;
 use base64::Engine as _;
+use chrono::DateTime;
+use chrono::Utc;
 use hmac::Hmac;
 use hmac::Mac;
+use observation_tools_shared::ProjectId;
+use serde::Deserialize;
+use serde::Serialize;
 use sha2::Sha256;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
 use std::sync::Once;
 use uuid::Uuid;
 
 #[derive(Debug, Clone)]
 pub struct ApiKeySecret(String);
 
+/// Identifies the API key a request was authenticated with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ApiKeyId(pub Uuid);
+
+/// What requests an API key may make
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApiKeyScope {
+  /// Read executions and observations
+  ReadOnly,
+  /// Create and update executions and observations
+  WriteOnly,
+  /// Read and write everything, including deleting executions
+  Admin,
+}
+
+impl ApiKeyScope {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      ApiKeyScope::ReadOnly => "read-only",
+      ApiKeyScope::WriteOnly => "write-only",
+      ApiKeyScope::Admin => "admin",
+    }
+  }
+
+  pub fn can_read(&self) -> bool {
+    matches!(self, ApiKeyScope::ReadOnly | ApiKeyScope::Admin)
+  }
+
+  pub fn can_write(&self) -> bool {
+    matches!(self, ApiKeyScope::WriteOnly | ApiKeyScope::Admin)
+  }
+}
+
+impl fmt::Display for ApiKeyScope {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
+impl FromStr for ApiKeyScope {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "read-only" => Ok(ApiKeyScope::ReadOnly),
+      "write-only" => Ok(ApiKeyScope::WriteOnly),
+      "admin" => Ok(ApiKeyScope::Admin),
+      _ => Err(format!(
+        "Invalid API key scope '{}', expected read-only, write-only or admin",
+        s
+      )),
+    }
+  }
+}
+
+/// An API key in the key registry. Added to the request extensions by
+/// [`api_key_middleware`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiKeyRecord {
+  pub id: Uuid,
+  pub name: String,
+  pub scope: ApiKeyScope,
+  /// Names of executions the key may access must start with one of these.
+  /// Any execution if empty.
+  #[serde(default)]
+  pub execution_prefixes: Vec<String>,
+  /// The only project the key may access. Any project if `None`.
+  #[serde(default)]
+  pub project: Option<ProjectId>,
+  pub created_at: DateTime<Utc>,
+  pub expires_at: Option<DateTime<Utc>>,
+  pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl ApiKeyRecord {
+  /// A record for a new key
+  pub fn new(name: impl Into<String>, scope: ApiKeyScope) -> Self {
+    ApiKeyRecord {
+      id: Uuid::new_v4(),
+      name: name.into(),
+      scope,
+      execution_prefixes: Vec::new(),
+      project: None,
+      created_at: Utc::now(),
+      expires_at: None,
+      revoked_at: None,
+    }
+  }
+
+  /// Stand-in for a valid key missing from the registry, which may only
+  /// write. Admin access requires a key created with `keys create`.
+  pub fn unregistered(id: Uuid) -> Self {
+    ApiKeyRecord {
+      id,
+      name: String::new(),
+      scope: ApiKeyScope::WriteOnly,
+      execution_prefixes: Vec::new(),
+      project: None,
+      created_at: DateTime::UNIX_EPOCH,
+      expires_at: None,
+      revoked_at: None,
+    }
+  }
+
+  /// Whether the key may access the execution named `name`
+  pub fn allows_execution(&self, name: &str) -> bool {
+    self.execution_prefixes.is_empty()
+      || self
+        .execution_prefixes
+        .iter()
+        .any(|prefix| name.starts_with(prefix.as_str()))
+  }
+
+  /// Whether the key may access executions in `project`
+  pub fn allows_project(&self, project: &ProjectId) -> bool {
+    self.project.as_ref().is_none_or(|p| p == project)
+  }
+
+  pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+    self.expires_at.is_some_and(|expires_at| expires_at <= now)
+  }
+}
+
 const MIN_SECRET_LENGTH: usize = 16;
 pub const ENV_API_KEY_SECRET: &str = "API_KEY_SECRET";
 
@@ -34,6 +183,27 @@ impl ApiKeySecret {
     }
     Ok(Self(secret.to_string()))
   }
+
+  /// A random secret, for signatures that need not outlive the process
+  pub(crate) fn random() -> Self {
+    Self(URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>()))
+  }
+
+  /// Sign `data` with the secret
+  pub(crate) fn sign(&self, data: &[u8]) -> Result<Vec<u8>, AuthError> {
+    let mut mac =
+      HmacSha256::new_from_slice(self.0.as_bytes()).map_err(|_| AuthError::HmacInitFailed)?;
+    mac.update(data);
+    Ok(mac.finalize().into_bytes().to_vec())
+  }
+
+  /// Check a signature created by [`ApiKeySecret::sign`]
+  pub(crate) fn verify(&self, data: &[u8], signature: &[u8]) -> Result<bool, AuthError> {
+    let mut mac =
+      HmacSha256::new_from_slice(self.0.as_bytes()).map_err(|_| AuthError::HmacInitFailed)?;
+    mac.update(data);
+    Ok(mac.verify_slice(signature).is_ok())
+  }
 }
 
 type HmacSha256 = Hmac<Sha256>;
@@ -50,6 +220,14 @@ pub enum AuthError {
   InvalidAuthFormat,
   #[error("Invalid API key")]
   InvalidApiKey,
+  #[error("API key has been revoked")]
+  RevokedApiKey,
+  #[error("API key has expired")]
+  ExpiredApiKey,
+  #[error("API key scope {0} does not allow this request")]
+  InsufficientScope(ApiKeyScope),
+  #[error("Failed to look up API key: {0}")]
+  Registry(#[from] StorageError),
   #[error("HMAC initialization failed")]
   HmacInitFailed,
 }
@@ -67,7 +245,17 @@ impl IntoResponse for AuthError {
         "Invalid Authorization header format",
       ),
       AuthError::InvalidApiKey => (StatusCode::UNAUTHORIZED, "Invalid API key"),
+      AuthError::RevokedApiKey => (StatusCode::UNAUTHORIZED, "API key has been revoked"),
+      AuthError::ExpiredApiKey => (StatusCode::UNAUTHORIZED, "API key has expired"),
+      AuthError::InsufficientScope(_) => (
+        StatusCode::FORBIDDEN,
+        "API key scope does not allow this request",
+      ),
       AuthError::HmacInitFailed => (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error"),
+      AuthError::Registry(ref err) => {
+        tracing::error!(error = %err, "Failed to look up API key");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+      }
     };
 
     (status, message).into_response()
@@ -76,13 +264,15 @@ impl IntoResponse for AuthError {
 
 static API_MIDDLEWARE_INIT_ONCE: Once = Once::new();
 
+/// Require an API key allowed to write, when authentication is enabled
 pub async fn api_key_middleware(
   secret: Option<ApiKeySecret>,
-  request: Request,
+  metadata: Arc<dyn MetadataStorage>,
+  mut request: Request,
   next: Next,
 ) -> Result<Response, AuthError> {
   API_MIDDLEWARE_INIT_ONCE.call_once(|| {
-    if let Some(_) = &secret {
+    if secret.is_some() {
       tracing::info!("API key authentication is enabled.");
     } else {
       tracing::warn!("API key authentication is disabled.");
@@ -90,54 +280,123 @@ pub async fn api_key_middleware(
   });
 
   if let Some(secret) = secret {
-    let auth_header = request
-      .headers()
-      .get(AUTHORIZATION)
-      .ok_or(AuthError::MissingAuthHeader)?
-      .to_str()
-      .map_err(|_| AuthError::InvalidAuthFormat)?;
-    let Some(token) = auth_header.strip_prefix("Bearer ") else {
-      return Err(AuthError::InvalidAuthFormat);
-    };
-    validate_api_key(&token, &secret)?;
+    let token = bearer_token(request.headers())?.ok_or(AuthError::MissingAuthHeader)?;
+    let key = authenticate(token, &secret, metadata.as_ref()).await?;
+    if !key.scope.can_write() {
+      return Err(AuthError::InsufficientScope(key.scope));
+    }
+    request.extensions_mut().insert(key);
   }
   Ok(next.run(request).await)
 }
 
-fn validate_api_key(api_key: &str, secret: &ApiKeySecret) -> Result<(), AuthError> {
-  if !api_key.starts_with(API_KEY_PREFIX) {
-    return Err(AuthError::InvalidApiKey);
+/// The API key in the Authorization header, if there is one
+pub(crate) fn bearer_token(headers: &HeaderMap) -> Result<Option<&str>, AuthError> {
+  let Some(auth_header) = headers.get(AUTHORIZATION) else {
+    return Ok(None);
+  };
+  let auth_header = auth_header
+    .to_str()
+    .map_err(|_| AuthError::InvalidAuthFormat)?;
+  match auth_header.strip_prefix("Bearer ") {
+    Some(token) => Ok(Some(token)),
+    None => Err(AuthError::InvalidAuthFormat),
+  }
+}
+
+/// Validate an API key and look it up in the key registry, rejecting revoked
+/// and expired keys
+pub async fn authenticate(
+  api_key: &str,
+  secret: &ApiKeySecret,
+  metadata: &dyn MetadataStorage,
+) -> Result<ApiKeyRecord, AuthError> {
+  let id = validate_api_key(api_key, secret)?;
+  let key = match metadata.get_api_key(id).await {
+    Ok(key) => key,
+    Err(StorageError::NotFound(_)) => ApiKeyRecord::unregistered(id),
+    Err(e) => return Err(e.into()),
+  };
+  if key.revoked_at.is_some() {
+    return Err(AuthError::RevokedApiKey);
   }
+  if key.is_expired(Utc::now()) {
+    return Err(AuthError::ExpiredApiKey);
+  }
+  Ok(key)
+}
 
-  let encoded = &api_key[API_KEY_PREFIX.len()..];
-  let payload = URL_SAFE_NO_PAD
+/// Split an API key into its ID and signature, without validating it
+fn decode_api_key(api_key: &str) -> Result<(Uuid, Vec<u8>), AuthError> {
+  let encoded = api_key
+    .strip_prefix(API_KEY_PREFIX)
+    .ok_or(AuthError::InvalidApiKey)?;
+  let mut payload = URL_SAFE_NO_PAD
     .decode(encoded)
     .map_err(|_| AuthError::InvalidApiKey)?;
-
   if payload.len() < 16 + 32 {
     return Err(AuthError::InvalidApiKey);
   }
+  let signature = payload.split_off(16);
+  let uuid = Uuid::from_slice(&payload).map_err(|_| AuthError::InvalidApiKey)?;
+  Ok((uuid, signature))
+}
 
-  let uuid_bytes = &payload[..16];
-  let provided_signature = &payload[16..];
-
-  let mut mac =
-    HmacSha256::new_from_slice(secret.0.as_bytes()).map_err(|_| AuthError::HmacInitFailed)?;
-  mac.update(uuid_bytes);
-  mac
-    .verify_slice(provided_signature)
-    .map_err(|_| AuthError::InvalidApiKey)?;
+/// The ID of an API key, e.g. to revoke a leaked key. Does not check the
+/// signature.
+pub fn api_key_id(api_key: &str) -> Result<Uuid, AuthError> {
+  Ok(decode_api_key(api_key)?.0)
+}
 
-  Ok(())
+fn validate_api_key(api_key: &str, secret: &ApiKeySecret) -> Result<Uuid, AuthError> {
+  let (uuid, provided_signature) = decode_api_key(api_key)?;
+  if !secret.verify(uuid.as_bytes(), &provided_signature)? {
+    return Err(AuthError::InvalidApiKey);
+  }
+  Ok(uuid)
 }
 
+/// Generate an API key that is not recorded in the key registry
 pub fn generate_api_key(secret: &ApiKeySecret) -> Result<String, AuthError> {
-  let uuid = Uuid::new_v4();
+  sign_api_key(Uuid::new_v4(), secret)
+}
+
+/// Generate an API key for `record` and add it to the key registry
+pub async fn create_api_key(
+  record: &ApiKeyRecord,
+  secret: &ApiKeySecret,
+  metadata: &dyn MetadataStorage,
+) -> Result<String, AuthError> {
+  let api_key = sign_api_key(record.id, secret)?;
+  metadata.store_api_key(record).await?;
+  Ok(api_key)
+}
+
+/// Revoke the API key with the given ID. Keys missing from the registry are
+/// added to it as revoked.
+pub async fn revoke_api_key(
+  id: Uuid,
+  metadata: &dyn MetadataStorage,
+) -> Result<ApiKeyRecord, AuthError> {
+  let mut key = match metadata.get_api_key(id).await {
+    Ok(key) => key,
+    Err(StorageError::NotFound(_)) => ApiKeyRecord {
+      name: "unregistered".to_string(),
+      created_at: Utc::now(),
+      ..ApiKeyRecord::unregistered(id)
+    },
+    Err(e) => return Err(e.into()),
+  };
+  if key.revoked_at.is_none() {
+    key.revoked_at = Some(Utc::now());
+    metadata.store_api_key(&key).await?;
+  }
+  Ok(key)
+}
+
+fn sign_api_key(uuid: Uuid, secret: &ApiKeySecret) -> Result<String, AuthError> {
   let uuid_bytes = uuid.as_bytes();
-  let mut mac =
-    HmacSha256::new_from_slice(secret.0.as_bytes()).map_err(|_| AuthError::HmacInitFailed)?;
-  mac.update(uuid_bytes);
-  let signature = mac.finalize().into_bytes();
+  let signature = secret.sign(uuid_bytes)?;
   let mut payload = Vec::new();
   payload.extend_from_slice(uuid_bytes);
   payload.extend_from_slice(&signature);
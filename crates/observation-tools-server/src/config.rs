@@ -2,8 +2,30 @@
 
 use crate::auth::ApiKeySecret;
 use directories::ProjectDirs;
+use observation_tools_shared::ContentEncoding;
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::time::Duration;
+
+/// Environment variable for the blob store URL
+pub const ENV_BLOB_URL: &str = "OBSERVATION_TOOLS_BLOB_URL";
+
+/// Environment variable for the metadata database URL
+pub const ENV_METADATA_URL: &str = "OBSERVATION_TOOLS_METADATA_URL";
+
+/// Default interval between retention sweeps
+pub const DEFAULT_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Default time an unreferenced content-addressed blob is kept before it is
+/// collected
+pub const DEFAULT_BLOB_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+/// Default time without new data after which a running execution is
+/// considered abandoned
+pub const DEFAULT_ABANDONED_AFTER: Duration = Duration::from_secs(60 * 60);
+
+/// Default time a UI login lasts
+pub const DEFAULT_SESSION_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
 
 /// Server configuration
 #[derive(Debug, Clone)]
@@ -14,11 +36,118 @@ pub struct Config {
   /// Directory for storing data
   pub data_dir: PathBuf,
 
-  /// Directory for storing blobs
+  /// Directory for storing blobs, used when `blob_url` is not set
   pub blob_dir: PathBuf,
 
+  /// Object store URL for storing blobs, e.g. `s3://bucket/prefix`. See
+  /// [`ObjectStoreBlobStorage::from_url`](crate::storage::ObjectStoreBlobStorage::from_url)
+  /// for supported schemes.
+  pub blob_url: Option<String>,
+
+  /// SQL database URL for metadata, e.g. `sqlite:///var/lib/ot/metadata.db`
+  /// or `postgres://user@host/db`. Uses the sled store in the data directory
+  /// when not set.
+  pub metadata_url: Option<String>,
+
   /// Optional API secret for authentication
   pub api_secret: Option<ApiKeySecret>,
+
+  /// Retention policy for stored executions
+  pub retention: RetentionConfig,
+
+  /// Mark running executions as abandoned after receiving no data for this
+  /// long. `None` or zero disables detection.
+  pub abandoned_after: Option<Duration>,
+
+  /// Compression for newly stored payloads. Payloads stored earlier keep
+  /// their encoding.
+  pub payload_encoding: ContentEncoding,
+
+  /// Limits on the data clients may upload
+  pub quotas: QuotaConfig,
+
+  /// Require authentication to read executions and observations. Requires
+  /// `api_secret`.
+  pub read_auth: Option<ReadAuthConfig>,
+}
+
+/// Retention policy for stored executions
+///
+/// Executions exceeding any of the limits are deleted, oldest first, along
+/// with their observations and payloads. Without limits no executions are
+/// deleted, but blobs no longer referenced by any payload are still collected.
+#[derive(Debug, Clone)]
+pub struct RetentionConfig {
+  /// Delete executions created longer ago than this
+  pub max_age: Option<Duration>,
+
+  /// Keep at most this many executions
+  pub max_executions: Option<usize>,
+
+  /// Keep at most this many payload bytes across all executions
+  pub max_total_bytes: Option<u64>,
+
+  /// How often the background sweeper runs
+  pub sweep_interval: Duration,
+
+  /// How long unreferenced blobs are kept, so that content uploaded for an
+  /// observation that is not stored yet survives
+  pub blob_grace_period: Duration,
+}
+
+impl RetentionConfig {
+  /// Whether any retention limit is configured
+  pub fn is_enabled(&self) -> bool {
+    self.max_age.is_some() || self.max_executions.is_some() || self.max_total_bytes.is_some()
+  }
+}
+
+impl Default for RetentionConfig {
+  fn default() -> Self {
+    Self {
+      max_age: None,
+      max_executions: None,
+      max_total_bytes: None,
+      sweep_interval: DEFAULT_SWEEP_INTERVAL,
+      blob_grace_period: DEFAULT_BLOB_GRACE_PERIOD,
+    }
+  }
+}
+
+/// Limits on the data clients may upload, enforced when observations are
+/// created. Unset limits are not enforced.
+#[derive(Debug, Clone, Default)]
+pub struct QuotaConfig {
+  /// Store at most this many observations per execution
+  pub max_observations_per_execution: Option<u64>,
+
+  /// Store at most this many payload bytes per execution
+  pub max_bytes_per_execution: Option<u64>,
+
+  /// Accept at most this many payload bytes per API key and UTC day
+  pub max_bytes_per_api_key_per_day: Option<u64>,
+}
+
+/// Authentication for the read-only API and the UI
+///
+/// API clients authenticate with a key allowed to read. UI users log in with
+/// a password from the users file, see [`Users`](crate::session::Users).
+#[derive(Debug, Clone)]
+pub struct ReadAuthConfig {
+  /// Users who may log in to the UI. Without it only API keys are accepted.
+  pub users_file: Option<PathBuf>,
+
+  /// How long a UI login lasts
+  pub session_lifetime: Duration,
+}
+
+impl Default for ReadAuthConfig {
+  fn default() -> Self {
+    Self {
+      users_file: None,
+      session_lifetime: DEFAULT_SESSION_LIFETIME,
+    }
+  }
 }
 
 impl Config {
@@ -33,7 +162,14 @@ impl Config {
       bind_addr: "127.0.0.1:3000".parse().unwrap(),
       data_dir,
       blob_dir,
+      blob_url: None,
+      metadata_url: None,
       api_secret: None,
+      retention: RetentionConfig::default(),
+      abandoned_after: Some(DEFAULT_ABANDONED_AFTER),
+      payload_encoding: ContentEncoding::Identity,
+      quotas: QuotaConfig::default(),
+      read_auth: None,
     }
   }
 
@@ -52,11 +188,95 @@ impl Config {
     self
   }
 
+  /// Store blobs in an object store instead of the blob directory. If `None`,
+  /// keeps the current setting.
+  pub fn with_blob_url(mut self, url: Option<String>) -> Self {
+    if let Some(url) = url {
+      self.blob_url = Some(url);
+    }
+    self
+  }
+
+  /// Store metadata in a SQL database instead of the data directory. If
+  /// `None`, keeps the current setting.
+  pub fn with_metadata_url(mut self, url: Option<String>) -> Self {
+    if let Some(url) = url {
+      self.metadata_url = Some(url);
+    }
+    self
+  }
+
   /// Set the API secret for authentication
   pub fn with_api_secret(mut self, secret: Option<ApiKeySecret>) -> Self {
     self.api_secret = secret;
     self
   }
+
+  /// Set the retention policy
+  pub fn with_retention(mut self, retention: RetentionConfig) -> Self {
+    self.retention = retention;
+    self
+  }
+
+  /// Set how long a running execution may go without data before it is
+  /// marked as abandoned. `None` disables detection.
+  pub fn with_abandoned_after(mut self, abandoned_after: Option<Duration>) -> Self {
+    self.abandoned_after = abandoned_after;
+    self
+  }
+
+  /// Compress payloads at rest with this encoding
+  pub fn with_payload_encoding(mut self, encoding: ContentEncoding) -> Self {
+    self.payload_encoding = encoding;
+    self
+  }
+
+  /// Set the upload quotas
+  pub fn with_quotas(mut self, quotas: QuotaConfig) -> Self {
+    self.quotas = quotas;
+    self
+  }
+
+  /// Require authentication for reads. `None` leaves reads open.
+  pub fn with_read_auth(mut self, read_auth: Option<ReadAuthConfig>) -> Self {
+    self.read_auth = read_auth;
+    self
+  }
+}
+
+/// Parse a duration such as `90s`, `30m`, `12h` or `7d`
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+  let s = s.trim();
+  let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+  let (value, unit) = s.split_at(split);
+  let value: u64 = value
+    .parse()
+    .map_err(|_| format!("Invalid duration '{}'", s))?;
+  let seconds = match unit {
+    "s" | "" => 1,
+    "m" => 60,
+    "h" => 60 * 60,
+    "d" => 24 * 60 * 60,
+    _ => return Err(format!("Invalid duration unit '{}', expected s, m, h or d", unit)),
+  };
+  Ok(Duration::from_secs(value * seconds))
+}
+
+/// Parse a byte size such as `500000`, `512K`, `100M` or `10G` (binary units)
+pub fn parse_byte_size(s: &str) -> Result<u64, String> {
+  let s = s.trim();
+  let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+  let (value, unit) = s.split_at(split);
+  let value: u64 = value.parse().map_err(|_| format!("Invalid size '{}'", s))?;
+  let multiplier: u64 = match unit.to_ascii_uppercase().as_str() {
+    "" | "B" => 1,
+    "K" | "KB" | "KIB" => 1 << 10,
+    "M" | "MB" | "MIB" => 1 << 20,
+    "G" | "GB" | "GIB" => 1 << 30,
+    "T" | "TB" | "TIB" => 1 << 40,
+    _ => return Err(format!("Invalid size unit '{}', expected K, M, G or T", unit)),
+  };
+  Ok(value * multiplier)
 }
 
 impl Default for Config {
@@ -1,23 +1,178 @@
+//! CSRF protection for browser requests
+//!
+//! Uses the [signed double-submit cookie pattern](https://cheatsheetseries.owasp.org/cheatsheets/Cross-Site_Request_Forgery_Prevention_Cheat_Sheet.html#signed-double-submit-cookie-recommended):
+//! UI pages set a random token, signed together with the session cookie, as
+//! a cookie and render it into the page, and htmx sends it back in the
+//! `X-CSRF-Token` header. Mutating requests must also come from the server's
+//! own origin, according to their Origin or Referer header.
+//!
+//! Requests with a bearer API key are exempt, since browsers do not send the
+//! key by themselves. So are requests without any cookies, which carry no
+//! credentials to abuse.
+
+use crate::auth::ApiKeySecret;
+use crate::session::SESSION_COOKIE_NAME;
 use axum::extract::FromRequestParts;
 use axum::extract::Request;
 use axum::http::header;
+use axum::http::HeaderMap;
 use axum::http::StatusCode;
 use axum::middleware::Next;
 use axum::response::IntoResponse;
 use axum::response::Response;
 use axum_extra::extract::cookie::Cookie;
 use axum_extra::extract::cookie::SameSite;
-use uuid::Uuid;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
 
 pub const CSRF_COOKIE_NAME: &str = "csrf_token";
 pub const CSRF_HEADER_NAME: &str = "x-csrf-token";
 
-/// Middleware to validate CSRF tokens using the [naive double-submit cookie pattern](https://cheatsheetseries.owasp.org/cheatsheets/Cross-Site_Request_Forgery_Prevention_Cheat_Sheet.html#naive-double-submit-cookie-pattern-discouraged)
-///
-/// TODO(doug): Upgrade to signed double-submit cookies
-/// TODO(doug): Verify origin/header for additional security
-pub async fn validate_csrf(req: Request, next: Next) -> Response {
-  // Only validate POST, PUT, DELETE, PATCH methods
+/// Keeps CSRF token signatures apart from other signatures
+const CSRF_SIGNATURE_CONTEXT: &[u8] = b"observation-tools csrf\0";
+
+/// Signs and checks CSRF tokens
+#[derive(Debug, Clone)]
+pub struct CsrfSecret(ApiKeySecret);
+
+impl CsrfSecret {
+  /// Sign tokens with the API key secret, so that they stay valid across
+  /// restarts, or with a random secret when there is none
+  pub fn new(api_secret: Option<ApiKeySecret>) -> Self {
+    CsrfSecret(api_secret.unwrap_or_else(ApiKeySecret::random))
+  }
+
+  /// A new token for the session with the given cookie value
+  pub fn generate(&self, session: &str) -> String {
+    let nonce = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 16]>());
+    let signature = self
+      .0
+      .sign(&Self::signed_data(session, &nonce))
+      .expect("HMAC accepts keys of any length");
+    format!("{}.{}", nonce, URL_SAFE_NO_PAD.encode(signature))
+  }
+
+  /// Whether `token` was generated for the session with the given cookie
+  /// value
+  pub fn is_valid(&self, token: &str, session: &str) -> bool {
+    let Some((nonce, signature)) = token.split_once('.') else {
+      return false;
+    };
+    let Ok(signature) = URL_SAFE_NO_PAD.decode(signature) else {
+      return false;
+    };
+    self
+      .0
+      .verify(&Self::signed_data(session, nonce), &signature)
+      .unwrap_or(false)
+  }
+
+  fn signed_data(session: &str, nonce: &str) -> Vec<u8> {
+    [
+      CSRF_SIGNATURE_CONTEXT,
+      session.as_bytes(),
+      b"\0",
+      nonce.as_bytes(),
+    ]
+    .concat()
+  }
+
+  /// Check a mutating request. Forms pass their token as `form_token`, other
+  /// requests in the CSRF header.
+  pub fn check(&self, headers: &HeaderMap, form_token: Option<&str>) -> Result<(), CsrfError> {
+    if headers
+      .get(header::AUTHORIZATION)
+      .and_then(|v| v.to_str().ok())
+      .is_some_and(|v| v.starts_with("Bearer "))
+    {
+      tracing::debug!("CSRF validation skipped: bearer authentication");
+      return Ok(());
+    }
+    check_origin(headers)?;
+
+    let cookie_token = cookie_value(headers, CSRF_COOKIE_NAME);
+    let session = cookie_value(headers, SESSION_COOKIE_NAME);
+    if cookie_token.is_none() && session.is_none() {
+      tracing::debug!("CSRF validation skipped: no cookie present (programmatic client)");
+      return Ok(());
+    }
+    let token = form_token.or_else(|| {
+      headers
+        .get(CSRF_HEADER_NAME)
+        .and_then(|v| v.to_str().ok())
+    });
+    let Some(token) = token else {
+      return Err(CsrfError::MissingToken);
+    };
+    if cookie_token.as_deref() != Some(token) {
+      return Err(CsrfError::TokenMismatch);
+    }
+    if !self.is_valid(token, session.as_deref().unwrap_or_default()) {
+      return Err(CsrfError::InvalidToken);
+    }
+    Ok(())
+  }
+}
+
+/// Why a mutating request was rejected
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum CsrfError {
+  #[error("Cross-origin request from '{0}'")]
+  CrossOrigin(String),
+  #[error("CSRF token missing in request header. Please include X-CSRF-Token header.")]
+  MissingToken,
+  #[error("CSRF token mismatch. Please refresh the page and try again.")]
+  TokenMismatch,
+  #[error("Invalid CSRF token. Please refresh the page and try again.")]
+  InvalidToken,
+}
+
+impl IntoResponse for CsrfError {
+  fn into_response(self) -> Response {
+    tracing::warn!(error = %self, "CSRF validation failed");
+    (StatusCode::FORBIDDEN, self.to_string()).into_response()
+  }
+}
+
+/// Require the Origin header, or the Referer when there is no Origin, to
+/// match the Host. Requests with neither are allowed, as non-browser clients
+/// send neither.
+fn check_origin(headers: &HeaderMap) -> Result<(), CsrfError> {
+  let Some(source) = headers
+    .get(header::ORIGIN)
+    .or_else(|| headers.get(header::REFERER))
+  else {
+    return Ok(());
+  };
+  let source = String::from_utf8_lossy(source.as_bytes());
+  let source_host = url::Url::parse(&source).ok().and_then(|url| {
+    let host = url.host_str()?;
+    Some(match url.port() {
+      Some(port) => format!("{}:{}", host, port),
+      None => host.to_string(),
+    })
+  });
+  let host = headers.get(header::HOST).and_then(|v| v.to_str().ok());
+  match (source_host, host) {
+    (Some(source_host), Some(host)) if source_host.eq_ignore_ascii_case(host) => Ok(()),
+    _ => Err(CsrfError::CrossOrigin(source.into_owned())),
+  }
+}
+
+/// The value of the named cookie in the request
+pub(crate) fn cookie_value(headers: &HeaderMap, name: &str) -> Option<String> {
+  headers
+    .get_all(header::COOKIE)
+    .iter()
+    .filter_map(|v| v.to_str().ok())
+    .flat_map(|cookies| Cookie::split_parse(cookies).flatten())
+    .find(|cookie| cookie.name() == name)
+    .map(|cookie| cookie.value().to_string())
+}
+
+/// Middleware to validate CSRF tokens of POST, PUT, DELETE and PATCH
+/// requests, see [`CsrfSecret::check`]
+pub async fn validate_csrf(secret: CsrfSecret, req: Request, next: Next) -> Response {
   let method = req.method();
   if !matches!(
     method,
@@ -29,56 +184,9 @@ pub async fn validate_csrf(req: Request, next: Next) -> Response {
     return next.run(req).await;
   }
 
-  let cookie_token = req
-    .headers()
-    .get(header::COOKIE)
-    .and_then(|v| v.to_str().ok())
-    .and_then(|cookies_str| {
-      Cookie::split_parse(cookies_str)
-        .flatten()
-        .find(|cookie| cookie.name() == CSRF_COOKIE_NAME)
-        .map(|cookie| cookie.value().to_string())
-    });
-  let header_token = req
-    .headers()
-    .get(CSRF_HEADER_NAME)
-    .and_then(|v| v.to_str().ok())
-    .map(|s| s.to_string());
-
-  // Lenient mode: only validate if cookie is present
-  match (cookie_token, header_token) {
-    // No cookie = programmatic client, allow it
-    (None, _) => {
-      tracing::debug!("CSRF validation skipped: no cookie present (programmatic client)");
-      next.run(req).await
-    }
-    // Cookie present but no header = potential CSRF attack
-    (Some(_), None) => {
-      tracing::warn!("CSRF validation failed: cookie present but missing header token");
-      (
-        StatusCode::FORBIDDEN,
-        "CSRF token missing in request header. Please include X-CSRF-Token header.",
-      )
-        .into_response()
-    }
-    // Both present, check they match
-    (Some(cookie), Some(header)) if cookie == header => {
-      tracing::debug!("CSRF token validated successfully");
-      next.run(req).await
-    }
-    // Both present but don't match = potential CSRF attack
-    (Some(cookie), Some(header)) => {
-      tracing::warn!(
-        "CSRF validation failed: token mismatch (cookie length: {}, header length: {})",
-        cookie.len(),
-        header.len()
-      );
-      (
-        StatusCode::FORBIDDEN,
-        "CSRF token mismatch. Please refresh the page and try again.",
-      )
-        .into_response()
-    }
+  match secret.check(req.headers(), None) {
+    Ok(()) => next.run(req).await,
+    Err(e) => e.into_response(),
   }
 }
 
@@ -109,19 +217,199 @@ where
   }
 }
 
-/// Middleware for UI routes that automatically generates and sets CSRF tokens
-pub async fn ui_csrf_middleware(mut req: Request, next: Next) -> Response {
-  let token = Uuid::new_v4().as_simple().to_string();
+/// Middleware for UI routes that provides a CSRF token, reusing the one in
+/// the request's cookie while it is valid for the session. Rotating it on
+/// every request would invalidate the token of pages already loaded.
+pub async fn ui_csrf_middleware(secret: CsrfSecret, mut req: Request, next: Next) -> Response {
+  let session = cookie_value(req.headers(), SESSION_COOKIE_NAME).unwrap_or_default();
+  let existing = cookie_value(req.headers(), CSRF_COOKIE_NAME)
+    .filter(|token| secret.is_valid(token, &session));
+  let token = existing
+    .clone()
+    .unwrap_or_else(|| secret.generate(&session));
   req.extensions_mut().insert(CsrfToken(token.clone()));
   let mut response = next.run(req).await;
-  let cookie = Cookie::build((CSRF_COOKIE_NAME, token.to_string()))
-    .path("/")
-    .same_site(SameSite::Strict)
-    .http_only(true)
-    .build();
-  response.headers_mut().insert(
-    header::SET_COOKIE,
-    cookie.to_string().parse().expect("valid cookie"),
-  );
+  if existing.is_none() {
+    let cookie = Cookie::build((CSRF_COOKIE_NAME, token))
+      .path("/")
+      .same_site(SameSite::Strict)
+      .http_only(true)
+      .build();
+    response.headers_mut().append(
+      header::SET_COOKIE,
+      cookie.to_string().parse().expect("valid cookie"),
+    );
+  }
   response
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn secret() -> CsrfSecret {
+    CsrfSecret::new(Some(ApiKeySecret::new("test-secret-key-for-csrf").unwrap()))
+  }
+
+  fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::HOST, "localhost:3000".parse().unwrap());
+    for (name, value) in pairs {
+      headers.append(
+        header::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+        value.parse().unwrap(),
+      );
+    }
+    headers
+  }
+
+  #[test]
+  fn test_valid_tokens() {
+    let secret = secret();
+    let token = secret.generate("");
+    let cookie = format!("{}={}", CSRF_COOKIE_NAME, token);
+    assert_eq!(
+      secret.check(&headers(&[("cookie", &cookie), (CSRF_HEADER_NAME, &token)]), None),
+      Ok(())
+    );
+    assert_eq!(
+      secret.check(&headers(&[("cookie", &cookie)]), Some(&token)),
+      Ok(())
+    );
+    assert_eq!(
+      secret.check(
+        &headers(&[
+          ("cookie", &cookie),
+          (CSRF_HEADER_NAME, &token),
+          ("origin", "http://localhost:3000"),
+        ]),
+        None
+      ),
+      Ok(())
+    );
+
+    // Tokens are bound to the session
+    let token = secret.generate("session-value");
+    let cookies = format!(
+      "{}={}; {}=session-value",
+      CSRF_COOKIE_NAME, token, SESSION_COOKIE_NAME
+    );
+    assert_eq!(
+      secret.check(&headers(&[("cookie", &cookies), (CSRF_HEADER_NAME, &token)]), None),
+      Ok(())
+    );
+  }
+
+  #[test]
+  fn test_exempt_requests() {
+    let secret = secret();
+    // No cookies
+    assert_eq!(secret.check(&headers(&[]), None), Ok(()));
+    // Bearer authentication, even from another origin with a stale cookie
+    let request = headers(&[
+      ("authorization", "Bearer obs_key"),
+      ("origin", "https://evil.example"),
+      ("cookie", "csrf_token=stale"),
+    ]);
+    assert_eq!(secret.check(&request, None), Ok(()));
+  }
+
+  #[test]
+  fn test_cross_origin() {
+    let secret = secret();
+    let token = secret.generate("");
+    let cookie = format!("{}={}", CSRF_COOKIE_NAME, token);
+    for (name, value) in [
+      ("origin", "https://evil.example"),
+      ("origin", "http://localhost:3001"),
+      ("origin", "null"),
+      ("referer", "https://evil.example/page"),
+    ] {
+      let request = headers(&[(name, value), ("cookie", &cookie), (CSRF_HEADER_NAME, &token)]);
+      assert_eq!(
+        secret.check(&request, None),
+        Err(CsrfError::CrossOrigin(value.to_string())),
+        "{}: {}",
+        name,
+        value
+      );
+    }
+    // Cross-origin requests are rejected without cookies too
+    let request = headers(&[("origin", "https://evil.example")]);
+    assert!(matches!(
+      secret.check(&request, None),
+      Err(CsrfError::CrossOrigin(_))
+    ));
+    // A Referer from the same origin
+    let request = headers(&[
+      ("referer", "http://localhost:3000/exe"),
+      ("cookie", &cookie),
+      (CSRF_HEADER_NAME, &token),
+    ]);
+    assert_eq!(secret.check(&request, None), Ok(()));
+  }
+
+  #[test]
+  fn test_missing_token() {
+    let secret = secret();
+    let cookie = format!("{}={}", CSRF_COOKIE_NAME, secret.generate(""));
+    assert_eq!(
+      secret.check(&headers(&[("cookie", &cookie)]), None),
+      Err(CsrfError::MissingToken)
+    );
+    // A session cookie alone requires a token
+    let cookie = format!("{}=session-value", SESSION_COOKIE_NAME);
+    assert_eq!(
+      secret.check(&headers(&[("cookie", &cookie)]), None),
+      Err(CsrfError::MissingToken)
+    );
+  }
+
+  #[test]
+  fn test_token_mismatch() {
+    let secret = secret();
+    let cookie = format!("{}={}", CSRF_COOKIE_NAME, secret.generate(""));
+    let other = secret.generate("");
+    assert_eq!(
+      secret.check(&headers(&[("cookie", &cookie), (CSRF_HEADER_NAME, &other)]), None),
+      Err(CsrfError::TokenMismatch)
+    );
+    // A token without its cookie
+    let cookie = format!("{}=session-value", SESSION_COOKIE_NAME);
+    let token = secret.generate("session-value");
+    assert_eq!(
+      secret.check(&headers(&[("cookie", &cookie), (CSRF_HEADER_NAME, &token)]), None),
+      Err(CsrfError::TokenMismatch)
+    );
+  }
+
+  #[test]
+  fn test_invalid_token() {
+    let secret = secret();
+    let check = |token: &str, session: Option<&str>| {
+      let mut cookies = format!("{}={}", CSRF_COOKIE_NAME, token);
+      if let Some(session) = session {
+        cookies += &format!("; {}={}", SESSION_COOKIE_NAME, session);
+      }
+      secret.check(&headers(&[("cookie", &cookies), (CSRF_HEADER_NAME, token)]), None)
+    };
+    // Unsigned, as set by the naive double-submit pattern
+    assert_eq!(
+      check("0123456789abcdef0123456789abcdef", None),
+      Err(CsrfError::InvalidToken)
+    );
+    // Signed with another secret
+    let other = CsrfSecret::new(Some(ApiKeySecret::new("another-secret-for-csrf").unwrap()));
+    assert_eq!(check(&other.generate(""), None), Err(CsrfError::InvalidToken));
+    // Signed for another session
+    let token = secret.generate("session-value");
+    assert_eq!(check(&token, Some("other-session")), Err(CsrfError::InvalidToken));
+    assert_eq!(check(&token, None), Err(CsrfError::InvalidToken));
+    // Tampered nonce
+    let (_, signature) = token.split_once('.').unwrap();
+    assert_eq!(
+      check(&format!("tampered.{}", signature), Some("session-value")),
+      Err(CsrfError::InvalidToken)
+    );
+  }
+}
@@ -0,0 +1,577 @@
+//! Comparing observations and payloads of two executions
+//!
+//! Observations are aligned by [`ObservationKey`], since their IDs differ
+//! between runs. JSON payloads are compared structurally and text payloads
+//! line by line.
+
+use crate::debug_parser::parse_debug_to_json;
+use crate::ui::parse_json_value;
+use observation_tools_shared::Observation;
+use observation_tools_shared::ObservationType;
+use observation_tools_shared::MIME_TYPE_RUST_DEBUG;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+use similar::capture_diff_slices;
+use similar::Algorithm;
+use similar::ChangeTag;
+use similar::DiffTag;
+use similar::TextDiff;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::ops::Range;
+use utoipa::ToSchema;
+
+/// Unchanged lines shown around each change of a text diff
+const CONTEXT_LINES: usize = 3;
+
+/// How an item differs between the left and the right side of a diff
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub enum DiffStatus {
+  /// Only on the right side
+  Added,
+  /// Only on the left side
+  Removed,
+  /// On both sides, with differences
+  Changed,
+  /// On both sides, without differences
+  Unchanged,
+  /// Moved to another position, for elements of JSON arrays
+  Moved,
+}
+
+/// What observations are matched by across executions
+///
+/// Observations created repeatedly at the same place are told apart by the
+/// order they were created in.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub struct ObservationKey {
+  /// Names of the groups containing the observation, outermost first
+  pub group_path: Vec<String>,
+
+  /// Name of the observation
+  pub name: String,
+
+  /// Source location as `file:line`
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub source: Option<String>,
+
+  /// Number of observations with the same key created before this one
+  pub occurrence: usize,
+}
+
+/// A pair of observations aligned by their key, as indices into the left
+/// and right observations
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlignedObservation {
+  pub key: ObservationKey,
+  pub left: Option<usize>,
+  pub right: Option<usize>,
+}
+
+/// A change at one location of a JSON document
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct JsonChange {
+  /// JSON pointer to the changed value, empty for the whole document
+  pub path: String,
+
+  /// Whether the value was added, removed, changed or moved
+  pub status: DiffStatus,
+
+  /// JSON pointer to where a moved value was on the left side
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub from: Option<String>,
+
+  /// Value on the left side
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub left: Option<Value>,
+
+  /// Value on the right side
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub right: Option<Value>,
+}
+
+/// A line of a text diff
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct LineChange {
+  /// Whether the line was added, removed or is unchanged context
+  pub status: DiffStatus,
+
+  /// Line number on the left side, starting at 1
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub left_line: Option<usize>,
+
+  /// Line number on the right side, starting at 1
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub right_line: Option<usize>,
+
+  /// Line content, without the line break
+  pub text: String,
+}
+
+/// Differences between the contents of two payloads
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub enum PayloadDiff {
+  /// Changes between two JSON documents
+  Json(Vec<JsonChange>),
+  /// Changed lines between two texts, with a few lines of context
+  Text(Vec<LineChange>),
+  /// Binary payloads, and payloads too large to display, are only compared
+  /// as a whole
+  Binary,
+}
+
+/// Compute the alignment key of every observation of an execution, in order
+pub fn observation_keys(observations: &[Observation]) -> Vec<ObservationKey> {
+  let groups: HashMap<_, _> = observations
+    .iter()
+    .filter(|o| o.observation_type == ObservationType::Group)
+    .filter_map(|o| Some((o.group_ids.first()?, o)))
+    .collect();
+  let mut occurrences = HashMap::new();
+  observations
+    .iter()
+    .map(|observation| {
+      let mut group_path = Vec::new();
+      let mut group_id = match observation.observation_type {
+        ObservationType::Group => observation.parent_group_id.as_ref(),
+        _ => observation.group_ids.first(),
+      };
+      // Group IDs are user provided, so guard against cycles
+      let mut seen = HashSet::new();
+      while let Some(group) = group_id.and_then(|id| groups.get(id)) {
+        if !seen.insert(group.id) {
+          break;
+        }
+        group_path.push(group.name.clone());
+        group_id = group.parent_group_id.as_ref();
+      }
+      group_path.reverse();
+      let mut key = ObservationKey {
+        group_path,
+        name: observation.name.clone(),
+        source: observation
+          .source
+          .as_ref()
+          .map(|source| format!("{}:{}", source.file, source.line)),
+        occurrence: 0,
+      };
+      let occurrence = occurrences.entry(key.clone()).or_insert(0);
+      key.occurrence = *occurrence;
+      *occurrence += 1;
+      key
+    })
+    .collect()
+}
+
+/// Align the observations of two executions by their keys
+///
+/// The result follows the order of the right side, with observations only on
+/// the left placed before the first observation that comes after them there.
+pub fn align_observations(
+  left: &[Observation],
+  right: &[Observation],
+) -> Vec<AlignedObservation> {
+  let left_keys = observation_keys(left);
+  let left_indices: HashMap<_, _> = left_keys.iter().enumerate().map(|(i, k)| (k, i)).collect();
+  let right_keys = observation_keys(right);
+  let left_matches: Vec<_> = right_keys.iter().map(|k| left_indices.get(k).copied()).collect();
+  // Whether each left observation is matched or already placed
+  let mut placed = vec![false; left.len()];
+  for &left_index in left_matches.iter().flatten() {
+    placed[left_index] = true;
+  }
+  let mut aligned = Vec::new();
+  let mut next_left = 0;
+  for ((right_index, key), left_index) in right_keys.into_iter().enumerate().zip(left_matches) {
+    if let Some(left_index) = left_index {
+      while next_left < left_index {
+        if !placed[next_left] {
+          placed[next_left] = true;
+          aligned.push(AlignedObservation {
+            key: left_keys[next_left].clone(),
+            left: Some(next_left),
+            right: None,
+          });
+        }
+        next_left += 1;
+      }
+    }
+    aligned.push(AlignedObservation {
+      key,
+      left: left_index,
+      right: Some(right_index),
+    });
+  }
+  for (left_index, key) in left_keys.into_iter().enumerate() {
+    if !placed[left_index] {
+      aligned.push(AlignedObservation {
+        key,
+        left: Some(left_index),
+        right: None,
+      });
+    }
+  }
+  aligned
+}
+
+/// Compare two JSON documents value by value
+///
+/// Objects are compared by key. Array elements are aligned by value, so
+/// inserting an element is not reported as changing every element after it,
+/// and elements that only changed position are reported as moved. Paths refer
+/// to the right document, except for removed values.
+pub fn diff_json(left: &Value, right: &Value) -> Vec<JsonChange> {
+  let mut changes = Vec::new();
+  diff_json_value("", left, right, &mut changes);
+  changes
+}
+
+fn diff_json_value(path: &str, left: &Value, right: &Value, changes: &mut Vec<JsonChange>) {
+  match (left, right) {
+    (Value::Object(left), Value::Object(right)) => {
+      for (key, left_value) in left {
+        let path = child_path(path, key);
+        match right.get(key) {
+          Some(right_value) => diff_json_value(&path, left_value, right_value, changes),
+          None => changes.push(json_change(path, Some(left_value), None)),
+        }
+      }
+      for (key, right_value) in right {
+        if !left.contains_key(key) {
+          changes.push(json_change(child_path(path, key), None, Some(right_value)));
+        }
+      }
+    }
+    (Value::Array(left), Value::Array(right)) => diff_json_array(path, left, right, changes),
+    _ if left == right => {}
+    _ => changes.push(json_change(path.to_string(), Some(left), Some(right))),
+  }
+}
+
+/// Align array elements by value. Elements removed at one place and added at
+/// another are moves, and the remaining removed and added elements of each
+/// changed range are compared pairwise.
+fn diff_json_array(path: &str, left: &[Value], right: &[Value], changes: &mut Vec<JsonChange>) {
+  // Serialized objects have sorted keys, so equal values serialize equally
+  let left_keys: Vec<_> = left.iter().map(Value::to_string).collect();
+  let right_keys: Vec<_> = right.iter().map(Value::to_string).collect();
+  // Changed ranges, merging adjacent removals and additions
+  let mut ranges: Vec<(Range<usize>, Range<usize>)> = Vec::new();
+  let mut after_equal = true;
+  for op in capture_diff_slices(Algorithm::Myers, &left_keys, &right_keys) {
+    let (old, new) = (op.old_range(), op.new_range());
+    match ranges.last_mut() {
+      _ if op.tag() == DiffTag::Equal => {}
+      Some(last) if !after_equal => *last = (last.0.start..old.end, last.1.start..new.end),
+      _ => ranges.push((old, new)),
+    }
+    after_equal = op.tag() == DiffTag::Equal;
+  }
+
+  let mut moved_to = HashMap::new();
+  let mut moved_from = HashSet::new();
+  for left_index in ranges.iter().flat_map(|(old, _)| old.clone()) {
+    let target = ranges
+      .iter()
+      .flat_map(|(_, new)| new.clone())
+      .find(|i| !moved_from.contains(i) && right_keys[*i] == left_keys[left_index]);
+    if let Some(right_index) = target {
+      moved_to.insert(left_index, right_index);
+      moved_from.insert(right_index);
+    }
+  }
+
+  for (old, new) in ranges {
+    let mut removed = Vec::new();
+    for left_index in old {
+      match moved_to.get(&left_index) {
+        Some(&right_index) => changes.push(JsonChange {
+          path: child_path(path, &right_index.to_string()),
+          status: DiffStatus::Moved,
+          from: Some(child_path(path, &left_index.to_string())),
+          left: None,
+          right: Some(right[right_index].clone()),
+        }),
+        None => removed.push(left_index),
+      }
+    }
+    let added: Vec<_> = new.filter(|i| !moved_from.contains(i)).collect();
+    for index in 0..removed.len().max(added.len()) {
+      match (removed.get(index), added.get(index)) {
+        (Some(&left_index), Some(&right_index)) => diff_json_value(
+          &child_path(path, &right_index.to_string()),
+          &left[left_index],
+          &right[right_index],
+          changes,
+        ),
+        (Some(&left_index), None) => changes.push(json_change(
+          child_path(path, &left_index.to_string()),
+          Some(&left[left_index]),
+          None,
+        )),
+        (None, Some(&right_index)) => changes.push(json_change(
+          child_path(path, &right_index.to_string()),
+          None,
+          Some(&right[right_index]),
+        )),
+        (None, None) => unreachable!(),
+      }
+    }
+  }
+}
+
+fn json_change(path: String, left: Option<&Value>, right: Option<&Value>) -> JsonChange {
+  let status = match (left, right) {
+    (None, _) => DiffStatus::Added,
+    (_, None) => DiffStatus::Removed,
+    _ => DiffStatus::Changed,
+  };
+  JsonChange {
+    path,
+    status,
+    from: None,
+    left: left.cloned(),
+    right: right.cloned(),
+  }
+}
+
+/// Append a key to a JSON pointer, escaped as in RFC 6901
+fn child_path(path: &str, key: &str) -> String {
+  format!("{}/{}", path, key.replace('~', "~0").replace('/', "~1"))
+}
+
+/// Compare two texts line by line. Only changed lines and the lines around
+/// them are returned.
+pub fn diff_lines(left: &str, right: &str) -> Vec<LineChange> {
+  let diff = TextDiff::from_lines(left, right);
+  let mut lines = Vec::new();
+  for op in diff.grouped_ops(CONTEXT_LINES).iter().flatten() {
+    for change in diff.iter_changes(op) {
+      lines.push(LineChange {
+        status: match change.tag() {
+          ChangeTag::Equal => DiffStatus::Unchanged,
+          ChangeTag::Delete => DiffStatus::Removed,
+          ChangeTag::Insert => DiffStatus::Added,
+        },
+        left_line: change.old_index().map(|index| index + 1),
+        right_line: change.new_index().map(|index| index + 1),
+        text: change.value().trim_end_matches(['\n', '\r']).to_string(),
+      });
+    }
+  }
+  lines
+}
+
+/// Compare the decoded contents of two differing payloads, structurally if
+/// both are JSON, line by line if both are text
+pub fn diff_payload_data(
+  left_mime_type: &str,
+  left: &[u8],
+  right_mime_type: &str,
+  right: &[u8],
+) -> PayloadDiff {
+  let json = (json_content(left_mime_type, left), json_content(right_mime_type, right));
+  if let (Some(left), Some(right)) = json {
+    return PayloadDiff::Json(diff_json(&left, &right));
+  }
+  let text = (text_content(left_mime_type, left), text_content(right_mime_type, right));
+  if let (Some(left), Some(right)) = text {
+    return PayloadDiff::Text(diff_lines(left, right));
+  }
+  PayloadDiff::Binary
+}
+
+/// Payload contents as JSON, parsing Rust Debug output like the UI does
+fn json_content(mime_type: &str, data: &[u8]) -> Option<Value> {
+  let text = std::str::from_utf8(data).ok()?;
+  if mime_type.starts_with("application/json") {
+    parse_json_value(text)
+  } else if mime_type.starts_with(MIME_TYPE_RUST_DEBUG) {
+    Some(parse_debug_to_json(text))
+  } else {
+    None
+  }
+}
+
+fn text_content<'a>(mime_type: &str, data: &'a [u8]) -> Option<&'a str> {
+  if mime_type.starts_with("text/") || mime_type.starts_with("application/json") {
+    std::str::from_utf8(data).ok()
+  } else {
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use observation_tools_shared::ExecutionId;
+  use observation_tools_shared::GroupId;
+  use observation_tools_shared::LogLevel;
+  use observation_tools_shared::ObservationId;
+  use observation_tools_shared::SourceInfo;
+  use serde_json::json;
+
+  fn observation(name: &str, group: Option<&str>) -> Observation {
+    Observation {
+      id: ObservationId::new(),
+      execution_id: ExecutionId::nil(),
+      name: name.to_string(),
+      observation_type: ObservationType::Payload,
+      log_level: LogLevel::Info,
+      source: Some(SourceInfo {
+        file: "src/main.rs".to_string(),
+        line: 10,
+        column: None,
+      }),
+      metadata: Default::default(),
+      group_ids: group.map(GroupId::from).into_iter().collect(),
+      parent_group_id: None,
+      parent_span_id: None,
+      created_at: chrono::Utc::now(),
+    }
+  }
+
+  fn group(name: &str, id: &str, parent: Option<&str>) -> Observation {
+    Observation {
+      observation_type: ObservationType::Group,
+      parent_group_id: parent.map(GroupId::from),
+      ..observation(name, Some(id))
+    }
+  }
+
+  #[test]
+  fn test_observation_keys() {
+    let observations = vec![
+      group("outer", "g1", None),
+      group("inner", "g2", Some("g1")),
+      observation("request", Some("g2")),
+      observation("request", Some("g2")),
+      observation("request", Some("unknown")),
+    ];
+    let keys = observation_keys(&observations);
+    assert_eq!(keys[0].group_path, Vec::<String>::new());
+    assert_eq!(keys[1].group_path, vec!["outer"]);
+    assert_eq!(keys[2].group_path, vec!["outer", "inner"]);
+    assert_eq!(keys[2].source.as_deref(), Some("src/main.rs:10"));
+    assert_eq!((keys[2].occurrence, keys[3].occurrence), (0, 1));
+    assert_eq!((keys[4].group_path.len(), keys[4].occurrence), (0, 0));
+  }
+
+  #[test]
+  fn test_align_observations() {
+    let left = vec![
+      observation("a", None),
+      observation("removed", None),
+      observation("b", None),
+      observation("b", None),
+    ];
+    let right = vec![
+      observation("a", None),
+      observation("b", None),
+      observation("added", None),
+    ];
+    // Reordered observations still match
+    let swapped: Vec<_> = align_observations(&left[..2], &[left[1].clone(), left[0].clone()])
+      .into_iter()
+      .map(|a| (a.left, a.right))
+      .collect();
+    assert_eq!(swapped, vec![(Some(1), Some(0)), (Some(0), Some(1))]);
+    let aligned: Vec<_> = align_observations(&left, &right)
+      .into_iter()
+      .map(|a| (a.key.name, a.left, a.right))
+      .collect();
+    assert_eq!(
+      aligned,
+      vec![
+        ("a".to_string(), Some(0), Some(0)),
+        ("removed".to_string(), Some(1), None),
+        ("b".to_string(), Some(2), Some(1)),
+        ("added".to_string(), None, Some(2)),
+        ("b".to_string(), Some(3), None),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_diff_json() {
+    let left = json!({"a": 1, "b": {"c": [1, 2, 3]}, "d/e": true, "same": "x"});
+    let right = json!({"a": 2, "b": {"c": [1, 5]}, "f": null, "same": "x"});
+    let changes: Vec<_> = diff_json(&left, &right)
+      .into_iter()
+      .map(|c| (c.path, c.status))
+      .collect();
+    assert_eq!(
+      changes,
+      vec![
+        ("/a".to_string(), DiffStatus::Changed),
+        ("/b/c/1".to_string(), DiffStatus::Changed),
+        ("/b/c/2".to_string(), DiffStatus::Removed),
+        ("/d~1e".to_string(), DiffStatus::Removed),
+        ("/f".to_string(), DiffStatus::Added),
+      ]
+    );
+    assert!(diff_json(&left, &left).is_empty());
+    assert_eq!(diff_json(&json!(1), &json!("1"))[0].path, "");
+  }
+
+  #[test]
+  fn test_diff_json_arrays() {
+    // Inserting an element does not change the ones after it
+    let changes = diff_json(&json!(["a", "b", "c"]), &json!(["x", "a", "b", "c"]));
+    assert_eq!(changes, vec![json_change("/0".to_string(), None, Some(&json!("x")))]);
+
+    let left = json!([1, 2, {"v": "old"}, 3]);
+    let right = json!([2, {"v": "new"}, 3, 1]);
+    let changes: Vec<_> = diff_json(&left, &right)
+      .into_iter()
+      .map(|c| (c.status, c.path, c.from))
+      .collect();
+    assert_eq!(
+      changes,
+      vec![
+        (DiffStatus::Moved, "/3".to_string(), Some("/0".to_string())),
+        (DiffStatus::Changed, "/1/v".to_string(), None),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_diff_lines() {
+    let left = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+    let right = "1\n2\n3\n4\n5\nsix\n7\n8\n9\n";
+    let lines = diff_lines(left, right);
+    let changed: Vec<_> = lines
+      .iter()
+      .filter(|l| l.status != DiffStatus::Unchanged)
+      .map(|l| (l.status, l.left_line, l.right_line, l.text.as_str()))
+      .collect();
+    assert_eq!(
+      changed,
+      vec![
+        (DiffStatus::Removed, Some(6), None, "6"),
+        (DiffStatus::Added, None, Some(6), "six"),
+      ]
+    );
+    // Only the context around the change is included
+    assert_eq!(lines.len(), 2 + 2 * CONTEXT_LINES);
+    assert!(diff_lines(left, left).is_empty());
+  }
+
+  #[test]
+  fn test_diff_payload_data() {
+    let json = "application/json";
+    let changes = diff_payload_data(json, b"{\"a\":1}", json, b"{\"a\":2}");
+    assert!(matches!(changes, PayloadDiff::Json(changes) if changes.len() == 1));
+    let debug = MIME_TYPE_RUST_DEBUG;
+    let debug = diff_payload_data(debug, b"Foo { a: 1 }", debug, b"Foo { a: 2 }");
+    assert!(matches!(debug, PayloadDiff::Json(_)));
+    let text = diff_payload_data("text/plain", b"a", "text/plain", b"b");
+    assert!(matches!(text, PayloadDiff::Text(lines) if lines.len() == 2));
+    let invalid_json = diff_payload_data(json, b"{", json, b"[");
+    assert!(matches!(invalid_json, PayloadDiff::Text(_)));
+    let binary = "application/octet-stream";
+    let binary = diff_payload_data(binary, &[0], binary, &[1]);
+    assert_eq!(binary, PayloadDiff::Binary);
+  }
+}
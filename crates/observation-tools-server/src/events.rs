@@ -0,0 +1,129 @@
+//! Live notifications about newly stored observations
+//!
+//! `create_observations` publishes every stored batch into a broadcast
+//! channel of its execution, and the SSE endpoints subscribe to it to push
+//! new observations to API clients and the UI as they arrive. Channels only
+//! exist while an execution has subscribers, so batches of executions nobody
+//! watches are not kept.
+
+use crate::storage::ObservationWithPayloads;
+use futures::Stream;
+use observation_tools_shared::models::ExecutionId;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Number of batches buffered per subscriber before it starts lagging
+const CHANNEL_CAPACITY: usize = 16;
+
+/// A batch of observations that was just stored for an execution
+#[derive(Debug)]
+pub struct ObservationBatch {
+  pub execution_id: ExecutionId,
+  pub observations: Vec<ObservationWithPayloads>,
+}
+
+/// An item received by a subscriber
+#[derive(Debug, Clone)]
+pub enum ExecutionEvent {
+  /// New observations were stored for the execution
+  Observations(Arc<ObservationBatch>),
+  /// The subscriber fell behind and missed this many batches
+  Lagged(u64),
+}
+
+type Channels = HashMap<ExecutionId, broadcast::Sender<Arc<ObservationBatch>>>;
+
+/// Broadcasts stored observation batches to live subscribers
+#[derive(Clone)]
+pub struct ObservationEvents {
+  channels: Arc<Mutex<Channels>>,
+}
+
+impl ObservationEvents {
+  pub fn new() -> Self {
+    Self {
+      channels: Default::default(),
+    }
+  }
+
+  /// Notify subscribers about newly stored observations
+  pub fn publish(&self, execution_id: ExecutionId, observations: Vec<ObservationWithPayloads>) {
+    let sender = {
+      let mut channels = self.channels.lock().expect("events lock poisoned");
+      match channels.get(&execution_id) {
+        Some(sender) if sender.receiver_count() > 0 => sender.clone(),
+        Some(_) => {
+          channels.remove(&execution_id);
+          return;
+        }
+        None => return,
+      }
+    };
+    // Sending only fails when the last subscriber just left
+    let _ = sender.send(Arc::new(ObservationBatch {
+      execution_id,
+      observations,
+    }));
+  }
+
+  /// Stream the batches published for one execution from now on
+  pub fn subscribe(&self, execution_id: ExecutionId) -> impl Stream<Item = ExecutionEvent> {
+    let receiver = {
+      let mut channels = self.channels.lock().expect("events lock poisoned");
+      channels.retain(|_, sender| sender.receiver_count() > 0);
+      channels
+        .entry(execution_id)
+        .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+        .subscribe()
+    };
+    futures::stream::unfold(receiver, |mut receiver| async move {
+      match receiver.recv().await {
+        Ok(batch) => Some((ExecutionEvent::Observations(batch), receiver)),
+        Err(RecvError::Lagged(missed)) => Some((ExecutionEvent::Lagged(missed), receiver)),
+        Err(RecvError::Closed) => None,
+      }
+    })
+  }
+
+  /// Number of executions with a channel, for tests
+  #[cfg(test)]
+  fn channel_count(&self) -> usize {
+    self.channels.lock().expect("events lock poisoned").len()
+  }
+}
+
+impl Default for ObservationEvents {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use futures::StreamExt;
+
+  #[tokio::test]
+  async fn test_channels_per_execution() {
+    let events = ObservationEvents::new();
+    let watched = ExecutionId::new();
+    let other = ExecutionId::new();
+    let mut stream = Box::pin(events.subscribe(watched));
+
+    events.publish(other, Vec::new());
+    assert_eq!(events.channel_count(), 1);
+    events.publish(watched, Vec::new());
+    let Some(ExecutionEvent::Observations(batch)) = stream.next().await else {
+      panic!("expected a batch");
+    };
+    assert_eq!(batch.execution_id, watched);
+
+    // The channel is dropped once its subscribers are gone
+    drop(stream);
+    events.publish(watched, Vec::new());
+    assert_eq!(events.channel_count(), 0);
+  }
+}
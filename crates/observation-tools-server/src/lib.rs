@@ -1,12 +1,21 @@
 //! Observation Tools Server library
 
+pub mod abandoned;
 pub mod api;
 pub mod auth;
 pub mod config;
 pub mod csrf;
 pub mod debug_parser;
+pub mod diff;
+pub mod events;
+pub mod migrate;
+pub mod quota;
+pub mod retention;
 pub mod server;
+pub mod session;
+pub mod snapshot;
 pub mod storage;
+pub mod tree;
 pub mod ui;
 
 pub use config::Config;
@@ -4,11 +4,32 @@
 //! observations.
 
 use clap::Parser;
+use observation_tools_server::auth::api_key_id;
+use observation_tools_server::auth::ApiKeyRecord;
+use observation_tools_server::auth::ApiKeyScope;
 use observation_tools_server::auth::ApiKeySecret;
+use observation_tools_server::config::parse_byte_size;
+use observation_tools_server::config::parse_duration;
+use observation_tools_server::config::QuotaConfig;
+use observation_tools_server::config::ReadAuthConfig;
+use observation_tools_server::config::RetentionConfig;
+use observation_tools_server::config::DEFAULT_ABANDONED_AFTER;
+use observation_tools_server::config::DEFAULT_BLOB_GRACE_PERIOD;
+use observation_tools_server::config::DEFAULT_SESSION_LIFETIME;
+use observation_tools_server::config::DEFAULT_SWEEP_INTERVAL;
+use observation_tools_server::config::ENV_BLOB_URL;
+use observation_tools_server::config::ENV_METADATA_URL;
+use observation_tools_server::migrate::Checkpoint;
+use observation_tools_server::session::set_user_password;
 use observation_tools_server::Config;
 use observation_tools_server::Server;
+use observation_tools_shared::ContentEncoding;
+use observation_tools_shared::ObservationId;
+use observation_tools_shared::ProjectId;
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::time::Duration;
+use uuid::Uuid;
 
 #[derive(Parser, Debug)]
 #[command(name = "observation-tools")]
@@ -25,9 +46,331 @@ enum Commands {
     /// Directory for storing data [default: platform data dir]
     #[arg(short, long)]
     data_dir: Option<PathBuf>,
+
+    /// Object store URL for payload blobs, e.g. `s3://bucket/prefix` or
+    /// `memory://` [default: $OBSERVATION_TOOLS_BLOB_URL, or the data dir]
+    #[arg(long)]
+    blob_url: Option<String>,
+
+    /// SQL database URL for metadata, e.g. `sqlite:///path/metadata.db` or
+    /// `postgres://user@host/db` [default: $OBSERVATION_TOOLS_METADATA_URL, or
+    /// the data dir]
+    #[arg(long)]
+    metadata_url: Option<String>,
+
+    #[command(flatten)]
+    retention: RetentionArgs,
+
+    /// How often to apply the retention policy, e.g. `30m`
+    #[arg(long, value_parser = parse_duration)]
+    retention_sweep_interval: Option<Duration>,
+
+    /// Mark running executions as abandoned after receiving no data for this
+    /// long, or `0` to never mark them [default: 1h]
+    #[arg(long, value_parser = parse_duration)]
+    abandoned_after: Option<Duration>,
+
+    /// Compress stored payloads with `gzip` or `zstd` [default: identity]
+    #[arg(long)]
+    payload_compression: Option<ContentEncoding>,
+
+    #[command(flatten)]
+    quotas: QuotaArgs,
+
+    #[command(flatten)]
+    read_auth: ReadAuthArgs,
   },
-  /// Generate an API key using the secret from OBSERVATION_TOOLS_API_SECRET
+  /// Generate an API key using the secret from API_KEY_SECRET. The key is not
+  /// recorded in the key registry and may only write, prefer `keys create`.
   GenerateKey,
+  /// Manage the API key registry. With the default metadata store, the server
+  /// must not be running against the same data dir.
+  Keys {
+    #[command(subcommand)]
+    command: KeysCommand,
+  },
+  /// Manage the projects executions are grouped in. With the default
+  /// metadata store, the server must not be running against the same data dir.
+  Projects {
+    #[command(subcommand)]
+    command: ProjectsCommand,
+  },
+  /// Manage the baselines that snapshots are compared with. With the default
+  /// metadata store, the server must not be running against the same data dir.
+  Snapshots {
+    #[command(subcommand)]
+    command: SnapshotsCommand,
+  },
+  /// Manage the users who may log in to the UI
+  Users {
+    #[command(subcommand)]
+    command: UsersCommand,
+  },
+  /// Delete executions that violate the retention policy and blobs no longer
+  /// referenced, then exit. The server must not be running against the same
+  /// data dir.
+  Gc {
+    /// Directory for storing data [default: platform data dir]
+    #[arg(short, long)]
+    data_dir: Option<PathBuf>,
+
+    /// Object store URL for payload blobs [default: $OBSERVATION_TOOLS_BLOB_URL,
+    /// or the data dir]
+    #[arg(long)]
+    blob_url: Option<String>,
+
+    /// SQL database URL for metadata [default: $OBSERVATION_TOOLS_METADATA_URL,
+    /// or the data dir]
+    #[arg(long)]
+    metadata_url: Option<String>,
+
+    #[command(flatten)]
+    retention: RetentionArgs,
+  },
+  /// Copy all executions, observations and blobs to different storage
+  /// backends. Interrupted migrations resume from a checkpoint file. No server
+  /// may be running against either side.
+  Migrate {
+    /// Source data directory [default: platform data dir]
+    #[arg(long)]
+    from_data_dir: Option<PathBuf>,
+
+    /// Source SQL database URL for metadata [default: the source data dir]
+    #[arg(long)]
+    from_metadata_url: Option<String>,
+
+    /// Source object store URL for payload blobs [default: the source data dir]
+    #[arg(long)]
+    from_blob_url: Option<String>,
+
+    /// Target data directory [default: platform data dir]
+    #[arg(long)]
+    to_data_dir: Option<PathBuf>,
+
+    /// Target SQL database URL for metadata [default: the target data dir]
+    #[arg(long)]
+    to_metadata_url: Option<String>,
+
+    /// Target object store URL for payload blobs [default: the target data dir]
+    #[arg(long)]
+    to_blob_url: Option<String>,
+
+    /// File recording which executions have been copied [default:
+    /// migrate.checkpoint in the target data dir]
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+  },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum KeysCommand {
+  /// Create an API key using the secret from API_KEY_SECRET and record it in
+  /// the key registry
+  Create {
+    #[command(flatten)]
+    store: MetadataArgs,
+
+    /// Name describing what the key is used for
+    name: String,
+
+    /// What the key may do: `read-only`, `write-only` or `admin`
+    #[arg(long, default_value = "write-only")]
+    scope: ApiKeyScope,
+
+    /// Expire the key after this long, e.g. `90d`
+    #[arg(long, value_parser = parse_duration)]
+    expires_in: Option<Duration>,
+
+    /// Only allow access to executions whose name starts with this prefix.
+    /// May be repeated.
+    #[arg(long = "execution-prefix")]
+    execution_prefixes: Vec<String>,
+
+    /// Only allow access to executions in this project
+    #[arg(long)]
+    project: Option<ProjectId>,
+  },
+  /// List the keys in the key registry
+  List {
+    #[command(flatten)]
+    store: MetadataArgs,
+  },
+  /// Revoke a key, given its ID or the key itself. Keys generated with
+  /// `generate-key` can be revoked too. While the server is running, use
+  /// `DELETE /api/keys/{id}` with an admin key instead.
+  Revoke {
+    #[command(flatten)]
+    store: MetadataArgs,
+
+    /// Key ID or API key
+    key: String,
+  },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum ProjectsCommand {
+  /// Create a project, or rename an existing one. Projects are also created
+  /// when the first execution in them is.
+  Create {
+    #[command(flatten)]
+    store: MetadataArgs,
+
+    /// Project ID, used in URLs: lowercase letters, digits, `-` and `_`
+    id: ProjectId,
+
+    /// Display name [default: the ID]
+    #[arg(long)]
+    name: Option<String>,
+  },
+  /// List the projects
+  List {
+    #[command(flatten)]
+    store: MetadataArgs,
+  },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum SnapshotsCommand {
+  /// Make an observation the baseline of its snapshot, replacing the
+  /// baseline of the same name
+  Accept {
+    #[command(flatten)]
+    store: MetadataArgs,
+
+    /// ID of the observation to accept
+    #[arg(value_parser = ObservationId::parse)]
+    observation_id: ObservationId,
+  },
+  /// List the snapshot baselines
+  List {
+    #[command(flatten)]
+    store: MetadataArgs,
+  },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum UsersCommand {
+  /// Add a user to the users file or change their password. The password is
+  /// read from stdin.
+  Add {
+    /// Users file to update, created if it does not exist
+    #[arg(long)]
+    users_file: PathBuf,
+
+    /// User name
+    name: String,
+  },
+}
+
+/// Metadata store of the `keys`, `projects` and `snapshots` commands
+#[derive(clap::Args, Debug)]
+struct MetadataArgs {
+  /// Directory for storing data [default: platform data dir]
+  #[arg(short, long)]
+  data_dir: Option<PathBuf>,
+
+  /// SQL database URL for metadata [default: $OBSERVATION_TOOLS_METADATA_URL,
+  /// or the data dir]
+  #[arg(long)]
+  metadata_url: Option<String>,
+}
+
+impl MetadataArgs {
+  fn into_config(self) -> Config {
+    Config::new()
+      .with_data_dir(self.data_dir)
+      .with_metadata_url(self.metadata_url.or_else(|| std::env::var(ENV_METADATA_URL).ok()))
+  }
+}
+
+/// Retention limits shared by `serve` and `gc`
+#[derive(clap::Args, Debug)]
+struct RetentionArgs {
+  /// Delete executions older than this, e.g. `7d` or `12h`
+  #[arg(long, value_parser = parse_duration)]
+  retention_max_age: Option<Duration>,
+
+  /// Keep at most this many executions
+  #[arg(long)]
+  retention_max_executions: Option<usize>,
+
+  /// Keep at most this many payload bytes, e.g. `10G`
+  #[arg(long, value_parser = parse_byte_size)]
+  retention_max_bytes: Option<u64>,
+
+  /// Keep blobs no payload references for this long before deleting them
+  /// [default: 1h]
+  #[arg(long, value_parser = parse_duration)]
+  blob_grace_period: Option<Duration>,
+}
+
+impl RetentionArgs {
+  fn into_config(self, sweep_interval: Option<Duration>) -> RetentionConfig {
+    RetentionConfig {
+      max_age: self.retention_max_age,
+      max_executions: self.retention_max_executions,
+      max_total_bytes: self.retention_max_bytes,
+      sweep_interval: sweep_interval.unwrap_or(DEFAULT_SWEEP_INTERVAL),
+      blob_grace_period: self.blob_grace_period.unwrap_or(DEFAULT_BLOB_GRACE_PERIOD),
+    }
+  }
+}
+
+/// Upload quotas of `serve`
+#[derive(clap::Args, Debug)]
+struct QuotaArgs {
+  /// Reject observations beyond this many per execution
+  #[arg(long)]
+  max_observations_per_execution: Option<u64>,
+
+  /// Reject payloads beyond this many bytes per execution, e.g. `1G`
+  #[arg(long, value_parser = parse_byte_size)]
+  max_bytes_per_execution: Option<u64>,
+
+  /// Reject payloads beyond this many bytes per API key per UTC day, e.g.
+  /// `10G`
+  #[arg(long, value_parser = parse_byte_size)]
+  max_bytes_per_api_key_per_day: Option<u64>,
+}
+
+impl From<QuotaArgs> for QuotaConfig {
+  fn from(args: QuotaArgs) -> Self {
+    QuotaConfig {
+      max_observations_per_execution: args.max_observations_per_execution,
+      max_bytes_per_execution: args.max_bytes_per_execution,
+      max_bytes_per_api_key_per_day: args.max_bytes_per_api_key_per_day,
+    }
+  }
+}
+
+/// Read authentication of `serve`
+#[derive(clap::Args, Debug)]
+struct ReadAuthArgs {
+  /// Require an API key allowed to read, or a UI login, to view executions.
+  /// Requires API_KEY_SECRET.
+  #[arg(long)]
+  require_read_auth: bool,
+
+  /// Users who may log in to the UI, see `users add`. Implies
+  /// `--require-read-auth`.
+  #[arg(long)]
+  users_file: Option<PathBuf>,
+
+  /// How long a UI login lasts [default: 24h]
+  #[arg(long, value_parser = parse_duration)]
+  session_lifetime: Option<Duration>,
+}
+
+impl ReadAuthArgs {
+  fn into_config(self) -> Option<ReadAuthConfig> {
+    if !self.require_read_auth && self.users_file.is_none() {
+      return None;
+    }
+    Some(ReadAuthConfig {
+      users_file: self.users_file,
+      session_lifetime: self.session_lifetime.unwrap_or(DEFAULT_SESSION_LIFETIME),
+    })
+  }
 }
 
 #[tokio::main]
@@ -44,7 +387,17 @@ async fn main() -> anyhow::Result<()> {
   let cli = Cli::parse();
 
   match cli.command {
-    Commands::Serve { data_dir } => {
+    Commands::Serve {
+      data_dir,
+      blob_url,
+      metadata_url,
+      retention,
+      retention_sweep_interval,
+      abandoned_after,
+      payload_compression,
+      quotas,
+      read_auth,
+    } => {
       let port = std::env::var("PORT")
         .ok()
         .and_then(|p| p.parse::<u16>().ok())
@@ -53,7 +406,14 @@ async fn main() -> anyhow::Result<()> {
       let config = Config::new()
         .with_bind_addr(bind_addr)
         .with_data_dir(data_dir)
-        .with_api_secret(ApiKeySecret::from_env()?);
+        .with_blob_url(blob_url.or_else(|| std::env::var(ENV_BLOB_URL).ok()))
+        .with_metadata_url(metadata_url.or_else(|| std::env::var(ENV_METADATA_URL).ok()))
+        .with_api_secret(ApiKeySecret::from_env()?)
+        .with_retention(retention.into_config(retention_sweep_interval))
+        .with_abandoned_after(Some(abandoned_after.unwrap_or(DEFAULT_ABANDONED_AFTER)))
+        .with_payload_encoding(payload_compression.unwrap_or_default())
+        .with_quotas(quotas.into())
+        .with_read_auth(read_auth.into_config());
       let listener = tokio::net::TcpListener::bind(&bind_addr).await?;
       let server = Server::new(config);
       server.run(listener).await?;
@@ -70,7 +430,198 @@ async fn main() -> anyhow::Result<()> {
       let api_key = observation_tools_server::auth::generate_api_key(&api_secret)?;
       println!("{}", api_key);
     }
+    Commands::Keys { command } => run_keys_command(command).await?,
+    Commands::Projects { command } => match command {
+      ProjectsCommand::Create { store, id, name } => {
+        let project = Server::new(store.into_config()).create_project(id, name).await?;
+        println!("{}  {}", project.id, project.name);
+      }
+      ProjectsCommand::List { store } => {
+        for project in Server::new(store.into_config()).list_projects().await? {
+          println!(
+            "{}  {}  created {}",
+            project.id,
+            project.name,
+            project.created_at.format("%Y-%m-%d %H:%M")
+          );
+        }
+      }
+    },
+    Commands::Snapshots { command } => match command {
+      SnapshotsCommand::Accept {
+        store,
+        observation_id,
+      } => {
+        let baseline = Server::new(store.into_config()).accept_snapshot(observation_id).await?;
+        println!(
+          "Accepted {} as the baseline of {} in {}",
+          baseline.observation_id, baseline.name, baseline.execution_name
+        );
+      }
+      SnapshotsCommand::List { store } => {
+        for baseline in Server::new(store.into_config()).list_snapshot_baselines().await? {
+          println!(
+            "{}  {}  {}  {}  accepted {}",
+            baseline.project,
+            baseline.execution_name,
+            baseline.name,
+            baseline.observation_id,
+            baseline.accepted_at.format("%Y-%m-%d %H:%M")
+          );
+        }
+      }
+    },
+    Commands::Users {
+      command: UsersCommand::Add { users_file, name },
+    } => {
+      eprint!("Password for {}: ", name);
+      let mut password = String::new();
+      std::io::stdin().read_line(&mut password)?;
+      let password = password.trim_end_matches(['\r', '\n']);
+      if password.is_empty() {
+        anyhow::bail!("Password must not be empty");
+      }
+      if set_user_password(&users_file, &name, password)? {
+        eprintln!("Added user {}", name);
+      } else {
+        eprintln!("Changed password of {}", name);
+      }
+    }
+    Commands::Gc {
+      data_dir,
+      blob_url,
+      metadata_url,
+      retention,
+    } => {
+      let retention = retention.into_config(None);
+      let config = Config::new()
+        .with_data_dir(data_dir)
+        .with_blob_url(blob_url.or_else(|| std::env::var(ENV_BLOB_URL).ok()))
+        .with_metadata_url(metadata_url.or_else(|| std::env::var(ENV_METADATA_URL).ok()))
+        .with_retention(retention);
+      let stats = Server::new(config).gc().await?;
+      println!(
+        "Deleted {} executions, {} observations and {} blobs",
+        stats.executions, stats.observations, stats.blobs
+      );
+    }
+    Commands::Migrate {
+      from_data_dir,
+      from_metadata_url,
+      from_blob_url,
+      to_data_dir,
+      to_metadata_url,
+      to_blob_url,
+      checkpoint,
+    } => {
+      let source = Config::new()
+        .with_data_dir(from_data_dir)
+        .with_metadata_url(from_metadata_url)
+        .with_blob_url(from_blob_url);
+      let target = Config::new()
+        .with_data_dir(to_data_dir)
+        .with_metadata_url(to_metadata_url)
+        .with_blob_url(to_blob_url);
+      let checkpoint_path =
+        checkpoint.unwrap_or_else(|| target.data_dir.join("migrate.checkpoint"));
+      let mut checkpoint = Checkpoint::open(&checkpoint_path)?;
+      if !checkpoint.is_empty() {
+        println!(
+          "Resuming from {}, skipping {} executions",
+          checkpoint_path.display(),
+          checkpoint.len()
+        );
+      }
+      let stats = Server::new(source)
+        .migrate(target, &mut checkpoint, |progress| {
+          println!(
+            "[{}/{}] {} ({}): {} observations, {} blobs",
+            progress.completed,
+            progress.total,
+            progress.execution.name,
+            progress.execution.id,
+            progress.stats.observations,
+            progress.stats.blobs
+          );
+        })
+        .await?;
+      checkpoint.remove()?;
+      println!(
+        "Migrated {} executions, {} observations, {} blobs, {} API keys, {} projects and {} \
+         snapshot baselines ({} executions already migrated)",
+        stats.executions,
+        stats.observations,
+        stats.blobs,
+        stats.api_keys,
+        stats.projects,
+        stats.snapshot_baselines,
+        stats.skipped_executions
+      );
+    }
   }
 
   Ok(())
 }
+
+async fn run_keys_command(command: KeysCommand) -> anyhow::Result<()> {
+  match command {
+    KeysCommand::Create {
+      store,
+      name,
+      scope,
+      expires_in,
+      execution_prefixes,
+      project,
+    } => {
+      let Some(api_secret) = ApiKeySecret::from_env()? else {
+        anyhow::bail!(
+          "No api key secret found. Please set the {} environment variable.",
+          observation_tools_server::auth::ENV_API_KEY_SECRET
+        );
+      };
+      let mut record = ApiKeyRecord::new(name, scope);
+      let expires_in = expires_in.map(chrono::Duration::from_std).transpose()?;
+      record.expires_at = expires_in.map(|expires_in| record.created_at + expires_in);
+      record.execution_prefixes = execution_prefixes;
+      record.project = project;
+      let config = store.into_config().with_api_secret(Some(api_secret));
+      let api_key = Server::new(config).create_api_key(&record).await?;
+      eprintln!("Created API key {} ({})", record.id, record.scope);
+      println!("{}", api_key);
+    }
+    KeysCommand::List { store } => {
+      let keys = Server::new(store.into_config()).list_api_keys().await?;
+      for key in keys {
+        let mut line = format!(
+          "{}  {}  {}  created {}",
+          key.id,
+          key.name,
+          key.scope,
+          key.created_at.format("%Y-%m-%d %H:%M")
+        );
+        if let Some(expires_at) = key.expires_at {
+          line += &format!("  expires {}", expires_at.format("%Y-%m-%d %H:%M"));
+        }
+        if let Some(revoked_at) = key.revoked_at {
+          line += &format!("  revoked {}", revoked_at.format("%Y-%m-%d %H:%M"));
+        }
+        if !key.execution_prefixes.is_empty() {
+          line += &format!("  executions {}*", key.execution_prefixes.join("*, "));
+        }
+        if let Some(project) = &key.project {
+          line += &format!("  project {}", project);
+        }
+        println!("{}", line);
+      }
+    }
+    KeysCommand::Revoke { store, key } => {
+      let id = match Uuid::parse_str(&key) {
+        Ok(id) => id,
+        Err(_) => api_key_id(&key)?,
+      };
+      let record = Server::new(store.into_config()).revoke_api_key(id).await?;
+      println!("Revoked API key {} ({})", record.id, record.name);
+    }
+  }
+  Ok(())
+}
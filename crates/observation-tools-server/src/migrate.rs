@@ -0,0 +1,509 @@
+//! Copying stored data between storage backends
+//!
+//! A migration streams every execution, its observations with their inline
+//! payloads, and their blobs from one set of stores to another, one page at a
+//! time. The API key registry, the projects and the snapshot baselines are
+//! copied first, in full.
+//! Content shared by several payloads is written once, and may be referenced
+//! by its digest from each execution and project it is copied for.
+//! Completed executions are appended to a checkpoint file so that an
+//! interrupted migration can be resumed without copying them again. Writes are
+//! upserts, so an execution that was only partially copied is simply copied
+//! again on resume.
+//!
+//! The source should not be written to while a migration is running.
+
+use crate::storage::content::add_scopes;
+use crate::storage::search::payload_text;
+use crate::storage::search::SearchDocument;
+use crate::storage::BlobKey;
+use crate::storage::BlobScope;
+use crate::storage::BlobStorage;
+use crate::storage::ExecutionFilter;
+use crate::storage::MetadataStorage;
+use crate::storage::ObservationFilter;
+use crate::storage::PayloadData;
+use crate::storage::SearchIndex;
+use crate::storage::StorageError;
+use crate::storage::StorageResult;
+use observation_tools_shared::Execution;
+use observation_tools_shared::ExecutionId;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use tracing::warn;
+
+/// Number of executions or observations read from the source at a time
+const PAGE_SIZE: usize = 100;
+
+/// What was copied by a migration
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MigrationStats {
+  pub executions: usize,
+  pub observations: usize,
+  pub blobs: usize,
+  pub api_keys: usize,
+  pub projects: usize,
+  pub snapshot_baselines: usize,
+  /// Executions skipped because the checkpoint says they were already copied
+  pub skipped_executions: usize,
+}
+
+impl std::ops::AddAssign for MigrationStats {
+  fn add_assign(&mut self, other: Self) {
+    self.executions += other.executions;
+    self.observations += other.observations;
+    self.blobs += other.blobs;
+    self.api_keys += other.api_keys;
+    self.projects += other.projects;
+    self.snapshot_baselines += other.snapshot_baselines;
+    self.skipped_executions += other.skipped_executions;
+  }
+}
+
+/// Reported after each execution has been copied
+#[derive(Debug)]
+pub struct MigrationProgress<'a> {
+  /// Executions processed so far, including skipped ones
+  pub completed: usize,
+  /// Total number of executions in the source
+  pub total: usize,
+  /// The execution that was just copied
+  pub execution: &'a Execution,
+  /// What was copied for this execution
+  pub stats: MigrationStats,
+}
+
+/// The stores that data is copied into
+pub struct MigrationTarget<'a> {
+  pub metadata: &'a dyn MetadataStorage,
+  /// `None` if the source and target share a blob store
+  pub blobs: Option<&'a dyn BlobStorage>,
+  /// `None` if the source and target share a search index
+  pub search: Option<&'a dyn SearchIndex>,
+}
+
+/// The set of executions that have been fully copied, persisted to a file
+pub struct Checkpoint {
+  path: PathBuf,
+  file: File,
+  completed: HashSet<ExecutionId>,
+}
+
+impl Checkpoint {
+  /// Open a checkpoint file, creating it if it does not exist. Executions
+  /// recorded in an existing file are skipped.
+  pub fn open(path: impl AsRef<Path>) -> StorageResult<Self> {
+    let path = path.as_ref().to_path_buf();
+    let mut completed = HashSet::new();
+    if path.exists() {
+      for line in BufReader::new(File::open(&path)?).lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+          continue;
+        }
+        let id = ExecutionId::parse(line).map_err(|e| {
+          StorageError::Internal(format!("Invalid checkpoint file {}: {}", path.display(), e))
+        })?;
+        completed.insert(id);
+      }
+    } else if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent)?;
+    }
+    let file = File::options().create(true).append(true).open(&path)?;
+    Ok(Self {
+      path,
+      file,
+      completed,
+    })
+  }
+
+  /// Number of executions recorded as copied
+  pub fn len(&self) -> usize {
+    self.completed.len()
+  }
+
+  /// Whether no executions have been recorded yet
+  pub fn is_empty(&self) -> bool {
+    self.completed.is_empty()
+  }
+
+  fn contains(&self, id: ExecutionId) -> bool {
+    self.completed.contains(&id)
+  }
+
+  fn record(&mut self, id: ExecutionId) -> StorageResult<()> {
+    writeln!(self.file, "{}", id)?;
+    self.file.sync_data()?;
+    self.completed.insert(id);
+    Ok(())
+  }
+
+  /// Delete the checkpoint file once the migration has finished
+  pub fn remove(self) -> StorageResult<()> {
+    std::fs::remove_file(&self.path)?;
+    Ok(())
+  }
+}
+
+/// Copy every execution from the source stores into the target, skipping
+/// executions recorded in the checkpoint
+pub async fn migrate(
+  source_metadata: &dyn MetadataStorage,
+  source_blobs: &dyn BlobStorage,
+  target: &MigrationTarget<'_>,
+  checkpoint: &mut Checkpoint,
+  mut on_progress: impl FnMut(&MigrationProgress),
+) -> StorageResult<MigrationStats> {
+  let executions_filter = ExecutionFilter::default();
+  let total = source_metadata.count_executions(&executions_filter).await?;
+  let mut stats = MigrationStats::default();
+  // Revocations must carry over, so keys are copied even when resuming
+  for key in source_metadata.list_api_keys().await? {
+    target.metadata.store_api_key(&key).await?;
+    stats.api_keys += 1;
+  }
+  for project in source_metadata.list_projects().await? {
+    target.metadata.store_project(&project).await?;
+    stats.projects += 1;
+  }
+  for baseline in source_metadata.list_snapshot_baselines().await? {
+    target.metadata.store_snapshot_baseline(&baseline).await?;
+    stats.snapshot_baselines += 1;
+  }
+  let mut offset = 0;
+  loop {
+    let page = source_metadata
+      .list_executions(Some(PAGE_SIZE), Some(offset), &executions_filter)
+      .await?;
+    if page.is_empty() {
+      break;
+    }
+    offset += page.len();
+    for execution in &page {
+      if checkpoint.contains(execution.id) {
+        stats.skipped_executions += 1;
+        continue;
+      }
+      let copied = migrate_execution(source_metadata, source_blobs, target, execution).await?;
+      checkpoint.record(execution.id)?;
+      stats += copied;
+      on_progress(&MigrationProgress {
+        completed: stats.executions + stats.skipped_executions,
+        total,
+        execution,
+        stats: copied,
+      });
+    }
+  }
+  Ok(stats)
+}
+
+/// Copy one execution with all of its observations and blobs
+async fn migrate_execution(
+  source_metadata: &dyn MetadataStorage,
+  source_blobs: &dyn BlobStorage,
+  target: &MigrationTarget<'_>,
+  execution: &Execution,
+) -> StorageResult<MigrationStats> {
+  let mut stats = MigrationStats {
+    executions: 1,
+    ..Default::default()
+  };
+  target.metadata.store_execution(execution).await?;
+  let scopes = [
+    BlobScope::Execution(execution.id),
+    BlobScope::Project(execution.project.clone()),
+  ];
+
+  let filter = ObservationFilter::default();
+  let mut offset = 0;
+  loop {
+    let page = source_metadata
+      .list_observations(execution.id, Some(PAGE_SIZE), Some(offset), &filter)
+      .await?;
+    if page.is_empty() {
+      break;
+    }
+    offset += page.len();
+
+    let mut observations = Vec::with_capacity(page.len());
+    let mut documents = Vec::with_capacity(page.len());
+    for listed in page {
+      // Listing only returns metadata, fetch again for inline payload data
+      let obs = source_metadata
+        .get_observation(listed.observation.id)
+        .await?;
+      let mut text = Vec::new();
+      for payload in &obs.payloads {
+        match &payload.data {
+          PayloadData::Inline(data) => {
+            text.extend(payload_text(&payload.mime_type, &payload.decode(data)?))
+          }
+          PayloadData::Blob if target.blobs.is_some() || target.search.is_some() => {
+            let key = payload.blob_key(obs.observation.id);
+            // Content shared with a payload copied earlier is already there
+            let copy = match target.blobs {
+              Some(blobs) => !blob_exists(blobs, &key).await?,
+              None => false,
+            };
+            if !copy && target.search.is_none() {
+              continue;
+            }
+            let data = match source_blobs.get_blob(&key).await {
+              Ok(data) => data,
+              Err(StorageError::NotFound(_)) => {
+                warn!(
+                  observation_id = %obs.observation.id,
+                  payload_id = %payload.id.as_str(),
+                  "Blob missing from source, skipping"
+                );
+                continue;
+              }
+              Err(e) => return Err(e),
+            };
+            text.extend(payload_text(&payload.mime_type, &payload.decode(&data)?));
+            // Blobs are copied as stored, compressed or not. Storing the
+            // observations registers content-addressed blobs in the target.
+            if let Some(blobs) = target.blobs.filter(|_| copy) {
+              blobs.store_blob(&key, data).await?;
+              stats.blobs += 1;
+            }
+          }
+          PayloadData::Blob => {}
+        }
+      }
+      documents.push(SearchDocument {
+        observation: obs.observation.clone(),
+        payload_text: text,
+      });
+      observations.push(obs);
+    }
+
+    stats.observations += observations.len();
+    let digests: HashSet<String> = observations
+      .iter()
+      .flat_map(|obs| &obs.payloads)
+      .filter_map(|payload| payload.digest.clone())
+      .collect();
+    target.metadata.store_observations(observations).await?;
+    for digest in &digests {
+      add_scopes(target.metadata, digest, &scopes).await?;
+    }
+    if let Some(search) = target.search {
+      search.index_observations(documents).await?;
+    }
+  }
+  Ok(stats)
+}
+
+/// Whether a blob is stored under `key`
+async fn blob_exists(blobs: &dyn BlobStorage, key: &BlobKey) -> StorageResult<bool> {
+  match blobs.blob_size(key).await {
+    Ok(_) => Ok(true),
+    Err(StorageError::NotFound(_)) => Ok(false),
+    Err(e) => Err(e),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::auth::ApiKeyRecord;
+  use crate::auth::ApiKeyScope;
+  use crate::snapshot::SnapshotBaseline;
+  use crate::storage::ObjectStoreBlobStorage;
+  use crate::storage::ObservationWithPayloads;
+  use crate::storage::SledStorage;
+  use crate::storage::SqlStorage;
+  use crate::storage::StoredPayload;
+  use bytes::Bytes;
+  use chrono::Utc;
+  use observation_tools_shared::ContentEncoding;
+  use observation_tools_shared::LogLevel;
+  use observation_tools_shared::Observation;
+  use observation_tools_shared::ObservationId;
+  use observation_tools_shared::ObservationType;
+  use observation_tools_shared::PayloadId;
+  use observation_tools_shared::Project;
+  use observation_tools_shared::ProjectId;
+  use std::collections::HashMap;
+
+  /// Digest of content shared by the payloads of every observation
+  const SHARED_DIGEST: &str = "cdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcd";
+
+  fn observation(execution_id: ExecutionId, name: &str) -> ObservationWithPayloads {
+    ObservationWithPayloads {
+      observation: Observation {
+        id: ObservationId::new(),
+        execution_id,
+        name: name.to_string(),
+        observation_type: ObservationType::LogEntry,
+        log_level: LogLevel::Info,
+        source: None,
+        metadata: HashMap::new(),
+        group_ids: vec![],
+        parent_group_id: None,
+        parent_span_id: None,
+        created_at: Utc::now(),
+      },
+      payloads: vec![
+        StoredPayload {
+          id: PayloadId::from("inline"),
+          name: "text".to_string(),
+          mime_type: "text/plain".to_string(),
+          size: 5,
+          encoding: ContentEncoding::Identity,
+          digest: None,
+          data: PayloadData::Inline(b"hello".to_vec()),
+        },
+        StoredPayload {
+          id: PayloadId::from("blob"),
+          name: "raw".to_string(),
+          mime_type: "application/octet-stream".to_string(),
+          size: 4,
+          encoding: ContentEncoding::Identity,
+          digest: None,
+          data: PayloadData::Blob,
+        },
+        StoredPayload {
+          id: PayloadId::from("content"),
+          name: "shared".to_string(),
+          mime_type: "application/octet-stream".to_string(),
+          size: 6,
+          encoding: ContentEncoding::Identity,
+          digest: Some(SHARED_DIGEST.to_string()),
+          data: PayloadData::Blob,
+        },
+      ],
+    }
+  }
+
+  #[tokio::test]
+  async fn test_migrate_and_resume() {
+    let dir = tempfile::tempdir().unwrap();
+    let source_metadata = SledStorage::new(dir.path().join("sled")).unwrap();
+    let source_blobs = ObjectStoreBlobStorage::from_url("memory://").unwrap();
+    let shared_key = BlobKey::content(SHARED_DIGEST, ContentEncoding::Identity);
+    source_blobs
+      .store_blob(&shared_key, Bytes::from("shared"))
+      .await
+      .unwrap();
+    let mut executions = Vec::new();
+    for name in ["first", "second", "third"] {
+      let execution = Execution::new(name);
+      let obs = observation(execution.id, name);
+      source_blobs
+        .store_blob(
+          &BlobKey::payload(obs.observation.id, PayloadId::from("blob")),
+          Bytes::from("blob"),
+        )
+        .await
+        .unwrap();
+      source_metadata.store_execution(&execution).await.unwrap();
+      source_metadata.store_observations(vec![obs]).await.unwrap();
+      executions.push(execution);
+    }
+    let key = ApiKeyRecord::new("ci", ApiKeyScope::WriteOnly);
+    source_metadata.store_api_key(&key).await.unwrap();
+    let project = Project::new(ProjectId::parse("team-a").unwrap(), "Team A");
+    source_metadata.store_project(&project).await.unwrap();
+    let baseline = SnapshotBaseline::new(&executions[1], &observation(executions[1].id, "second"));
+    source_metadata.store_snapshot_baseline(&baseline).await.unwrap();
+
+    let url = format!("sqlite://{}", dir.path().join("metadata.db").display());
+    let target_metadata = SqlStorage::connect(&url).await.unwrap();
+    let target_blobs = ObjectStoreBlobStorage::from_url("memory://").unwrap();
+    let target = MigrationTarget {
+      metadata: &target_metadata,
+      blobs: Some(&target_blobs),
+      search: None,
+    };
+
+    // Pretend a previous run already copied the first execution
+    let checkpoint_path = dir.path().join("checkpoint");
+    std::fs::write(&checkpoint_path, format!("{}\n", executions[0].id)).unwrap();
+    let mut checkpoint = Checkpoint::open(&checkpoint_path).unwrap();
+    let mut progress = Vec::new();
+    let stats = migrate(&source_metadata, &source_blobs, &target, &mut checkpoint, |p| {
+      progress.push((p.completed, p.total))
+    })
+    .await
+    .unwrap();
+    assert_eq!(
+      stats,
+      MigrationStats {
+        executions: 2,
+        observations: 2,
+        // The shared content is copied once
+        blobs: 3,
+        api_keys: 1,
+        projects: 1,
+        snapshot_baselines: 1,
+        skipped_executions: 1,
+      }
+    );
+    assert_eq!(target_metadata.get_api_key(key.id).await.unwrap(), key);
+    assert_eq!(target_metadata.get_project(&project.id).await.unwrap(), project);
+    assert_eq!(
+      target_metadata
+        .get_snapshot_baseline(&baseline.project, "second", "second")
+        .await
+        .unwrap(),
+      baseline
+    );
+    assert_eq!(progress.len(), 2);
+    assert!(progress.iter().all(|(_, total)| *total == 3));
+    assert_eq!(checkpoint.len(), 3);
+    assert!(matches!(
+      target_metadata.get_execution(executions[0].id).await,
+      Err(StorageError::NotFound(_))
+    ));
+
+    for execution in &executions[1..] {
+      let copied = target_metadata
+        .list_observations(execution.id, None, None, &ObservationFilter::default())
+        .await
+        .unwrap();
+      assert_eq!(copied.len(), 1);
+      let obs = target_metadata
+        .get_observation(copied[0].observation.id)
+        .await
+        .unwrap();
+      assert!(matches!(&obs.payloads[0].data, PayloadData::Inline(d) if d == b"hello"));
+      let blob = target_blobs
+        .get_blob(&BlobKey::payload(obs.observation.id, PayloadId::from("blob")))
+        .await
+        .unwrap();
+      assert_eq!(blob, Bytes::from("blob"));
+      // Clients may reference the copied content by its digest
+      assert!(target_metadata
+        .blob_in_scope(SHARED_DIGEST, &BlobScope::Execution(execution.id))
+        .await
+        .unwrap());
+    }
+    assert_eq!(target_blobs.get_blob(&shared_key).await.unwrap(), Bytes::from("shared"));
+    assert!(target_metadata
+      .blob_in_scope(SHARED_DIGEST, &BlobScope::Project(executions[1].project.clone()))
+      .await
+      .unwrap());
+    assert!(!target_metadata
+      .blob_in_scope(SHARED_DIGEST, &BlobScope::Execution(executions[0].id))
+      .await
+      .unwrap());
+
+    // Everything is recorded, so running again copies nothing
+    let stats = migrate(&source_metadata, &source_blobs, &target, &mut checkpoint, |_| {})
+      .await
+      .unwrap();
+    assert_eq!(stats.executions, 0);
+    assert_eq!(stats.skipped_executions, 3);
+    checkpoint.remove().unwrap();
+    assert!(!checkpoint_path.exists());
+  }
+}
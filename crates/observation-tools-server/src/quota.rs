@@ -0,0 +1,299 @@
+//! Upload quotas
+//!
+//! Per-execution quotas are checked against usage counters kept in metadata
+//! storage, which a batch is added to before it is stored. Observations that
+//! are stored again, e.g. by a retried upload, only count with the bytes they
+//! add, and free the bytes they no longer hold. Content uploaded
+//! separately counts against the daily quota when it is received, and against
+//! the execution once an observation references it.
+//!
+//! Daily per-API-key usage is tracked in memory, so it starts over when the
+//! server restarts. Requests made without authentication share one daily
+//! budget.
+
+use crate::api::AppError;
+use crate::auth::ApiKeyId;
+use crate::config::QuotaConfig;
+use crate::storage::MetadataStorage;
+use chrono::DateTime;
+use chrono::NaiveDate;
+use chrono::Utc;
+use observation_tools_shared::ExecutionId;
+use observation_tools_shared::Quota;
+use observation_tools_shared::QuotaExceeded;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Amount of data stored for, or added to, an execution
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecutionUsage {
+  pub observations: u64,
+  pub bytes: u64,
+}
+
+impl ExecutionUsage {
+  pub fn saturating_add(self, other: ExecutionUsage) -> ExecutionUsage {
+    ExecutionUsage {
+      observations: self.observations.saturating_add(other.observations),
+      bytes: self.bytes.saturating_add(other.bytes),
+    }
+  }
+
+  pub fn saturating_sub(self, other: ExecutionUsage) -> ExecutionUsage {
+    ExecutionUsage {
+      observations: self.observations.saturating_sub(other.observations),
+      bytes: self.bytes.saturating_sub(other.bytes),
+    }
+  }
+}
+
+/// Bytes uploaded with one API key on one UTC day
+#[derive(Debug, Clone, Copy)]
+struct DailyUsage {
+  day: NaiveDate,
+  bytes: u64,
+}
+
+/// Enforces the configured [`QuotaConfig`]
+#[derive(Debug, Clone, Default)]
+pub struct Quotas {
+  config: QuotaConfig,
+  daily: Arc<Mutex<HashMap<Option<ApiKeyId>, DailyUsage>>>,
+}
+
+impl Quotas {
+  pub fn new(config: QuotaConfig) -> Self {
+    Self {
+      config,
+      daily: Default::default(),
+    }
+  }
+
+  /// Whether any per-execution quota is configured
+  pub fn limits_executions(&self) -> bool {
+    self.config.max_observations_per_execution.is_some()
+      || self.config.max_bytes_per_execution.is_some()
+  }
+
+  /// Check that adding `requested` to an execution currently holding `used`
+  /// stays within the per-execution quotas
+  pub fn check_execution(
+    &self,
+    used: ExecutionUsage,
+    requested: ExecutionUsage,
+  ) -> Result<(), QuotaExceeded> {
+    check(
+      Quota::ExecutionObservations,
+      self.config.max_observations_per_execution,
+      used.observations,
+      requested.observations,
+    )?;
+    check(
+      Quota::ExecutionBytes,
+      self.config.max_bytes_per_execution,
+      used.bytes,
+      requested.bytes,
+    )
+  }
+
+  /// Add `requested` to the usage counters of an execution, failing without
+  /// adding it if that would exceed the per-execution quotas
+  pub async fn reserve_execution(
+    &self,
+    metadata: &dyn MetadataStorage,
+    execution_id: ExecutionId,
+    requested: ExecutionUsage,
+  ) -> Result<(), AppError> {
+    self
+      .add_execution_usage(metadata, execution_id, requested, requested)
+      .await
+  }
+
+  /// Return usage reserved with [`Quotas::reserve_execution`] for data that
+  /// was not stored after all
+  pub async fn release_execution(
+    &self,
+    metadata: &dyn MetadataStorage,
+    execution_id: ExecutionId,
+    usage: ExecutionUsage,
+  ) -> Result<(), AppError> {
+    Ok(metadata.remove_execution_usage(execution_id, usage).await?)
+  }
+
+  /// Check that `requested` fits into the per-execution quotas without
+  /// reserving it, for data that is reserved once an observation uses it
+  pub async fn check_execution_usage(
+    &self,
+    metadata: &dyn MetadataStorage,
+    execution_id: ExecutionId,
+    requested: ExecutionUsage,
+  ) -> Result<(), AppError> {
+    if !self.limits_executions() {
+      return Ok(());
+    }
+    self
+      .add_execution_usage(metadata, execution_id, ExecutionUsage::default(), requested)
+      .await
+  }
+
+  async fn add_execution_usage(
+    &self,
+    metadata: &dyn MetadataStorage,
+    execution_id: ExecutionId,
+    usage: ExecutionUsage,
+    requested: ExecutionUsage,
+  ) -> Result<(), AppError> {
+    let quotas = self.clone();
+    let fits = move |used| quotas.check_execution(used, requested).is_ok();
+    let (used, added) = metadata
+      .add_execution_usage(execution_id, usage, &fits)
+      .await?;
+    if !added {
+      self.check_execution(used, requested)?;
+    }
+    Ok(())
+  }
+
+  /// Count `bytes` against the daily quota of `key`, failing without
+  /// counting them if that would exceed the quota
+  pub fn charge_api_key(
+    &self,
+    key: Option<ApiKeyId>,
+    bytes: u64,
+    now: DateTime<Utc>,
+  ) -> Result<(), QuotaExceeded> {
+    let Some(limit) = self.config.max_bytes_per_api_key_per_day else {
+      return Ok(());
+    };
+    let today = now.date_naive();
+    let mut daily = self.daily.lock().expect("quota lock poisoned");
+    daily.retain(|_, usage| usage.day == today);
+    let usage = daily.entry(key).or_insert(DailyUsage {
+      day: today,
+      bytes: 0,
+    });
+    check(Quota::ApiKeyDailyBytes, Some(limit), usage.bytes, bytes)?;
+    usage.bytes += bytes;
+    Ok(())
+  }
+
+  /// Return bytes charged with [`Quotas::charge_api_key`] for data that was
+  /// not stored after all
+  pub fn refund_api_key(&self, key: Option<ApiKeyId>, bytes: u64, now: DateTime<Utc>) {
+    let mut daily = self.daily.lock().expect("quota lock poisoned");
+    if let Some(usage) = daily.get_mut(&key) {
+      if usage.day == now.date_naive() {
+        usage.bytes = usage.bytes.saturating_sub(bytes);
+      }
+    }
+  }
+}
+
+fn check(quota: Quota, limit: Option<u64>, used: u64, requested: u64) -> Result<(), QuotaExceeded> {
+  match limit {
+    Some(limit) if used.saturating_add(requested) > limit => {
+      Err(QuotaExceeded::new(quota, limit, used, requested))
+    }
+    _ => Ok(()),
+  }
+}
+
+/// Time until daily quotas start over, at the next UTC midnight
+pub fn until_next_day(now: DateTime<Utc>) -> Duration {
+  let midnight = now
+    .date_naive()
+    .succ_opt()
+    .and_then(|day| day.and_hms_opt(0, 0, 0))
+    .map(|midnight| midnight.and_utc());
+  midnight
+    .and_then(|midnight| (midnight - now).to_std().ok())
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::TimeZone;
+  use uuid::Uuid;
+
+  fn config() -> QuotaConfig {
+    QuotaConfig {
+      max_observations_per_execution: Some(10),
+      max_bytes_per_execution: Some(1000),
+      max_bytes_per_api_key_per_day: Some(100),
+    }
+  }
+
+  #[test]
+  fn test_check_execution() {
+    let quotas = Quotas::new(config());
+    let used = ExecutionUsage {
+      observations: 8,
+      bytes: 900,
+    };
+    let fits = ExecutionUsage {
+      observations: 2,
+      bytes: 100,
+    };
+    assert_eq!(quotas.check_execution(used, fits), Ok(()));
+
+    let err = quotas
+      .check_execution(
+        used,
+        ExecutionUsage {
+          observations: 3,
+          bytes: 0,
+        },
+      )
+      .unwrap_err();
+    assert_eq!(err.quota, Quota::ExecutionObservations);
+    assert_eq!((err.limit, err.used, err.requested), (10, 8, 3));
+
+    let err = quotas
+      .check_execution(
+        used,
+        ExecutionUsage {
+          observations: 1,
+          bytes: 101,
+        },
+      )
+      .unwrap_err();
+    assert_eq!(err.quota, Quota::ExecutionBytes);
+
+    let unlimited = Quotas::default();
+    assert!(!unlimited.limits_executions());
+    assert_eq!(unlimited.check_execution(used, fits), Ok(()));
+  }
+
+  #[test]
+  fn test_api_key_daily_bytes() {
+    let quotas = Quotas::new(config());
+    let key = Some(ApiKeyId(Uuid::new_v4()));
+    let now = Utc.with_ymd_and_hms(2024, 5, 1, 23, 0, 0).unwrap();
+
+    quotas.charge_api_key(key, 60, now).unwrap();
+    let err = quotas.charge_api_key(key, 60, now).unwrap_err();
+    assert_eq!(err.quota, Quota::ApiKeyDailyBytes);
+    assert_eq!((err.limit, err.used, err.requested), (100, 60, 60));
+
+    // Other keys have their own budget
+    quotas.charge_api_key(None, 60, now).unwrap();
+
+    quotas.refund_api_key(key, 30, now);
+    quotas.charge_api_key(key, 60, now).unwrap();
+
+    // Usage starts over the next day
+    let tomorrow = now + chrono::Duration::hours(2);
+    quotas.charge_api_key(key, 100, tomorrow).unwrap();
+  }
+
+  #[test]
+  fn test_until_next_day() {
+    let now = Utc.with_ymd_and_hms(2024, 5, 1, 23, 59, 30).unwrap();
+    assert_eq!(until_next_day(now), Duration::from_secs(30));
+  }
+}
@@ -0,0 +1,251 @@
+//! Execution retention and garbage collection
+//!
+//! Deleting an execution touches every store: the metadata database, the blob
+//! store and the search index. The background sweeper applies the configured
+//! [`RetentionConfig`] periodically, and the same code backs the
+//! `DELETE /api/exe/{id}` endpoint and the `gc` CLI subcommand. The policy
+//! never deletes executions holding a snapshot baseline.
+//!
+//! Content-addressed blobs may be shared between executions, so deleting an
+//! execution only releases its references. Each sweep then collects the blobs
+//! left without references.
+
+use crate::config::RetentionConfig;
+use crate::storage::BlobKey;
+use crate::storage::BlobStorage;
+use crate::storage::ExecutionFilter;
+use crate::storage::MetadataStorage;
+use crate::storage::PayloadData;
+use crate::storage::SearchIndex;
+use crate::storage::StorageResult;
+use chrono::DateTime;
+use chrono::Utc;
+use observation_tools_shared::Execution;
+use observation_tools_shared::ExecutionId;
+use std::collections::HashSet;
+use std::sync::Arc;
+use tracing::info;
+use tracing::warn;
+
+/// What was removed by deleting one or more executions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeletionStats {
+  pub executions: usize,
+  pub observations: usize,
+  pub blobs: usize,
+}
+
+impl std::ops::AddAssign for DeletionStats {
+  fn add_assign(&mut self, other: Self) {
+    self.executions += other.executions;
+    self.observations += other.observations;
+    self.blobs += other.blobs;
+  }
+}
+
+/// Delete an execution and everything stored for it, except content-addressed
+/// blobs, which are left to [`collect_blobs`]
+pub async fn delete_execution(
+  metadata: &dyn MetadataStorage,
+  blobs: &dyn BlobStorage,
+  search: &dyn SearchIndex,
+  execution_id: ExecutionId,
+) -> StorageResult<DeletionStats> {
+  let observations = metadata.delete_execution(execution_id).await?;
+  let mut stats = DeletionStats {
+    executions: 1,
+    observations: observations.len(),
+    blobs: 0,
+  };
+  for obs in &observations {
+    for payload in &obs.payloads {
+      if matches!(payload.data, PayloadData::Blob) && payload.digest.is_none() {
+        blobs
+          .delete_blob(&payload.blob_key(obs.observation.id))
+          .await?;
+        stats.blobs += 1;
+      }
+    }
+  }
+  search.remove_execution(execution_id).await?;
+  Ok(stats)
+}
+
+/// Delete content-addressed blobs that no payload has referenced since
+/// `before`, returning how many were deleted
+pub async fn collect_blobs(
+  metadata: &dyn MetadataStorage,
+  blobs: &dyn BlobStorage,
+  before: DateTime<Utc>,
+) -> StorageResult<usize> {
+  let mut deleted = 0;
+  for record in metadata.unreferenced_blobs(before).await? {
+    // The record goes first, so a blob is never missing while it is
+    // registered. A reference added since listing keeps both.
+    if !metadata.delete_blob_record(&record.digest, before).await? {
+      continue;
+    }
+    blobs
+      .delete_blob(&BlobKey::content(record.digest, record.encoding))
+      .await?;
+    deleted += 1;
+  }
+  Ok(deleted)
+}
+
+/// Pick the executions that violate the retention policy.
+///
+/// `executions` must be sorted newest first and paired with their size in
+/// bytes. Newer executions are kept in preference to older ones.
+fn expired_executions(
+  policy: &RetentionConfig,
+  executions: &[(Execution, u64)],
+  now: DateTime<Utc>,
+) -> Vec<ExecutionId> {
+  let mut total_bytes: u64 = 0;
+  let mut expired = Vec::new();
+  for (index, (execution, size)) in executions.iter().enumerate() {
+    total_bytes = total_bytes.saturating_add(*size);
+    let too_old = policy.max_age.is_some_and(|max_age| {
+      now
+        .signed_duration_since(execution.created_at)
+        .to_std()
+        .is_ok_and(|age| age > max_age)
+    });
+    let too_many = policy.max_executions.is_some_and(|max| index >= max);
+    let too_large = policy.max_total_bytes.is_some_and(|max| total_bytes > max);
+    if too_old || too_many || too_large {
+      expired.push(execution.id);
+    }
+  }
+  expired
+}
+
+/// Delete all executions that violate the retention policy, then collect
+/// unreferenced blobs older than the policy's grace period
+pub async fn sweep(
+  metadata: &dyn MetadataStorage,
+  blobs: &dyn BlobStorage,
+  search: &dyn SearchIndex,
+  policy: &RetentionConfig,
+) -> StorageResult<DeletionStats> {
+  let mut stats = DeletionStats::default();
+  if policy.is_enabled() {
+    stats += delete_expired(metadata, blobs, search, policy).await?;
+  }
+  let before = chrono::Duration::from_std(policy.blob_grace_period)
+    .ok()
+    .and_then(|grace_period| Utc::now().checked_sub_signed(grace_period))
+    .unwrap_or(DateTime::<Utc>::MIN_UTC);
+  stats.blobs += collect_blobs(metadata, blobs, before).await?;
+  Ok(stats)
+}
+
+async fn delete_expired(
+  metadata: &dyn MetadataStorage,
+  blobs: &dyn BlobStorage,
+  search: &dyn SearchIndex,
+  policy: &RetentionConfig,
+) -> StorageResult<DeletionStats> {
+  let mut stats = DeletionStats::default();
+  let filter = ExecutionFilter::default();
+  let count = metadata.count_executions(&filter).await?;
+  let executions = metadata.list_executions(Some(count), None, &filter).await?;
+  let mut sized = Vec::with_capacity(executions.len());
+  for execution in executions {
+    let size = if policy.max_total_bytes.is_some() {
+      metadata.execution_size(execution.id).await?
+    } else {
+      0
+    };
+    sized.push((execution, size));
+  }
+
+  // Snapshot baselines are kept until they are replaced
+  let baselines: HashSet<ExecutionId> = metadata
+    .list_snapshot_baselines()
+    .await?
+    .into_iter()
+    .map(|baseline| baseline.execution_id)
+    .collect();
+  for execution_id in expired_executions(policy, &sized, Utc::now()) {
+    if baselines.contains(&execution_id) {
+      continue;
+    }
+    stats += delete_execution(metadata, blobs, search, execution_id).await?;
+  }
+  Ok(stats)
+}
+
+/// Spawn a task that runs [`sweep`] every `policy.sweep_interval`
+pub fn spawn_sweeper(
+  metadata: Arc<dyn MetadataStorage>,
+  blobs: Arc<dyn BlobStorage>,
+  search: Arc<dyn SearchIndex>,
+  policy: RetentionConfig,
+) -> tokio::task::JoinHandle<()> {
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(policy.sweep_interval);
+    loop {
+      interval.tick().await;
+      match sweep(metadata.as_ref(), blobs.as_ref(), search.as_ref(), &policy).await {
+        Ok(stats) if stats != DeletionStats::default() => {
+          info!(
+            executions = stats.executions,
+            observations = stats.observations,
+            blobs = stats.blobs,
+            "Retention sweep deleted expired data"
+          );
+        }
+        Ok(_) => {}
+        Err(e) => warn!(error = %e, "Retention sweep failed"),
+      }
+    }
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::Duration;
+
+  fn execution(name: &str, age: chrono::Duration, now: DateTime<Utc>) -> Execution {
+    let mut execution = Execution::new(name);
+    execution.created_at = now - age;
+    execution
+  }
+
+  #[test]
+  fn test_expired_executions() {
+    let now = Utc::now();
+    let executions = vec![
+      (execution("new", chrono::Duration::hours(1), now), 100),
+      (execution("mid", chrono::Duration::days(2), now), 100),
+      (execution("old", chrono::Duration::days(10), now), 100),
+    ];
+    let ids: Vec<ExecutionId> = executions.iter().map(|(e, _)| e.id).collect();
+
+    let by_age = RetentionConfig {
+      max_age: Some(Duration::from_secs(7 * 24 * 60 * 60)),
+      ..Default::default()
+    };
+    assert_eq!(expired_executions(&by_age, &executions, now), vec![ids[2]]);
+
+    let by_count = RetentionConfig {
+      max_executions: Some(1),
+      ..Default::default()
+    };
+    assert_eq!(
+      expired_executions(&by_count, &executions, now),
+      vec![ids[1], ids[2]]
+    );
+
+    let by_size = RetentionConfig {
+      max_total_bytes: Some(250),
+      ..Default::default()
+    };
+    assert_eq!(expired_executions(&by_size, &executions, now), vec![ids[2]]);
+
+    assert!(expired_executions(&RetentionConfig::default(), &executions, now).is_empty());
+  }
+}
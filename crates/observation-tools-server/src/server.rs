@@ -1,13 +1,39 @@
+use crate::abandoned;
 use crate::api::AppState;
+use crate::auth;
+use crate::auth::ApiKeyRecord;
 use crate::api::{self};
 use crate::config::Config;
 use crate::csrf;
-use crate::storage::LocalBlobStorage;
+use crate::csrf::CsrfSecret;
+use crate::events::ObservationEvents;
+use crate::migrate;
+use crate::migrate::Checkpoint;
+use crate::migrate::MigrationProgress;
+use crate::migrate::MigrationStats;
+use crate::migrate::MigrationTarget;
+use crate::quota::Quotas;
+use crate::retention;
+use crate::retention::DeletionStats;
+use crate::session;
+use crate::session::ReadAuth;
+use crate::snapshot;
+use crate::snapshot::SnapshotBaseline;
+use crate::storage::BlobStorage;
+use crate::storage::MetadataStorage;
+use crate::storage::ObjectStoreBlobStorage;
+use crate::storage::SearchIndex;
+use crate::storage::SledSearchIndex;
 use crate::storage::SledStorage;
+use crate::storage::SqlStorage;
 use crate::ui;
 use axum::middleware;
 use axum::routing::get;
+use axum::routing::post;
 use axum::Router;
+use observation_tools_shared::ObservationId;
+use observation_tools_shared::Project;
+use observation_tools_shared::ProjectId;
 use std::sync::Arc;
 use tower_http::services::ServeDir;
 use tower_http::trace::TraceLayer;
@@ -15,6 +41,14 @@ use tracing::debug;
 use tracing::info;
 use tracing::warn;
 use utoipa_swagger_ui::SwaggerUi;
+use uuid::Uuid;
+
+/// The stores backing the server
+struct Storage {
+  metadata: Arc<dyn MetadataStorage>,
+  blobs: Arc<dyn BlobStorage>,
+  search: Arc<dyn SearchIndex>,
+}
 
 /// The Observation Tools server
 pub struct Server {
@@ -26,25 +60,236 @@ impl Server {
     Self { config }
   }
 
+  /// Open the metadata, blob and search stores configured for the server
+  async fn open_storage(&self) -> anyhow::Result<Storage> {
+    debug!(
+      data_dir = ?self.config.data_dir,
+      blob_url = ?self.config.blob_url,
+      metadata_url = ?self.config.metadata_url,
+      "Initializing storage"
+    );
+    Ok(Storage {
+      metadata: open_metadata(&self.config).await?,
+      blobs: open_blobs(&self.config)?,
+      search: open_search(&self.config)?,
+    })
+  }
+
+  /// Run a single retention sweep without starting the server, which also
+  /// collects unreferenced blobs.
+  ///
+  /// The data directory must not be in use by a running server.
+  pub async fn gc(self) -> anyhow::Result<DeletionStats> {
+    let storage = self.open_storage().await?;
+    let stats = retention::sweep(
+      storage.metadata.as_ref(),
+      storage.blobs.as_ref(),
+      storage.search.as_ref(),
+      &self.config.retention,
+    )
+    .await?;
+    Ok(stats)
+  }
+
+  /// Add an API key to the key registry, returning the key. Requires the API
+  /// secret.
+  ///
+  /// Like all key registry methods, this opens the metadata store, so with
+  /// the sled store the data directory must not be in use by a running
+  /// server.
+  pub async fn create_api_key(self, record: &ApiKeyRecord) -> anyhow::Result<String> {
+    let Some(secret) = &self.config.api_secret else {
+      anyhow::bail!("No API key secret configured");
+    };
+    let metadata = open_metadata(&self.config).await?;
+    Ok(auth::create_api_key(record, secret, metadata.as_ref()).await?)
+  }
+
+  /// List the API keys in the key registry, oldest first
+  pub async fn list_api_keys(self) -> anyhow::Result<Vec<ApiKeyRecord>> {
+    let metadata = open_metadata(&self.config).await?;
+    Ok(metadata.list_api_keys().await?)
+  }
+
+  /// Revoke the API key with the given ID, returning its record
+  pub async fn revoke_api_key(self, id: Uuid) -> anyhow::Result<ApiKeyRecord> {
+    let metadata = open_metadata(&self.config).await?;
+    Ok(auth::revoke_api_key(id, metadata.as_ref()).await?)
+  }
+
+  /// Create a project, or rename an existing one
+  pub async fn create_project(
+    self,
+    id: ProjectId,
+    name: Option<String>,
+  ) -> anyhow::Result<Project> {
+    let metadata = open_metadata(&self.config).await?;
+    Ok(api::projects::upsert_project(metadata.as_ref(), id, name).await?)
+  }
+
+  /// List the projects, ordered by ID
+  pub async fn list_projects(self) -> anyhow::Result<Vec<Project>> {
+    let metadata = open_metadata(&self.config).await?;
+    Ok(metadata.list_projects().await?)
+  }
+
+  /// List the snapshot baselines, ordered by project, execution name and name
+  pub async fn list_snapshot_baselines(self) -> anyhow::Result<Vec<SnapshotBaseline>> {
+    let metadata = open_metadata(&self.config).await?;
+    Ok(metadata.list_snapshot_baselines().await?)
+  }
+
+  /// Make an observation the baseline of its snapshot
+  pub async fn accept_snapshot(
+    self,
+    observation_id: ObservationId,
+  ) -> anyhow::Result<SnapshotBaseline> {
+    let metadata = open_metadata(&self.config).await?;
+    let observation = metadata.get_observation(observation_id).await?;
+    let execution = metadata.get_execution(observation.observation.execution_id).await?;
+    Ok(snapshot::accept_snapshot(metadata.as_ref(), &execution, &observation).await?)
+  }
+
+  /// Copy all executions, observations and blobs into the stores configured
+  /// by `target`, skipping executions already recorded in `checkpoint`.
+  ///
+  /// Stores shared by both configurations are not copied. Neither side may be
+  /// in use by a running server.
+  pub async fn migrate(
+    self,
+    target: Config,
+    checkpoint: &mut Checkpoint,
+    on_progress: impl FnMut(&MigrationProgress),
+  ) -> anyhow::Result<MigrationStats> {
+    let same_data_dir = self.config.data_dir == target.data_dir;
+    let same_metadata = match (&self.config.metadata_url, &target.metadata_url) {
+      (None, None) => same_data_dir,
+      (source, target) => source == target,
+    };
+    if same_metadata {
+      anyhow::bail!("Source and target metadata stores are the same");
+    }
+    let same_blobs = match (&self.config.blob_url, &target.blob_url) {
+      (None, None) => self.config.blob_dir == target.blob_dir,
+      (source, target) => source == target,
+    };
+
+    let source = self.open_storage().await?;
+    let target_metadata = open_metadata(&target).await?;
+    let target_blobs = if same_blobs {
+      None
+    } else {
+      Some(open_blobs(&target)?)
+    };
+    let target_search = if same_data_dir {
+      None
+    } else {
+      Some(open_search(&target)?)
+    };
+    let stats = migrate::migrate(
+      source.metadata.as_ref(),
+      source.blobs.as_ref(),
+      &MigrationTarget {
+        metadata: target_metadata.as_ref(),
+        blobs: target_blobs.as_deref(),
+        search: target_search.as_deref(),
+      },
+      checkpoint,
+      on_progress,
+    )
+    .await?;
+    Ok(stats)
+  }
+
   pub async fn run(self, listener: tokio::net::TcpListener) -> anyhow::Result<()> {
     info!("Starting Observation Tools server");
-    debug!(data_dir = ?self.config.data_dir, "Initializing storage");
+    let read_auth = match &self.config.read_auth {
+      Some(config) => {
+        let Some(secret) = self.config.api_secret.clone() else {
+          anyhow::bail!("Read authentication requires an API key secret");
+        };
+        info!("Read authentication is enabled");
+        Some(ReadAuth::new(config, secret)?)
+      }
+      None => None,
+    };
+    let storage = self.open_storage().await?;
     let state = AppState {
-      metadata: Arc::new(SledStorage::new(&self.config.data_dir.join("metadata"))?),
-      blobs: Arc::new(LocalBlobStorage::new(&self.config.blob_dir)?),
+      metadata: storage.metadata,
+      blobs: storage.blobs,
+      search: storage.search,
+      events: ObservationEvents::new(),
       templates: ui::init_templates(),
+      payload_encoding: self.config.payload_encoding,
+      quotas: Quotas::new(self.config.quotas.clone()),
+      read_auth,
+      csrf_secret: CsrfSecret::new(self.config.api_secret.clone()),
     };
 
-    let ui_router = Router::new()
-      .route("/", get(ui::index))
+    let abandoned_detector = self
+      .config
+      .abandoned_after
+      .filter(|after| !after.is_zero())
+      .map(|after| {
+        info!(abandoned_after = ?after, "Starting abandoned execution detection");
+        abandoned::spawn_detector(state.metadata.clone(), after)
+      });
+    // Runs without retention limits too, to collect unreferenced blobs
+    info!(retention = ?self.config.retention, "Starting retention sweeper");
+    let sweeper = retention::spawn_sweeper(
+      state.metadata.clone(),
+      state.blobs.clone(),
+      state.search.clone(),
+      self.config.retention.clone(),
+    );
+
+    // Execution pages show every project, or one when nested in its scope
+    let execution_pages = Router::new()
       .route("/exe", get(ui::list_executions))
       .route("/exe/{id}", get(ui::execution_detail_log))
       .route("/exe/{id}/payload", get(ui::execution_detail_payload))
+      .route("/exe/{id}/tree", get(ui::execution_detail_tree))
+      .route("/exe/{id}/events", get(ui::execution_events_log))
+      .route("/exe/{id}/payload/events", get(ui::execution_events_payload))
       .route(
         "/exe/{execution_id}/obs/{observation_id}",
         get(ui::observation_detail),
       )
-      .layer(middleware::from_fn(csrf::ui_csrf_middleware))
+      .route(
+        "/exe/{execution_id}/obs/{observation_id}/payload/{payload_id}/diff",
+        get(ui::diff_payload),
+      )
+      .route(
+        "/exe/{execution_id}/obs/{observation_id}/snapshot/accept",
+        post(ui::accept_snapshot_baseline).layer(middleware::from_fn({
+          let csrf_secret = state.csrf_secret.clone();
+          move |req, next| csrf::validate_csrf(csrf_secret.clone(), req, next)
+        })),
+      );
+    let ui_router = Router::new()
+      .route("/", get(ui::index))
+      .route("/diff", get(ui::diff_executions))
+      .merge(execution_pages.clone())
+      .nest("/p/{project}", execution_pages)
+      .route_layer(middleware::from_fn({
+        let read_auth = state.read_auth.clone();
+        let metadata = state.metadata.clone();
+        move |req, next| {
+          session::ui_read_auth_middleware(read_auth.clone(), metadata.clone(), req, next)
+        }
+      }))
+      .route("/login", get(ui::login_page).post(ui::login))
+      .route(
+        "/logout",
+        post(ui::logout).layer(middleware::from_fn({
+          let csrf_secret = state.csrf_secret.clone();
+          move |req, next| csrf::validate_csrf(csrf_secret.clone(), req, next)
+        })),
+      )
+      .layer(middleware::from_fn({
+        let csrf_secret = state.csrf_secret.clone();
+        move |req, next| csrf::ui_csrf_middleware(csrf_secret.clone(), req, next)
+      }))
       .nest_service("/static", {
         let static_dir = std::env::current_dir()?.join("crates/observation-tools-server/static");
         if !static_dir.exists() {
@@ -60,15 +305,25 @@ impl Server {
       });
 
     let api_secret = self.config.api_secret.clone();
+    let key_registry = state.metadata.clone();
     let (mutating_router, readonly_router, openapi) = api::build_api();
     let api_router = Router::new()
       .merge(
         mutating_router.layer(axum::middleware::from_fn(move |req, next| {
-          crate::auth::api_key_middleware(api_secret.clone(), req, next)
+          crate::auth::api_key_middleware(api_secret.clone(), key_registry.clone(), req, next)
         })),
       )
-      .merge(readonly_router)
-      .layer(middleware::from_fn(csrf::validate_csrf));
+      .merge(readonly_router.route_layer(middleware::from_fn({
+        let read_auth = state.read_auth.clone();
+        let metadata = state.metadata.clone();
+        move |req, next| {
+          session::read_auth_middleware(read_auth.clone(), metadata.clone(), req, next)
+        }
+      })))
+      .layer(middleware::from_fn({
+        let csrf_secret = state.csrf_secret.clone();
+        move |req, next| csrf::validate_csrf(csrf_secret.clone(), req, next)
+      }));
     let app = Router::new()
       .merge(ui_router)
       .merge(api_router)
@@ -79,8 +334,31 @@ impl Server {
     let bound_addr = listener.local_addr()?;
     info!("Server listening on http://{}", bound_addr);
 
-    axum::serve(listener, app).await?;
+    let result = axum::serve(listener, app).await;
+    sweeper.abort();
+    if let Some(detector) = abandoned_detector {
+      detector.abort();
+    }
+    result?;
 
     Ok(())
   }
 }
+
+async fn open_metadata(config: &Config) -> anyhow::Result<Arc<dyn MetadataStorage>> {
+  Ok(match &config.metadata_url {
+    Some(url) => Arc::new(SqlStorage::connect(url).await?),
+    None => Arc::new(SledStorage::new(config.data_dir.join("metadata"))?),
+  })
+}
+
+fn open_blobs(config: &Config) -> anyhow::Result<Arc<dyn BlobStorage>> {
+  Ok(Arc::new(match &config.blob_url {
+    Some(url) => ObjectStoreBlobStorage::from_url(url)?,
+    None => ObjectStoreBlobStorage::new_local(&config.blob_dir)?,
+  }))
+}
+
+fn open_search(config: &Config) -> anyhow::Result<Arc<dyn SearchIndex>> {
+  Ok(Arc::new(SledSearchIndex::new(config.data_dir.join("search"))?))
+}
@@ -0,0 +1,414 @@
+//! Authentication for reads
+//!
+//! When read authentication is enabled, the read-only API and the UI require
+//! an API key allowed to read or a UI login. UI users are listed in a users
+//! file with argon2 password hashes, see [`Users`]. A login is kept in a
+//! session cookie signed with the API key secret, which is valid until it
+//! expires or the user is removed from the file.
+
+use crate::auth::authenticate;
+use crate::auth::bearer_token;
+use crate::auth::ApiKeySecret;
+use crate::auth::AuthError;
+use crate::config::ReadAuthConfig;
+use crate::csrf::cookie_value;
+use crate::storage::MetadataStorage;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::PasswordHash;
+use argon2::password_hash::PasswordHasher;
+use argon2::password_hash::PasswordVerifier;
+use argon2::password_hash::SaltString;
+use argon2::Argon2;
+use axum::extract::FromRequestParts;
+use axum::extract::Request;
+use axum::http::HeaderMap;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum::response::Redirect;
+use axum::response::Response;
+use axum_extra::extract::cookie::Cookie;
+use axum_extra::extract::cookie::SameSite;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use chrono::DateTime;
+use chrono::Utc;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::path::Path;
+use std::sync::Arc;
+
+pub const SESSION_COOKIE_NAME: &str = "obs_session";
+
+/// Keeps session signatures apart from API key signatures
+const SESSION_SIGNATURE_CONTEXT: &[u8] = b"observation-tools session\0";
+
+/// A UI login. Added to the request extensions by
+/// [`ui_read_auth_middleware`] and [`read_auth_middleware`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+  pub user: String,
+  pub expires_at: DateTime<Utc>,
+}
+
+/// The logged in UI user, if any, for showing in templates
+#[derive(Debug, Clone)]
+pub struct CurrentUser(pub Option<String>);
+
+impl<S> FromRequestParts<S> for CurrentUser
+where
+  S: Send + Sync,
+{
+  type Rejection = Infallible;
+
+  async fn from_request_parts(
+    parts: &mut axum::http::request::Parts,
+    _state: &S,
+  ) -> Result<Self, Self::Rejection> {
+    Ok(CurrentUser(
+      parts
+        .extensions
+        .get::<Session>()
+        .map(|session| session.user.clone()),
+    ))
+  }
+}
+
+/// Users who may log in to the UI
+///
+/// The users file has a `name:hash` line per user, where `hash` is an argon2
+/// hash in PHC string format as written by `users add`. Empty lines and lines
+/// starting with `#` are ignored.
+#[derive(Default)]
+pub struct Users {
+  hashes: HashMap<String, String>,
+}
+
+impl Users {
+  pub fn load(path: &Path) -> anyhow::Result<Self> {
+    let contents = std::fs::read_to_string(path)
+      .map_err(|e| anyhow::anyhow!("Failed to read users file {}: {}", path.display(), e))?;
+    let mut hashes = HashMap::new();
+    for (index, line) in contents.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+      let Some((name, hash)) = line.split_once(':') else {
+        anyhow::bail!("{}:{}: Expected `name:hash`", path.display(), index + 1);
+      };
+      if let Err(e) = PasswordHash::new(hash) {
+        anyhow::bail!("{}:{}: Invalid password hash: {}", path.display(), index + 1, e);
+      }
+      hashes.insert(name.to_string(), hash.to_string());
+    }
+    Ok(Users { hashes })
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.hashes.is_empty()
+  }
+
+  pub fn contains(&self, name: &str) -> bool {
+    self.hashes.contains_key(name)
+  }
+
+  /// Whether `password` is the password of `name`
+  pub fn verify(&self, name: &str, password: &str) -> bool {
+    let Some(hash) = self.hashes.get(name) else {
+      return false;
+    };
+    // Checked when the file was loaded
+    let Ok(hash) = PasswordHash::new(hash) else {
+      return false;
+    };
+    Argon2::default()
+      .verify_password(password.as_bytes(), &hash)
+      .is_ok()
+  }
+}
+
+/// Hash a password for the users file
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+  let salt = SaltString::generate(&mut OsRng);
+  let hash = Argon2::default()
+    .hash_password(password.as_bytes(), &salt)
+    .map_err(|e| anyhow::anyhow!("Failed to hash password: {}", e))?;
+  Ok(hash.to_string())
+}
+
+/// Add a user to the users file or change their password, creating the file
+/// if needed. Returns whether the user was added.
+pub fn set_user_password(path: &Path, name: &str, password: &str) -> anyhow::Result<bool> {
+  let reserved = name.starts_with('#') || name.contains(':');
+  if name.is_empty() || reserved || name.contains(char::is_whitespace) {
+    anyhow::bail!("Invalid user name '{}'", name);
+  }
+  let contents = match std::fs::read_to_string(path) {
+    Ok(contents) => contents,
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+    Err(e) => return Err(e.into()),
+  };
+  let entry = format!("{}:{}", name, hash_password(password)?);
+  let mut added = true;
+  let mut lines = Vec::new();
+  for line in contents.lines() {
+    if line.trim().split_once(':').is_some_and(|(user, _)| user == name) {
+      lines.push(entry.clone());
+      added = false;
+    } else {
+      lines.push(line.to_string());
+    }
+  }
+  if added {
+    lines.push(entry);
+  }
+  std::fs::write(path, lines.join("\n") + "\n")?;
+  Ok(added)
+}
+
+/// Checks reads when read authentication is enabled
+#[derive(Clone)]
+pub struct ReadAuth {
+  secret: ApiKeySecret,
+  users: Arc<Users>,
+  session_lifetime: chrono::Duration,
+}
+
+impl ReadAuth {
+  pub fn new(config: &ReadAuthConfig, secret: ApiKeySecret) -> anyhow::Result<Self> {
+    let users = match &config.users_file {
+      Some(path) => Users::load(path)?,
+      None => Users::default(),
+    };
+    Ok(ReadAuth {
+      secret,
+      users: Arc::new(users),
+      session_lifetime: chrono::Duration::from_std(config.session_lifetime)?,
+    })
+  }
+
+  /// Whether any UI users may log in
+  pub fn has_users(&self) -> bool {
+    !self.users.is_empty()
+  }
+
+  /// Check a UI user's password, returning the session cookie to set
+  pub fn login(&self, user: &str, password: &str) -> Result<Option<Cookie<'static>>, AuthError> {
+    if !self.users.verify(user, password) {
+      return Ok(None);
+    }
+    let session = Session {
+      user: user.to_string(),
+      expires_at: Utc::now() + self.session_lifetime,
+    };
+    let cookie = Cookie::build((SESSION_COOKIE_NAME, self.sign_session(&session)?))
+      .path("/")
+      .same_site(SameSite::Lax)
+      .http_only(true)
+      .build();
+    Ok(Some(cookie))
+  }
+
+  fn sign_session(&self, session: &Session) -> Result<String, AuthError> {
+    let payload = serde_json::to_vec(session).expect("session serializes");
+    let signature = self
+      .secret
+      .sign(&[SESSION_SIGNATURE_CONTEXT, &payload].concat())?;
+    Ok(format!(
+      "{}.{}",
+      URL_SAFE_NO_PAD.encode(&payload),
+      URL_SAFE_NO_PAD.encode(&signature)
+    ))
+  }
+
+  /// The valid session in the request's cookies, if any
+  fn session(&self, headers: &HeaderMap, now: DateTime<Utc>) -> Option<Session> {
+    let value = cookie_value(headers, SESSION_COOKIE_NAME)?;
+    self.verify_session(&value, now)
+  }
+
+  fn verify_session(&self, value: &str, now: DateTime<Utc>) -> Option<Session> {
+    let (payload, signature) = value.split_once('.')?;
+    let payload = URL_SAFE_NO_PAD.decode(payload).ok()?;
+    let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+    let signed = [SESSION_SIGNATURE_CONTEXT, &payload].concat();
+    if !self.secret.verify(&signed, &signature).ok()? {
+      return None;
+    }
+    let session: Session = serde_json::from_slice(&payload).ok()?;
+    (session.expires_at > now && self.users.contains(&session.user)).then_some(session)
+  }
+}
+
+/// A cookie removing the session cookie
+pub fn logout_cookie() -> Cookie<'static> {
+  let mut cookie = Cookie::build((SESSION_COOKIE_NAME, "")).path("/").build();
+  cookie.make_removal();
+  cookie
+}
+
+/// Only redirect to paths on this server after logging in
+pub fn local_redirect(next: Option<&str>) -> &str {
+  match next {
+    Some(next) if next.starts_with('/') && !next.starts_with("//") && !next.contains('\\') => next,
+    _ => "/",
+  }
+}
+
+/// Require an API key allowed to read or a UI login, when read
+/// authentication is enabled
+pub async fn read_auth_middleware(
+  read_auth: Option<ReadAuth>,
+  metadata: Arc<dyn MetadataStorage>,
+  mut request: Request,
+  next: Next,
+) -> Response {
+  let Some(read_auth) = read_auth else {
+    return next.run(request).await;
+  };
+  match authorize_read(&read_auth, metadata.as_ref(), &mut request).await {
+    Ok(()) => next.run(request).await,
+    Err(e) => e.into_response(),
+  }
+}
+
+/// Like [`read_auth_middleware`], but sends UI users without a login to the
+/// login page
+pub async fn ui_read_auth_middleware(
+  read_auth: Option<ReadAuth>,
+  metadata: Arc<dyn MetadataStorage>,
+  mut request: Request,
+  next: Next,
+) -> Response {
+  let Some(read_auth) = read_auth else {
+    return next.run(request).await;
+  };
+  match authorize_read(&read_auth, metadata.as_ref(), &mut request).await {
+    Ok(()) => next.run(request).await,
+    Err(AuthError::MissingAuthHeader) => {
+      let path = request
+        .uri()
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+      let login_url = format!(
+        "/login?next={}",
+        url::form_urlencoded::byte_serialize(path.as_bytes()).collect::<String>()
+      );
+      // htmx would swap the login page into the current one
+      if request.headers().contains_key("hx-request") {
+        (StatusCode::UNAUTHORIZED, [("hx-redirect", login_url)]).into_response()
+      } else {
+        Redirect::to(&login_url).into_response()
+      }
+    }
+    Err(e) => e.into_response(),
+  }
+}
+
+/// Authenticate a read with the request's API key or session cookie, adding
+/// the key or session to the request extensions
+async fn authorize_read(
+  read_auth: &ReadAuth,
+  metadata: &dyn MetadataStorage,
+  request: &mut Request,
+) -> Result<(), AuthError> {
+  if let Some(token) = bearer_token(request.headers())? {
+    let key = authenticate(token, &read_auth.secret, metadata).await?;
+    if !key.scope.can_read() {
+      return Err(AuthError::InsufficientScope(key.scope));
+    }
+    request.extensions_mut().insert(key);
+    return Ok(());
+  }
+  let session = read_auth
+    .session(request.headers(), Utc::now())
+    .ok_or(AuthError::MissingAuthHeader)?;
+  request.extensions_mut().insert(session);
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn read_auth(dir: &Path) -> ReadAuth {
+    let users_file = dir.join("users");
+    set_user_password(&users_file, "alice", "correct horse").unwrap();
+    let config = ReadAuthConfig {
+      users_file: Some(users_file),
+      ..Default::default()
+    };
+    ReadAuth::new(&config, ApiKeySecret::new("test-secret-key-for-sessions").unwrap()).unwrap()
+  }
+
+  #[test]
+  fn test_users_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("users");
+    std::fs::write(&path, "# UI users\n\n").unwrap();
+    assert!(set_user_password(&path, "alice", "first").unwrap());
+    assert!(set_user_password(&path, "bob", "second").unwrap());
+    assert!(!set_user_password(&path, "alice", "changed").unwrap());
+    assert!(set_user_password(&path, "a:b", "password").is_err());
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    assert!(contents.starts_with("# UI users\n"));
+    let users = Users::load(&path).unwrap();
+    assert!(users.verify("alice", "changed"));
+    assert!(!users.verify("alice", "first"));
+    assert!(users.verify("bob", "second"));
+    assert!(!users.verify("carol", "second"));
+
+    std::fs::write(&path, "alice:not-a-hash\n").unwrap();
+    assert!(Users::load(&path).is_err());
+  }
+
+  #[test]
+  fn test_session_cookie() {
+    let dir = tempfile::tempdir().unwrap();
+    let read_auth = read_auth(dir.path());
+    assert!(read_auth.login("alice", "wrong").unwrap().is_none());
+    assert!(read_auth.login("bob", "correct horse").unwrap().is_none());
+
+    let cookie = read_auth.login("alice", "correct horse").unwrap().unwrap();
+    let now = Utc::now();
+    let session = read_auth.verify_session(cookie.value(), now).unwrap();
+    assert_eq!(session.user, "alice");
+    assert!(read_auth
+      .verify_session(cookie.value(), session.expires_at)
+      .is_none());
+
+    // Changing the user invalidates the signature
+    let (_, signature) = cookie.value().split_once('.').unwrap();
+    let forged = Session {
+      user: "admin".to_string(),
+      ..session
+    };
+    let forged = format!(
+      "{}.{}",
+      URL_SAFE_NO_PAD.encode(serde_json::to_vec(&forged).unwrap()),
+      signature
+    );
+    assert!(read_auth.verify_session(&forged, now).is_none());
+
+    // As does a different secret
+    let other = ReadAuth {
+      secret: ApiKeySecret::new("another-secret-key-for-sessions").unwrap(),
+      ..read_auth.clone()
+    };
+    assert!(other.verify_session(cookie.value(), now).is_none());
+  }
+
+  #[test]
+  fn test_local_redirect() {
+    assert_eq!(local_redirect(Some("/exe?status=running")), "/exe?status=running");
+    assert_eq!(local_redirect(Some("//evil.example")), "/");
+    assert_eq!(local_redirect(Some("/\\evil.example")), "/");
+    assert_eq!(local_redirect(Some("https://evil.example")), "/");
+    assert_eq!(local_redirect(None), "/");
+  }
+}
@@ -0,0 +1,163 @@
+//! Snapshot testing against baselines
+//!
+//! A snapshot is an observation whose payload is compared with the accepted
+//! value of the same observation from an earlier run, its baseline. Baselines
+//! are identified by project, execution name and observation name, and point
+//! at the observation that was accepted. The first snapshot of a name becomes
+//! its baseline.
+
+use crate::api::diff::payload_diff;
+use crate::api::diff::PayloadChange;
+use crate::api::AppError;
+use crate::diff::DiffStatus;
+use crate::storage::BlobStorage;
+use crate::storage::MetadataStorage;
+use crate::storage::ObservationWithPayloads;
+use crate::storage::StorageError;
+use crate::storage::StorageResult;
+use chrono::DateTime;
+use chrono::Utc;
+use observation_tools_shared::Execution;
+use observation_tools_shared::ExecutionId;
+use observation_tools_shared::ObservationId;
+use observation_tools_shared::ProjectId;
+use serde::Deserialize;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// The accepted value of a snapshot
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct SnapshotBaseline {
+  pub project: ProjectId,
+  pub execution_name: String,
+  /// Name of the observation
+  pub name: String,
+  pub execution_id: ExecutionId,
+  pub observation_id: ObservationId,
+  pub accepted_at: DateTime<Utc>,
+}
+
+impl SnapshotBaseline {
+  /// Make `observation` of `execution` the baseline for its name
+  pub fn new(execution: &Execution, observation: &ObservationWithPayloads) -> Self {
+    Self {
+      project: execution.project.clone(),
+      execution_name: execution.name.clone(),
+      name: observation.observation.name.clone(),
+      execution_id: execution.id,
+      observation_id: observation.observation.id,
+      accepted_at: Utc::now(),
+    }
+  }
+}
+
+/// Outcome of checking a snapshot against its baseline
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum SnapshotStatus {
+  /// There was no baseline, the snapshot became the baseline
+  Created,
+  /// The snapshot matches the baseline
+  Matched,
+  /// The snapshot differs from the baseline
+  Mismatched,
+  /// The snapshot differed from the baseline and replaced it
+  Accepted,
+}
+
+/// A snapshot compared with its baseline
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SnapshotCheck {
+  pub status: SnapshotStatus,
+  pub baseline: SnapshotBaseline,
+  /// Differences of the snapshot's first payload from the baseline's
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub diff: Option<PayloadChange>,
+}
+
+/// Compare an observation with the baseline for its name, if there is one.
+/// Baselines whose observation has since been deleted are ignored.
+pub async fn compare_with_baseline(
+  metadata: &dyn MetadataStorage,
+  blobs: &dyn BlobStorage,
+  execution: &Execution,
+  observation: &ObservationWithPayloads,
+) -> Result<Option<SnapshotCheck>, AppError> {
+  let baseline = match metadata
+    .get_snapshot_baseline(&execution.project, &execution.name, &observation.observation.name)
+    .await
+  {
+    Ok(baseline) => baseline,
+    Err(StorageError::NotFound(_)) => return Ok(None),
+    Err(e) => return Err(e.into()),
+  };
+  let Some(payload) = observation.payloads.first() else {
+    return Err(AppError::BadRequest(format!(
+      "Observation {} has no payload to compare",
+      observation.observation.id
+    )));
+  };
+  if baseline.observation_id == observation.observation.id {
+    return Ok(Some(SnapshotCheck {
+      status: SnapshotStatus::Matched,
+      baseline,
+      diff: None,
+    }));
+  }
+  let baseline_observation = match metadata.get_observation(baseline.observation_id).await {
+    Ok(baseline_observation) => baseline_observation,
+    Err(StorageError::NotFound(_)) => return Ok(None),
+    Err(e) => return Err(e.into()),
+  };
+  let diff = payload_diff(blobs, &baseline_observation, None, observation, &payload.id).await?;
+  let status = match diff.status {
+    DiffStatus::Unchanged => SnapshotStatus::Matched,
+    _ => SnapshotStatus::Mismatched,
+  };
+  Ok(Some(SnapshotCheck {
+    status,
+    baseline,
+    diff: Some(diff),
+  }))
+}
+
+/// Compare an observation with its baseline, making it the baseline if there
+/// is none yet or, when `accept` is set, if it differs
+pub async fn check_snapshot(
+  metadata: &dyn MetadataStorage,
+  blobs: &dyn BlobStorage,
+  execution: &Execution,
+  observation: &ObservationWithPayloads,
+  accept: bool,
+) -> Result<SnapshotCheck, AppError> {
+  let check = compare_with_baseline(metadata, blobs, execution, observation).await?;
+  match check {
+    Some(check) if check.status == SnapshotStatus::Matched || !accept => Ok(check),
+    Some(check) => Ok(SnapshotCheck {
+      status: SnapshotStatus::Accepted,
+      baseline: accept_snapshot(metadata, execution, observation).await?,
+      diff: check.diff,
+    }),
+    None => Ok(SnapshotCheck {
+      status: SnapshotStatus::Created,
+      baseline: accept_snapshot(metadata, execution, observation).await?,
+      diff: None,
+    }),
+  }
+}
+
+/// Make an observation the baseline for its name
+pub async fn accept_snapshot(
+  metadata: &dyn MetadataStorage,
+  execution: &Execution,
+  observation: &ObservationWithPayloads,
+) -> StorageResult<SnapshotBaseline> {
+  let baseline = SnapshotBaseline::new(execution, observation);
+  metadata.store_snapshot_baseline(&baseline).await?;
+  tracing::info!(
+    execution_name = %baseline.execution_name,
+    name = %baseline.name,
+    observation_id = %baseline.observation_id,
+    "Snapshot baseline accepted"
+  );
+  Ok(baseline)
+}
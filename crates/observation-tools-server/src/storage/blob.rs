@@ -3,83 +3,265 @@
 use super::StorageError;
 use super::StorageResult;
 use bytes::Bytes;
+use futures::stream::BoxStream;
+use futures::StreamExt;
 use object_store::local::LocalFileSystem;
+use object_store::memory::InMemory;
 use object_store::path::Path as ObjectPath;
+use object_store::prefix::PrefixStore;
+use object_store::GetOptions;
+use object_store::GetRange;
 use object_store::ObjectStore;
+use object_store::WriteMultipart;
+use observation_tools_shared::ContentEncoding;
 use observation_tools_shared::ObservationId;
 use observation_tools_shared::PayloadId;
+use std::fmt;
+use std::ops::Range;
 use std::path::Path;
 use std::sync::Arc;
+use url::Url;
+
+/// Stream of blob data chunks
+pub type BlobStream = BoxStream<'static, std::io::Result<Bytes>>;
+
+/// Number of parts uploaded concurrently while streaming a blob
+const MAX_CONCURRENT_PARTS: usize = 4;
+
+/// Location of a blob in blob storage
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlobKey {
+  /// Payload data identified by the SHA-256 of its uncompressed content,
+  /// shared by every payload with the same content
+  Content {
+    digest: String,
+    encoding: ContentEncoding,
+  },
+  /// Payload data stored per payload, before blobs were content-addressed
+  Payload {
+    observation_id: ObservationId,
+    payload_id: PayloadId,
+  },
+  /// Streamed upload whose digest is not known yet
+  Upload(uuid::Uuid),
+}
+
+impl BlobKey {
+  pub fn content(digest: impl Into<String>, encoding: ContentEncoding) -> Self {
+    BlobKey::Content {
+      digest: digest.into(),
+      encoding,
+    }
+  }
+
+  pub fn payload(observation_id: ObservationId, payload_id: PayloadId) -> Self {
+    BlobKey::Payload {
+      observation_id,
+      payload_id,
+    }
+  }
+
+  /// Key for a new streamed upload
+  pub fn upload() -> Self {
+    BlobKey::Upload(uuid::Uuid::new_v4())
+  }
+
+  fn path(&self) -> ObjectPath {
+    ObjectPath::from(self.to_string())
+  }
+}
+
+impl fmt::Display for BlobKey {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      BlobKey::Content { digest, encoding } if encoding.is_identity() => {
+        write!(f, "sha256/{}", digest)
+      }
+      BlobKey::Content { digest, encoding } => write!(f, "sha256/{}.{}", digest, encoding),
+      BlobKey::Payload {
+        observation_id,
+        payload_id,
+      } => write!(f, "{}/{}", observation_id, payload_id.as_str()),
+      BlobKey::Upload(id) => write!(f, "uploads/{}", id),
+    }
+  }
+}
 
 /// Trait for storing and retrieving blob data
 #[async_trait::async_trait]
 pub trait BlobStorage: Send + Sync {
-  /// Store blob data for an observation payload
-  async fn store_blob(
+  /// Store blob data under a key
+  async fn store_blob(&self, key: &BlobKey, data: Bytes) -> StorageResult<()>;
+
+  /// Store blob data read from a stream without buffering it in memory,
+  /// returning the number of bytes written. Nothing is stored if the stream
+  /// fails.
+  async fn store_blob_stream(&self, key: &BlobKey, stream: BlobStream) -> StorageResult<u64>;
+
+  /// Retrieve blob data
+  async fn get_blob(&self, key: &BlobKey) -> StorageResult<Bytes>;
+
+  /// Size of the stored blob data, in bytes
+  async fn blob_size(&self, key: &BlobKey) -> StorageResult<u64>;
+
+  /// Stream blob data, optionally only the given byte range, which must lie
+  /// within the blob
+  async fn get_blob_stream(
     &self,
-    obs_id: ObservationId,
-    payload_id: PayloadId,
-    data: Bytes,
-  ) -> StorageResult<()>;
+    key: &BlobKey,
+    range: Option<Range<u64>>,
+  ) -> StorageResult<BlobStream>;
+
+  /// Move blob data to another key, replacing any blob stored there
+  async fn rename_blob(&self, from: &BlobKey, to: &BlobKey) -> StorageResult<()>;
 
-  /// Retrieve blob data for an observation payload
-  async fn get_blob(&self, obs_id: ObservationId, payload_id: PayloadId) -> StorageResult<Bytes>;
+  /// Delete blob data. Deleting a blob that does not exist is not an error.
+  async fn delete_blob(&self, key: &BlobKey) -> StorageResult<()>;
 }
 
 /// Object store-based blob storage
-pub struct LocalBlobStorage {
+///
+/// Blobs can live on the local filesystem or in any supported object store,
+/// see [`ObjectStoreBlobStorage::from_url`].
+pub struct ObjectStoreBlobStorage {
   store: Arc<dyn ObjectStore>,
 }
 
-impl LocalBlobStorage {
-  /// Create a new local blob storage instance
-  pub fn new(path: impl AsRef<Path>) -> StorageResult<Self> {
+impl ObjectStoreBlobStorage {
+  /// Create a blob storage instance backed by a local directory
+  pub fn new_local(path: impl AsRef<Path>) -> StorageResult<Self> {
     let path = path.as_ref();
 
     // Ensure the directory exists
     std::fs::create_dir_all(path)?;
 
-    let store =
-      LocalFileSystem::new_with_prefix(path).map_err(|e| StorageError::Internal(e.to_string()))?;
+    let store = LocalFileSystem::new_with_prefix(path)
+      .map_err(|e| StorageError::Internal(e.to_string()))?
+      // Remove the per-observation directories once their blobs are deleted
+      .with_automatic_cleanup(true);
 
     Ok(Self {
       store: Arc::new(store),
     })
   }
 
-  /// Convert observation ID + payload ID to object path
-  fn id_to_path(&self, obs_id: ObservationId, payload_id: PayloadId) -> ObjectPath {
-    ObjectPath::from(format!("{}/{}", obs_id, payload_id.as_str()))
+  /// Create a blob storage instance from an object store URL.
+  ///
+  /// Supported schemes:
+  /// - `file:///path/to/dir` - local directory
+  /// - `memory://` - in-memory store, for testing
+  /// - `s3://bucket/prefix` - Amazon S3 or S3-compatible (requires the `aws`
+  ///   feature)
+  /// - `gs://bucket/prefix` - Google Cloud Storage (requires the `gcp`
+  ///   feature)
+  /// - `az://container/prefix` - Azure Blob Storage (requires the `azure`
+  ///   feature)
+  ///
+  /// Cloud stores read credentials and settings from the standard environment
+  /// variables, e.g. `AWS_ACCESS_KEY_ID` and `AWS_ENDPOINT` for an
+  /// S3-compatible server such as MinIO. Any path after the bucket is used as
+  /// a key prefix.
+  pub fn from_url(url: &str) -> StorageResult<Self> {
+    let url = Url::parse(url)
+      .map_err(|e| StorageError::Internal(format!("Invalid blob store URL '{}': {}", url, e)))?;
+
+    let store: Arc<dyn ObjectStore> = match url.scheme() {
+      "file" => {
+        let path = url
+          .to_file_path()
+          .map_err(|_| StorageError::Internal(format!("Invalid file URL '{}'", url)))?;
+        return Self::new_local(path);
+      }
+      "memory" => Arc::new(InMemory::new()),
+      #[cfg(feature = "aws")]
+      "s3" | "s3a" => Arc::new(
+        object_store::aws::AmazonS3Builder::from_env()
+          .with_url(url.as_str())
+          .build()
+          .map_err(|e| StorageError::Internal(e.to_string()))?,
+      ),
+      #[cfg(feature = "gcp")]
+      "gs" => Arc::new(
+        object_store::gcp::GoogleCloudStorageBuilder::from_env()
+          .with_url(url.as_str())
+          .build()
+          .map_err(|e| StorageError::Internal(e.to_string()))?,
+      ),
+      #[cfg(feature = "azure")]
+      "az" | "azure" | "abfs" | "abfss" => Arc::new(
+        object_store::azure::MicrosoftAzureBuilder::from_env()
+          .with_url(url.as_str())
+          .build()
+          .map_err(|e| StorageError::Internal(e.to_string()))?,
+      ),
+      scheme => {
+        return Err(StorageError::Internal(format!(
+          "Unsupported blob store URL scheme '{}'. The server may need to be built with the \
+           matching feature (aws, gcp or azure).",
+          scheme
+        )))
+      }
+    };
+
+    // The builders only use the bucket from the URL, so apply the rest of the
+    // path as a prefix
+    let prefix = ObjectPath::from_url_path(url.path())
+      .map_err(|e| StorageError::Internal(format!("Invalid blob store prefix: {}", e)))?;
+    if prefix.as_ref().is_empty() {
+      return Ok(Self { store });
+    }
+    Ok(Self {
+      store: Arc::new(PrefixStore::new(store, prefix)),
+    })
   }
 }
 
 #[async_trait::async_trait]
-impl BlobStorage for LocalBlobStorage {
-  async fn store_blob(
-    &self,
-    obs_id: ObservationId,
-    payload_id: PayloadId,
-    data: Bytes,
-  ) -> StorageResult<()> {
-    let path = self.id_to_path(obs_id, payload_id);
-
+impl BlobStorage for ObjectStoreBlobStorage {
+  async fn store_blob(&self, key: &BlobKey, data: Bytes) -> StorageResult<()> {
     self
       .store
-      .put(&path, data.into())
+      .put(&key.path(), data.into())
       .await
       .map_err(|e| StorageError::Internal(e.to_string()))?;
 
     Ok(())
   }
 
-  async fn get_blob(&self, obs_id: ObservationId, payload_id: PayloadId) -> StorageResult<Bytes> {
-    let path = self.id_to_path(obs_id, payload_id);
-
-    let result = self
+  async fn store_blob_stream(&self, key: &BlobKey, mut stream: BlobStream) -> StorageResult<u64> {
+    let upload = self
       .store
-      .get(&path)
+      .put_multipart(&key.path())
+      .await
+      .map_err(|e| StorageError::Internal(e.to_string()))?;
+
+    let mut writer = WriteMultipart::new(upload);
+    let mut size = 0;
+    while let Some(chunk) = stream.next().await {
+      let chunk = match chunk {
+        Ok(chunk) => chunk,
+        Err(e) => {
+          let _ = writer.abort().await;
+          return Err(e.into());
+        }
+      };
+      size += chunk.len() as u64;
+      writer
+        .wait_for_capacity(MAX_CONCURRENT_PARTS)
+        .await
+        .map_err(|e| StorageError::Internal(e.to_string()))?;
+      writer.put(chunk);
+    }
+    writer
+      .finish()
       .await
-      .map_err(|e| StorageError::NotFound(format!("Blob not found: {}", e)))?;
+      .map_err(|e| StorageError::Internal(e.to_string()))?;
+
+    Ok(size)
+  }
+
+  async fn get_blob(&self, key: &BlobKey) -> StorageResult<Bytes> {
+    let result = self.store.get(&key.path()).await.map_err(map_read_error)?;
 
     let bytes = result
       .bytes()
@@ -88,4 +270,176 @@ impl BlobStorage for LocalBlobStorage {
 
     Ok(bytes)
   }
+
+  async fn blob_size(&self, key: &BlobKey) -> StorageResult<u64> {
+    let meta = self.store.head(&key.path()).await.map_err(map_read_error)?;
+    Ok(meta.size)
+  }
+
+  async fn get_blob_stream(
+    &self,
+    key: &BlobKey,
+    range: Option<Range<u64>>,
+  ) -> StorageResult<BlobStream> {
+    let options = GetOptions {
+      range: range.map(GetRange::Bounded),
+      ..Default::default()
+    };
+    let result = self
+      .store
+      .get_opts(&key.path(), options)
+      .await
+      .map_err(map_read_error)?;
+    Ok(result.into_stream().map(|chunk| chunk.map_err(std::io::Error::other)).boxed())
+  }
+
+  async fn rename_blob(&self, from: &BlobKey, to: &BlobKey) -> StorageResult<()> {
+    self
+      .store
+      .rename(&from.path(), &to.path())
+      .await
+      .map_err(map_read_error)
+  }
+
+  async fn delete_blob(&self, key: &BlobKey) -> StorageResult<()> {
+    match self.store.delete(&key.path()).await {
+      Ok(()) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+      Err(e) => Err(StorageError::Internal(e.to_string())),
+    }
+  }
+}
+
+fn map_read_error(e: object_store::Error) -> StorageError {
+  match e {
+    object_store::Error::NotFound { .. } => {
+      StorageError::NotFound(format!("Blob not found: {}", e))
+    }
+    e => StorageError::Internal(e.to_string()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn test_from_url_memory_round_trip() {
+    let storage = ObjectStoreBlobStorage::from_url("memory:///some/prefix").unwrap();
+    let key = BlobKey::payload(ObservationId::new(), PayloadId::new());
+    storage
+      .store_blob(&key, Bytes::from_static(b"data"))
+      .await
+      .unwrap();
+    assert_eq!(
+      storage.get_blob(&key).await.unwrap(),
+      Bytes::from_static(b"data")
+    );
+
+    storage.delete_blob(&key).await.unwrap();
+    assert!(matches!(
+      storage.get_blob(&key).await,
+      Err(StorageError::NotFound(_))
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_store_blob_stream() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = ObjectStoreBlobStorage::new_local(dir.path()).unwrap();
+    let key = BlobKey::payload(ObservationId::new(), PayloadId::new());
+    let chunks = vec![
+      Ok(Bytes::from_static(b"first ")),
+      Ok(Bytes::from_static(b"second")),
+    ];
+    let size = storage
+      .store_blob_stream(&key, futures::stream::iter(chunks).boxed())
+      .await
+      .unwrap();
+    assert_eq!(size, 12);
+    assert_eq!(
+      storage.get_blob(&key).await.unwrap(),
+      Bytes::from_static(b"first second")
+    );
+
+    // A failing stream leaves nothing behind
+    let key = BlobKey::upload();
+    let chunks = vec![
+      Ok(Bytes::from_static(b"partial")),
+      Err(std::io::Error::other("connection reset")),
+    ];
+    assert!(storage
+      .store_blob_stream(&key, futures::stream::iter(chunks).boxed())
+      .await
+      .is_err());
+    assert!(matches!(
+      storage.get_blob(&key).await,
+      Err(StorageError::NotFound(_))
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_get_blob_stream_range() {
+    let storage = ObjectStoreBlobStorage::from_url("memory://").unwrap();
+    let key = BlobKey::payload(ObservationId::new(), PayloadId::new());
+    storage
+      .store_blob(&key, Bytes::from_static(b"0123456789"))
+      .await
+      .unwrap();
+    assert_eq!(storage.blob_size(&key).await.unwrap(), 10);
+
+    let read = |range| async {
+      let chunks: Vec<_> = storage
+        .get_blob_stream(&key, range)
+        .await
+        .unwrap()
+        .collect()
+        .await;
+      chunks.into_iter().map(|c| c.unwrap()).collect::<Vec<_>>().concat()
+    };
+    assert_eq!(read(None).await, b"0123456789");
+    assert_eq!(read(Some(2..5)).await, b"234");
+    assert!(matches!(
+      storage.blob_size(&BlobKey::upload()).await,
+      Err(StorageError::NotFound(_))
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_rename_blob() {
+    let storage = ObjectStoreBlobStorage::from_url("memory://").unwrap();
+    let upload = BlobKey::upload();
+    storage.store_blob(&upload, Bytes::from_static(b"data")).await.unwrap();
+    let content = BlobKey::content("ab".repeat(32), ContentEncoding::Zstd);
+    storage.rename_blob(&upload, &content).await.unwrap();
+    assert_eq!(storage.get_blob(&content).await.unwrap(), Bytes::from_static(b"data"));
+    assert!(matches!(
+      storage.get_blob(&upload).await,
+      Err(StorageError::NotFound(_))
+    ));
+  }
+
+  #[test]
+  fn test_blob_key_path() {
+    let digest = "ab".repeat(32);
+    assert_eq!(
+      BlobKey::content(digest.clone(), ContentEncoding::Identity).to_string(),
+      format!("sha256/{}", digest)
+    );
+    assert_eq!(
+      BlobKey::content(digest.clone(), ContentEncoding::Zstd).to_string(),
+      format!("sha256/{}.zstd", digest)
+    );
+    let observation_id = ObservationId::new();
+    let payload_id = PayloadId::new();
+    assert_eq!(
+      BlobKey::payload(observation_id, payload_id.clone()).to_string(),
+      format!("{}/{}", observation_id, payload_id.as_str())
+    );
+  }
+
+  #[test]
+  fn test_from_url_rejects_unknown_scheme() {
+    assert!(ObjectStoreBlobStorage::from_url("ftp://example.com/blobs").is_err());
+    assert!(ObjectStoreBlobStorage::from_url("not a url").is_err());
+  }
 }
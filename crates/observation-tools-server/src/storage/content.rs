@@ -0,0 +1,126 @@
+//! Content-addressed storage of payload blobs
+//!
+//! Blob payloads are stored once per content digest, see [`BlobRecord`].
+//! Both helpers write the blob before registering its record, so that a
+//! registered digest always has its blob. If another encoding of the same
+//! content was registered first, the new copy is dropped in favor of it.
+//!
+//! Payloads may only reference stored content by its digest within the
+//! [`BlobScope`]s its data was received in.
+
+use super::BlobKey;
+use super::BlobRecord;
+use super::BlobScope;
+use super::BlobStorage;
+use super::BlobStream;
+use super::MetadataStorage;
+use super::StorageError;
+use super::StorageResult;
+use bytes::Bytes;
+use futures::StreamExt;
+use observation_tools_shared::ContentDigest;
+use observation_tools_shared::ContentEncoding;
+use observation_tools_shared::ExecutionId;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+/// Store payload data under its content digest, returning the record of the
+/// blob now holding the content
+pub async fn store_content(
+  metadata: &dyn MetadataStorage,
+  blobs: &dyn BlobStorage,
+  record: BlobRecord,
+  data: Bytes,
+) -> StorageResult<BlobRecord> {
+  let key = BlobKey::content(record.digest.clone(), record.encoding);
+  blobs.store_blob(&key, data).await?;
+  register(metadata, blobs, record, &key).await
+}
+
+/// Store uncompressed payload data read from a stream, hashing it on the way.
+/// The data is written to a temporary upload key and moved to its content
+/// key once the digest is known.
+pub async fn store_content_stream(
+  metadata: &dyn MetadataStorage,
+  blobs: &dyn BlobStorage,
+  stream: BlobStream,
+) -> StorageResult<BlobRecord> {
+  let digest = Arc::new(Mutex::new(ContentDigest::default()));
+  let hashed = {
+    let digest = digest.clone();
+    stream
+      .map(move |chunk| {
+        if let Ok(chunk) = &chunk {
+          digest.lock().expect("digest lock poisoned").update(chunk);
+        }
+        chunk
+      })
+      .boxed()
+  };
+  let upload = BlobKey::upload();
+  let size = blobs.store_blob_stream(&upload, hashed).await?;
+  let digest = digest.lock().expect("digest lock poisoned").clone().finish();
+
+  let record = BlobRecord::new(digest, size, ContentEncoding::Identity);
+  let key = BlobKey::content(record.digest.clone(), record.encoding);
+  if let Err(e) = blobs.rename_blob(&upload, &key).await {
+    let _ = blobs.delete_blob(&upload).await;
+    return Err(e);
+  }
+  register(metadata, blobs, record, &key).await
+}
+
+/// Scopes of content received for an execution: the execution itself, and
+/// its project once the execution is stored
+pub async fn execution_scopes(
+  metadata: &dyn MetadataStorage,
+  execution_id: ExecutionId,
+) -> StorageResult<Vec<BlobScope>> {
+  let mut scopes = vec![BlobScope::Execution(execution_id)];
+  match metadata.get_execution(execution_id).await {
+    Ok(execution) => scopes.push(BlobScope::Project(execution.project)),
+    Err(StorageError::NotFound(_)) => {}
+    Err(e) => return Err(e),
+  }
+  Ok(scopes)
+}
+
+/// Allow content to be referenced by its digest in each of `scopes`
+pub async fn add_scopes(
+  metadata: &dyn MetadataStorage,
+  digest: &str,
+  scopes: &[BlobScope],
+) -> StorageResult<()> {
+  for scope in scopes {
+    metadata.add_blob_scope(digest, scope).await?;
+  }
+  Ok(())
+}
+
+/// Whether content may be referenced by its digest in any of `scopes`
+pub async fn in_any_scope(
+  metadata: &dyn MetadataStorage,
+  digest: &str,
+  scopes: &[BlobScope],
+) -> StorageResult<bool> {
+  for scope in scopes {
+    if metadata.blob_in_scope(digest, scope).await? {
+      return Ok(true);
+    }
+  }
+  Ok(false)
+}
+
+/// Register a blob just written to `key`
+async fn register(
+  metadata: &dyn MetadataStorage,
+  blobs: &dyn BlobStorage,
+  record: BlobRecord,
+  key: &BlobKey,
+) -> StorageResult<BlobRecord> {
+  let (stored, created) = metadata.register_blob(&record).await?;
+  if !created && stored.encoding != record.encoding {
+    blobs.delete_blob(key).await?;
+  }
+  Ok(stored)
+}
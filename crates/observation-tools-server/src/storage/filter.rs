@@ -0,0 +1,340 @@
+//! Filters for narrowing down listed executions and observations
+
+use chrono::DateTime;
+use chrono::Utc;
+use observation_tools_shared::Execution;
+use observation_tools_shared::ExecutionStatus;
+use observation_tools_shared::GroupId;
+use observation_tools_shared::LogLevel;
+use observation_tools_shared::Observation;
+use observation_tools_shared::ObservationType;
+use observation_tools_shared::ProjectId;
+use std::collections::BTreeMap;
+
+/// Structured filter for listing and counting executions
+///
+/// All criteria are optional and AND'ed together. The default filter matches
+/// every execution.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExecutionFilter {
+  /// Only include executions in this project
+  pub project: Option<ProjectId>,
+
+  /// Case-insensitive substring of the execution name
+  pub name: Option<String>,
+
+  /// Only include executions with this status
+  pub status: Option<ExecutionStatus>,
+
+  /// Only include executions with this tag
+  pub tag: Option<String>,
+
+  /// Metadata pairs that must all be present with exactly these values
+  pub metadata: BTreeMap<String, String>,
+
+  /// Only include executions created at or after this time
+  pub created_after: Option<DateTime<Utc>>,
+
+  /// Only include executions created before this time
+  pub created_before: Option<DateTime<Utc>>,
+}
+
+impl ExecutionFilter {
+  /// Whether an execution satisfies every criterion of this filter
+  pub fn matches(&self, execution: &Execution) -> bool {
+    if self.project.as_ref().is_some_and(|p| execution.project != *p) {
+      return false;
+    }
+    if let Some(name) = &self.name {
+      if !execution.name.to_lowercase().contains(&name.to_lowercase()) {
+        return false;
+      }
+    }
+    if self.created_after.is_some_and(|t| execution.created_at < t) {
+      return false;
+    }
+    if self.created_before.is_some_and(|t| execution.created_at >= t) {
+      return false;
+    }
+    if self.status.is_some_and(|status| execution.status != status) {
+      return false;
+    }
+    if let Some(tag) = &self.tag {
+      if !execution.tags.contains(tag) {
+        return false;
+      }
+    }
+    self
+      .metadata
+      .iter()
+      .all(|(key, value)| execution.metadata.get(key) == Some(value))
+  }
+
+  /// Whether the only criteria are the created time range
+  pub fn is_time_range_only(&self) -> bool {
+    let time_range = ExecutionFilter {
+      created_after: self.created_after,
+      created_before: self.created_before,
+      ..Default::default()
+    };
+    *self == time_range
+  }
+}
+
+/// Structured filter for listing and counting observations
+///
+/// All criteria are optional and AND'ed together. The default filter matches
+/// every observation.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ObservationFilter {
+  /// Only include observations of this type
+  pub observation_type: Option<ObservationType>,
+
+  /// Only include observations at or above this log level
+  pub min_log_level: Option<LogLevel>,
+
+  /// Name prefix, or a glob pattern if it contains `*` or `?`
+  pub name: Option<String>,
+
+  /// Metadata pairs that must all be present with exactly these values
+  pub metadata: BTreeMap<String, String>,
+
+  /// Only include observations created at or after this time
+  pub created_after: Option<DateTime<Utc>>,
+
+  /// Only include observations created before this time
+  pub created_before: Option<DateTime<Utc>>,
+
+  /// Only include observations belonging to this group
+  pub group_id: Option<GroupId>,
+
+  /// Only include observations from this source file. Matches the full path
+  /// or any trailing path components, e.g. `main.rs` or `src/main.rs`.
+  pub source_file: Option<String>,
+}
+
+impl ObservationFilter {
+  /// Filter matching only observations of the given type
+  pub fn with_type(observation_type: ObservationType) -> Self {
+    Self {
+      observation_type: Some(observation_type),
+      ..Default::default()
+    }
+  }
+
+  /// Whether an observation satisfies every criterion of this filter
+  pub fn matches(&self, obs: &Observation) -> bool {
+    if self
+      .observation_type
+      .is_some_and(|t| obs.observation_type != t)
+    {
+      return false;
+    }
+    if self.min_log_level.is_some_and(|l| obs.log_level < l) {
+      return false;
+    }
+    if let Some(name) = &self.name {
+      let matched = if is_glob(name) {
+        glob_match(name, &obs.name)
+      } else {
+        obs.name.starts_with(name.as_str())
+      };
+      if !matched {
+        return false;
+      }
+    }
+    if !self
+      .metadata
+      .iter()
+      .all(|(key, value)| obs.metadata.get(key) == Some(value))
+    {
+      return false;
+    }
+    if self.created_after.is_some_and(|t| obs.created_at < t) {
+      return false;
+    }
+    if self.created_before.is_some_and(|t| obs.created_at >= t) {
+      return false;
+    }
+    if let Some(group_id) = &self.group_id {
+      let in_group =
+        obs.group_ids.contains(group_id) || obs.parent_group_id.as_ref() == Some(group_id);
+      if !in_group {
+        return false;
+      }
+    }
+    if let Some(file) = &self.source_file {
+      let matched = obs.source.as_ref().is_some_and(|source| {
+        source.file == *file || source.file.ends_with(&format!("/{}", file))
+      });
+      if !matched {
+        return false;
+      }
+    }
+    true
+  }
+}
+
+/// Parse a comma separated list of `key=value` pairs
+pub fn parse_metadata_pairs(s: &str) -> Result<BTreeMap<String, String>, String> {
+  let mut pairs = BTreeMap::new();
+  for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+    let Some((key, value)) = pair.split_once('=') else {
+      return Err(format!(
+        "Invalid metadata filter '{}', expected key=value",
+        pair
+      ));
+    };
+    pairs.insert(key.trim().to_string(), value.trim().to_string());
+  }
+  Ok(pairs)
+}
+
+fn is_glob(pattern: &str) -> bool {
+  pattern.contains(['*', '?'])
+}
+
+/// Match a glob pattern where `*` matches any run of characters and `?`
+/// matches exactly one
+fn glob_match(pattern: &str, text: &str) -> bool {
+  let pattern: Vec<char> = pattern.chars().collect();
+  let text: Vec<char> = text.chars().collect();
+  let (mut p, mut t) = (0, 0);
+  // Position of the last `*` and the text position it was tried at
+  let mut backtrack: Option<(usize, usize)> = None;
+  while t < text.len() {
+    match pattern.get(p) {
+      Some('*') => {
+        backtrack = Some((p, t));
+        p += 1;
+      }
+      Some('?') => {
+        p += 1;
+        t += 1;
+      }
+      Some(c) if *c == text[t] => {
+        p += 1;
+        t += 1;
+      }
+      _ => match backtrack {
+        Some((star_p, star_t)) => {
+          p = star_p + 1;
+          t = star_t + 1;
+          backtrack = Some((star_p, star_t + 1));
+        }
+        None => return false,
+      },
+    }
+  }
+  pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use observation_tools_shared::ExecutionId;
+  use observation_tools_shared::ObservationId;
+  use observation_tools_shared::SourceInfo;
+  use std::collections::HashMap;
+
+  fn observation(name: &str, log_level: LogLevel) -> Observation {
+    Observation {
+      id: ObservationId::new(),
+      execution_id: ExecutionId::new(),
+      name: name.to_string(),
+      observation_type: ObservationType::LogEntry,
+      log_level,
+      source: Some(SourceInfo {
+        file: "crates/app/src/main.rs".to_string(),
+        line: 10,
+        column: None,
+      }),
+      metadata: HashMap::from([("env".to_string(), "prod".to_string())]),
+      group_ids: vec![GroupId::from("g1")],
+      parent_group_id: None,
+      parent_span_id: None,
+      created_at: Utc::now(),
+    }
+  }
+
+  #[test]
+  fn test_glob_match() {
+    assert!(glob_match("http.*", "http.request"));
+    assert!(glob_match("*error*", "db error: timeout"));
+    assert!(glob_match("a?c", "abc"));
+    assert!(!glob_match("a?c", "abbc"));
+    assert!(!glob_match("*.json", "data.jsonl"));
+  }
+
+  #[test]
+  fn test_filter_matches() {
+    let obs = observation("http.request", LogLevel::Warning);
+    assert!(ObservationFilter::default().matches(&obs));
+
+    let level = |l| ObservationFilter {
+      min_log_level: Some(l),
+      ..Default::default()
+    };
+    assert!(level(LogLevel::Info).matches(&obs));
+    assert!(!level(LogLevel::Error).matches(&obs));
+
+    let name = |n: &str| ObservationFilter {
+      name: Some(n.to_string()),
+      ..Default::default()
+    };
+    assert!(name("http.").matches(&obs));
+    assert!(name("*request").matches(&obs));
+    assert!(!name("request").matches(&obs));
+
+    let source = |f: &str| ObservationFilter {
+      source_file: Some(f.to_string()),
+      ..Default::default()
+    };
+    assert!(source("src/main.rs").matches(&obs));
+    assert!(!source("ain.rs").matches(&obs));
+
+    let combined = ObservationFilter {
+      metadata: parse_metadata_pairs("env=prod").unwrap(),
+      group_id: Some(GroupId::from("g1")),
+      created_before: Some(obs.created_at),
+      ..Default::default()
+    };
+    assert!(!combined.matches(&obs));
+  }
+
+  #[test]
+  fn test_execution_filter_matches() {
+    let mut execution = Execution::new("Nightly Build");
+    execution.tags = vec!["ci".to_string()];
+    assert!(ExecutionFilter::default().matches(&execution));
+
+    let name = |n: &str| ExecutionFilter {
+      name: Some(n.to_string()),
+      ..Default::default()
+    };
+    assert!(name("build").matches(&execution));
+    assert!(name("NIGHTLY").matches(&execution));
+    assert!(!name("release").matches(&execution));
+
+    let range = ExecutionFilter {
+      created_after: Some(execution.created_at),
+      created_before: Some(execution.created_at + chrono::Duration::seconds(1)),
+      tag: Some("ci".to_string()),
+      ..Default::default()
+    };
+    assert!(range.matches(&execution));
+    let before = ExecutionFilter {
+      created_before: Some(execution.created_at),
+      ..Default::default()
+    };
+    assert!(!before.matches(&execution));
+  }
+
+  #[test]
+  fn test_parse_metadata_pairs() {
+    let pairs = parse_metadata_pairs("env=prod, user_id = 42,").unwrap();
+    assert_eq!(pairs.get("env"), Some(&"prod".to_string()));
+    assert_eq!(pairs.get("user_id"), Some(&"42".to_string()));
+    assert!(parse_metadata_pairs("env").is_err());
+  }
+}
@@ -3,19 +3,117 @@
 use super::proto::StoredInlinePayload;
 use super::proto::StoredObservation;
 use super::proto::StoredPayloadMeta;
+use super::ExecutionFilter;
+use super::ObservationFilter;
 use super::ObservationWithPayloads;
 use super::PayloadData;
 use super::StorageError;
 use super::StorageResult;
-use super::StoredPayload;
+use crate::auth::ApiKeyRecord;
+use crate::quota::ExecutionUsage;
+use crate::snapshot::SnapshotBaseline;
+use chrono::DateTime;
+use chrono::Utc;
+use observation_tools_shared::ContentEncoding;
 use observation_tools_shared::Execution;
 use observation_tools_shared::ExecutionId;
+use observation_tools_shared::Observation;
 use observation_tools_shared::ObservationId;
-use observation_tools_shared::ObservationType;
 use observation_tools_shared::PayloadId;
+use observation_tools_shared::Project;
+use observation_tools_shared::ProjectId;
 use prost::Message;
+use serde::Deserialize;
+use serde::Serialize;
+use sled::transaction::ConflictableTransactionError;
+use sled::transaction::TransactionError;
+use sled::transaction::TransactionResult;
+use sled::Transactional;
+use std::collections::HashMap;
 use std::path::Path;
 use tracing::trace;
+use uuid::Uuid;
+
+/// A content-addressed blob, shared by all payloads with the same content
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BlobRecord {
+  /// SHA-256 of the uncompressed content
+  pub digest: String,
+  /// Size of the content in bytes, before any compression
+  pub size: u64,
+  /// Compression of the stored blob
+  pub encoding: ContentEncoding,
+  /// Number of stored payloads referencing the blob
+  pub refcount: i64,
+  /// When the blob was last registered or released. Unreferenced blobs are
+  /// only collected once this is older than a grace period, so that uploads
+  /// whose observation is not stored yet are kept.
+  pub updated_at: DateTime<Utc>,
+}
+
+impl BlobRecord {
+  /// A record for a newly stored blob, not referenced by any payload yet
+  pub fn new(digest: impl Into<String>, size: u64, encoding: ContentEncoding) -> Self {
+    BlobRecord {
+      digest: digest.into(),
+      size,
+      encoding,
+      refcount: 0,
+      updated_at: Utc::now(),
+    }
+  }
+}
+
+/// Where content may be referenced by payloads declaring only its digest.
+/// Content is shared within the scopes its data was received in, so that
+/// knowing a digest does not give access to the content.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BlobScope {
+  /// Executions of a project
+  Project(ProjectId),
+  /// A single execution, for content received before the execution is stored
+  Execution(ExecutionId),
+}
+
+impl BlobScope {
+  pub(super) fn key(&self) -> String {
+    match self {
+      BlobScope::Project(project) => format!("project:{}", project.as_str()),
+      BlobScope::Execution(id) => format!("execution:{}", id),
+    }
+  }
+}
+
+/// Content digests referenced by a stored observation's payloads
+pub(super) fn payload_digests(stored: &StoredObservation) -> impl Iterator<Item = &str> {
+  stored
+    .payload_manifest
+    .iter()
+    .filter(|pm| !pm.digest.is_empty())
+    .map(|pm| pm.digest.as_str())
+}
+
+/// Observations are stored by ID, so one may only be replaced by an
+/// observation of the same execution
+pub(super) fn check_replaced_execution(
+  previous: &StoredObservation,
+  observation: &Observation,
+) -> StorageResult<()> {
+  if previous.execution_id == observation.execution_id.to_string() {
+    return Ok(());
+  }
+  Err(StorageError::AlreadyExists(format!(
+    "Observation {} belongs to another execution",
+    observation.id
+  )))
+}
+
+/// Change to a stored execution, see [`MetadataStorage::update_execution`]
+pub type ExecutionUpdate = dyn Fn(&mut Execution) -> bool + Send + Sync;
+
+/// Check of an execution's usage, see
+/// [`MetadataStorage::add_execution_usage`]
+pub type UsageCheck = dyn Fn(ExecutionUsage) -> bool + Send + Sync;
 
 /// Trait for storing and retrieving execution and observation metadata
 #[async_trait::async_trait]
@@ -26,17 +124,31 @@ pub trait MetadataStorage: Send + Sync {
   /// Get an execution by ID
   async fn get_execution(&self, id: ExecutionId) -> StorageResult<Execution>;
 
-  /// List all executions (with optional pagination)
+  /// Apply `update` to a stored execution and store the result if `update`
+  /// returns true. Concurrent updates are not overwritten, `update` is
+  /// applied again to the execution they stored instead. Returns the stored
+  /// execution, or `None` if `update` returned false.
+  async fn update_execution(
+    &self,
+    id: ExecutionId,
+    update: &ExecutionUpdate,
+  ) -> StorageResult<Option<Execution>>;
+
+  /// List executions matching a filter, most recent first (with optional
+  /// pagination)
   async fn list_executions(
     &self,
     limit: Option<usize>,
     offset: Option<usize>,
+    filter: &ExecutionFilter,
   ) -> StorageResult<Vec<Execution>>;
 
-  /// Count total number of executions
-  async fn count_executions(&self) -> StorageResult<usize>;
+  /// Count total number of executions matching a filter
+  async fn count_executions(&self, filter: &ExecutionFilter) -> StorageResult<usize>;
 
-  /// Store multiple observations with their payloads in a batch
+  /// Store multiple observations with their payloads in a batch. Payloads
+  /// with a content digest add a reference to its [`BlobRecord`]. Stored
+  /// observations are replaced, unless they belong to another execution.
   async fn store_observations(
     &self,
     observations: Vec<ObservationWithPayloads>,
@@ -48,23 +160,114 @@ pub trait MetadataStorage: Send + Sync {
     id: ObservationId,
   ) -> StorageResult<ObservationWithPayloads>;
 
-  /// List observations for an execution (with optional pagination and type filter).
-  /// Returns observations with all payloads as PayloadData::Blob (metadata only).
+  /// List observations for an execution matching a filter (with optional
+  /// pagination). Returns observations with all payloads as
+  /// PayloadData::Blob (metadata only).
   async fn list_observations(
     &self,
     execution_id: ExecutionId,
     limit: Option<usize>,
     offset: Option<usize>,
-    observation_type: Option<ObservationType>,
+    filter: &ObservationFilter,
   ) -> StorageResult<Vec<ObservationWithPayloads>>;
 
-  /// Count total number of observations for an execution (with optional type
-  /// filter)
+  /// Count total number of observations for an execution matching a filter
   async fn count_observations(
     &self,
     execution_id: ExecutionId,
-    observation_type: Option<ObservationType>,
+    filter: &ObservationFilter,
   ) -> StorageResult<usize>;
+
+  /// Total payload bytes stored for an execution
+  async fn execution_size(&self, execution_id: ExecutionId) -> StorageResult<u64>;
+
+  /// Total payload bytes of each observation in `ids` that is stored
+  async fn observation_sizes(
+    &self,
+    ids: &[ObservationId],
+  ) -> StorageResult<HashMap<ObservationId, u64>>;
+
+  /// Add `usage` to the usage counters of an execution if `check` accepts
+  /// the usage they hold, without scanning its observations. Counters start
+  /// from the stored observations when first used. Returns the usage before
+  /// adding, and whether `usage` was added.
+  async fn add_execution_usage(
+    &self,
+    execution_id: ExecutionId,
+    usage: ExecutionUsage,
+    check: &UsageCheck,
+  ) -> StorageResult<(ExecutionUsage, bool)>;
+
+  /// Subtract `usage` added with [`MetadataStorage::add_execution_usage`]
+  /// for data that was not stored after all
+  async fn remove_execution_usage(
+    &self,
+    execution_id: ExecutionId,
+    usage: ExecutionUsage,
+  ) -> StorageResult<()>;
+
+  /// Delete an execution with all of its observations and inline payloads,
+  /// releasing their references to content-addressed blobs and removing the
+  /// execution's blob scopes. Returns the deleted observations so that
+  /// callers can clean up other payloads stored as blobs.
+  async fn delete_execution(
+    &self,
+    execution_id: ExecutionId,
+  ) -> StorageResult<Vec<ObservationWithPayloads>>;
+
+  /// Record a content-addressed blob, unless a record for its digest exists.
+  /// Returns the stored record, with `true` if it was created. An existing
+  /// record's `updated_at` is refreshed.
+  async fn register_blob(&self, record: &BlobRecord) -> StorageResult<(BlobRecord, bool)>;
+
+  /// Get the record of a content-addressed blob
+  async fn get_blob_record(&self, digest: &str) -> StorageResult<BlobRecord>;
+
+  /// Blobs no payload references that were last updated before `before`
+  async fn unreferenced_blobs(&self, before: DateTime<Utc>) -> StorageResult<Vec<BlobRecord>>;
+
+  /// Delete a blob record if it is still unreferenced and was last updated
+  /// before `before`, along with its scopes. Returns whether it was deleted.
+  async fn delete_blob_record(&self, digest: &str, before: DateTime<Utc>) -> StorageResult<bool>;
+
+  /// Allow payloads in `scope` to reference content by its digest
+  async fn add_blob_scope(&self, digest: &str, scope: &BlobScope) -> StorageResult<()>;
+
+  /// Whether payloads in `scope` may reference content by its digest
+  async fn blob_in_scope(&self, digest: &str, scope: &BlobScope) -> StorageResult<bool>;
+
+  /// Add or replace an API key in the key registry
+  async fn store_api_key(&self, key: &ApiKeyRecord) -> StorageResult<()>;
+
+  /// Get an API key from the key registry
+  async fn get_api_key(&self, id: Uuid) -> StorageResult<ApiKeyRecord>;
+
+  /// List all API keys in the key registry, oldest first
+  async fn list_api_keys(&self) -> StorageResult<Vec<ApiKeyRecord>>;
+
+  /// Add or replace a project
+  async fn store_project(&self, project: &Project) -> StorageResult<()>;
+
+  /// Get a project by ID
+  async fn get_project(&self, id: &ProjectId) -> StorageResult<Project>;
+
+  /// List all projects, ordered by ID
+  async fn list_projects(&self) -> StorageResult<Vec<Project>>;
+
+  /// Add or replace the baseline of a snapshot
+  async fn store_snapshot_baseline(&self, baseline: &SnapshotBaseline) -> StorageResult<()>;
+
+  /// Get the baseline of the snapshot `name` in executions named
+  /// `execution_name`
+  async fn get_snapshot_baseline(
+    &self,
+    project: &ProjectId,
+    execution_name: &str,
+    name: &str,
+  ) -> StorageResult<SnapshotBaseline>;
+
+  /// List all snapshot baselines, ordered by project, execution name and name
+  async fn list_snapshot_baselines(&self) -> StorageResult<Vec<SnapshotBaseline>>;
 }
 
 /// Sled-based metadata storage implementation
@@ -86,15 +289,52 @@ fn inline_payload_key(obs_id: &ObservationId, payload_id: &PayloadId) -> String
   format!("{}{}{}", obs_id, KEY_PAYLOAD_INFIX, payload_id.as_str())
 }
 
+/// Key of a snapshot baseline. The separator sorts before any other
+/// character, so keys are ordered like their parts.
+fn snapshot_baseline_key(project: &ProjectId, execution_name: &str, name: &str) -> String {
+  format!("{}\0{}\0{}", project, execution_name, name)
+}
+
 fn obs_prefix(obs_id: &ObservationId) -> String {
   format!("{}:", obs_id)
 }
 
+/// Big-endian microseconds with the sign bit flipped, so that byte order
+/// matches chronological order
+fn time_prefix(time: DateTime<Utc>) -> [u8; 8] {
+  ((time.timestamp_micros() as u64) ^ (1 << 63)).to_be_bytes()
+}
+
+/// Key in the executions-by-time index: "{created_at}{execution_id}"
+fn execution_time_key(execution: &Execution) -> Vec<u8> {
+  let mut key = time_prefix(execution.created_at).to_vec();
+  key.extend_from_slice(execution.id.to_string().as_bytes());
+  key
+}
+
+/// Whether sled failed to lock its database files
+fn is_lock_error(e: &std::io::Error) -> bool {
+  e.to_string().contains("could not acquire lock")
+}
+
 impl SledStorage {
   /// Create a new Sled storage instance
   pub fn new(path: impl AsRef<Path>) -> StorageResult<Self> {
-    let db = sled::open(path)?;
-    Ok(Self { db })
+    // sled releases the lock of a closed database from a background thread,
+    // so reopening it right away, e.g. after `keys create`, can briefly fail
+    let mut attempts = 0;
+    let db = loop {
+      match sled::open(path.as_ref()) {
+        Err(sled::Error::Io(e)) if is_lock_error(&e) && attempts < 20 => {
+          attempts += 1;
+          std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+        result => break result?,
+      }
+    };
+    let storage = Self { db };
+    storage.ensure_execution_time_index()?;
+    Ok(storage)
   }
 
   /// Get the executions tree
@@ -102,6 +342,128 @@ impl SledStorage {
     Ok(self.db.open_tree("executions")?)
   }
 
+  /// Get the execution usage tree, mapping execution IDs to JSON
+  /// [`ExecutionUsage`] counters
+  fn execution_usage_tree(&self) -> StorageResult<sled::Tree> {
+    Ok(self.db.open_tree("execution_usage")?)
+  }
+
+  /// Get the executions-by-time index tree, mapping
+  /// [`execution_time_key`] to the execution ID
+  fn executions_by_time_tree(&self) -> StorageResult<sled::Tree> {
+    Ok(self.db.open_tree("executions_by_time")?)
+  }
+
+  /// Rebuild the executions-by-time index if it is out of sync with the
+  /// executions tree, e.g. for databases created before the index existed
+  fn ensure_execution_time_index(&self) -> StorageResult<()> {
+    let exec_tree = self.executions_tree()?;
+    let index = self.executions_by_time_tree()?;
+    if index.len() == exec_tree.len() {
+      return Ok(());
+    }
+    index.clear()?;
+    let mut batch = sled::Batch::default();
+    for item in exec_tree.iter() {
+      let (key, value) = item?;
+      let execution: Execution = serde_json::from_slice(&value)?;
+      batch.insert(execution_time_key(&execution), key);
+    }
+    index.apply_batch(batch)?;
+    trace!("Rebuilt execution time index with {} entries", index.len());
+    Ok(())
+  }
+
+  /// IDs of executions created within the filter's time range, most recent
+  /// first
+  fn execution_ids_by_time(
+    &self,
+    filter: &ExecutionFilter,
+  ) -> StorageResult<impl Iterator<Item = StorageResult<sled::IVec>>> {
+    let index = self.executions_by_time_tree()?;
+    let start = filter.created_after.map(time_prefix).unwrap_or([0; 8]);
+    let range = match filter.created_before.map(time_prefix) {
+      Some(end) if end <= start => index.range(start..start),
+      Some(end) => index.range(start..end),
+      None => index.range(start..),
+    };
+    Ok(range.values().rev().map(|id| Ok(id?)))
+  }
+
+  /// Load an execution referenced by the time index, skipping entries whose
+  /// execution has since been removed
+  fn load_indexed_execution(
+    &self,
+    exec_tree: &sled::Tree,
+    id: sled::IVec,
+  ) -> StorageResult<Option<Execution>> {
+    match exec_tree.get(id)? {
+      Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+      None => Ok(None),
+    }
+  }
+
+  /// Get the content-addressed blobs tree, mapping digests to JSON
+  /// [`BlobRecord`]s
+  fn blobs_tree(&self) -> StorageResult<sled::Tree> {
+    Ok(self.db.open_tree("blobs")?)
+  }
+
+  /// Get the blob scopes tree, with a `{digest}\0{scope}` key for each scope
+  /// content was received in
+  fn blob_scopes_tree(&self) -> StorageResult<sled::Tree> {
+    Ok(self.db.open_tree("blob_scopes")?)
+  }
+
+  /// Add `delta` references to a blob record. Records missing when adding a
+  /// reference are created from `payload`.
+  fn update_refcount(
+    &self,
+    blobs: &sled::Tree,
+    digest: &str,
+    delta: i64,
+    payload: Option<&StoredPayloadMeta>,
+  ) -> StorageResult<()> {
+    let mut error = None;
+    blobs.update_and_fetch(digest.as_bytes(), |value| {
+      let mut record: BlobRecord = match value.map(serde_json::from_slice) {
+        Some(Ok(record)) => record,
+        Some(Err(e)) => {
+          error = Some(e);
+          return value.map(|v| v.to_vec());
+        }
+        None => {
+          let payload = payload?;
+          let encoding = payload.encoding.parse().unwrap_or_default();
+          BlobRecord::new(digest, payload.size, encoding)
+        }
+      };
+      record.refcount += delta;
+      record.updated_at = Utc::now();
+      serde_json::to_vec(&record).ok()
+    })?;
+    match error {
+      Some(e) => Err(e.into()),
+      None => Ok(()),
+    }
+  }
+
+  /// Get the API key registry tree, mapping key IDs to JSON
+  /// [`ApiKeyRecord`]s
+  fn api_keys_tree(&self) -> StorageResult<sled::Tree> {
+    Ok(self.db.open_tree("api_keys")?)
+  }
+
+  /// Get the projects tree, mapping project IDs to JSON
+  fn projects_tree(&self) -> StorageResult<sled::Tree> {
+    Ok(self.db.open_tree("projects")?)
+  }
+
+  /// Get the snapshot baselines tree, mapping baseline keys to JSON
+  fn snapshot_baselines_tree(&self) -> StorageResult<sled::Tree> {
+    Ok(self.db.open_tree("snapshot_baselines")?)
+  }
+
   /// Get the observations tree
   fn observations_tree(&self) -> StorageResult<sled::Tree> {
     Ok(self.db.open_tree("observations")?)
@@ -112,6 +474,25 @@ impl SledStorage {
     Ok(self.db.open_tree("execution_observations")?)
   }
 
+  /// Get the IDs of all observations indexed for an execution
+  fn execution_observation_ids(
+    &self,
+    exec_obs_tree: &sled::Tree,
+    execution_id: ExecutionId,
+  ) -> StorageResult<Vec<ObservationId>> {
+    let prefix = format!("{}:", execution_id);
+    let mut ids = Vec::new();
+    for item in exec_obs_tree.scan_prefix(prefix.as_bytes()).values() {
+      let obs_id_bytes = item?;
+      let obs_id_str = String::from_utf8(obs_id_bytes.to_vec())
+        .map_err(|e| StorageError::Internal(format!("Invalid key encoding: {}", e)))?;
+      ids.push(ObservationId::parse(&obs_id_str).map_err(|e| {
+        StorageError::Internal(format!("Invalid observation ID in index: {}", e))
+      })?);
+    }
+    Ok(ids)
+  }
+
   /// Decode a stored observation from a metadata key's value, returning
   /// observation with all payloads marked as Blob
   fn decode_metadata_only(
@@ -123,15 +504,7 @@ impl SledStorage {
     let payloads = stored
       .payload_manifest
       .iter()
-      .map(|pm| {
-        StoredPayload {
-          id: pm.to_payload_id(),
-          name: pm.name.clone(),
-          mime_type: pm.mime_type.clone(),
-          size: pm.size as usize,
-          data: PayloadData::Blob,
-        }
-      })
+      .map(|pm| pm.to_payload(PayloadData::Blob))
       .collect();
 
     Ok(ObservationWithPayloads {
@@ -183,13 +556,7 @@ impl SledStorage {
         } else {
           PayloadData::Blob
         };
-        StoredPayload {
-          id: pm.to_payload_id(),
-          name: pm.name.clone(),
-          mime_type: pm.mime_type.clone(),
-          size: pm.size as usize,
-          data,
-        }
+        pm.to_payload(data)
       })
       .collect();
 
@@ -204,10 +571,23 @@ impl SledStorage {
 impl MetadataStorage for SledStorage {
   async fn store_execution(&self, execution: &Execution) -> StorageResult<()> {
     let tree = self.executions_tree()?;
+    let index = self.executions_by_time_tree()?;
     let key = execution.id.to_string();
     let value = serde_json::to_vec(execution)?;
-    tree.insert(key.as_bytes(), value)?;
-    Ok(())
+    let result: TransactionResult<(), serde_json::Error> =
+      (&tree, &index).transaction(|(tree, index)| {
+        if let Some(previous) = tree.insert(key.as_bytes(), value.as_slice())? {
+          let previous: Execution =
+            serde_json::from_slice(&previous).map_err(ConflictableTransactionError::Abort)?;
+          index.remove(execution_time_key(&previous))?;
+        }
+        index.insert(execution_time_key(execution), key.as_bytes())?;
+        Ok(())
+      });
+    result.map_err(|e| match e {
+      TransactionError::Abort(e) => e.into(),
+      TransactionError::Storage(e) => e.into(),
+    })
   }
 
   async fn get_execution(&self, id: ExecutionId) -> StorageResult<Execution> {
@@ -222,29 +602,94 @@ impl MetadataStorage for SledStorage {
     Ok(execution)
   }
 
+  async fn update_execution(
+    &self,
+    id: ExecutionId,
+    update: &ExecutionUpdate,
+  ) -> StorageResult<Option<Execution>> {
+    let tree = self.executions_tree()?;
+    let index = self.executions_by_time_tree()?;
+    let key = id.to_string();
+    let result: TransactionResult<Option<Execution>, StorageError> =
+      (&tree, &index).transaction(|(tree, index)| {
+        let abort = |e: StorageError| ConflictableTransactionError::Abort(e);
+        let previous = tree
+          .get(key.as_bytes())?
+          .ok_or_else(|| abort(StorageError::NotFound(format!("Execution {} not found", id))))?;
+        let previous: Execution =
+          serde_json::from_slice(&previous).map_err(|e| abort(e.into()))?;
+        let mut execution = previous.clone();
+        if !update(&mut execution) {
+          return Ok(None);
+        }
+        let value = serde_json::to_vec(&execution).map_err(|e| abort(e.into()))?;
+        tree.insert(key.as_bytes(), value)?;
+        index.remove(execution_time_key(&previous))?;
+        index.insert(execution_time_key(&execution), key.as_bytes())?;
+        Ok(Some(execution))
+      });
+    result.map_err(|e| match e {
+      TransactionError::Abort(e) => e,
+      TransactionError::Storage(e) => e.into(),
+    })
+  }
+
   async fn list_executions(
     &self,
     limit: Option<usize>,
     offset: Option<usize>,
+    filter: &ExecutionFilter,
   ) -> StorageResult<Vec<Execution>> {
     let tree = self.executions_tree()?;
-    let offset = offset.unwrap_or(0);
+    let mut offset = offset.unwrap_or(0);
     let limit = limit.unwrap_or(100);
-    let mut executions: Vec<Execution> = tree
-      .iter()
-      .values()
-      .filter_map(|result| result.ok().and_then(|v| serde_json::from_slice(&v).ok()))
-      .collect();
-    // Sort by created_at descending (most recent first)
-    executions.sort_by(|a, b| b.created_at.cmp(&a.created_at));
-    // Apply pagination after sorting
-    let executions: Vec<Execution> = executions.into_iter().skip(offset).take(limit).collect();
+    let mut ids = self.execution_ids_by_time(filter)?;
+    // The time range is served by the index, so without other criteria the
+    // skipped executions don't need to be loaded
+    if filter.is_time_range_only() {
+      ids.by_ref().take(offset).for_each(drop);
+      offset = 0;
+    }
+    let mut executions = Vec::new();
+    for id in ids {
+      if executions.len() == limit {
+        break;
+      }
+      let Some(execution) = self.load_indexed_execution(&tree, id?)? else {
+        continue;
+      };
+      if !filter.matches(&execution) {
+        continue;
+      }
+      if offset > 0 {
+        offset -= 1;
+        continue;
+      }
+      executions.push(execution);
+    }
     Ok(executions)
   }
 
-  async fn count_executions(&self) -> StorageResult<usize> {
+  async fn count_executions(&self, filter: &ExecutionFilter) -> StorageResult<usize> {
     let tree = self.executions_tree()?;
-    Ok(tree.len())
+    if *filter == ExecutionFilter::default() {
+      return Ok(tree.len());
+    }
+    let time_range_only = filter.is_time_range_only();
+    let mut count = 0;
+    for id in self.execution_ids_by_time(filter)? {
+      let id = id?;
+      if time_range_only {
+        count += 1;
+        continue;
+      }
+      if let Some(execution) = self.load_indexed_execution(&tree, id)? {
+        if filter.matches(&execution) {
+          count += 1;
+        }
+      }
+    }
+    Ok(count)
   }
 
   async fn store_observations(
@@ -253,6 +698,14 @@ impl MetadataStorage for SledStorage {
   ) -> StorageResult<()> {
     let obs_tree = self.observations_tree()?;
     let exec_obs_tree = self.execution_observations_tree()?;
+    let blobs = self.blobs_tree()?;
+    // Checked up front so that a rejected batch stores nothing
+    for obs_with_payloads in &observations {
+      let obs = &obs_with_payloads.observation;
+      if let Some(previous) = obs_tree.get(metadata_key(&obs.id).as_bytes())? {
+        check_replaced_execution(&StoredObservation::decode(previous.as_ref())?, obs)?;
+      }
+    }
     for obs_with_payloads in observations {
       let obs = &obs_with_payloads.observation;
       let obs_id = obs.id;
@@ -260,19 +713,26 @@ impl MetadataStorage for SledStorage {
       // Build the stored observation with payload manifest
       let mut stored = StoredObservation::from_observation(obs);
       for payload in &obs_with_payloads.payloads {
-        stored.payload_manifest.push(StoredPayloadMeta {
-          payload_id: payload.id.as_str().to_string(),
-          name: payload.name.clone(),
-          mime_type: payload.mime_type.clone(),
-          size: payload.size as u64,
-          is_blob: matches!(payload.data, PayloadData::Blob),
-        });
+        stored
+          .payload_manifest
+          .push(StoredPayloadMeta::from_payload(payload));
       }
 
       // Store the metadata key
       let key = metadata_key(&obs_id);
       let value = stored.encode_to_vec();
-      obs_tree.insert(key.as_bytes(), value)?;
+      let previous = obs_tree.insert(key.as_bytes(), value)?;
+
+      // Move blob references from a replaced observation to this one
+      for pm in stored.payload_manifest.iter().filter(|pm| !pm.digest.is_empty()) {
+        self.update_refcount(&blobs, &pm.digest, 1, Some(pm))?;
+      }
+      if let Some(previous) = previous {
+        let previous = StoredObservation::decode(previous.as_ref())?;
+        for digest in payload_digests(&previous) {
+          self.update_refcount(&blobs, digest, -1, None)?;
+        }
+      }
 
       // Store inline payloads
       for payload in &obs_with_payloads.payloads {
@@ -304,7 +764,7 @@ impl MetadataStorage for SledStorage {
     execution_id: ExecutionId,
     limit: Option<usize>,
     offset: Option<usize>,
-    observation_type: Option<ObservationType>,
+    filter: &ObservationFilter,
   ) -> StorageResult<Vec<ObservationWithPayloads>> {
     let obs_tree = self.observations_tree()?;
     let exec_obs_tree = self.execution_observations_tree()?;
@@ -324,7 +784,7 @@ impl MetadataStorage for SledStorage {
             .and_then(|v| self.decode_metadata_only(&v).ok())
         })
       })
-      .filter(|obs| observation_type.map_or(true, |t| obs.observation.observation_type == t))
+      .filter(|obs| filter.matches(&obs.observation))
       .skip(offset.unwrap_or(0))
       .take(limit.unwrap_or(100))
       .collect();
@@ -334,7 +794,7 @@ impl MetadataStorage for SledStorage {
   async fn count_observations(
     &self,
     execution_id: ExecutionId,
-    observation_type: Option<ObservationType>,
+    filter: &ObservationFilter,
   ) -> StorageResult<usize> {
     let obs_tree = self.observations_tree()?;
     let exec_obs_tree = self.execution_observations_tree()?;
@@ -354,8 +814,1107 @@ impl MetadataStorage for SledStorage {
             .and_then(|v| self.decode_metadata_only(&v).ok())
         })
       })
-      .filter(|obs| observation_type.map_or(true, |t| obs.observation.observation_type == t))
+      .filter(|obs| filter.matches(&obs.observation))
       .count();
     Ok(count)
   }
+
+  async fn execution_size(&self, execution_id: ExecutionId) -> StorageResult<u64> {
+    let obs_tree = self.observations_tree()?;
+    let exec_obs_tree = self.execution_observations_tree()?;
+    let mut size = 0;
+    for obs_id in self.execution_observation_ids(&exec_obs_tree, execution_id)? {
+      if let Some(value) = obs_tree.get(metadata_key(&obs_id).as_bytes())? {
+        let stored = StoredObservation::decode(value.as_ref())?;
+        size += stored.payload_manifest.iter().map(|pm| pm.size).sum::<u64>();
+      }
+    }
+    Ok(size)
+  }
+
+  async fn observation_sizes(
+    &self,
+    ids: &[ObservationId],
+  ) -> StorageResult<HashMap<ObservationId, u64>> {
+    let obs_tree = self.observations_tree()?;
+    let mut sizes = HashMap::new();
+    for id in ids {
+      if let Some(value) = obs_tree.get(metadata_key(id).as_bytes())? {
+        let stored = StoredObservation::decode(value.as_ref())?;
+        sizes.insert(*id, stored.payload_manifest.iter().map(|pm| pm.size).sum());
+      }
+    }
+    Ok(sizes)
+  }
+
+  async fn add_execution_usage(
+    &self,
+    execution_id: ExecutionId,
+    usage: ExecutionUsage,
+    check: &UsageCheck,
+  ) -> StorageResult<(ExecutionUsage, bool)> {
+    let tree = self.execution_usage_tree()?;
+    let key = execution_id.to_string();
+    loop {
+      let current = tree.get(key.as_bytes())?;
+      let used = match &current {
+        Some(value) => serde_json::from_slice(value)?,
+        None => ExecutionUsage {
+          observations: self
+            .count_observations(execution_id, &ObservationFilter::default())
+            .await? as u64,
+          bytes: self.execution_size(execution_id).await?,
+        },
+      };
+      if !check(used) {
+        return Ok((used, false));
+      }
+      let added = serde_json::to_vec(&used.saturating_add(usage))?;
+      if tree
+        .compare_and_swap(key.as_bytes(), current, Some(added))?
+        .is_ok()
+      {
+        return Ok((used, true));
+      }
+    }
+  }
+
+  async fn remove_execution_usage(
+    &self,
+    execution_id: ExecutionId,
+    usage: ExecutionUsage,
+  ) -> StorageResult<()> {
+    let tree = self.execution_usage_tree()?;
+    let key = execution_id.to_string();
+    loop {
+      let Some(current) = tree.get(key.as_bytes())? else {
+        return Ok(());
+      };
+      let used: ExecutionUsage = serde_json::from_slice(&current)?;
+      let removed = serde_json::to_vec(&used.saturating_sub(usage))?;
+      if tree
+        .compare_and_swap(key.as_bytes(), Some(current), Some(removed))?
+        .is_ok()
+      {
+        return Ok(());
+      }
+    }
+  }
+
+  async fn delete_execution(
+    &self,
+    execution_id: ExecutionId,
+  ) -> StorageResult<Vec<ObservationWithPayloads>> {
+    let exec_tree = self.executions_tree()?;
+    let obs_tree = self.observations_tree()?;
+    let exec_obs_tree = self.execution_observations_tree()?;
+
+    let obs_ids = self.execution_observation_ids(&exec_obs_tree, execution_id)?;
+    let removed = exec_tree.remove(execution_id.to_string().as_bytes())?;
+    let execution_existed = removed.is_some();
+    if let Some(value) = removed {
+      let execution: Execution = serde_json::from_slice(&value)?;
+      self
+        .executions_by_time_tree()?
+        .remove(execution_time_key(&execution))?;
+    }
+    if !execution_existed && obs_ids.is_empty() {
+      return Err(StorageError::NotFound(format!(
+        "Execution {} not found",
+        execution_id
+      )));
+    }
+
+    let mut deleted = Vec::with_capacity(obs_ids.len());
+    let mut obs_batch = sled::Batch::default();
+    let mut exec_obs_batch = sled::Batch::default();
+    for obs_id in obs_ids {
+      match self.decode_with_inline_payloads(&obs_tree, &obs_id) {
+        Ok(obs) => deleted.push(obs),
+        Err(StorageError::NotFound(_)) => {}
+        Err(e) => return Err(e),
+      }
+      // Removes the metadata key and all inline payload keys
+      for key in obs_tree.scan_prefix(obs_prefix(&obs_id).as_bytes()).keys() {
+        obs_batch.remove(key?);
+      }
+      exec_obs_batch.remove(format!("{}:{}", execution_id, obs_id).as_bytes());
+    }
+    obs_tree.apply_batch(obs_batch)?;
+    exec_obs_tree.apply_batch(exec_obs_batch)?;
+    self
+      .execution_usage_tree()?
+      .remove(execution_id.to_string().as_bytes())?;
+    // Scope keys start with the digest, so the whole tree is scanned
+    let scopes = self.blob_scopes_tree()?;
+    let scope_suffix = format!("\0{}", BlobScope::Execution(execution_id).key());
+    let mut scopes_batch = sled::Batch::default();
+    for key in scopes.iter().keys() {
+      let key = key?;
+      if key.ends_with(scope_suffix.as_bytes()) {
+        scopes_batch.remove(key);
+      }
+    }
+    scopes.apply_batch(scopes_batch)?;
+    let blobs = self.blobs_tree()?;
+    for obs in &deleted {
+      for digest in obs.payloads.iter().filter_map(|p| p.digest.as_deref()) {
+        self.update_refcount(&blobs, digest, -1, None)?;
+      }
+    }
+    trace!(
+      "Deleted execution {} with {} observations",
+      execution_id,
+      deleted.len()
+    );
+    Ok(deleted)
+  }
+
+  async fn register_blob(&self, record: &BlobRecord) -> StorageResult<(BlobRecord, bool)> {
+    let blobs = self.blobs_tree()?;
+    let value = serde_json::to_vec(record)?;
+    let key = record.digest.as_bytes();
+    if blobs
+      .compare_and_swap(key, None as Option<&[u8]>, Some(value))?
+      .is_ok()
+    {
+      return Ok((record.clone(), true));
+    }
+    let mut error = None;
+    let updated = blobs.update_and_fetch(key, |value| {
+      let value = value?;
+      match serde_json::from_slice::<BlobRecord>(value) {
+        Ok(mut existing) => {
+          existing.updated_at = Utc::now();
+          serde_json::to_vec(&existing).ok()
+        }
+        Err(e) => {
+          error = Some(e);
+          Some(value.to_vec())
+        }
+      }
+    })?;
+    if let Some(e) = error {
+      return Err(e.into());
+    }
+    match updated {
+      Some(value) => Ok((serde_json::from_slice(&value)?, false)),
+      // Collected in the meantime
+      None => self.register_blob(record).await,
+    }
+  }
+
+  async fn get_blob_record(&self, digest: &str) -> StorageResult<BlobRecord> {
+    let value = self
+      .blobs_tree()?
+      .get(digest.as_bytes())?
+      .ok_or_else(|| StorageError::NotFound(format!("Blob {} not found", digest)))?;
+    Ok(serde_json::from_slice(&value)?)
+  }
+
+  async fn unreferenced_blobs(&self, before: DateTime<Utc>) -> StorageResult<Vec<BlobRecord>> {
+    let mut records = Vec::new();
+    for item in self.blobs_tree()?.iter().values() {
+      let record: BlobRecord = serde_json::from_slice(&item?)?;
+      if record.refcount <= 0 && record.updated_at < before {
+        records.push(record);
+      }
+    }
+    Ok(records)
+  }
+
+  async fn delete_blob_record(&self, digest: &str, before: DateTime<Utc>) -> StorageResult<bool> {
+    let blobs = self.blobs_tree()?;
+    loop {
+      let Some(value) = blobs.get(digest.as_bytes())? else {
+        return Ok(false);
+      };
+      let record: BlobRecord = serde_json::from_slice(&value)?;
+      if record.refcount > 0 || record.updated_at >= before {
+        return Ok(false);
+      }
+      if blobs
+        .compare_and_swap(digest.as_bytes(), Some(value), None as Option<&[u8]>)?
+        .is_ok()
+      {
+        let scopes = self.blob_scopes_tree()?;
+        for key in scopes.scan_prefix(format!("{}\0", digest).as_bytes()).keys() {
+          scopes.remove(key?)?;
+        }
+        return Ok(true);
+      }
+    }
+  }
+
+  async fn add_blob_scope(&self, digest: &str, scope: &BlobScope) -> StorageResult<()> {
+    let key = format!("{}\0{}", digest, scope.key());
+    self.blob_scopes_tree()?.insert(key.as_bytes(), &[])?;
+    Ok(())
+  }
+
+  async fn blob_in_scope(&self, digest: &str, scope: &BlobScope) -> StorageResult<bool> {
+    let key = format!("{}\0{}", digest, scope.key());
+    Ok(self.blob_scopes_tree()?.contains_key(key.as_bytes())?)
+  }
+
+  async fn store_api_key(&self, key: &ApiKeyRecord) -> StorageResult<()> {
+    self
+      .api_keys_tree()?
+      .insert(key.id.to_string().as_bytes(), serde_json::to_vec(key)?)?;
+    Ok(())
+  }
+
+  async fn get_api_key(&self, id: Uuid) -> StorageResult<ApiKeyRecord> {
+    let value = self
+      .api_keys_tree()?
+      .get(id.to_string().as_bytes())?
+      .ok_or_else(|| StorageError::NotFound(format!("API key {} not found", id)))?;
+    Ok(serde_json::from_slice(&value)?)
+  }
+
+  async fn list_api_keys(&self) -> StorageResult<Vec<ApiKeyRecord>> {
+    let mut keys = self
+      .api_keys_tree()?
+      .iter()
+      .values()
+      .map(|value| Ok(serde_json::from_slice::<ApiKeyRecord>(&value?)?))
+      .collect::<StorageResult<Vec<_>>>()?;
+    keys.sort_by_key(|key| key.created_at);
+    Ok(keys)
+  }
+
+  async fn store_project(&self, project: &Project) -> StorageResult<()> {
+    self
+      .projects_tree()?
+      .insert(project.id.as_str().as_bytes(), serde_json::to_vec(project)?)?;
+    Ok(())
+  }
+
+  async fn get_project(&self, id: &ProjectId) -> StorageResult<Project> {
+    let value = self
+      .projects_tree()?
+      .get(id.as_str().as_bytes())?
+      .ok_or_else(|| StorageError::NotFound(format!("Project {} not found", id)))?;
+    Ok(serde_json::from_slice(&value)?)
+  }
+
+  async fn list_projects(&self) -> StorageResult<Vec<Project>> {
+    // Keys are project IDs, so the tree is already ordered by ID
+    self
+      .projects_tree()?
+      .iter()
+      .values()
+      .map(|value| Ok(serde_json::from_slice(&value?)?))
+      .collect()
+  }
+
+  async fn store_snapshot_baseline(&self, baseline: &SnapshotBaseline) -> StorageResult<()> {
+    let key = snapshot_baseline_key(&baseline.project, &baseline.execution_name, &baseline.name);
+    self
+      .snapshot_baselines_tree()?
+      .insert(key.as_bytes(), serde_json::to_vec(baseline)?)?;
+    Ok(())
+  }
+
+  async fn get_snapshot_baseline(
+    &self,
+    project: &ProjectId,
+    execution_name: &str,
+    name: &str,
+  ) -> StorageResult<SnapshotBaseline> {
+    let value = self
+      .snapshot_baselines_tree()?
+      .get(snapshot_baseline_key(project, execution_name, name).as_bytes())?
+      .ok_or_else(|| {
+        StorageError::NotFound(format!("No baseline for snapshot {} of {}", name, execution_name))
+      })?;
+    Ok(serde_json::from_slice(&value)?)
+  }
+
+  async fn list_snapshot_baselines(&self) -> StorageResult<Vec<SnapshotBaseline>> {
+    self
+      .snapshot_baselines_tree()?
+      .iter()
+      .values()
+      .map(|value| Ok(serde_json::from_slice(&value?)?))
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::storage::SqlStorage;
+  use crate::storage::StoredPayload;
+  use chrono::Duration;
+  use chrono::Utc;
+  use observation_tools_shared::ContentEncoding;
+  use observation_tools_shared::ExecutionStatus;
+  use observation_tools_shared::LogLevel;
+  use observation_tools_shared::Observation;
+  use observation_tools_shared::ObservationType;
+  use std::collections::HashMap;
+
+  /// A backend under test, keeping its temporary directory alive
+  struct Backend {
+    name: &'static str,
+    storage: Box<dyn MetadataStorage>,
+    _dir: tempfile::TempDir,
+  }
+
+  /// Every metadata backend available in this build. Postgres is included
+  /// when built with the `postgres` feature and `TEST_POSTGRES_URL` is set.
+  async fn backends() -> Vec<Backend> {
+    let sled_dir = tempfile::tempdir().unwrap();
+    let sqlite_dir = tempfile::tempdir().unwrap();
+    let sqlite_url = format!("sqlite://{}", sqlite_dir.path().join("metadata.db").display());
+    let mut backends = vec![
+      Backend {
+        name: "sled",
+        storage: Box::new(SledStorage::new(sled_dir.path()).unwrap()),
+        _dir: sled_dir,
+      },
+      Backend {
+        name: "sqlite",
+        storage: Box::new(SqlStorage::connect(&sqlite_url).await.unwrap()),
+        _dir: sqlite_dir,
+      },
+    ];
+    if let Ok(url) = std::env::var("TEST_POSTGRES_URL") {
+      if cfg!(feature = "postgres") {
+        let url = create_postgres_database(&url).await;
+        backends.push(Backend {
+          name: "postgres",
+          storage: Box::new(SqlStorage::connect(&url).await.unwrap()),
+          _dir: tempfile::tempdir().unwrap(),
+        });
+      }
+    }
+    backends
+  }
+
+  /// Create an empty database on the server at `url` so that tests running
+  /// in parallel do not see each other's data
+  async fn create_postgres_database(url: &str) -> String {
+    sqlx::any::install_default_drivers();
+    let name = format!("observation_tools_test_{}", uuid::Uuid::new_v4().simple());
+    let pool = sqlx::AnyPool::connect(url).await.unwrap();
+    sqlx::query(&format!("CREATE DATABASE {}", name))
+      .execute(&pool)
+      .await
+      .unwrap();
+    let mut url = url::Url::parse(url).unwrap();
+    url.set_path(&name);
+    url.to_string()
+  }
+
+  fn observation(
+    execution_id: ExecutionId,
+    name: &str,
+    observation_type: ObservationType,
+    log_level: LogLevel,
+  ) -> Observation {
+    Observation {
+      id: ObservationId::new(),
+      execution_id,
+      name: name.to_string(),
+      observation_type,
+      log_level,
+      source: None,
+      metadata: HashMap::new(),
+      group_ids: vec![],
+      parent_group_id: None,
+      parent_span_id: None,
+      created_at: Utc::now(),
+    }
+  }
+
+  fn with_payloads(observation: Observation) -> ObservationWithPayloads {
+    ObservationWithPayloads {
+      observation,
+      payloads: vec![
+        StoredPayload {
+          id: PayloadId::from("inline"),
+          name: "text".to_string(),
+          mime_type: "text/plain".to_string(),
+          size: 5,
+          encoding: ContentEncoding::Identity,
+          digest: None,
+          data: PayloadData::Inline(b"hello".to_vec()),
+        },
+        StoredPayload {
+          id: PayloadId::from("blob"),
+          name: "raw".to_string(),
+          mime_type: "application/octet-stream".to_string(),
+          size: 100,
+          encoding: ContentEncoding::Zstd,
+          digest: None,
+          data: PayloadData::Blob,
+        },
+      ],
+    }
+  }
+
+  #[tokio::test]
+  async fn test_executions() {
+    for backend in backends().await {
+      let storage = backend.storage.as_ref();
+      let all = ExecutionFilter::default();
+      let now = Utc::now();
+      let mut old = Execution::new("old");
+      old.created_at = now - Duration::hours(2);
+      let mut new = Execution::new("new");
+      new.created_at = now;
+      storage.store_execution(&old).await.unwrap();
+      storage.store_execution(&new).await.unwrap();
+
+      let fetched = storage.get_execution(old.id).await.unwrap();
+      assert_eq!(fetched.name, "old", "{}", backend.name);
+      assert_eq!(storage.count_executions(&all).await.unwrap(), 2, "{}", backend.name);
+
+      let listed = storage.list_executions(None, None, &all).await.unwrap();
+      let names: Vec<&str> = listed.iter().map(|e| e.name.as_str()).collect();
+      assert_eq!(names, vec!["new", "old"], "{}", backend.name);
+      let page = storage.list_executions(Some(1), Some(1), &all).await.unwrap();
+      assert_eq!(page[0].name, "old", "{}", backend.name);
+
+      // Storing again updates in place
+      old.name = "renamed".to_string();
+      storage.store_execution(&old).await.unwrap();
+      assert_eq!(storage.count_executions(&all).await.unwrap(), 2, "{}", backend.name);
+      let fetched = storage.get_execution(old.id).await.unwrap();
+      assert_eq!(fetched.name, "renamed", "{}", backend.name);
+
+      // Filtering by status
+      old.status = ExecutionStatus::Failure;
+      old.ended_at = Some(now);
+      storage.store_execution(&old).await.unwrap();
+      let failed = ExecutionFilter {
+        status: Some(ExecutionStatus::Failure),
+        ..Default::default()
+      };
+      let listed = storage.list_executions(None, None, &failed).await.unwrap();
+      let names: Vec<&str> = listed.iter().map(|e| e.name.as_str()).collect();
+      assert_eq!(names, vec!["renamed"], "{}", backend.name);
+      assert_eq!(listed[0].ended_at, Some(now), "{}", backend.name);
+      assert_eq!(storage.count_executions(&failed).await.unwrap(), 1, "{}", backend.name);
+      let running = ExecutionFilter {
+        status: Some(ExecutionStatus::Running),
+        ..Default::default()
+      };
+      let listed = storage.list_executions(None, None, &running).await.unwrap();
+      assert_eq!(listed[0].name, "new", "{}", backend.name);
+
+      // Filtering by tag and metadata
+      new.tags = vec!["ci".to_string()];
+      new.metadata.insert("branch".to_string(), "main".to_string());
+      storage.store_execution(&new).await.unwrap();
+      let tagged = ExecutionFilter {
+        tag: Some("ci".to_string()),
+        ..Default::default()
+      };
+      let listed = storage.list_executions(None, None, &tagged).await.unwrap();
+      assert_eq!(listed.len(), 1, "{}", backend.name);
+      assert_eq!(listed[0].name, "new", "{}", backend.name);
+      let on_main = ExecutionFilter {
+        metadata: [("branch".to_string(), "main".to_string())].into(),
+        ..Default::default()
+      };
+      assert_eq!(storage.count_executions(&on_main).await.unwrap(), 1, "{}", backend.name);
+      let on_other = ExecutionFilter {
+        metadata: [("branch".to_string(), "other".to_string())].into(),
+        ..tagged
+      };
+      assert_eq!(storage.count_executions(&on_other).await.unwrap(), 0, "{}", backend.name);
+
+      // Filtering by name substring and created time
+      let mut nightly = Execution::new("Nightly 50%_build");
+      nightly.created_at = now - Duration::hours(1);
+      storage.store_execution(&nightly).await.unwrap();
+      let name = |n: &str| ExecutionFilter {
+        name: Some(n.to_string()),
+        ..Default::default()
+      };
+      let listed = storage.list_executions(None, None, &name("nightly")).await.unwrap();
+      assert_eq!(listed.len(), 1, "{}", backend.name);
+      assert_eq!(storage.count_executions(&name("50%_")).await.unwrap(), 1, "{}", backend.name);
+      assert_eq!(storage.count_executions(&name("50_")).await.unwrap(), 0, "{}", backend.name);
+      let recent = ExecutionFilter {
+        created_after: Some(now - Duration::minutes(90)),
+        ..Default::default()
+      };
+      let listed = storage.list_executions(None, None, &recent).await.unwrap();
+      let names: Vec<&str> = listed.iter().map(|e| e.name.as_str()).collect();
+      assert_eq!(names, vec!["new", "Nightly 50%_build"], "{}", backend.name);
+      let page = storage.list_executions(Some(1), Some(1), &recent).await.unwrap();
+      assert_eq!(page[0].name, "Nightly 50%_build", "{}", backend.name);
+      assert_eq!(storage.count_executions(&recent).await.unwrap(), 2, "{}", backend.name);
+      let window = ExecutionFilter {
+        created_before: Some(now),
+        status: Some(ExecutionStatus::Running),
+        ..recent
+      };
+      let listed = storage.list_executions(None, None, &window).await.unwrap();
+      assert_eq!(listed.len(), 1, "{}", backend.name);
+      assert_eq!(listed[0].name, "Nightly 50%_build", "{}", backend.name);
+      let empty = ExecutionFilter {
+        created_after: Some(now),
+        created_before: Some(now - Duration::hours(1)),
+        ..Default::default()
+      };
+      assert_eq!(storage.count_executions(&empty).await.unwrap(), 0, "{}", backend.name);
+
+      assert!(
+        matches!(
+          storage.get_execution(ExecutionId::new()).await,
+          Err(StorageError::NotFound(_))
+        ),
+        "{}",
+        backend.name
+      );
+    }
+  }
+
+  #[tokio::test]
+  async fn test_update_execution() {
+    for backend in backends().await {
+      let storage = backend.storage.as_ref();
+      let execution = Execution::new("execution");
+      storage.store_execution(&execution).await.unwrap();
+
+      let finish = |e: &mut Execution| {
+        if e.status != ExecutionStatus::Running {
+          return false;
+        }
+        e.status = ExecutionStatus::Success;
+        true
+      };
+      let updated = storage.update_execution(execution.id, &finish).await.unwrap();
+      assert_eq!(updated.map(|e| e.status), Some(ExecutionStatus::Success), "{}", backend.name);
+      let fetched = storage.get_execution(execution.id).await.unwrap();
+      assert_eq!(fetched.status, ExecutionStatus::Success, "{}", backend.name);
+      let finished = ExecutionFilter {
+        status: Some(ExecutionStatus::Success),
+        ..Default::default()
+      };
+      assert_eq!(storage.count_executions(&finished).await.unwrap(), 1, "{}", backend.name);
+      // The update sees the stored execution, so it is not applied twice
+      assert!(
+        storage.update_execution(execution.id, &finish).await.unwrap().is_none(),
+        "{}",
+        backend.name
+      );
+      assert!(
+        matches!(
+          storage.update_execution(ExecutionId::new(), &finish).await,
+          Err(StorageError::NotFound(_))
+        ),
+        "{}",
+        backend.name
+      );
+    }
+  }
+
+  #[tokio::test]
+  async fn test_concurrent_execution_updates() {
+    for backend in backends().await {
+      let storage = backend.storage.as_ref();
+      let execution = Execution::new("execution");
+      storage.store_execution(&execution).await.unwrap();
+
+      let updates = (0..10).map(|i| async move {
+        let add_tag = move |e: &mut Execution| {
+          e.tags.push(format!("tag-{}", i));
+          true
+        };
+        storage.update_execution(execution.id, &add_tag).await.unwrap();
+      });
+      futures::future::join_all(updates).await;
+
+      // No update overwrote another
+      let fetched = storage.get_execution(execution.id).await.unwrap();
+      assert_eq!(fetched.tags.len(), 10, "{}", backend.name);
+    }
+  }
+
+  #[tokio::test]
+  async fn test_execution_usage() {
+    for backend in backends().await {
+      let storage = backend.storage.as_ref();
+      let execution = Execution::new("execution");
+      storage.store_execution(&execution).await.unwrap();
+      let stored = observation(execution.id, "data", ObservationType::Payload, LogLevel::Info);
+      storage
+        .store_observations(vec![with_payloads(stored)])
+        .await
+        .unwrap();
+      let size = storage.execution_size(execution.id).await.unwrap();
+      let batch = ExecutionUsage {
+        observations: 2,
+        bytes: 10,
+      };
+      let any = |_| true;
+
+      // Counters start from the stored observations
+      let (used, added) = storage
+        .add_execution_usage(execution.id, batch, &any)
+        .await
+        .unwrap();
+      let stored_usage = ExecutionUsage {
+        observations: 1,
+        bytes: size,
+      };
+      assert_eq!((used, added), (stored_usage, true), "{}", backend.name);
+      let full = |used: ExecutionUsage| used.observations < 3;
+      let (used, added) = storage
+        .add_execution_usage(execution.id, batch, &full)
+        .await
+        .unwrap();
+      assert_eq!((used, added), (stored_usage.saturating_add(batch), false), "{}", backend.name);
+
+      storage
+        .remove_execution_usage(execution.id, batch)
+        .await
+        .unwrap();
+      let (used, _) = storage
+        .add_execution_usage(execution.id, ExecutionUsage::default(), &any)
+        .await
+        .unwrap();
+      assert_eq!(used, stored_usage, "{}", backend.name);
+
+      storage.delete_execution(execution.id).await.unwrap();
+      let (used, _) = storage
+        .add_execution_usage(execution.id, ExecutionUsage::default(), &any)
+        .await
+        .unwrap();
+      assert_eq!(used, ExecutionUsage::default(), "{}", backend.name);
+    }
+  }
+
+  #[tokio::test]
+  async fn test_observation_sizes() {
+    for backend in backends().await {
+      let storage = backend.storage.as_ref();
+      let execution = Execution::new("execution");
+      storage.store_execution(&execution).await.unwrap();
+      let stored = observation(execution.id, "data", ObservationType::Payload, LogLevel::Info);
+      let stored_id = stored.id;
+      storage
+        .store_observations(vec![with_payloads(stored)])
+        .await
+        .unwrap();
+      let size = storage.execution_size(execution.id).await.unwrap();
+
+      // Observations that are not stored are left out
+      let sizes = storage
+        .observation_sizes(&[stored_id, ObservationId::new()])
+        .await
+        .unwrap();
+      assert_eq!(sizes, HashMap::from([(stored_id, size)]), "{}", backend.name);
+    }
+  }
+
+  #[tokio::test]
+  async fn test_sled_rebuilds_execution_time_index() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut old = Execution::new("old");
+    old.created_at = Utc::now() - Duration::hours(1);
+    let new = Execution::new("new");
+    {
+      // Executions written before the time index existed
+      let db = sled::open(dir.path()).unwrap();
+      let tree = db.open_tree("executions").unwrap();
+      for execution in [&old, &new] {
+        let value = serde_json::to_vec(execution).unwrap();
+        tree.insert(execution.id.to_string().as_bytes(), value).unwrap();
+      }
+      db.flush().unwrap();
+    }
+    let storage = SledStorage::new(dir.path()).unwrap();
+    let listed = storage
+      .list_executions(None, None, &ExecutionFilter::default())
+      .await
+      .unwrap();
+    let names: Vec<&str> = listed.iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(names, vec!["new", "old"]);
+
+    // Deleting removes the index entry too
+    storage.delete_execution(old.id).await.unwrap();
+    let recent = ExecutionFilter {
+      created_before: Some(Utc::now() + Duration::hours(1)),
+      ..Default::default()
+    };
+    assert_eq!(storage.count_executions(&recent).await.unwrap(), 1);
+  }
+
+  #[tokio::test]
+  async fn test_observations() {
+    for backend in backends().await {
+      let storage = backend.storage.as_ref();
+      let execution = Execution::new("execution");
+      storage.store_execution(&execution).await.unwrap();
+      let log = observation(execution.id, "log", ObservationType::LogEntry, LogLevel::Info);
+      let error = observation(execution.id, "error", ObservationType::LogEntry, LogLevel::Error);
+      let payload = observation(execution.id, "data", ObservationType::Payload, LogLevel::Debug);
+      storage
+        .store_observations(vec![
+          with_payloads(log.clone()),
+          with_payloads(error.clone()),
+          with_payloads(payload.clone()),
+        ])
+        .await
+        .unwrap();
+
+      let fetched = storage.get_observation(log.id).await.unwrap();
+      assert_eq!(fetched.observation.name, "log", "{}", backend.name);
+      assert!(
+        matches!(&fetched.payloads[0].data, PayloadData::Inline(d) if d == b"hello"),
+        "{}",
+        backend.name
+      );
+      assert!(
+        matches!(fetched.payloads[1].data, PayloadData::Blob),
+        "{}",
+        backend.name
+      );
+      assert_eq!(fetched.payloads[0].encoding, ContentEncoding::Identity, "{}", backend.name);
+      assert_eq!(fetched.payloads[1].encoding, ContentEncoding::Zstd, "{}", backend.name);
+
+      let all = ObservationFilter::default();
+      let listed = storage
+        .list_observations(execution.id, None, None, &all)
+        .await
+        .unwrap();
+      assert_eq!(listed.len(), 3, "{}", backend.name);
+      assert!(
+        listed
+          .iter()
+          .all(|o| o.payloads.iter().all(|p| matches!(p.data, PayloadData::Blob))),
+        "{}",
+        backend.name
+      );
+      let page = storage
+        .list_observations(execution.id, Some(2), Some(1), &all)
+        .await
+        .unwrap();
+      assert_eq!(page.len(), 2, "{}", backend.name);
+      assert_eq!(
+        storage.count_observations(execution.id, &all).await.unwrap(),
+        3,
+        "{}",
+        backend.name
+      );
+
+      let payloads = ObservationFilter::with_type(ObservationType::Payload);
+      assert_eq!(
+        storage.count_observations(execution.id, &payloads).await.unwrap(),
+        1,
+        "{}",
+        backend.name
+      );
+      let warnings = ObservationFilter {
+        min_log_level: Some(LogLevel::Warning),
+        ..Default::default()
+      };
+      let listed = storage
+        .list_observations(execution.id, None, None, &warnings)
+        .await
+        .unwrap();
+      assert_eq!(listed.len(), 1, "{}", backend.name);
+      assert_eq!(listed[0].observation.name, "error", "{}", backend.name);
+      let named = ObservationFilter {
+        name: Some("er*".to_string()),
+        min_log_level: Some(LogLevel::Debug),
+        ..Default::default()
+      };
+      assert_eq!(
+        storage.count_observations(execution.id, &named).await.unwrap(),
+        1,
+        "{}",
+        backend.name
+      );
+      let later = ObservationFilter {
+        created_after: Some(Utc::now() + Duration::hours(1)),
+        ..Default::default()
+      };
+      assert_eq!(
+        storage.count_observations(execution.id, &later).await.unwrap(),
+        0,
+        "{}",
+        backend.name
+      );
+
+      assert_eq!(
+        storage.execution_size(execution.id).await.unwrap(),
+        315,
+        "{}",
+        backend.name
+      );
+
+      // Observations cannot be moved to another execution by replacing them
+      let mut moved = log.clone();
+      moved.execution_id = ExecutionId::new();
+      let result = storage.store_observations(vec![with_payloads(moved)]).await;
+      assert!(
+        matches!(result, Err(StorageError::AlreadyExists(_))),
+        "{}",
+        backend.name
+      );
+      assert_eq!(
+        storage.get_observation(log.id).await.unwrap().observation.execution_id,
+        execution.id,
+        "{}",
+        backend.name
+      );
+    }
+  }
+
+  #[tokio::test]
+  async fn test_delete_execution() {
+    for backend in backends().await {
+      let storage = backend.storage.as_ref();
+      let execution = Execution::new("execution");
+      let other = Execution::new("other");
+      storage.store_execution(&execution).await.unwrap();
+      storage.store_execution(&other).await.unwrap();
+      let obs = observation(execution.id, "log", ObservationType::LogEntry, LogLevel::Info);
+      let kept = observation(other.id, "kept", ObservationType::LogEntry, LogLevel::Info);
+      storage
+        .store_observations(vec![with_payloads(obs.clone()), with_payloads(kept.clone())])
+        .await
+        .unwrap();
+
+      let digest = "cd".repeat(32);
+      let scope = BlobScope::Execution(execution.id);
+      let other_scope = BlobScope::Execution(other.id);
+      storage.add_blob_scope(&digest, &scope).await.unwrap();
+      storage.add_blob_scope(&digest, &other_scope).await.unwrap();
+
+      let deleted = storage.delete_execution(execution.id).await.unwrap();
+      assert_eq!(deleted.len(), 1, "{}", backend.name);
+      // Only the scope of the deleted execution is removed
+      assert!(!storage.blob_in_scope(&digest, &scope).await.unwrap(), "{}", backend.name);
+      assert!(storage.blob_in_scope(&digest, &other_scope).await.unwrap(), "{}", backend.name);
+      assert!(
+        matches!(&deleted[0].payloads[0].data, PayloadData::Inline(d) if d == b"hello"),
+        "{}",
+        backend.name
+      );
+      assert!(
+        matches!(
+          storage.get_observation(obs.id).await,
+          Err(StorageError::NotFound(_))
+        ),
+        "{}",
+        backend.name
+      );
+      assert!(storage.get_observation(kept.id).await.is_ok(), "{}", backend.name);
+      let remaining = storage
+        .count_executions(&ExecutionFilter::default())
+        .await
+        .unwrap();
+      assert_eq!(remaining, 1, "{}", backend.name);
+      assert!(
+        matches!(
+          storage.delete_execution(execution.id).await,
+          Err(StorageError::NotFound(_))
+        ),
+        "{}",
+        backend.name
+      );
+    }
+  }
+
+  #[tokio::test]
+  async fn test_blob_records() {
+    for backend in backends().await {
+      let storage = backend.storage.as_ref();
+      let digest = "ab".repeat(32);
+      let record = BlobRecord::new(digest.clone(), 100, ContentEncoding::Zstd);
+      let (stored, created) = storage.register_blob(&record).await.unwrap();
+      assert!(created, "{}", backend.name);
+      assert_eq!(stored.refcount, 0, "{}", backend.name);
+
+      // The first registered encoding wins
+      let other = BlobRecord::new(digest.clone(), 100, ContentEncoding::Identity);
+      let (stored, created) = storage.register_blob(&other).await.unwrap();
+      assert!(!created, "{}", backend.name);
+      assert_eq!(stored.encoding, ContentEncoding::Zstd, "{}", backend.name);
+
+      // Two observations in different executions share the blob
+      let execution = Execution::new("execution");
+      let other_execution = Execution::new("other");
+      let scope = BlobScope::Execution(execution.id);
+      let other_scope = BlobScope::Project(ProjectId::parse("other").unwrap());
+      storage.add_blob_scope(&digest, &scope).await.unwrap();
+      // Adding a scope again is a no-op
+      storage.add_blob_scope(&digest, &scope).await.unwrap();
+      assert!(storage.blob_in_scope(&digest, &scope).await.unwrap(), "{}", backend.name);
+      assert!(!storage.blob_in_scope(&digest, &other_scope).await.unwrap(), "{}", backend.name);
+      let mut references = Vec::new();
+      for execution_id in [execution.id, other_execution.id] {
+        let mut obs = with_payloads(observation(
+          execution_id,
+          "blob",
+          ObservationType::Payload,
+          LogLevel::Info,
+        ));
+        obs.payloads[1].digest = Some(digest.clone());
+        references.push(obs);
+      }
+      storage.store_observations(references.clone()).await.unwrap();
+      // Storing an observation again does not add a reference
+      storage.store_observations(references[..1].to_vec()).await.unwrap();
+      let fetched = storage.get_observation(references[0].observation.id).await.unwrap();
+      assert_eq!(fetched.payloads[1].digest.as_deref(), Some(digest.as_str()), "{}", backend.name);
+      assert_eq!(fetched.payloads[0].digest, None, "{}", backend.name);
+      assert_eq!(storage.get_blob_record(&digest).await.unwrap().refcount, 2, "{}", backend.name);
+
+      let future = Utc::now() + Duration::hours(1);
+      storage.delete_execution(execution.id).await.unwrap();
+      assert_eq!(storage.get_blob_record(&digest).await.unwrap().refcount, 1, "{}", backend.name);
+      assert!(storage.unreferenced_blobs(future).await.unwrap().is_empty(), "{}", backend.name);
+      assert!(!storage.delete_blob_record(&digest, future).await.unwrap(), "{}", backend.name);
+
+      storage.delete_execution(other_execution.id).await.unwrap();
+      let unreferenced = storage.unreferenced_blobs(future).await.unwrap();
+      assert_eq!(unreferenced.len(), 1, "{}", backend.name);
+      assert_eq!(unreferenced[0].digest, digest, "{}", backend.name);
+      // Recently released blobs are kept for the grace period
+      let past = Utc::now() - Duration::hours(1);
+      assert!(storage.unreferenced_blobs(past).await.unwrap().is_empty(), "{}", backend.name);
+      assert!(!storage.delete_blob_record(&digest, past).await.unwrap(), "{}", backend.name);
+      assert!(storage.delete_blob_record(&digest, future).await.unwrap(), "{}", backend.name);
+      assert!(!storage.blob_in_scope(&digest, &scope).await.unwrap(), "{}", backend.name);
+      assert!(
+        matches!(
+          storage.get_blob_record(&digest).await,
+          Err(StorageError::NotFound(_))
+        ),
+        "{}",
+        backend.name
+      );
+    }
+  }
+
+  #[tokio::test]
+  async fn test_api_keys() {
+    use crate::auth::ApiKeyScope;
+
+    for backend in backends().await {
+      let storage = backend.storage.as_ref();
+      let mut first = ApiKeyRecord::new("ci", ApiKeyScope::WriteOnly);
+      first.created_at = Utc::now() - Duration::hours(1);
+      first.execution_prefixes = vec!["ci-".to_string()];
+      let second = ApiKeyRecord::new("dashboard", ApiKeyScope::ReadOnly);
+      storage.store_api_key(&second).await.unwrap();
+      storage.store_api_key(&first).await.unwrap();
+
+      assert_eq!(storage.get_api_key(first.id).await.unwrap(), first, "{}", backend.name);
+      let ids: Vec<_> = storage.list_api_keys().await.unwrap().iter().map(|k| k.id).collect();
+      assert_eq!(ids, vec![first.id, second.id], "{}", backend.name);
+
+      first.revoked_at = Some(Utc::now());
+      storage.store_api_key(&first).await.unwrap();
+      let fetched = storage.get_api_key(first.id).await.unwrap();
+      assert!(fetched.revoked_at.is_some(), "{}", backend.name);
+      assert_eq!(storage.list_api_keys().await.unwrap().len(), 2, "{}", backend.name);
+
+      assert!(
+        matches!(
+          storage.get_api_key(Uuid::new_v4()).await,
+          Err(StorageError::NotFound(_))
+        ),
+        "{}",
+        backend.name
+      );
+    }
+  }
+
+  #[tokio::test]
+  async fn test_projects() {
+    for backend in backends().await {
+      let storage = backend.storage.as_ref();
+      let team = Project::new(ProjectId::parse("team").unwrap(), "Team");
+      storage.store_project(&team).await.unwrap();
+      storage.store_project(&Project::default_project()).await.unwrap();
+      let ids: Vec<_> = storage.list_projects().await.unwrap().into_iter().map(|p| p.id).collect();
+      assert_eq!(ids, vec![ProjectId::default(), team.id.clone()], "{}", backend.name);
+      assert_eq!(storage.get_project(&team.id).await.unwrap(), team, "{}", backend.name);
+      assert!(
+        matches!(
+          storage.get_project(&ProjectId::parse("other").unwrap()).await,
+          Err(StorageError::NotFound(_))
+        ),
+        "{}",
+        backend.name
+      );
+
+      let execution = Execution::new("team-run").in_project(team.id.clone());
+      storage.store_execution(&execution).await.unwrap();
+      storage.store_execution(&Execution::new("default-run")).await.unwrap();
+      let filter = ExecutionFilter {
+        project: Some(team.id.clone()),
+        ..Default::default()
+      };
+      let listed = storage.list_executions(Some(10), None, &filter).await.unwrap();
+      assert_eq!(listed.len(), 1, "{}", backend.name);
+      assert_eq!(listed[0].id, execution.id, "{}", backend.name);
+      assert_eq!(storage.count_executions(&filter).await.unwrap(), 1, "{}", backend.name);
+    }
+  }
+
+  #[tokio::test]
+  async fn test_snapshot_baselines() {
+    for backend in backends().await {
+      let storage = backend.storage.as_ref();
+      let execution = Execution::new("suite");
+      let first = with_payloads(observation(
+        execution.id,
+        "report",
+        ObservationType::Payload,
+        LogLevel::Info,
+      ));
+      let second = with_payloads(observation(
+        execution.id,
+        "report",
+        ObservationType::Payload,
+        LogLevel::Info,
+      ));
+      let other = with_payloads(observation(
+        execution.id,
+        "config",
+        ObservationType::Payload,
+        LogLevel::Info,
+      ));
+      let report = SnapshotBaseline::new(&execution, &first);
+      storage.store_snapshot_baseline(&report).await.unwrap();
+      storage.store_snapshot_baseline(&SnapshotBaseline::new(&execution, &other)).await.unwrap();
+      // Accepting another observation of the same name replaces the baseline
+      let report = SnapshotBaseline::new(&execution, &second);
+      storage.store_snapshot_baseline(&report).await.unwrap();
+
+      let baseline = storage
+        .get_snapshot_baseline(&execution.project, "suite", "report")
+        .await
+        .unwrap();
+      assert_eq!(baseline, report, "{}", backend.name);
+      let names: Vec<_> = storage
+        .list_snapshot_baselines()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|b| b.name)
+        .collect();
+      assert_eq!(names, vec!["config", "report"], "{}", backend.name);
+      assert!(
+        matches!(
+          storage.get_snapshot_baseline(&execution.project, "other-suite", "report").await,
+          Err(StorageError::NotFound(_))
+        ),
+        "{}",
+        backend.name
+      );
+    }
+  }
 }
@@ -1,13 +1,30 @@
 //! Storage layer abstractions and implementations
 
 pub mod blob;
+pub mod content;
+pub mod filter;
 pub mod metadata;
 pub mod proto;
+pub mod search;
+pub mod sql;
 
+pub use blob::BlobKey;
 pub use blob::BlobStorage;
-pub use blob::LocalBlobStorage;
+pub use blob::BlobStream;
+pub use blob::ObjectStoreBlobStorage;
+pub use filter::ExecutionFilter;
+pub use filter::ObservationFilter;
+pub use metadata::BlobRecord;
+pub use metadata::BlobScope;
+pub use metadata::ExecutionUpdate;
+pub use metadata::UsageCheck;
 pub use metadata::MetadataStorage;
 pub use metadata::SledStorage;
+pub use search::SearchIndex;
+pub use search::SledSearchIndex;
+pub use sql::SqlStorage;
+use observation_tools_shared::ContentEncoding;
+use observation_tools_shared::ObservationId;
 use observation_tools_shared::PayloadId;
 use thiserror::Error;
 
@@ -29,6 +46,9 @@ pub enum StorageError {
   #[error("Database error: {0}")]
   Database(#[from] sled::Error),
 
+  #[error("SQL error: {0}")]
+  Sql(#[from] sqlx::Error),
+
   #[error("Protobuf decode error: {0}")]
   Protobuf(#[from] prost::DecodeError),
 
@@ -55,10 +75,31 @@ pub struct StoredPayload {
   pub id: PayloadId,
   pub name: String,
   pub mime_type: String,
+  /// Size of the payload in bytes, before any compression
   pub size: usize,
+  /// Compression of the stored data, inline or in blob storage
+  pub encoding: ContentEncoding,
+  /// Content digest of blob payloads stored content-addressed, see
+  /// [`BlobRecord`]
+  pub digest: Option<String>,
   pub data: PayloadData,
 }
 
+impl StoredPayload {
+  /// Decompress data stored for this payload
+  pub fn decode(&self, data: &[u8]) -> StorageResult<Vec<u8>> {
+    Ok(self.encoding.decode(data, self.size)?)
+  }
+
+  /// Key of the blob storing this payload's data
+  pub fn blob_key(&self, observation_id: ObservationId) -> BlobKey {
+    match &self.digest {
+      Some(digest) => BlobKey::content(digest.clone(), self.encoding),
+      None => BlobKey::payload(observation_id, self.id.clone()),
+    }
+  }
+}
+
 /// Whether payload data is inline or stored as a blob
 #[derive(Clone, Debug)]
 pub enum PayloadData {
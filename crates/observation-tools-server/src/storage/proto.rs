@@ -1,3 +1,4 @@
+use observation_tools_shared::ContentEncoding;
 use observation_tools_shared::ExecutionId;
 use observation_tools_shared::GroupId;
 use observation_tools_shared::LogLevel;
@@ -8,7 +9,9 @@ use observation_tools_shared::PayloadId;
 use observation_tools_shared::SourceInfo;
 use std::collections::HashMap;
 
+use super::PayloadData;
 use super::StorageError;
+use super::StoredPayload;
 
 /// Protobuf-encoded observation metadata + payload manifest
 #[derive(Clone, PartialEq, prost::Message)]
@@ -91,6 +94,12 @@ pub struct StoredPayloadMeta {
   /// Whether this payload is stored as a blob (true) or inline (false)
   #[prost(bool, tag = "5")]
   pub is_blob: bool,
+  /// Compression of the stored data, empty if not compressed
+  #[prost(string, tag = "6")]
+  pub encoding: String,
+  /// Content digest of content-addressed blobs, empty for other payloads
+  #[prost(string, tag = "7")]
+  pub digest: String,
 }
 
 /// Protobuf-encoded inline payload data
@@ -167,12 +176,41 @@ impl StoredObservation {
 }
 
 impl StoredPayloadMeta {
+  pub fn from_payload(payload: &StoredPayload) -> Self {
+    StoredPayloadMeta {
+      payload_id: payload.id.as_str().to_string(),
+      name: payload.name.clone(),
+      mime_type: payload.mime_type.clone(),
+      size: payload.size as u64,
+      is_blob: matches!(payload.data, PayloadData::Blob),
+      encoding: match payload.encoding {
+        ContentEncoding::Identity => String::new(),
+        encoding => encoding.as_str().to_string(),
+      },
+      digest: payload.digest.clone().unwrap_or_default(),
+    }
+  }
+
   pub fn to_payload_id(&self) -> PayloadId {
     PayloadId::from(self.payload_id.clone())
   }
+
+  /// Convert to a payload with the given data
+  pub fn to_payload(&self, data: PayloadData) -> StoredPayload {
+    StoredPayload {
+      id: self.to_payload_id(),
+      name: self.name.clone(),
+      mime_type: self.mime_type.clone(),
+      size: self.size as usize,
+      // Only encodings known to this server are ever written
+      encoding: self.encoding.parse().unwrap_or_default(),
+      digest: (!self.digest.is_empty()).then(|| self.digest.clone()),
+      data,
+    }
+  }
 }
 
-fn observation_type_to_i32(t: ObservationType) -> i32 {
+pub(crate) fn observation_type_to_i32(t: ObservationType) -> i32 {
   match t {
     ObservationType::LogEntry => 0,
     ObservationType::Payload => 1,
@@ -191,7 +229,7 @@ fn observation_type_from_i32(v: i32) -> ObservationType {
   }
 }
 
-fn log_level_to_i32(l: LogLevel) -> i32 {
+pub(crate) fn log_level_to_i32(l: LogLevel) -> i32 {
   match l {
     LogLevel::Trace => 0,
     LogLevel::Debug => 1,
@@ -0,0 +1,390 @@
+//! Full-text search index over observations
+//!
+//! The index is an inverted index stored in its own sled database next to the
+//! metadata database. Each posting is a key of the form
+//! `"{term}\0{execution_id}\0{observation_id}"`, so a term can be looked up
+//! globally (prefix `"{term}\0"`) or within a single execution (prefix
+//! `"{term}\0{execution_id}\0"`). A second tree holds the same entries keyed
+//! by `"{execution_id}\0{observation_id}\0{term}"` so that everything indexed
+//! for an execution can be removed without scanning every posting, and so
+//! that the terms of a document can be checked while scanning the postings of
+//! another term.
+
+use super::StorageError;
+use super::StorageResult;
+use observation_tools_shared::ExecutionId;
+use observation_tools_shared::Observation;
+use observation_tools_shared::ObservationId;
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// Maximum number of payload bytes indexed per payload
+pub const MAX_INDEXED_PAYLOAD_BYTES: usize = 1024 * 1024;
+
+/// Maximum length of a single indexed token, longer tokens are truncated
+const MAX_TOKEN_LENGTH: usize = 64;
+
+/// Term prefix for words from names, metadata and payload text
+const TEXT_TERM_PREFIX: &str = "t:";
+
+/// Term prefix for exact `key=value` metadata pairs
+const METADATA_TERM_PREFIX: &str = "m:";
+
+const KEY_SEPARATOR: char = '\0';
+
+/// An observation and the text extracted from its payloads, ready to be
+/// indexed
+#[derive(Clone, Debug)]
+pub struct SearchDocument {
+  pub observation: Observation,
+  pub payload_text: Vec<String>,
+}
+
+/// A search request
+#[derive(Clone, Debug)]
+pub struct SearchQuery {
+  /// Query text. Whitespace separated terms are AND'ed together. A term of the
+  /// form `key=value` matches observations with that exact metadata pair, and
+  /// a trailing `*` turns a term into a prefix match.
+  pub text: String,
+
+  /// Restrict results to a single execution
+  pub execution_id: Option<ExecutionId>,
+}
+
+/// A single search result
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SearchHit {
+  pub execution_id: ExecutionId,
+  pub observation_id: ObservationId,
+}
+
+/// Search hits, most recent first
+pub type SearchHits = Box<dyn Iterator<Item = StorageResult<SearchHit>> + Send>;
+
+/// Trait for indexing and searching observations
+#[async_trait::async_trait]
+pub trait SearchIndex: Send + Sync {
+  /// Add observations to the index
+  async fn index_observations(&self, documents: Vec<SearchDocument>) -> StorageResult<()>;
+
+  /// Find observations matching a query, most recent first. Global searches
+  /// list the hits of more recent executions first. Hits are read from the
+  /// index as they are consumed.
+  async fn search(&self, query: &SearchQuery) -> StorageResult<SearchHits>;
+
+  /// Remove all indexed observations for an execution
+  async fn remove_execution(&self, execution_id: ExecutionId) -> StorageResult<()>;
+}
+
+/// Extract indexable text from a payload, if its MIME type is text-like
+pub fn payload_text(mime_type: &str, data: &[u8]) -> Option<String> {
+  let is_text = mime_type.starts_with("text/")
+    || mime_type.starts_with("application/json")
+    || mime_type.starts_with("application/xml");
+  if !is_text {
+    return None;
+  }
+  let data = &data[..data.len().min(MAX_INDEXED_PAYLOAD_BYTES)];
+  Some(String::from_utf8_lossy(data).into_owned())
+}
+
+/// Split text into lowercase alphanumeric tokens
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+  text
+    .split(|c: char| !c.is_alphanumeric())
+    .filter(|t| !t.is_empty())
+    .map(|t| t.chars().take(MAX_TOKEN_LENGTH).collect::<String>().to_lowercase())
+}
+
+fn text_term(token: &str) -> String {
+  format!("{}{}", TEXT_TERM_PREFIX, token)
+}
+
+fn metadata_term(key: &str, value: &str) -> String {
+  format!(
+    "{}{}={}",
+    METADATA_TERM_PREFIX,
+    key.to_lowercase(),
+    value.to_lowercase()
+  )
+}
+
+/// Collect all terms for a document
+fn document_terms(document: &SearchDocument) -> BTreeSet<String> {
+  let obs = &document.observation;
+  let mut terms = BTreeSet::new();
+  for token in tokenize(&obs.name) {
+    terms.insert(text_term(&token));
+  }
+  for (key, value) in &obs.metadata {
+    terms.insert(metadata_term(key, value));
+    for token in tokenize(key).chain(tokenize(value)) {
+      terms.insert(text_term(&token));
+    }
+  }
+  for text in &document.payload_text {
+    for token in tokenize(text) {
+      terms.insert(text_term(&token));
+    }
+  }
+  terms
+}
+
+/// A parsed query term
+#[derive(Debug, PartialEq, Eq)]
+enum QueryTerm {
+  Exact(String),
+  Prefix(String),
+}
+
+fn parse_query(text: &str) -> StorageResult<Vec<QueryTerm>> {
+  let mut terms = Vec::new();
+  for word in text.split_whitespace() {
+    if let Some((key, value)) = word.split_once('=') {
+      if !key.is_empty() {
+        terms.push(QueryTerm::Exact(metadata_term(key, value)));
+        continue;
+      }
+    }
+    let (word, is_prefix) = match word.strip_suffix('*') {
+      Some(stripped) => (stripped, true),
+      None => (word, false),
+    };
+    let tokens: Vec<String> = tokenize(word).collect();
+    let last = tokens.len().saturating_sub(1);
+    for (i, token) in tokens.into_iter().enumerate() {
+      if is_prefix && i == last {
+        terms.push(QueryTerm::Prefix(text_term(&token)));
+      } else {
+        terms.push(QueryTerm::Exact(text_term(&token)));
+      }
+    }
+  }
+  if terms.is_empty() {
+    return Err(StorageError::Search(format!(
+      "Search query '{}' contains no searchable terms",
+      text
+    )));
+  }
+  Ok(terms)
+}
+
+/// Sled-based search index implementation
+pub struct SledSearchIndex {
+  db: sled::Db,
+}
+
+impl SledSearchIndex {
+  /// Create a new Sled search index instance
+  pub fn new(path: impl AsRef<Path>) -> StorageResult<Self> {
+    let db = sled::open(path)?;
+    Ok(Self { db })
+  }
+
+  /// Get the postings tree
+  fn postings_tree(&self) -> StorageResult<sled::Tree> {
+    Ok(self.db.open_tree("postings")?)
+  }
+
+  /// Get the document->terms tree, the reverse of the postings tree
+  fn documents_tree(&self) -> StorageResult<sled::Tree> {
+    Ok(self.db.open_tree("documents")?)
+  }
+}
+
+/// Split a posting key into its (execution_id, observation_id)
+fn posting_document(key: &[u8]) -> StorageResult<(String, String)> {
+  let key = std::str::from_utf8(key)
+    .map_err(|e| StorageError::Search(format!("Invalid posting key: {}", e)))?;
+  let mut parts = key.rsplitn(3, KEY_SEPARATOR);
+  match (parts.next(), parts.next()) {
+    (Some(obs_id), Some(exec_id)) => Ok((exec_id.to_string(), obs_id.to_string())),
+    _ => Err(StorageError::Search(format!("Invalid posting key: {}", key))),
+  }
+}
+
+/// Split a document key into its (execution_id, observation_id)
+fn document_key(key: &[u8]) -> StorageResult<(String, String)> {
+  let key = std::str::from_utf8(key)
+    .map_err(|e| StorageError::Search(format!("Invalid document key: {}", e)))?;
+  let mut parts = key.splitn(3, KEY_SEPARATOR);
+  match (parts.next(), parts.next()) {
+    (Some(exec_id), Some(obs_id)) => Ok((exec_id.to_string(), obs_id.to_string())),
+    _ => Err(StorageError::Search(format!("Invalid document key: {}", key))),
+  }
+}
+
+/// Whether a document has a term, looked up in the documents tree
+fn has_term(docs: &sled::Tree, exec_id: &str, obs_id: &str, term: &QueryTerm) -> StorageResult<bool> {
+  let prefix = format!("{exec_id}{KEY_SEPARATOR}{obs_id}{KEY_SEPARATOR}");
+  Ok(match term {
+    QueryTerm::Exact(term) => docs.contains_key(format!("{prefix}{term}").as_bytes())?,
+    QueryTerm::Prefix(term) => docs
+      .scan_prefix(format!("{prefix}{term}").as_bytes())
+      .next()
+      .transpose()?
+      .is_some(),
+  })
+}
+
+#[async_trait::async_trait]
+impl SearchIndex for SledSearchIndex {
+  async fn index_observations(&self, documents: Vec<SearchDocument>) -> StorageResult<()> {
+    let postings = self.postings_tree()?;
+    let docs = self.documents_tree()?;
+    let mut postings_batch = sled::Batch::default();
+    let mut docs_batch = sled::Batch::default();
+    for document in &documents {
+      let obs = &document.observation;
+      for term in document_terms(document) {
+        let key = format!(
+          "{term}{KEY_SEPARATOR}{}{KEY_SEPARATOR}{}",
+          obs.execution_id, obs.id
+        );
+        postings_batch.insert(key.as_bytes(), &[]);
+        let doc_key = format!(
+          "{}{KEY_SEPARATOR}{}{KEY_SEPARATOR}{term}",
+          obs.execution_id, obs.id
+        );
+        docs_batch.insert(doc_key.as_bytes(), &[]);
+      }
+    }
+    postings.apply_batch(postings_batch)?;
+    docs.apply_batch(docs_batch)?;
+    Ok(())
+  }
+
+  async fn search(&self, query: &SearchQuery) -> StorageResult<SearchHits> {
+    let terms = parse_query(&query.text)?;
+    let postings = self.postings_tree()?;
+    let docs = self.documents_tree()?;
+    let execution_prefix = query
+      .execution_id
+      .map(|id| format!("{id}{KEY_SEPARATOR}"))
+      .unwrap_or_default();
+
+    // Candidates are scanned backwards, since observation IDs are UUIDv7 and
+    // sort by creation time. The postings of an exact term list only the
+    // documents that have it, otherwise every document of the scope is a
+    // candidate.
+    let exact_term = terms.iter().find_map(|term| match term {
+      QueryTerm::Exact(term) => Some(term),
+      QueryTerm::Prefix(_) => None,
+    });
+    let candidates: Box<dyn Iterator<Item = StorageResult<(String, String)>> + Send> =
+      match exact_term {
+        Some(term) => {
+          let prefix = format!("{term}{KEY_SEPARATOR}{execution_prefix}");
+          Box::new(
+            postings
+              .scan_prefix(prefix.as_bytes())
+              .rev()
+              .map(|item| posting_document(&item?.0)),
+          )
+        }
+        None => {
+          // Each document has one key per term
+          let mut previous = None;
+          Box::new(
+            docs
+              .scan_prefix(execution_prefix.as_bytes())
+              .rev()
+              .map(|item| document_key(&item?.0))
+              .filter(move |document| {
+                let Ok(document) = document else {
+                  return true;
+                };
+                if previous.as_ref() == Some(document) {
+                  return false;
+                }
+                previous = Some(document.clone());
+                true
+              }),
+          )
+        }
+      };
+
+    let hits = candidates.filter_map(move |document| {
+      let matches = document.and_then(|(exec_id, obs_id)| {
+        for term in &terms {
+          if !has_term(&docs, &exec_id, &obs_id, term)? {
+            return Ok(None);
+          }
+        }
+        Ok(Some(SearchHit {
+          execution_id: ExecutionId::parse(&exec_id)
+            .map_err(|e| StorageError::Search(format!("Invalid execution ID in index: {}", e)))?,
+          observation_id: ObservationId::parse(&obs_id)
+            .map_err(|e| StorageError::Search(format!("Invalid observation ID in index: {}", e)))?,
+        }))
+      });
+      matches.transpose()
+    });
+    Ok(Box::new(hits))
+  }
+
+  async fn remove_execution(&self, execution_id: ExecutionId) -> StorageResult<()> {
+    let postings = self.postings_tree()?;
+    let docs = self.documents_tree()?;
+    let prefix = format!("{execution_id}{KEY_SEPARATOR}");
+    let mut postings_batch = sled::Batch::default();
+    let mut docs_batch = sled::Batch::default();
+    for item in docs.scan_prefix(prefix.as_bytes()) {
+      let (key, _) = item?;
+      let doc_key = std::str::from_utf8(&key)
+        .map_err(|e| StorageError::Search(format!("Invalid document key: {}", e)))?;
+      let mut parts = doc_key.splitn(3, KEY_SEPARATOR);
+      let (Some(exec_id), Some(obs_id), Some(term)) = (parts.next(), parts.next(), parts.next())
+      else {
+        continue;
+      };
+      let posting_key = format!("{term}{KEY_SEPARATOR}{exec_id}{KEY_SEPARATOR}{obs_id}");
+      postings_batch.remove(posting_key.as_bytes());
+      docs_batch.remove(key);
+    }
+    postings.apply_batch(postings_batch)?;
+    docs.apply_batch(docs_batch)?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_tokenize() {
+    let tokens: Vec<String> = tokenize("Hello, World! user_id=42").collect();
+    assert_eq!(tokens, vec!["hello", "world", "user", "id", "42"]);
+  }
+
+  #[test]
+  fn test_parse_query_metadata_pair() {
+    let terms = parse_query("user_id=42 Login*").unwrap();
+    assert_eq!(
+      terms,
+      vec![
+        QueryTerm::Exact("m:user_id=42".to_string()),
+        QueryTerm::Prefix("t:login".to_string()),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_parse_query_empty() {
+    assert!(matches!(
+      parse_query("  ,; "),
+      Err(StorageError::Search(_))
+    ));
+  }
+
+  #[test]
+  fn test_payload_text_skips_binary() {
+    assert_eq!(payload_text("application/octet-stream", b"abc"), None);
+    assert_eq!(
+      payload_text("application/json", br#"{"a":1}"#),
+      Some(r#"{"a":1}"#.to_string())
+    );
+  }
+}
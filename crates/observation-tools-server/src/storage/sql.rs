@@ -0,0 +1,1159 @@
+//! SQL metadata storage for executions and observations
+//!
+//! Uses `sqlx::Any` so the same queries run against SQLite (always available)
+//! and Postgres (with the `postgres` feature). Observations are stored as the
+//! same protobuf blobs as [`SledStorage`](super::SledStorage), with the
+//! columns needed for filtering and pagination pulled out and indexed.
+
+use super::proto::observation_type_to_i32;
+use super::proto::log_level_to_i32;
+use super::metadata::check_replaced_execution;
+use super::metadata::payload_digests;
+use super::proto::StoredObservation;
+use super::proto::StoredPayloadMeta;
+use super::BlobRecord;
+use super::BlobScope;
+use super::ExecutionFilter;
+use super::ExecutionUpdate;
+use super::MetadataStorage;
+use super::ObservationFilter;
+use super::ObservationWithPayloads;
+use super::PayloadData;
+use super::StorageError;
+use super::StorageResult;
+use super::UsageCheck;
+use crate::auth::ApiKeyRecord;
+use crate::quota::ExecutionUsage;
+use crate::snapshot::SnapshotBaseline;
+use chrono::DateTime;
+use chrono::Utc;
+use observation_tools_shared::Execution;
+use observation_tools_shared::ExecutionId;
+use observation_tools_shared::ExecutionStatus;
+use observation_tools_shared::ObservationId;
+use observation_tools_shared::Project;
+use observation_tools_shared::ProjectId;
+use prost::Message;
+use sqlx::any::AnyPoolOptions;
+use sqlx::any::AnyRow;
+use sqlx::AnyConnection;
+use sqlx::AnyPool;
+use sqlx::Row;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// The SQL dialect of the connected database
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dialect {
+  Sqlite,
+  Postgres,
+}
+
+impl Dialect {
+  fn from_url(url: &str) -> StorageResult<Self> {
+    if url.starts_with("sqlite:") {
+      Ok(Dialect::Sqlite)
+    } else if url.starts_with("postgres:") || url.starts_with("postgresql:") {
+      Ok(Dialect::Postgres)
+    } else {
+      Err(StorageError::Internal(format!(
+        "Unsupported metadata database URL '{}', expected sqlite:// or postgres://",
+        url
+      )))
+    }
+  }
+
+  fn blob_type(self) -> &'static str {
+    match self {
+      Dialect::Sqlite => "BLOB",
+      Dialect::Postgres => "BYTEA",
+    }
+  }
+}
+
+/// Schema, applied on connect. Every statement is idempotent.
+fn schema(dialect: Dialect) -> Vec<String> {
+  let blob = dialect.blob_type();
+  vec![
+    "CREATE TABLE IF NOT EXISTS executions (
+      id TEXT PRIMARY KEY,
+      name TEXT NOT NULL,
+      created_at BIGINT NOT NULL,
+      status TEXT NOT NULL,
+      project TEXT NOT NULL,
+      data TEXT NOT NULL
+    )"
+    .to_string(),
+    "CREATE INDEX IF NOT EXISTS executions_created_at ON executions (created_at)".to_string(),
+    "CREATE INDEX IF NOT EXISTS executions_status ON executions (status, created_at)".to_string(),
+    format!(
+      "CREATE TABLE IF NOT EXISTS observations (
+        id TEXT PRIMARY KEY,
+        execution_id TEXT NOT NULL,
+        name TEXT NOT NULL,
+        observation_type BIGINT NOT NULL,
+        log_level BIGINT NOT NULL,
+        created_at BIGINT NOT NULL,
+        payload_size BIGINT NOT NULL,
+        data {blob} NOT NULL
+      )"
+    ),
+    "CREATE INDEX IF NOT EXISTS observations_execution_id ON observations (execution_id, id)"
+      .to_string(),
+    "CREATE INDEX IF NOT EXISTS observations_execution_type
+      ON observations (execution_id, observation_type, id)"
+      .to_string(),
+    "CREATE INDEX IF NOT EXISTS observations_execution_level
+      ON observations (execution_id, log_level, id)"
+      .to_string(),
+    "CREATE INDEX IF NOT EXISTS observations_created_at ON observations (created_at)".to_string(),
+    format!(
+      "CREATE TABLE IF NOT EXISTS payloads (
+        observation_id TEXT NOT NULL,
+        payload_id TEXT NOT NULL,
+        data {blob} NOT NULL,
+        PRIMARY KEY (observation_id, payload_id)
+      )"
+    ),
+    "CREATE TABLE IF NOT EXISTS blobs (
+      digest TEXT PRIMARY KEY,
+      size BIGINT NOT NULL,
+      encoding TEXT NOT NULL,
+      refcount BIGINT NOT NULL,
+      updated_at BIGINT NOT NULL
+    )"
+    .to_string(),
+    "CREATE INDEX IF NOT EXISTS blobs_unreferenced ON blobs (refcount, updated_at)".to_string(),
+    "CREATE TABLE IF NOT EXISTS blob_scopes (
+      digest TEXT NOT NULL,
+      scope TEXT NOT NULL,
+      PRIMARY KEY (digest, scope)
+    )"
+    .to_string(),
+    "CREATE INDEX IF NOT EXISTS blob_scopes_scope ON blob_scopes (scope)".to_string(),
+    "CREATE TABLE IF NOT EXISTS execution_usage (
+      execution_id TEXT PRIMARY KEY,
+      observations BIGINT NOT NULL,
+      bytes BIGINT NOT NULL
+    )"
+    .to_string(),
+    "CREATE TABLE IF NOT EXISTS api_keys (
+      id TEXT PRIMARY KEY,
+      created_at BIGINT NOT NULL,
+      data TEXT NOT NULL
+    )"
+    .to_string(),
+    "CREATE TABLE IF NOT EXISTS projects (
+      id TEXT PRIMARY KEY,
+      data TEXT NOT NULL
+    )"
+    .to_string(),
+    "CREATE TABLE IF NOT EXISTS snapshot_baselines (
+      project TEXT NOT NULL,
+      execution_name TEXT NOT NULL,
+      name TEXT NOT NULL,
+      data TEXT NOT NULL,
+      PRIMARY KEY (project, execution_name, name)
+    )"
+    .to_string(),
+  ]
+}
+
+/// Add the `project` column to `executions` tables created before projects
+/// existed, filled in from each execution's JSON data
+async fn add_project_column(pool: &AnyPool, dialect: Dialect) -> StorageResult<()> {
+  let (columns, project) = match dialect {
+    Dialect::Sqlite => (
+      "SELECT COUNT(*) AS count FROM pragma_table_info('executions') WHERE name = 'project'",
+      "json_extract(data, '$.project')",
+    ),
+    Dialect::Postgres => (
+      "SELECT COUNT(*) AS count FROM information_schema.columns
+      WHERE table_name = 'executions' AND column_name = 'project'",
+      "CAST(data AS JSON) ->> 'project'",
+    ),
+  };
+  let count: i64 = sqlx::query(columns).fetch_one(pool).await?.try_get("count")?;
+  if count == 0 {
+    let mut tx = pool.begin().await?;
+    sqlx::query("ALTER TABLE executions ADD COLUMN project TEXT NOT NULL DEFAULT ''")
+      .execute(&mut *tx)
+      .await?;
+    sqlx::query(&format!(
+      "UPDATE executions SET project = COALESCE({}, '{}')",
+      project,
+      ProjectId::DEFAULT
+    ))
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+  }
+  sqlx::query("CREATE INDEX IF NOT EXISTS executions_project ON executions (project, created_at)")
+    .execute(pool)
+    .await?;
+  Ok(())
+}
+
+/// Bind values for a dynamically built query
+enum Param {
+  Text(String),
+  Int(i64),
+}
+
+/// The part of an [`ObservationFilter`] that can be evaluated in SQL
+struct SqlFilter {
+  clauses: Vec<String>,
+  params: Vec<Param>,
+  /// Whether criteria remain that must be checked in Rust after loading
+  has_residual: bool,
+}
+
+impl SqlFilter {
+  /// Build the WHERE clause for listing executions
+  fn executions(filter: &ExecutionFilter) -> Self {
+    let mut sql = SqlFilter {
+      clauses: vec![],
+      params: vec![],
+      has_residual: false,
+    };
+    if let Some(name) = &filter.name {
+      sql.params.push(Param::Text(like_pattern(name)));
+      sql
+        .clauses
+        .push(format!("LOWER(name) LIKE ${} ESCAPE '\\'", sql.params.len()));
+    }
+    if let Some(status) = filter.status {
+      sql.push("status =", Param::Text(status_text(status)));
+    }
+    if let Some(project) = &filter.project {
+      sql.push("project =", Param::Text(project.as_str().to_string()));
+    }
+    if let Some(after) = filter.created_after {
+      sql.push("created_at >=", Param::Int(after.timestamp_micros()));
+    }
+    if let Some(before) = filter.created_before {
+      sql.push("created_at <", Param::Int(before.timestamp_micros()));
+    }
+    // Tags and metadata live in the JSON data
+    sql.has_residual = filter.tag.is_some() || !filter.metadata.is_empty();
+    sql
+  }
+
+  /// Build the WHERE clause for an execution's observations. Parameter
+  /// numbering starts at `$1`, which is always the execution ID.
+  fn new(execution_id: ExecutionId, filter: &ObservationFilter) -> Self {
+    let mut sql = SqlFilter {
+      clauses: vec!["execution_id = $1".to_string()],
+      params: vec![Param::Text(execution_id.to_string())],
+      has_residual: false,
+    };
+    if let Some(t) = filter.observation_type {
+      sql.push("observation_type =", Param::Int(observation_type_to_i32(t) as i64));
+    }
+    if let Some(level) = filter.min_log_level {
+      sql.push("log_level >=", Param::Int(log_level_to_i32(level) as i64));
+    }
+    if let Some(after) = filter.created_after {
+      sql.push("created_at >=", Param::Int(after.timestamp_micros()));
+    }
+    if let Some(before) = filter.created_before {
+      sql.push("created_at <", Param::Int(before.timestamp_micros()));
+    }
+    // Name globs, metadata, groups and sources live in the protobuf data
+    let residual = ObservationFilter {
+      observation_type: None,
+      min_log_level: None,
+      created_after: None,
+      created_before: None,
+      ..filter.clone()
+    };
+    sql.has_residual = residual != ObservationFilter::default();
+    sql
+  }
+
+  fn push(&mut self, clause: &str, param: Param) {
+    self.params.push(param);
+    self.clauses.push(format!("{} ${}", clause, self.params.len()));
+  }
+
+  fn where_clause(&self) -> String {
+    if self.clauses.is_empty() {
+      return "1 = 1".to_string();
+    }
+    self.clauses.join(" AND ")
+  }
+
+  fn bind<'q>(
+    &'q self,
+    mut query: sqlx::query::Query<'q, sqlx::Any, sqlx::any::AnyArguments<'q>>,
+  ) -> sqlx::query::Query<'q, sqlx::Any, sqlx::any::AnyArguments<'q>> {
+    for param in &self.params {
+      query = match param {
+        Param::Text(s) => query.bind(s.as_str()),
+        Param::Int(i) => query.bind(*i),
+      };
+    }
+    query
+  }
+}
+
+/// A `LIKE` pattern matching names containing `name`, case-insensitively
+fn like_pattern(name: &str) -> String {
+  let mut pattern = String::from("%");
+  for c in name.to_lowercase().chars() {
+    if matches!(c, '%' | '_' | '\\') {
+      pattern.push('\\');
+    }
+    pattern.push(c);
+  }
+  pattern.push('%');
+  pattern
+}
+
+/// The value stored in the `status` column, the same as its JSON form
+fn status_text(status: ExecutionStatus) -> String {
+  match serde_json::to_value(status) {
+    Ok(serde_json::Value::String(s)) => s,
+    _ => format!("{:?}", status),
+  }
+}
+
+fn decode_blob_record(row: &AnyRow) -> StorageResult<BlobRecord> {
+  let updated_at = row.try_get::<i64, _>("updated_at")?;
+  Ok(BlobRecord {
+    digest: row.try_get("digest")?,
+    size: row.try_get::<i64, _>("size")? as u64,
+    encoding: row
+      .try_get::<String, _>("encoding")?
+      .parse()
+      .map_err(|e| StorageError::Internal(format!("Invalid blob encoding: {}", e)))?,
+    refcount: row.try_get("refcount")?,
+    updated_at: DateTime::from_timestamp_micros(updated_at)
+      .ok_or_else(|| StorageError::Internal(format!("Invalid timestamp {}", updated_at)))?,
+  })
+}
+
+/// Add `delta` references to a blob record. Records missing when adding a
+/// reference are created from `payload`.
+async fn update_refcount(
+  conn: &mut AnyConnection,
+  digest: &str,
+  delta: i64,
+  payload: Option<&StoredPayloadMeta>,
+) -> StorageResult<()> {
+  let now = Utc::now().timestamp_micros();
+  match payload {
+    Some(payload) => {
+      let encoding: observation_tools_shared::ContentEncoding =
+        payload.encoding.parse().unwrap_or_default();
+      sqlx::query(
+        "INSERT INTO blobs (digest, size, encoding, refcount, updated_at)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (digest) DO UPDATE SET
+          refcount = blobs.refcount + excluded.refcount, updated_at = excluded.updated_at",
+      )
+      .bind(digest.to_string())
+      .bind(payload.size as i64)
+      .bind(encoding.to_string())
+      .bind(delta)
+      .bind(now)
+      .execute(&mut *conn)
+      .await?;
+    }
+    None => {
+      sqlx::query("UPDATE blobs SET refcount = refcount + $2, updated_at = $3 WHERE digest = $1")
+        .bind(digest.to_string())
+        .bind(delta)
+        .bind(now)
+        .execute(&mut *conn)
+        .await?;
+    }
+  }
+  Ok(())
+}
+
+/// SQL-based metadata storage implementation
+pub struct SqlStorage {
+  pool: AnyPool,
+}
+
+impl SqlStorage {
+  /// Connect to a database such as `sqlite:///path/to/metadata.db` or
+  /// `postgres://user@host/db` and create the schema if needed
+  pub async fn connect(url: &str) -> StorageResult<Self> {
+    sqlx::any::install_default_drivers();
+    let dialect = Dialect::from_url(url)?;
+    let mut options = AnyPoolOptions::new();
+    if dialect == Dialect::Sqlite {
+      // Serialize writers to avoid SQLITE_BUSY errors under concurrent uploads
+      options = options.max_connections(1);
+    }
+    let url = match dialect {
+      // Create the database file if it does not exist
+      Dialect::Sqlite if !url.contains("mode=") => {
+        let separator = if url.contains('?') { '&' } else { '?' };
+        format!("{}{}mode=rwc", url, separator)
+      }
+      _ => url.to_string(),
+    };
+    let pool = options.connect(&url).await?;
+    for statement in schema(dialect) {
+      sqlx::query(&statement).execute(&pool).await?;
+    }
+    add_project_column(&pool, dialect).await?;
+    Ok(Self { pool })
+  }
+
+  /// Decode an observations row, returning the observation with all payloads
+  /// marked as Blob (metadata only)
+  fn decode_metadata_only(&self, data: &[u8]) -> StorageResult<ObservationWithPayloads> {
+    let stored = StoredObservation::decode(data)?;
+    let observation = stored.to_observation()?;
+    let payloads = stored
+      .payload_manifest
+      .iter()
+      .map(|pm| pm.to_payload(PayloadData::Blob))
+      .collect();
+    Ok(ObservationWithPayloads {
+      observation,
+      payloads,
+    })
+  }
+
+  /// Load executions matching the SQL part of a filter, most recent first
+  async fn query_executions(
+    &self,
+    sql_filter: &SqlFilter,
+    page: Option<(usize, usize)>,
+  ) -> StorageResult<Vec<Execution>> {
+    let mut query = format!(
+      "SELECT data FROM executions WHERE {} ORDER BY created_at DESC, id DESC",
+      sql_filter.where_clause()
+    );
+    if let Some((limit, offset)) = page {
+      query += &format!(" LIMIT {} OFFSET {}", limit, offset);
+    }
+    let rows = sql_filter.bind(sqlx::query(&query)).fetch_all(&self.pool).await?;
+    rows
+      .iter()
+      .map(|row| Ok(serde_json::from_str(&row.try_get::<String, _>("data")?)?))
+      .collect()
+  }
+
+  /// Load observations for an execution matching the SQL part of a filter,
+  /// in ID order
+  async fn query_observations(
+    &self,
+    sql_filter: &SqlFilter,
+    page: Option<(usize, usize)>,
+  ) -> StorageResult<Vec<ObservationWithPayloads>> {
+    let mut query = format!(
+      "SELECT data FROM observations WHERE {} ORDER BY id",
+      sql_filter.where_clause()
+    );
+    if let Some((limit, offset)) = page {
+      query += &format!(" LIMIT {} OFFSET {}", limit, offset);
+    }
+    let rows = sql_filter.bind(sqlx::query(&query)).fetch_all(&self.pool).await?;
+    rows
+      .iter()
+      .map(|row| self.decode_metadata_only(&row.try_get::<Vec<u8>, _>("data")?))
+      .collect()
+  }
+}
+
+#[async_trait::async_trait]
+impl MetadataStorage for SqlStorage {
+  async fn store_execution(&self, execution: &Execution) -> StorageResult<()> {
+    sqlx::query(
+      "INSERT INTO executions (id, name, created_at, status, project, data)
+      VALUES ($1, $2, $3, $4, $5, $6)
+      ON CONFLICT (id) DO UPDATE SET
+        name = excluded.name, created_at = excluded.created_at, status = excluded.status,
+        project = excluded.project, data = excluded.data",
+    )
+    .bind(execution.id.to_string())
+    .bind(execution.name.clone())
+    .bind(execution.created_at.timestamp_micros())
+    .bind(status_text(execution.status))
+    .bind(execution.project.as_str().to_string())
+    .bind(serde_json::to_string(execution)?)
+    .execute(&self.pool)
+    .await?;
+    Ok(())
+  }
+
+  async fn get_execution(&self, id: ExecutionId) -> StorageResult<Execution> {
+    let row = sqlx::query("SELECT data FROM executions WHERE id = $1")
+      .bind(id.to_string())
+      .fetch_optional(&self.pool)
+      .await?
+      .ok_or_else(|| StorageError::NotFound(format!("Execution {} not found", id)))?;
+    Ok(serde_json::from_str(&row.try_get::<String, _>("data")?)?)
+  }
+
+  async fn update_execution(
+    &self,
+    id: ExecutionId,
+    update: &ExecutionUpdate,
+  ) -> StorageResult<Option<Execution>> {
+    loop {
+      let row = sqlx::query("SELECT data FROM executions WHERE id = $1")
+        .bind(id.to_string())
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| StorageError::NotFound(format!("Execution {} not found", id)))?;
+      let previous = row.try_get::<String, _>("data")?;
+      let mut execution: Execution = serde_json::from_str(&previous)?;
+      if !update(&mut execution) {
+        return Ok(None);
+      }
+      // Only replace the data that was read, and start over if it changed
+      let result = sqlx::query(
+        "UPDATE executions SET name = $1, created_at = $2, status = $3, project = $4, data = $5
+        WHERE id = $6 AND data = $7",
+      )
+      .bind(execution.name.clone())
+      .bind(execution.created_at.timestamp_micros())
+      .bind(status_text(execution.status))
+      .bind(execution.project.as_str().to_string())
+      .bind(serde_json::to_string(&execution)?)
+      .bind(id.to_string())
+      .bind(previous)
+      .execute(&self.pool)
+      .await?;
+      if result.rows_affected() == 1 {
+        return Ok(Some(execution));
+      }
+    }
+  }
+
+  async fn list_executions(
+    &self,
+    limit: Option<usize>,
+    offset: Option<usize>,
+    filter: &ExecutionFilter,
+  ) -> StorageResult<Vec<Execution>> {
+    let limit = limit.unwrap_or(100);
+    let offset = offset.unwrap_or(0);
+    let sql_filter = SqlFilter::executions(filter);
+    if !sql_filter.has_residual {
+      return self
+        .query_executions(&sql_filter, Some((limit, offset)))
+        .await;
+    }
+    let executions = self.query_executions(&sql_filter, None).await?;
+    Ok(
+      executions
+        .into_iter()
+        .filter(|execution| filter.matches(execution))
+        .skip(offset)
+        .take(limit)
+        .collect(),
+    )
+  }
+
+  async fn count_executions(&self, filter: &ExecutionFilter) -> StorageResult<usize> {
+    let sql_filter = SqlFilter::executions(filter);
+    if sql_filter.has_residual {
+      let executions = self.query_executions(&sql_filter, None).await?;
+      return Ok(executions.iter().filter(|e| filter.matches(e)).count());
+    }
+    let query = format!(
+      "SELECT COUNT(*) AS count FROM executions WHERE {}",
+      sql_filter.where_clause()
+    );
+    let count: i64 = sql_filter
+      .bind(sqlx::query(&query))
+      .fetch_one(&self.pool)
+      .await?
+      .try_get("count")?;
+    Ok(count as usize)
+  }
+
+  async fn store_observations(
+    &self,
+    observations: Vec<ObservationWithPayloads>,
+  ) -> StorageResult<()> {
+    let mut tx = self.pool.begin().await?;
+    for obs_with_payloads in observations {
+      let obs = &obs_with_payloads.observation;
+
+      // Build the stored observation with payload manifest
+      let mut stored = StoredObservation::from_observation(obs);
+      for payload in &obs_with_payloads.payloads {
+        stored
+          .payload_manifest
+          .push(StoredPayloadMeta::from_payload(payload));
+      }
+      let payload_size: u64 = stored.payload_manifest.iter().map(|pm| pm.size).sum();
+
+      // Move blob references from a replaced observation to this one
+      let previous = sqlx::query("SELECT data FROM observations WHERE id = $1")
+        .bind(obs.id.to_string())
+        .fetch_optional(&mut *tx)
+        .await?;
+      if let Some(previous) = previous {
+        let previous: Vec<u8> = previous.try_get("data")?;
+        let previous = StoredObservation::decode(previous.as_slice())?;
+        check_replaced_execution(&previous, obs)?;
+        for digest in payload_digests(&previous) {
+          update_refcount(&mut tx, digest, -1, None).await?;
+        }
+      }
+      for pm in stored.payload_manifest.iter().filter(|pm| !pm.digest.is_empty()) {
+        update_refcount(&mut tx, &pm.digest, 1, Some(pm)).await?;
+      }
+
+      sqlx::query(
+        "INSERT INTO observations
+          (id, execution_id, name, observation_type, log_level, created_at, payload_size, data)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        ON CONFLICT (id) DO UPDATE SET
+          execution_id = excluded.execution_id,
+          name = excluded.name,
+          observation_type = excluded.observation_type,
+          log_level = excluded.log_level,
+          created_at = excluded.created_at,
+          payload_size = excluded.payload_size,
+          data = excluded.data",
+      )
+      .bind(obs.id.to_string())
+      .bind(obs.execution_id.to_string())
+      .bind(obs.name.clone())
+      .bind(observation_type_to_i32(obs.observation_type) as i64)
+      .bind(log_level_to_i32(obs.log_level) as i64)
+      .bind(obs.created_at.timestamp_micros())
+      .bind(payload_size as i64)
+      .bind(stored.encode_to_vec())
+      .execute(&mut *tx)
+      .await?;
+
+      // Store inline payloads
+      sqlx::query("DELETE FROM payloads WHERE observation_id = $1")
+        .bind(obs.id.to_string())
+        .execute(&mut *tx)
+        .await?;
+      for payload in &obs_with_payloads.payloads {
+        if let PayloadData::Inline(ref data) = payload.data {
+          sqlx::query("INSERT INTO payloads (observation_id, payload_id, data) VALUES ($1, $2, $3)")
+            .bind(obs.id.to_string())
+            .bind(payload.id.as_str().to_string())
+            .bind(data.clone())
+            .execute(&mut *tx)
+            .await?;
+        }
+      }
+    }
+    tx.commit().await?;
+    Ok(())
+  }
+
+  async fn get_observation(
+    &self,
+    id: ObservationId,
+  ) -> StorageResult<ObservationWithPayloads> {
+    let row = sqlx::query("SELECT data FROM observations WHERE id = $1")
+      .bind(id.to_string())
+      .fetch_optional(&self.pool)
+      .await?
+      .ok_or_else(|| StorageError::NotFound(format!("Observation {} not found", id)))?;
+    let stored = StoredObservation::decode(row.try_get::<Vec<u8>, _>("data")?.as_slice())?;
+
+    let mut inline_data: HashMap<String, Vec<u8>> = HashMap::new();
+    let rows = sqlx::query("SELECT payload_id, data FROM payloads WHERE observation_id = $1")
+      .bind(id.to_string())
+      .fetch_all(&self.pool)
+      .await?;
+    for row in rows {
+      inline_data.insert(row.try_get("payload_id")?, row.try_get("data")?);
+    }
+
+    let observation = stored.to_observation()?;
+    let payloads = stored
+      .payload_manifest
+      .iter()
+      .map(|pm| {
+        let data = match inline_data.remove(&pm.payload_id) {
+          Some(inline) if !pm.is_blob => PayloadData::Inline(inline),
+          _ => PayloadData::Blob,
+        };
+        pm.to_payload(data)
+      })
+      .collect();
+    Ok(ObservationWithPayloads {
+      observation,
+      payloads,
+    })
+  }
+
+  async fn list_observations(
+    &self,
+    execution_id: ExecutionId,
+    limit: Option<usize>,
+    offset: Option<usize>,
+    filter: &ObservationFilter,
+  ) -> StorageResult<Vec<ObservationWithPayloads>> {
+    let limit = limit.unwrap_or(100);
+    let offset = offset.unwrap_or(0);
+    let sql_filter = SqlFilter::new(execution_id, filter);
+    if !sql_filter.has_residual {
+      return self
+        .query_observations(&sql_filter, Some((limit, offset)))
+        .await;
+    }
+    let observations = self.query_observations(&sql_filter, None).await?;
+    Ok(
+      observations
+        .into_iter()
+        .filter(|obs| filter.matches(&obs.observation))
+        .skip(offset)
+        .take(limit)
+        .collect(),
+    )
+  }
+
+  async fn count_observations(
+    &self,
+    execution_id: ExecutionId,
+    filter: &ObservationFilter,
+  ) -> StorageResult<usize> {
+    let sql_filter = SqlFilter::new(execution_id, filter);
+    if sql_filter.has_residual {
+      let observations = self.query_observations(&sql_filter, None).await?;
+      return Ok(
+        observations
+          .iter()
+          .filter(|obs| filter.matches(&obs.observation))
+          .count(),
+      );
+    }
+    let query = format!(
+      "SELECT COUNT(*) AS count FROM observations WHERE {}",
+      sql_filter.where_clause()
+    );
+    let count: i64 = sql_filter
+      .bind(sqlx::query(&query))
+      .fetch_one(&self.pool)
+      .await?
+      .try_get("count")?;
+    Ok(count as usize)
+  }
+
+  async fn execution_size(&self, execution_id: ExecutionId) -> StorageResult<u64> {
+    let size: i64 = sqlx::query(
+      "SELECT CAST(COALESCE(SUM(payload_size), 0) AS BIGINT) AS size
+      FROM observations WHERE execution_id = $1",
+    )
+    .bind(execution_id.to_string())
+    .fetch_one(&self.pool)
+    .await?
+    .try_get("size")?;
+    Ok(size as u64)
+  }
+
+  async fn observation_sizes(
+    &self,
+    ids: &[ObservationId],
+  ) -> StorageResult<HashMap<ObservationId, u64>> {
+    let mut sizes = HashMap::new();
+    for id in ids {
+      let row = sqlx::query("SELECT payload_size FROM observations WHERE id = $1")
+        .bind(id.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+      if let Some(row) = row {
+        sizes.insert(*id, row.try_get::<i64, _>("payload_size")? as u64);
+      }
+    }
+    Ok(sizes)
+  }
+
+  async fn add_execution_usage(
+    &self,
+    execution_id: ExecutionId,
+    usage: ExecutionUsage,
+    check: &UsageCheck,
+  ) -> StorageResult<(ExecutionUsage, bool)> {
+    loop {
+      let row = sqlx::query(
+        "SELECT observations, bytes FROM execution_usage WHERE execution_id = $1",
+      )
+      .bind(execution_id.to_string())
+      .fetch_optional(&self.pool)
+      .await?;
+      let Some(row) = row else {
+        let observations = self
+          .count_observations(execution_id, &ObservationFilter::default())
+          .await?;
+        let bytes = self.execution_size(execution_id).await?;
+        sqlx::query(
+          "INSERT INTO execution_usage (execution_id, observations, bytes)
+          VALUES ($1, $2, $3)
+          ON CONFLICT (execution_id) DO NOTHING",
+        )
+        .bind(execution_id.to_string())
+        .bind(observations as i64)
+        .bind(bytes as i64)
+        .execute(&self.pool)
+        .await?;
+        continue;
+      };
+      let used = ExecutionUsage {
+        observations: row.try_get::<i64, _>("observations")? as u64,
+        bytes: row.try_get::<i64, _>("bytes")? as u64,
+      };
+      if !check(used) {
+        return Ok((used, false));
+      }
+      // Only add to the usage that was read, and start over if it changed
+      let added = used.saturating_add(usage);
+      let result = sqlx::query(
+        "UPDATE execution_usage SET observations = $1, bytes = $2
+        WHERE execution_id = $3 AND observations = $4 AND bytes = $5",
+      )
+      .bind(added.observations as i64)
+      .bind(added.bytes as i64)
+      .bind(execution_id.to_string())
+      .bind(used.observations as i64)
+      .bind(used.bytes as i64)
+      .execute(&self.pool)
+      .await?;
+      if result.rows_affected() == 1 {
+        return Ok((used, true));
+      }
+    }
+  }
+
+  async fn remove_execution_usage(
+    &self,
+    execution_id: ExecutionId,
+    usage: ExecutionUsage,
+  ) -> StorageResult<()> {
+    sqlx::query(
+      "UPDATE execution_usage SET
+        observations = CASE WHEN observations > $1 THEN observations - $2 ELSE 0 END,
+        bytes = CASE WHEN bytes > $3 THEN bytes - $4 ELSE 0 END
+      WHERE execution_id = $5",
+    )
+    .bind(usage.observations as i64)
+    .bind(usage.observations as i64)
+    .bind(usage.bytes as i64)
+    .bind(usage.bytes as i64)
+    .bind(execution_id.to_string())
+    .execute(&self.pool)
+    .await?;
+    Ok(())
+  }
+
+  async fn delete_execution(
+    &self,
+    execution_id: ExecutionId,
+  ) -> StorageResult<Vec<ObservationWithPayloads>> {
+    let ids: Vec<String> = sqlx::query("SELECT id FROM observations WHERE execution_id = $1")
+      .bind(execution_id.to_string())
+      .fetch_all(&self.pool)
+      .await?
+      .iter()
+      .map(|row| row.try_get("id"))
+      .collect::<Result<_, _>>()?;
+    let mut deleted = Vec::with_capacity(ids.len());
+    for id in &ids {
+      let obs_id = ObservationId::parse(id)
+        .map_err(|e| StorageError::Internal(format!("Invalid observation ID: {}", e)))?;
+      deleted.push(self.get_observation(obs_id).await?);
+    }
+
+    let mut tx = self.pool.begin().await?;
+    sqlx::query(
+      "DELETE FROM payloads WHERE observation_id IN
+        (SELECT id FROM observations WHERE execution_id = $1)",
+    )
+    .bind(execution_id.to_string())
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("DELETE FROM observations WHERE execution_id = $1")
+      .bind(execution_id.to_string())
+      .execute(&mut *tx)
+      .await?;
+    sqlx::query("DELETE FROM execution_usage WHERE execution_id = $1")
+      .bind(execution_id.to_string())
+      .execute(&mut *tx)
+      .await?;
+    sqlx::query("DELETE FROM blob_scopes WHERE scope = $1")
+      .bind(BlobScope::Execution(execution_id).key())
+      .execute(&mut *tx)
+      .await?;
+    let result = sqlx::query("DELETE FROM executions WHERE id = $1")
+      .bind(execution_id.to_string())
+      .execute(&mut *tx)
+      .await?;
+    if result.rows_affected() == 0 && ids.is_empty() {
+      return Err(StorageError::NotFound(format!(
+        "Execution {} not found",
+        execution_id
+      )));
+    }
+    for obs in &deleted {
+      for digest in obs.payloads.iter().filter_map(|p| p.digest.as_deref()) {
+        update_refcount(&mut tx, digest, -1, None).await?;
+      }
+    }
+    tx.commit().await?;
+    Ok(deleted)
+  }
+
+  async fn register_blob(&self, record: &BlobRecord) -> StorageResult<(BlobRecord, bool)> {
+    let inserted = sqlx::query(
+      "INSERT INTO blobs (digest, size, encoding, refcount, updated_at)
+      VALUES ($1, $2, $3, $4, $5)
+      ON CONFLICT (digest) DO NOTHING",
+    )
+    .bind(record.digest.clone())
+    .bind(record.size as i64)
+    .bind(record.encoding.to_string())
+    .bind(record.refcount)
+    .bind(record.updated_at.timestamp_micros())
+    .execute(&self.pool)
+    .await?;
+    if inserted.rows_affected() == 1 {
+      return Ok((record.clone(), true));
+    }
+    sqlx::query("UPDATE blobs SET updated_at = $2 WHERE digest = $1")
+      .bind(record.digest.clone())
+      .bind(Utc::now().timestamp_micros())
+      .execute(&self.pool)
+      .await?;
+    match self.get_blob_record(&record.digest).await {
+      Ok(existing) => Ok((existing, false)),
+      // Collected in the meantime
+      Err(StorageError::NotFound(_)) => self.register_blob(record).await,
+      Err(e) => Err(e),
+    }
+  }
+
+  async fn get_blob_record(&self, digest: &str) -> StorageResult<BlobRecord> {
+    let row = sqlx::query(
+      "SELECT digest, size, encoding, refcount, updated_at FROM blobs WHERE digest = $1",
+    )
+    .bind(digest.to_string())
+    .fetch_optional(&self.pool)
+    .await?
+    .ok_or_else(|| StorageError::NotFound(format!("Blob {} not found", digest)))?;
+    decode_blob_record(&row)
+  }
+
+  async fn unreferenced_blobs(&self, before: DateTime<Utc>) -> StorageResult<Vec<BlobRecord>> {
+    sqlx::query(
+      "SELECT digest, size, encoding, refcount, updated_at FROM blobs
+      WHERE refcount <= 0 AND updated_at < $1",
+    )
+    .bind(before.timestamp_micros())
+    .fetch_all(&self.pool)
+    .await?
+    .iter()
+    .map(decode_blob_record)
+    .collect()
+  }
+
+  async fn delete_blob_record(&self, digest: &str, before: DateTime<Utc>) -> StorageResult<bool> {
+    let mut tx = self.pool.begin().await?;
+    let result =
+      sqlx::query("DELETE FROM blobs WHERE digest = $1 AND refcount <= 0 AND updated_at < $2")
+        .bind(digest.to_string())
+        .bind(before.timestamp_micros())
+        .execute(&mut *tx)
+        .await?;
+    let deleted = result.rows_affected() == 1;
+    if deleted {
+      sqlx::query("DELETE FROM blob_scopes WHERE digest = $1")
+        .bind(digest.to_string())
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(deleted)
+  }
+
+  async fn add_blob_scope(&self, digest: &str, scope: &BlobScope) -> StorageResult<()> {
+    sqlx::query(
+      "INSERT INTO blob_scopes (digest, scope) VALUES ($1, $2)
+      ON CONFLICT (digest, scope) DO NOTHING",
+    )
+    .bind(digest.to_string())
+    .bind(scope.key())
+    .execute(&self.pool)
+    .await?;
+    Ok(())
+  }
+
+  async fn blob_in_scope(&self, digest: &str, scope: &BlobScope) -> StorageResult<bool> {
+    let row = sqlx::query("SELECT 1 AS found FROM blob_scopes WHERE digest = $1 AND scope = $2")
+      .bind(digest.to_string())
+      .bind(scope.key())
+      .fetch_optional(&self.pool)
+      .await?;
+    Ok(row.is_some())
+  }
+
+  async fn store_api_key(&self, key: &ApiKeyRecord) -> StorageResult<()> {
+    sqlx::query(
+      "INSERT INTO api_keys (id, created_at, data) VALUES ($1, $2, $3)
+      ON CONFLICT (id) DO UPDATE SET created_at = excluded.created_at, data = excluded.data",
+    )
+    .bind(key.id.to_string())
+    .bind(key.created_at.timestamp_micros())
+    .bind(serde_json::to_string(key)?)
+    .execute(&self.pool)
+    .await?;
+    Ok(())
+  }
+
+  async fn get_api_key(&self, id: Uuid) -> StorageResult<ApiKeyRecord> {
+    let row = sqlx::query("SELECT data FROM api_keys WHERE id = $1")
+      .bind(id.to_string())
+      .fetch_optional(&self.pool)
+      .await?
+      .ok_or_else(|| StorageError::NotFound(format!("API key {} not found", id)))?;
+    Ok(serde_json::from_str(&row.try_get::<String, _>("data")?)?)
+  }
+
+  async fn list_api_keys(&self) -> StorageResult<Vec<ApiKeyRecord>> {
+    sqlx::query("SELECT data FROM api_keys ORDER BY created_at, id")
+      .fetch_all(&self.pool)
+      .await?
+      .iter()
+      .map(|row| Ok(serde_json::from_str(&row.try_get::<String, _>("data")?)?))
+      .collect()
+  }
+
+  async fn store_project(&self, project: &Project) -> StorageResult<()> {
+    sqlx::query(
+      "INSERT INTO projects (id, data) VALUES ($1, $2)
+      ON CONFLICT (id) DO UPDATE SET data = excluded.data",
+    )
+    .bind(project.id.to_string())
+    .bind(serde_json::to_string(project)?)
+    .execute(&self.pool)
+    .await?;
+    Ok(())
+  }
+
+  async fn get_project(&self, id: &ProjectId) -> StorageResult<Project> {
+    let row = sqlx::query("SELECT data FROM projects WHERE id = $1")
+      .bind(id.to_string())
+      .fetch_optional(&self.pool)
+      .await?
+      .ok_or_else(|| StorageError::NotFound(format!("Project {} not found", id)))?;
+    Ok(serde_json::from_str(&row.try_get::<String, _>("data")?)?)
+  }
+
+  async fn list_projects(&self) -> StorageResult<Vec<Project>> {
+    sqlx::query("SELECT data FROM projects ORDER BY id")
+      .fetch_all(&self.pool)
+      .await?
+      .iter()
+      .map(|row| Ok(serde_json::from_str(&row.try_get::<String, _>("data")?)?))
+      .collect()
+  }
+
+  async fn store_snapshot_baseline(&self, baseline: &SnapshotBaseline) -> StorageResult<()> {
+    sqlx::query(
+      "INSERT INTO snapshot_baselines (project, execution_name, name, data) VALUES ($1, $2, $3, $4)
+      ON CONFLICT (project, execution_name, name) DO UPDATE SET data = excluded.data",
+    )
+    .bind(baseline.project.to_string())
+    .bind(baseline.execution_name.clone())
+    .bind(baseline.name.clone())
+    .bind(serde_json::to_string(baseline)?)
+    .execute(&self.pool)
+    .await?;
+    Ok(())
+  }
+
+  async fn get_snapshot_baseline(
+    &self,
+    project: &ProjectId,
+    execution_name: &str,
+    name: &str,
+  ) -> StorageResult<SnapshotBaseline> {
+    let row = sqlx::query(
+      "SELECT data FROM snapshot_baselines
+      WHERE project = $1 AND execution_name = $2 AND name = $3",
+    )
+    .bind(project.to_string())
+    .bind(execution_name.to_string())
+    .bind(name.to_string())
+    .fetch_optional(&self.pool)
+    .await?
+    .ok_or_else(|| {
+      StorageError::NotFound(format!("No baseline for snapshot {} of {}", name, execution_name))
+    })?;
+    Ok(serde_json::from_str(&row.try_get::<String, _>("data")?)?)
+  }
+
+  async fn list_snapshot_baselines(&self) -> StorageResult<Vec<SnapshotBaseline>> {
+    sqlx::query("SELECT data FROM snapshot_baselines ORDER BY project, execution_name, name")
+      .fetch_all(&self.pool)
+      .await?
+      .iter()
+      .map(|row| Ok(serde_json::from_str(&row.try_get::<String, _>("data")?)?))
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn test_adds_project_column() {
+    sqlx::any::install_default_drivers();
+    let dir = tempfile::tempdir().unwrap();
+    let url = format!("sqlite://{}?mode=rwc", dir.path().join("metadata.db").display());
+    let mut team = Execution::new("team");
+    team.project = ProjectId::parse("team-a").unwrap();
+    let mut legacy = serde_json::to_value(Execution::new("legacy")).unwrap();
+    legacy.as_object_mut().unwrap().remove("project");
+    {
+      // The executions table as created before projects existed
+      let pool = AnyPool::connect(&url).await.unwrap();
+      sqlx::query(
+        "CREATE TABLE executions (
+          id TEXT PRIMARY KEY,
+          name TEXT NOT NULL,
+          created_at BIGINT NOT NULL,
+          status TEXT NOT NULL,
+          data TEXT NOT NULL
+        )",
+      )
+      .execute(&pool)
+      .await
+      .unwrap();
+      for (id, data) in [
+        (team.id.to_string(), serde_json::to_string(&team).unwrap()),
+        (legacy["id"].as_str().unwrap().to_string(), legacy.to_string()),
+      ] {
+        sqlx::query("INSERT INTO executions VALUES ($1, 'name', 0, 'Running', $2)")
+          .bind(id)
+          .bind(data)
+          .execute(&pool)
+          .await
+          .unwrap();
+      }
+      pool.close().await;
+    }
+
+    let storage = SqlStorage::connect(&url).await.unwrap();
+    for (project, name) in [("team-a", "team"), (ProjectId::DEFAULT, "legacy")] {
+      let filter = ExecutionFilter {
+        project: Some(ProjectId::parse(project).unwrap()),
+        ..Default::default()
+      };
+      let listed = storage.list_executions(None, None, &filter).await.unwrap();
+      assert_eq!(listed.len(), 1, "{}", project);
+      assert_eq!(listed[0].name, name);
+      assert_eq!(storage.count_executions(&filter).await.unwrap(), 1);
+    }
+  }
+}
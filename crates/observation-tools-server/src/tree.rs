@@ -0,0 +1,331 @@
+//! Hierarchy of groups and observations of an execution
+//!
+//! Groups are observations of type [`ObservationType::Group`] whose first
+//! group ID is the group's own ID, nested through their `parent_group_id`.
+//! Spans recorded by the tracing layer become groups the same way, and events
+//! inside a span refer to its group through their `parent_span_id`.
+
+use crate::api::AppError;
+use crate::storage::MetadataStorage;
+use crate::storage::ObservationFilter;
+use observation_tools_shared::ExecutionId;
+use observation_tools_shared::GroupId;
+use observation_tools_shared::Observation;
+use observation_tools_shared::ObservationId;
+use observation_tools_shared::ObservationType;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+/// Maximum number of observations of an execution arranged in a tree
+pub const MAX_TREE_OBSERVATIONS: usize = 10_000;
+
+/// Groups and observations of an execution, arranged by nesting
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct ExecutionTree {
+  /// Groups without a parent, in the order they were created
+  pub groups: Vec<GroupNode>,
+
+  /// Observations that do not belong to any group
+  pub observations: Vec<Observation>,
+}
+
+/// A group with its child groups and observations
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct GroupNode {
+  pub id: GroupId,
+
+  /// Name of the group, or its ID if the group observation was not found
+  pub name: String,
+
+  /// ID of the observation that created the group, if it was found
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub observation_id: Option<ObservationId>,
+
+  /// Groups nested in this group
+  #[schema(no_recursion)]
+  pub children: Vec<GroupNode>,
+
+  /// Observations belonging directly to this group
+  pub observations: Vec<Observation>,
+
+  /// Number of observations in this group and all nested groups
+  pub observation_count: usize,
+}
+
+/// A group while the tree is built, referring to other groups by index
+struct PendingGroup {
+  id: GroupId,
+  name: String,
+  observation_id: Option<ObservationId>,
+  parent: Option<usize>,
+  children: Vec<usize>,
+  observations: Vec<Observation>,
+}
+
+impl ExecutionTree {
+  /// Arrange observations by the groups they belong to
+  ///
+  /// Observations are placed in their first group, like the group path of
+  /// [`crate::diff::ObservationKey`], or else in the group of their parent
+  /// span if it was recorded. Groups that are referenced but were not
+  /// recorded get a node named by their ID at the top level. A group whose
+  /// parent would make a cycle is placed at the top level too.
+  pub fn build(observations: Vec<Observation>) -> Self {
+    let mut groups: Vec<PendingGroup> = Vec::new();
+    let mut index: HashMap<GroupId, usize> = HashMap::new();
+    let mut parents = Vec::new();
+    let mut members = Vec::new();
+    for observation in observations {
+      let group_id = observation.group_ids.first().cloned();
+      match group_id {
+        // A group recorded twice is shown once, its other records as members
+        Some(id)
+          if observation.observation_type == ObservationType::Group
+            && !index.contains_key(&id) =>
+        {
+          index.insert(id.clone(), groups.len());
+          parents.push((groups.len(), observation.parent_group_id.clone()));
+          groups.push(PendingGroup {
+            id,
+            name: observation.name,
+            observation_id: Some(observation.id),
+            parent: None,
+            children: Vec::new(),
+            observations: Vec::new(),
+          });
+        }
+        _ => members.push(observation),
+      }
+    }
+
+    let mut tree = ExecutionTree::default();
+    for (i, parent_id) in parents {
+      let Some(parent_id) = parent_id else {
+        continue;
+      };
+      let parent = group_index(&mut groups, &mut index, parent_id);
+      if !is_ancestor(&groups, i, parent) {
+        groups[i].parent = Some(parent);
+      }
+    }
+    for observation in members {
+      match observation.group_ids.first().cloned() {
+        Some(id) => {
+          let i = group_index(&mut groups, &mut index, id);
+          groups[i].observations.push(observation);
+        }
+        None => {
+          let span_group = observation
+            .parent_span_id
+            .as_deref()
+            .and_then(|span_id| index.get(&GroupId::from(span_id)));
+          match span_group {
+            Some(&i) => groups[i].observations.push(observation),
+            None => tree.observations.push(observation),
+          }
+        }
+      }
+    }
+
+    let mut roots = Vec::new();
+    for i in 0..groups.len() {
+      match groups[i].parent {
+        Some(parent) => groups[parent].children.push(i),
+        None => roots.push(i),
+      }
+    }
+    let mut groups: Vec<Option<PendingGroup>> = groups.into_iter().map(Some).collect();
+    tree.groups = roots.into_iter().map(|i| take_node(&mut groups, i)).collect();
+    tree
+  }
+
+  /// Total number of observations in the tree, excluding the groups
+  pub fn observation_count(&self) -> usize {
+    self.observations.len() + self.groups.iter().map(|g| g.observation_count).sum::<usize>()
+  }
+}
+
+/// Index of a group, adding a node named by its ID if it was not recorded
+fn group_index(
+  groups: &mut Vec<PendingGroup>,
+  index: &mut HashMap<GroupId, usize>,
+  id: GroupId,
+) -> usize {
+  if let Some(&i) = index.get(&id) {
+    return i;
+  }
+  index.insert(id.clone(), groups.len());
+  groups.push(PendingGroup {
+    name: id.as_str().to_string(),
+    id,
+    observation_id: None,
+    parent: None,
+    children: Vec::new(),
+    observations: Vec::new(),
+  });
+  groups.len() - 1
+}
+
+/// Whether `group` is `other` or one of its ancestors
+fn is_ancestor(groups: &[PendingGroup], group: usize, other: usize) -> bool {
+  let mut current = Some(other);
+  while let Some(i) = current {
+    if i == group {
+      return true;
+    }
+    current = groups[i].parent;
+  }
+  false
+}
+
+fn take_node(groups: &mut [Option<PendingGroup>], i: usize) -> GroupNode {
+  let group = groups[i].take().expect("each group has a single parent");
+  let children: Vec<GroupNode> = group
+    .children
+    .into_iter()
+    .map(|child| take_node(groups, child))
+    .collect();
+  let observation_count = group.observations.len()
+    + children
+      .iter()
+      .map(|child| child.observation_count)
+      .sum::<usize>();
+  GroupNode {
+    id: group.id,
+    name: group.name,
+    observation_id: group.observation_id,
+    children,
+    observations: group.observations,
+    observation_count,
+  }
+}
+
+/// Load the observations of an execution and arrange them in a tree
+pub async fn execution_tree(
+  metadata: &dyn MetadataStorage,
+  execution_id: ExecutionId,
+) -> Result<ExecutionTree, AppError> {
+  let observations = metadata
+    .list_observations(
+      execution_id,
+      Some(MAX_TREE_OBSERVATIONS + 1),
+      None,
+      &ObservationFilter::default(),
+    )
+    .await?;
+  if observations.len() > MAX_TREE_OBSERVATIONS {
+    return Err(AppError::BadRequest(format!(
+      "Execution {} has too many observations to show as a tree, at most {} are supported",
+      execution_id, MAX_TREE_OBSERVATIONS
+    )));
+  }
+  Ok(ExecutionTree::build(
+    observations.into_iter().map(|o| o.observation).collect(),
+  ))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use observation_tools_shared::LogLevel;
+
+  fn observation(name: &str, group: Option<&str>) -> Observation {
+    Observation {
+      id: ObservationId::new(),
+      execution_id: ExecutionId::nil(),
+      name: name.to_string(),
+      observation_type: ObservationType::Payload,
+      log_level: LogLevel::Info,
+      source: None,
+      metadata: Default::default(),
+      group_ids: group.map(GroupId::from).into_iter().collect(),
+      parent_group_id: None,
+      parent_span_id: None,
+      created_at: chrono::Utc::now(),
+    }
+  }
+
+  fn group(name: &str, id: &str, parent: Option<&str>) -> Observation {
+    Observation {
+      observation_type: ObservationType::Group,
+      parent_group_id: parent.map(GroupId::from),
+      ..observation(name, Some(id))
+    }
+  }
+
+  fn names(observations: &[Observation]) -> Vec<&str> {
+    observations.iter().map(|o| o.name.as_str()).collect()
+  }
+
+  #[test]
+  fn test_build_tree() {
+    let tree = ExecutionTree::build(vec![
+      observation("start", None),
+      group("outer", "g1", None),
+      observation("a", Some("g1")),
+      group("inner", "g2", Some("g1")),
+      observation("b", Some("g2")),
+      observation("c", Some("g2")),
+      group("second", "g3", None),
+    ]);
+    assert_eq!(names(&tree.observations), vec!["start"]);
+    assert_eq!(tree.groups.len(), 2);
+    let outer = &tree.groups[0];
+    assert_eq!((outer.name.as_str(), outer.observation_count), ("outer", 3));
+    assert_eq!(names(&outer.observations), vec!["a"]);
+    assert_eq!(outer.children.len(), 1);
+    let inner = &outer.children[0];
+    assert_eq!((inner.name.as_str(), inner.observation_count), ("inner", 2));
+    assert_eq!(names(&inner.observations), vec!["b", "c"]);
+    assert_eq!(tree.groups[1].observation_count, 0);
+    assert_eq!(tree.observation_count(), 4);
+  }
+
+  #[test]
+  fn test_build_tree_missing_groups() {
+    let tree = ExecutionTree::build(vec![
+      observation("a", Some("unknown")),
+      observation("b", Some("g1")),
+      group("child", "g2", Some("missing")),
+      group("late", "g1", None),
+    ]);
+    let names: Vec<_> = tree.groups.iter().map(|g| g.name.as_str()).collect();
+    assert_eq!(names, vec!["late", "missing", "unknown"]);
+    assert!(tree.groups[0].observation_id.is_some());
+    assert_eq!(tree.groups[0].observation_count, 1);
+    assert!(tree.groups[1].observation_id.is_none());
+    assert_eq!(tree.groups[1].children[0].name, "child");
+    assert_eq!(tree.groups[2].observation_count, 1);
+  }
+
+  #[test]
+  fn test_build_tree_spans() {
+    let event = |name: &str, span: &str| Observation {
+      observation_type: ObservationType::LogEntry,
+      parent_span_id: Some(span.to_string()),
+      ..observation(name, None)
+    };
+    let tree = ExecutionTree::build(vec![
+      group("request", "1", None),
+      event("handled", "1"),
+      event("dropped span", "2"),
+    ]);
+    assert_eq!(names(&tree.groups[0].observations), vec!["handled"]);
+    assert_eq!(names(&tree.observations), vec!["dropped span"]);
+  }
+
+  #[test]
+  fn test_build_tree_cycle() {
+    let tree = ExecutionTree::build(vec![
+      group("a", "g1", Some("g2")),
+      group("b", "g2", Some("g1")),
+      observation("x", Some("g2")),
+    ]);
+    assert_eq!(tree.groups.len(), 1);
+    assert_eq!(tree.groups[0].name, "b");
+    assert_eq!(tree.groups[0].children[0].name, "a");
+    assert_eq!(tree.observation_count(), 1);
+  }
+}
@@ -0,0 +1,128 @@
+//! Execution and payload diff page handlers
+
+use crate::api::diff::execution_diff;
+use crate::api::diff::payload_diff;
+use crate::api::authorize_read;
+use crate::api::types::PayloadDiffQuery;
+use crate::api::AppError;
+use crate::auth::ApiKeyRecord;
+use crate::csrf::CsrfToken;
+use crate::storage::BlobStorage;
+use crate::storage::MetadataStorage;
+use crate::storage::StorageError;
+use crate::ui::execution_detail::non_empty;
+use crate::ui::nav::NavBar;
+use axum::extract::Path;
+use axum::extract::Query;
+use axum::extract::State;
+use axum::response::Html;
+use axum::Extension;
+use minijinja::context;
+use minijinja_autoreload::AutoReloader;
+use observation_tools_shared::models::ExecutionId;
+use observation_tools_shared::ObservationId;
+use observation_tools_shared::PayloadId;
+use std::sync::Arc;
+
+/// Query parameters for the diff page. The page only shows the form to pick
+/// executions until both are given.
+#[derive(Debug, serde::Deserialize)]
+pub struct DiffQuery {
+  left: Option<String>,
+  right: Option<String>,
+}
+
+/// Diff page comparing two executions
+#[tracing::instrument(skip(metadata, blobs, templates, api_key))]
+pub async fn diff_executions(
+  State(metadata): State<Arc<dyn MetadataStorage>>,
+  State(blobs): State<Arc<dyn BlobStorage>>,
+  State(templates): State<Arc<AutoReloader>>,
+  api_key: Option<Extension<ApiKeyRecord>>,
+  Query(query): Query<DiffQuery>,
+  csrf: CsrfToken,
+  nav_bar: NavBar,
+) -> Result<Html<String>, AppError> {
+  let api_key = api_key.as_ref().map(|Extension(key)| key);
+  let diff = match (non_empty(&query.left), non_empty(&query.right)) {
+    (Some(left), Some(right)) => {
+      let (left, right) = (ExecutionId::parse(left)?, ExecutionId::parse(right)?);
+      authorize_read(metadata.as_ref(), api_key, left).await?;
+      authorize_read(metadata.as_ref(), api_key, right).await?;
+      Some(execution_diff(metadata.as_ref(), blobs.as_ref(), left, right).await?)
+    }
+    _ => None,
+  };
+  let env = templates.acquire_env()?;
+  let tmpl = env.get_template("diff.html")?;
+  let html = tmpl.render(context! {
+      diff => diff,
+      left => query.left,
+      right => query.right,
+      csrf_token => csrf.0,
+      nav_bar => nav_bar,
+  })?;
+  Ok(Html(html))
+}
+
+/// Path parameters of a payload diff, which may be nested in a project
+#[derive(Debug, serde::Deserialize)]
+pub struct PayloadDiffPath {
+  observation_id: String,
+  payload_id: String,
+}
+
+/// Fragment comparing a payload with a payload of another observation, loaded
+/// into the observation panel. Errors about the chosen observation are shown
+/// in the fragment.
+#[tracing::instrument(skip(metadata, blobs, templates, api_key))]
+#[allow(clippy::too_many_arguments)]
+pub async fn diff_payload(
+  State(metadata): State<Arc<dyn MetadataStorage>>,
+  State(blobs): State<Arc<dyn BlobStorage>>,
+  State(templates): State<Arc<AutoReloader>>,
+  api_key: Option<Extension<ApiKeyRecord>>,
+  Path(PayloadDiffPath {
+    observation_id,
+    payload_id,
+  }): Path<PayloadDiffPath>,
+  Query(query): Query<PayloadDiffQuery>,
+  nav_bar: NavBar,
+) -> Result<Html<String>, AppError> {
+  let api_key = api_key.as_ref().map(|Extension(key)| key);
+  let right = metadata.get_observation(ObservationId::parse(&observation_id)?).await?;
+  authorize_read(metadata.as_ref(), api_key, right.observation.execution_id).await?;
+  let left_payload_id = non_empty(&query.with_payload).map(PayloadId::from);
+  let right_payload_id = PayloadId::from(payload_id);
+  let result = async {
+    let left_id = ObservationId::parse(query.with.trim())
+      .map_err(|_| AppError::BadRequest(format!("'{}' is not an observation ID", query.with)))?;
+    let left = metadata.get_observation(left_id).await?;
+    authorize_read(metadata.as_ref(), api_key, left.observation.execution_id).await?;
+    let payload = payload_diff(
+      blobs.as_ref(),
+      &left,
+      left_payload_id.as_ref(),
+      &right,
+      &right_payload_id,
+    )
+    .await?;
+    Ok::<_, AppError>((left.observation, payload))
+  }
+  .await;
+  let (other, payload, error) = match result {
+    Ok((other, payload)) => (Some(other), Some(payload), None),
+    Err(AppError::BadRequest(message))
+    | Err(AppError::Storage(StorageError::NotFound(message))) => (None, None, Some(message)),
+    Err(e) => return Err(e),
+  };
+  let env = templates.acquire_env()?;
+  let tmpl = env.get_template("payload_diff.html")?;
+  let html = tmpl.render(context! {
+      payload => payload,
+      error => error,
+      other => other,
+      nav_bar => nav_bar,
+  })?;
+  Ok(Html(html))
+}
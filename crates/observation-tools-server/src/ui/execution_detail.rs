@@ -1,17 +1,41 @@
 //! Execution detail page handlers
 
 use crate::api::observations::GetObservation;
+use crate::api::authorize_read;
+use crate::api::search::search_page;
 use crate::api::AppError;
+use crate::auth::ApiKeyRecord;
 use crate::csrf::CsrfToken;
+use crate::events::ExecutionEvent;
+use crate::events::ObservationEvents;
+use crate::storage::filter::parse_metadata_pairs;
+use crate::storage::BlobStorage;
 use crate::storage::MetadataStorage;
+use crate::storage::ObservationFilter;
+use crate::storage::SearchIndex;
 use crate::storage::StorageError;
+use crate::tree::execution_tree;
+use crate::ui::nav::NavBar;
+use crate::ui::nav::ProjectScope;
+use crate::ui::snapshot::observation_snapshot;
 use axum::extract::Path;
 use axum::extract::Query;
 use axum::extract::State;
+use axum::response::sse::Event;
+use axum::response::sse::KeepAlive;
+use axum::response::sse::Sse;
 use axum::response::Html;
+use axum::Extension;
+use chrono::DateTime;
+use chrono::NaiveDateTime;
+use chrono::Utc;
+use futures::Stream;
+use futures::StreamExt;
 use minijinja::context;
 use minijinja_autoreload::AutoReloader;
 use observation_tools_shared::models::ExecutionId;
+use observation_tools_shared::GroupId;
+use observation_tools_shared::LogLevel;
 use observation_tools_shared::ObservationId;
 use observation_tools_shared::ObservationType;
 use std::sync::Arc;
@@ -29,6 +53,118 @@ pub struct ExecutionDetailQuery {
 
   /// Optional observation ID to display in side panel
   obs: Option<String>,
+
+  /// Optional search query to filter observations by
+  q: Option<String>,
+
+  /// Minimum log level (e.g. `warning`)
+  level: Option<String>,
+
+  /// Name prefix or glob pattern
+  name: Option<String>,
+
+  /// Comma separated `key=value` metadata pairs
+  metadata: Option<String>,
+
+  /// Only show observations created at or after this time
+  created_after: Option<String>,
+
+  /// Only show observations created before this time
+  created_before: Option<String>,
+
+  /// Only show observations belonging to this group
+  group: Option<String>,
+
+  /// Only show observations from this source file
+  source_file: Option<String>,
+}
+
+impl ExecutionDetailQuery {
+  /// Filter and search parameters that should be carried over to pagination
+  /// and observation links, as a query string fragment starting with `&`
+  fn filter_params(&self) -> String {
+    let mut serializer = url::form_urlencoded::Serializer::new(String::new());
+    for (key, value) in [
+      ("q", &self.q),
+      ("level", &self.level),
+      ("name", &self.name),
+      ("metadata", &self.metadata),
+      ("created_after", &self.created_after),
+      ("created_before", &self.created_before),
+      ("group", &self.group),
+      ("source_file", &self.source_file),
+    ] {
+      if let Some(value) = non_empty(value) {
+        serializer.append_pair(key, value);
+      }
+    }
+    let params = serializer.finish();
+    if params.is_empty() {
+      params
+    } else {
+      format!("&{}", params)
+    }
+  }
+
+  /// Build the storage filter from the query string. Empty form fields are
+  /// ignored.
+  fn observation_filter(&self, view: ExecutionView) -> Result<ObservationFilter, AppError> {
+    let observation_type = match view {
+      ExecutionView::Log => None,
+      ExecutionView::Payload => Some(ObservationType::Payload),
+      ExecutionView::Tree => None,
+    };
+    let metadata = match non_empty(&self.metadata) {
+      Some(pairs) => parse_metadata_pairs(pairs).map_err(AppError::BadRequest)?,
+      None => Default::default(),
+    };
+    Ok(ObservationFilter {
+      observation_type,
+      min_log_level: non_empty(&self.level).map(parse_log_level).transpose()?,
+      name: non_empty(&self.name).map(str::to_string),
+      metadata,
+      created_after: non_empty(&self.created_after)
+        .map(parse_time)
+        .transpose()?,
+      created_before: non_empty(&self.created_before)
+        .map(parse_time)
+        .transpose()?,
+      group_id: non_empty(&self.group).map(GroupId::from),
+      source_file: non_empty(&self.source_file).map(str::to_string),
+    })
+  }
+}
+
+/// Path parameters of execution pages, which may be nested in a project
+#[derive(Debug, serde::Deserialize)]
+pub struct ExecutionPath {
+  id: String,
+}
+
+pub(super) fn non_empty(value: &Option<String>) -> Option<&str> {
+  value.as_deref().map(str::trim).filter(|v| !v.is_empty())
+}
+
+fn parse_log_level(level: &str) -> Result<LogLevel, AppError> {
+  match level.to_lowercase().as_str() {
+    "trace" => Ok(LogLevel::Trace),
+    "debug" => Ok(LogLevel::Debug),
+    "info" => Ok(LogLevel::Info),
+    "warn" | "warning" => Ok(LogLevel::Warning),
+    "error" => Ok(LogLevel::Error),
+    _ => Err(AppError::BadRequest(format!("Invalid log level '{}'", level))),
+  }
+}
+
+/// Parse an RFC 3339 timestamp, or a `datetime-local` form value as UTC
+pub(super) fn parse_time(time: &str) -> Result<DateTime<Utc>, AppError> {
+  if let Ok(time) = DateTime::parse_from_rfc3339(time) {
+    return Ok(time.with_timezone(&Utc));
+  }
+  NaiveDateTime::parse_from_str(time, "%Y-%m-%dT%H:%M")
+    .or_else(|_| NaiveDateTime::parse_from_str(time, "%Y-%m-%dT%H:%M:%S"))
+    .map(|time| time.and_utc())
+    .map_err(|_| AppError::BadRequest(format!("Invalid time '{}'", time)))
 }
 
 /// View type for execution detail page
@@ -36,48 +172,148 @@ pub struct ExecutionDetailQuery {
 pub enum ExecutionView {
   Log,
   Payload,
+  /// Groups and observations arranged by nesting
+  Tree,
+}
+
+impl ExecutionView {
+  fn name(self) -> &'static str {
+    match self {
+      ExecutionView::Log => "log",
+      ExecutionView::Payload => "payload",
+      ExecutionView::Tree => "tree",
+    }
+  }
+
+  fn base_path(self, scope: &ProjectScope, id: &str) -> String {
+    match self {
+      ExecutionView::Log => format!("{}/{}", scope.exe_base(), id),
+      ExecutionView::Payload => format!("{}/{}/payload", scope.exe_base(), id),
+      ExecutionView::Tree => format!("{}/{}/tree", scope.exe_base(), id),
+    }
+  }
 }
 
 /// Execution detail page - Log view (shows all observations)
-#[tracing::instrument(skip(metadata, templates))]
+#[tracing::instrument(skip(metadata, blobs, search, templates, api_key))]
+#[allow(clippy::too_many_arguments)]
 pub async fn execution_detail_log(
   State(metadata): State<Arc<dyn MetadataStorage>>,
+  State(blobs): State<Arc<dyn BlobStorage>>,
+  State(search): State<Arc<dyn SearchIndex>>,
   State(templates): State<Arc<AutoReloader>>,
-  Path(id): Path<String>,
+  api_key: Option<Extension<ApiKeyRecord>>,
+  Path(path): Path<ExecutionPath>,
   Query(query): Query<ExecutionDetailQuery>,
   csrf: CsrfToken,
+  nav_bar: NavBar,
 ) -> Result<Html<String>, AppError> {
-  execution_detail_view(metadata, templates, id, query, csrf, ExecutionView::Log).await
+  execution_detail_view(
+    metadata,
+    blobs,
+    search,
+    templates,
+    api_key.map(|Extension(key)| key),
+    path.id,
+    query,
+    csrf,
+    nav_bar,
+    ExecutionView::Log,
+  )
+  .await
 }
 
 /// Execution detail page - Payload view (shows only payload observations)
-#[tracing::instrument(skip(metadata, templates))]
+#[tracing::instrument(skip(metadata, blobs, search, templates, api_key))]
+#[allow(clippy::too_many_arguments)]
 pub async fn execution_detail_payload(
   State(metadata): State<Arc<dyn MetadataStorage>>,
+  State(blobs): State<Arc<dyn BlobStorage>>,
+  State(search): State<Arc<dyn SearchIndex>>,
   State(templates): State<Arc<AutoReloader>>,
-  Path(id): Path<String>,
+  api_key: Option<Extension<ApiKeyRecord>>,
+  Path(path): Path<ExecutionPath>,
   Query(query): Query<ExecutionDetailQuery>,
   csrf: CsrfToken,
+  nav_bar: NavBar,
 ) -> Result<Html<String>, AppError> {
-  execution_detail_view(metadata, templates, id, query, csrf, ExecutionView::Payload).await
+  execution_detail_view(
+    metadata,
+    blobs,
+    search,
+    templates,
+    api_key.map(|Extension(key)| key),
+    path.id,
+    query,
+    csrf,
+    nav_bar,
+    ExecutionView::Payload,
+  )
+  .await
 }
 
+/// Execution detail page - Tree view (shows groups and their observations)
+#[tracing::instrument(skip(metadata, blobs, search, templates, api_key))]
+#[allow(clippy::too_many_arguments)]
+pub async fn execution_detail_tree(
+  State(metadata): State<Arc<dyn MetadataStorage>>,
+  State(blobs): State<Arc<dyn BlobStorage>>,
+  State(search): State<Arc<dyn SearchIndex>>,
+  State(templates): State<Arc<AutoReloader>>,
+  api_key: Option<Extension<ApiKeyRecord>>,
+  Path(path): Path<ExecutionPath>,
+  Query(query): Query<ExecutionDetailQuery>,
+  csrf: CsrfToken,
+  nav_bar: NavBar,
+) -> Result<Html<String>, AppError> {
+  execution_detail_view(
+    metadata,
+    blobs,
+    search,
+    templates,
+    api_key.map(|Extension(key)| key),
+    path.id,
+    query,
+    csrf,
+    nav_bar,
+    ExecutionView::Tree,
+  )
+  .await
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn execution_detail_view(
   metadata: Arc<dyn MetadataStorage>,
+  blobs: Arc<dyn BlobStorage>,
+  search: Arc<dyn SearchIndex>,
   templates: Arc<AutoReloader>,
+  api_key: Option<ApiKeyRecord>,
   id: String,
   query: ExecutionDetailQuery,
   csrf: CsrfToken,
+  nav_bar: NavBar,
   view: ExecutionView,
 ) -> Result<Html<String>, AppError> {
   tracing::debug!(execution_id = %id, ?view, "Rendering execution detail page");
   let execution_id = ExecutionId::parse(&id)?;
+  // Keys bound to a project cannot wait for executions to be uploaded, as
+  // their project is not known until then
+  authorize_read(metadata.as_ref(), api_key.as_ref(), execution_id).await?;
   let execution = match metadata.get_execution(execution_id).await {
-    Ok(execution) => Some(execution),
+    Ok(execution) if nav_bar.scope.contains(&execution) => Some(execution),
+    Ok(execution) => {
+      return Err(
+        StorageError::NotFound(format!(
+          "Execution {} is in project {}",
+          execution.id, execution.project
+        ))
+        .into(),
+      )
+    }
     Err(StorageError::NotFound(_)) => {
       // The user may go to the page before it's uploaded. Since the page
-      // auto-refreshes, we do not throw an error so it will show up once it's
-      // available.
+      // polls until the execution exists, we do not throw an error so it will
+      // show up once it's available.
       None
     }
     Err(e) => return Err(e.into()),
@@ -85,39 +321,55 @@ async fn execution_detail_view(
 
   let limit = query.limit.unwrap_or(100);
   let offset = query.offset.unwrap_or(0);
-  let observation_type_filter = match view {
-    ExecutionView::Log => None,
-    ExecutionView::Payload => Some(ObservationType::Payload),
-  };
+  let filter = query.observation_filter(view)?;
 
-  let total_count = metadata
-    .count_observations(execution_id, observation_type_filter)
-    .await?;
-
-  let mut observations = metadata
-    .list_observations(
-      execution_id,
-      Some(limit + 1),
-      Some(offset),
-      observation_type_filter,
+  let search_text = non_empty(&query.q);
+  let mut tree = None;
+  let (observations, total_count, has_next_page) = if view == ExecutionView::Tree {
+    // The tree shows the whole execution, without search or pagination
+    if execution.is_some() {
+      tree = Some(execution_tree(metadata.as_ref(), execution_id).await?);
+    }
+    (Vec::new(), Some(0), false)
+  } else if let Some(text) = search_text {
+    // Search results are not counted
+    let (observations, has_next_page) = search_page(
+      metadata.as_ref(),
+      search.as_ref(),
+      text,
+      Some(execution_id),
+      &filter,
+      limit,
+      offset,
     )
     .await?;
-  let has_next_page = observations.len() > limit;
-  if has_next_page {
-    observations.pop();
-  }
-  let page = (offset / limit) + 1;
+    (observations, None, has_next_page)
+  } else {
+    let total_count = metadata.count_observations(execution_id, &filter).await?;
 
-  let observations: Vec<_> = observations
-    .into_iter()
-    .map(|obs| GetObservation::new(obs))
-    .collect();
+    let mut observations = metadata
+      .list_observations(execution_id, Some(limit + 1), Some(offset), &filter)
+      .await?;
+    let has_next_page = observations.len() > limit;
+    if has_next_page {
+      observations.pop();
+    }
+    let observations: Vec<_> = observations.into_iter().map(GetObservation::new).collect();
+    (observations, Some(total_count), has_next_page)
+  };
+  let page = (offset / limit) + 1;
 
   // If observation ID is provided, load the observation for the side panel
+  let mut snapshot = None;
   let selected_observation = if let Some(obs_id) = &query.obs {
     let observation_id = ObservationId::parse(obs_id)?;
     match metadata.get_observation(observation_id).await {
-      Ok(obs) => Some(GetObservation::new(obs)),
+      Ok(obs) => {
+        let obs_execution_id = obs.observation.execution_id;
+        authorize_read(metadata.as_ref(), api_key.as_ref(), obs_execution_id).await?;
+        snapshot = observation_snapshot(metadata.as_ref(), blobs.as_ref(), &obs).await?;
+        Some(GetObservation::new(obs))
+      }
       Err(StorageError::NotFound(_)) => None,
       Err(e) => return Err(e.into()),
     }
@@ -125,28 +377,146 @@ async fn execution_detail_view(
     None
   };
 
+  // New observations are streamed into the list while the last page of an
+  // existing execution is shown. Search results are ranked, so they are not.
+  let live = execution.is_some()
+    && view != ExecutionView::Tree
+    && search_text.is_none()
+    && !has_next_page;
+
   let env = templates.acquire_env()?;
   let tmpl = env.get_template("execution_detail.html")?;
-  let (view_name, base_path) = match view {
-    ExecutionView::Log => ("log", format!("/exe/{}", id)),
-    ExecutionView::Payload => ("payload", format!("/exe/{}/payload", id)),
-  };
 
   let html = tmpl.render(context! {
       execution => execution,
       execution_id => id,
       observations => observations,
+      tree => tree,
       has_next_page => has_next_page,
       total_count => total_count,
       offset => offset,
       limit => limit,
       page => page,
       selected_observation => selected_observation,
+      snapshot => snapshot,
       display_threshold => observation_tools_shared::DISPLAY_THRESHOLD_BYTES,
       csrf_token => csrf.0,
-      view => view_name,
-      base_path => base_path,
+      nav_bar => nav_bar,
+      view => view.name(),
+      base_path => view.base_path(&nav_bar.scope, &id),
+      live => live,
+      search_query => search_text,
+      filter_params => query.filter_params(),
+      filters_active => filter != ObservationFilter { observation_type: filter.observation_type, ..Default::default() },
+      filters => context! {
+        level => non_empty(&query.level),
+        name => non_empty(&query.name),
+        metadata => non_empty(&query.metadata),
+        created_after => non_empty(&query.created_after),
+        created_before => non_empty(&query.created_before),
+        group => non_empty(&query.group),
+        source_file => non_empty(&query.source_file),
+      },
   })?;
 
   Ok(Html(html))
 }
+
+/// Live observation rows for the execution detail page - Log view
+#[tracing::instrument(skip(metadata, events, templates, api_key))]
+#[allow(clippy::too_many_arguments)]
+pub async fn execution_events_log(
+  State(metadata): State<Arc<dyn MetadataStorage>>,
+  State(events): State<ObservationEvents>,
+  State(templates): State<Arc<AutoReloader>>,
+  api_key: Option<Extension<ApiKeyRecord>>,
+  Path(path): Path<ExecutionPath>,
+  Query(query): Query<ExecutionDetailQuery>,
+  scope: ProjectScope,
+) -> Result<Sse<impl Stream<Item = Result<Event, minijinja::Error>>>, AppError> {
+  execution_events_view(
+    metadata,
+    events,
+    templates,
+    api_key.map(|Extension(key)| key),
+    scope,
+    path.id,
+    query,
+    ExecutionView::Log,
+  )
+  .await
+}
+
+/// Live observation rows for the execution detail page - Payload view
+#[tracing::instrument(skip(metadata, events, templates, api_key))]
+#[allow(clippy::too_many_arguments)]
+pub async fn execution_events_payload(
+  State(metadata): State<Arc<dyn MetadataStorage>>,
+  State(events): State<ObservationEvents>,
+  State(templates): State<Arc<AutoReloader>>,
+  api_key: Option<Extension<ApiKeyRecord>>,
+  Path(path): Path<ExecutionPath>,
+  Query(query): Query<ExecutionDetailQuery>,
+  scope: ProjectScope,
+) -> Result<Sse<impl Stream<Item = Result<Event, minijinja::Error>>>, AppError> {
+  execution_events_view(
+    metadata,
+    events,
+    templates,
+    api_key.map(|Extension(key)| key),
+    scope,
+    path.id,
+    query,
+    ExecutionView::Payload,
+  )
+  .await
+}
+
+/// Stream each new observation matching the page's filters as a rendered list
+/// row, to be appended to the list by the htmx SSE extension
+#[allow(clippy::too_many_arguments)]
+async fn execution_events_view(
+  metadata: Arc<dyn MetadataStorage>,
+  events: ObservationEvents,
+  templates: Arc<AutoReloader>,
+  api_key: Option<ApiKeyRecord>,
+  scope: ProjectScope,
+  id: String,
+  query: ExecutionDetailQuery,
+  view: ExecutionView,
+) -> Result<Sse<impl Stream<Item = Result<Event, minijinja::Error>>>, AppError> {
+  let execution_id = ExecutionId::parse(&id)?;
+  authorize_read(metadata.as_ref(), api_key.as_ref(), execution_id).await?;
+  let filter = query.observation_filter(view)?;
+  let base_path = view.base_path(&scope, &id);
+  let filter_params = query.filter_params();
+  let limit = query.limit.unwrap_or(100);
+  let offset = query.offset.unwrap_or(0);
+
+  let stream = events.subscribe(execution_id).flat_map(move |event| {
+    let events: Vec<Result<Event, minijinja::Error>> = match event {
+      ExecutionEvent::Observations(batch) => batch
+        .observations
+        .iter()
+        .filter(|obs| filter.matches(&obs.observation))
+        .map(|obs| {
+          let env = templates.acquire_env()?;
+          let html = env.get_template("_observation_row.html")?.render(context! {
+            obs => GetObservation::new(obs.clone()),
+            view => view.name(),
+            base_path => base_path,
+            offset => offset,
+            limit => limit,
+            filter_params => filter_params,
+            selected_observation => None::<GetObservation>,
+          })?;
+          // SSE data cannot contain carriage returns
+          Ok(Event::default().event("observation").data(html.replace('\r', "")))
+        })
+        .collect(),
+      ExecutionEvent::Lagged(_) => vec![Ok(Event::default().event("lagged").data(""))],
+    };
+    futures::stream::iter(events)
+  });
+  Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
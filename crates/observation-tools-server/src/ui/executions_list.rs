@@ -1,23 +1,107 @@
 //! List executions page handler
 
-use crate::api::types::ListExecutionsQuery;
 use crate::api::AppError;
+use crate::auth::ApiKeyRecord;
 use crate::csrf::CsrfToken;
+use crate::storage::filter::parse_metadata_pairs;
+use crate::storage::ExecutionFilter;
 use crate::storage::MetadataStorage;
+use crate::ui::execution_detail::non_empty;
+use crate::ui::execution_detail::parse_time;
+use crate::ui::nav::NavBar;
 use axum::extract::Query;
 use axum::extract::State;
 use axum::response::Html;
+use axum::Extension;
 use minijinja::context;
 use minijinja_autoreload::AutoReloader;
+use observation_tools_shared::ExecutionStatus;
 use std::sync::Arc;
 
+/// Query parameters for the executions list page
+#[derive(Debug, serde::Deserialize)]
+pub struct ExecutionsListQuery {
+  /// Maximum number of results to return
+  limit: Option<usize>,
+
+  /// Number of results to skip (for pagination)
+  offset: Option<usize>,
+
+  /// Only show executions with this status
+  status: Option<ExecutionStatus>,
+
+  /// Case-insensitive substring of the execution name
+  name: Option<String>,
+
+  /// Only show executions with this tag
+  tag: Option<String>,
+
+  /// Comma separated `key=value` metadata pairs
+  metadata: Option<String>,
+
+  /// Only show executions created at or after this time
+  created_after: Option<String>,
+
+  /// Only show executions created before this time
+  created_before: Option<String>,
+}
+
+impl ExecutionsListQuery {
+  /// Filters to carry over to status tabs and pagination, as a query string
+  /// fragment starting with `&`
+  fn filter_params(&self) -> String {
+    let mut serializer = url::form_urlencoded::Serializer::new(String::new());
+    for (key, value) in [
+      ("name", &self.name),
+      ("tag", &self.tag),
+      ("metadata", &self.metadata),
+      ("created_after", &self.created_after),
+      ("created_before", &self.created_before),
+    ] {
+      if let Some(value) = non_empty(value) {
+        serializer.append_pair(key, value);
+      }
+    }
+    let params = serializer.finish();
+    if params.is_empty() {
+      params
+    } else {
+      format!("&{}", params)
+    }
+  }
+
+  /// Build the storage filter from the query string. Empty form fields are
+  /// ignored.
+  fn execution_filter(&self) -> Result<ExecutionFilter, AppError> {
+    let metadata = match non_empty(&self.metadata) {
+      Some(pairs) => parse_metadata_pairs(pairs).map_err(AppError::BadRequest)?,
+      None => Default::default(),
+    };
+    Ok(ExecutionFilter {
+      project: None,
+      name: non_empty(&self.name).map(str::to_string),
+      status: self.status,
+      tag: non_empty(&self.tag).map(str::to_string),
+      metadata,
+      created_after: non_empty(&self.created_after)
+        .map(parse_time)
+        .transpose()?,
+      created_before: non_empty(&self.created_before)
+        .map(parse_time)
+        .transpose()?,
+    })
+  }
+}
+
 /// List executions page
-#[tracing::instrument(skip(metadata, templates))]
+#[tracing::instrument(skip(metadata, templates, api_key))]
 pub async fn list_executions(
   State(metadata): State<Arc<dyn MetadataStorage>>,
   State(templates): State<Arc<AutoReloader>>,
-  Query(query): Query<ListExecutionsQuery>,
+  api_key: Option<Extension<ApiKeyRecord>>,
+  Query(query): Query<ExecutionsListQuery>,
   csrf: CsrfToken,
+  nav_bar: NavBar,
 ) -> Result<Html<String>, AppError> {
   let limit = query.limit.unwrap_or(100);
   let offset = query.offset.unwrap_or(0);
@@ -27,9 +111,19 @@ pub async fn list_executions(
     "Rendering executions list page"
   );
 
+  // The nav bar already checked the key may read the page's project
+  let project = nav_bar
+    .project
+    .clone()
+    .or_else(|| api_key.and_then(|Extension(key)| key.project));
+  let filter = ExecutionFilter {
+    project,
+    ..query.execution_filter()?
+  };
+
   // Fetch one extra to determine if there are more pages
   let mut executions = metadata
-    .list_executions(Some(limit + 1), Some(offset))
+    .list_executions(Some(limit + 1), Some(offset), &filter)
     .await?;
 
   let has_next_page = executions.len() > limit;
@@ -38,7 +132,7 @@ pub async fn list_executions(
   }
 
   // Get total count for pagination info
-  let total_count = metadata.count_executions().await?;
+  let total_count = metadata.count_executions(&filter).await?;
   let page = (offset / limit) + 1;
 
   tracing::debug!(
@@ -58,7 +152,24 @@ pub async fn list_executions(
       offset => offset,
       limit => limit,
       page => page,
+      status => query.status,
+      filters => context! {
+        name => query.name,
+        tag => query.tag,
+        metadata => query.metadata,
+        created_after => query.created_after,
+        created_before => query.created_before,
+      },
+      filter_params => query.filter_params(),
+      statuses => [
+        ExecutionStatus::Running,
+        ExecutionStatus::Success,
+        ExecutionStatus::Failure,
+        ExecutionStatus::Cancelled,
+        ExecutionStatus::Abandoned,
+      ],
       csrf_token => csrf.0,
+      nav_bar => nav_bar,
   })?;
 
   Ok(Html(html))
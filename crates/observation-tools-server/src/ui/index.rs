@@ -2,6 +2,7 @@
 
 use crate::api::AppError;
 use crate::csrf::CsrfToken;
+use crate::ui::nav::NavBar;
 use axum::extract::State;
 use axum::response::Html;
 use minijinja::context;
@@ -13,10 +14,11 @@ use std::sync::Arc;
 pub async fn index(
   State(templates): State<Arc<AutoReloader>>,
   csrf: CsrfToken,
+  nav_bar: NavBar,
 ) -> Result<Html<String>, AppError> {
   tracing::debug!("Rendering home page");
   let env = templates.acquire_env()?;
   let tmpl = env.get_template("index.html")?;
-  let html = tmpl.render(context! { csrf_token => csrf.0 })?;
+  let html = tmpl.render(context! { csrf_token => csrf.0, nav_bar => nav_bar })?;
   Ok(Html(html))
 }
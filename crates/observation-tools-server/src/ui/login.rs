@@ -0,0 +1,126 @@
+//! Login page handlers
+
+use crate::api::AppError;
+use crate::csrf::CsrfSecret;
+use crate::csrf::CsrfToken;
+use crate::session::local_redirect;
+use crate::session::logout_cookie;
+use crate::session::ReadAuth;
+use axum::extract::Query;
+use axum::extract::State;
+use axum::http::header;
+use axum::http::HeaderMap;
+use axum::http::StatusCode;
+use axum::response::Html;
+use axum::response::IntoResponse;
+use axum::response::Redirect;
+use axum::response::Response;
+use axum::Form;
+use minijinja::context;
+use minijinja_autoreload::AutoReloader;
+use std::sync::Arc;
+
+/// Query parameters for the login page
+#[derive(Debug, serde::Deserialize)]
+pub struct LoginQuery {
+  /// Page to go to after logging in
+  next: Option<String>,
+}
+
+/// Login form fields
+#[derive(serde::Deserialize)]
+pub struct LoginForm {
+  username: String,
+  password: String,
+  next: Option<String>,
+  csrf_token: String,
+}
+
+/// Login page
+#[tracing::instrument(skip(templates, read_auth))]
+pub async fn login_page(
+  State(templates): State<Arc<AutoReloader>>,
+  State(read_auth): State<Option<ReadAuth>>,
+  Query(query): Query<LoginQuery>,
+  csrf: CsrfToken,
+) -> Result<Response, AppError> {
+  let Some(read_auth) = read_auth else {
+    return Ok(Redirect::to(local_redirect(query.next.as_deref())).into_response());
+  };
+  let html = render_login(&templates, &read_auth, query.next.as_deref(), None, csrf)?;
+  Ok(html.into_response())
+}
+
+/// Check a login and set the session cookie
+#[tracing::instrument(skip_all, fields(username = %form.username))]
+pub async fn login(
+  State(templates): State<Arc<AutoReloader>>,
+  State(read_auth): State<Option<ReadAuth>>,
+  State(csrf_secret): State<CsrfSecret>,
+  headers: HeaderMap,
+  csrf: CsrfToken,
+  Form(form): Form<LoginForm>,
+) -> Result<Response, AppError> {
+  // Forms are posted without the CSRF header, so the token is a form field
+  if let Err(e) = csrf_secret.check(&headers, Some(&form.csrf_token)) {
+    return Ok(e.into_response());
+  }
+  let next = local_redirect(form.next.as_deref());
+  let Some(read_auth) = read_auth else {
+    return Ok(Redirect::to(next).into_response());
+  };
+  let cookie = match read_auth.login(&form.username, &form.password) {
+    Ok(cookie) => cookie,
+    Err(e) => return Ok(e.into_response()),
+  };
+  let Some(cookie) = cookie else {
+    tracing::warn!("Failed login");
+    let html = render_login(
+      &templates,
+      &read_auth,
+      form.next.as_deref(),
+      Some("Invalid username or password"),
+      csrf,
+    )?;
+    return Ok((StatusCode::UNAUTHORIZED, html).into_response());
+  };
+  tracing::info!("User logged in");
+  Ok(
+    (
+      [(header::SET_COOKIE, cookie.to_string())],
+      Redirect::to(next),
+    )
+      .into_response(),
+  )
+}
+
+/// Remove the session cookie. Posted by htmx from the nav bar, so the CSRF
+/// token is checked by [`validate_csrf`](crate::csrf::validate_csrf).
+pub async fn logout() -> Response {
+  (
+    [
+      (header::SET_COOKIE, logout_cookie().to_string()),
+      (header::HeaderName::from_static("hx-redirect"), "/login".to_string()),
+    ],
+    Redirect::to("/login"),
+  )
+    .into_response()
+}
+
+fn render_login(
+  templates: &AutoReloader,
+  read_auth: &ReadAuth,
+  next: Option<&str>,
+  error: Option<&str>,
+  csrf: CsrfToken,
+) -> Result<Html<String>, AppError> {
+  let env = templates.acquire_env()?;
+  let tmpl = env.get_template("login.html")?;
+  let html = tmpl.render(context! {
+      next => local_redirect(next),
+      error => error,
+      has_users => read_auth.has_users(),
+      csrf_token => csrf.0,
+  })?;
+  Ok(Html(html))
+}
@@ -1,14 +1,28 @@
 //! Web UI handlers
 
+mod diff;
 pub mod execution_detail;
 mod executions_list;
 mod index;
+mod login;
+pub mod nav;
 mod observation_detail;
+mod snapshot;
 mod templates;
 
+pub use diff::diff_executions;
+pub use diff::diff_payload;
 pub use execution_detail::execution_detail_log;
 pub use execution_detail::execution_detail_payload;
+pub use execution_detail::execution_detail_tree;
+pub use execution_detail::execution_events_log;
+pub use execution_detail::execution_events_payload;
 pub use executions_list::list_executions;
 pub use index::index;
+pub use login::login;
+pub use login::login_page;
+pub use login::logout;
 pub use observation_detail::observation_detail;
+pub use snapshot::accept_snapshot_baseline;
 pub use templates::init_templates;
+pub(crate) use templates::parse_json_value;
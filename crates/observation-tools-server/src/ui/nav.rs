@@ -0,0 +1,108 @@
+//! Project scope and navigation bar context shared by the UI pages
+
+use crate::api::authorize_project;
+use crate::api::AppError;
+use crate::auth::ApiKeyRecord;
+use crate::session::CurrentUser;
+use crate::storage::MetadataStorage;
+use crate::storage::StorageError;
+use axum::extract::FromRef;
+use axum::extract::FromRequestParts;
+use axum::extract::RawPathParams;
+use axum::http::request::Parts;
+use observation_tools_shared::Execution;
+use observation_tools_shared::Project;
+use observation_tools_shared::ProjectId;
+use serde::Serialize;
+use std::sync::Arc;
+
+/// The project a page is scoped to by the `/p/{project}` route prefix, or
+/// `None` for pages showing every project
+///
+/// Scopes only narrow down what is listed. Logged in users may see every
+/// project, so they are not an access control. API keys bound to a project
+/// are checked by each handler, like in the API.
+#[derive(Debug, Clone, Default)]
+pub struct ProjectScope(pub Option<ProjectId>);
+
+impl ProjectScope {
+  /// Path of the executions list in this scope, which execution pages are
+  /// nested under
+  pub fn exe_base(&self) -> String {
+    match &self.0 {
+      Some(project) => format!("/p/{}/exe", project),
+      None => "/exe".to_string(),
+    }
+  }
+
+  /// Whether an execution belongs in this scope
+  pub fn contains(&self, execution: &Execution) -> bool {
+    self.0.as_ref().is_none_or(|project| execution.project == *project)
+  }
+}
+
+impl<S> FromRequestParts<S> for ProjectScope
+where
+  S: Send + Sync,
+{
+  type Rejection = AppError;
+
+  async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+    // Routes without parameters have none to extract
+    let Ok(params) = RawPathParams::from_request_parts(parts, state).await else {
+      return Ok(ProjectScope(None));
+    };
+    let project = params
+      .iter()
+      .find(|(key, _)| *key == "project")
+      .map(|(_, value)| ProjectId::parse(value))
+      .transpose()?;
+    Ok(ProjectScope(project))
+  }
+}
+
+/// What the nav bar shows: the logged in user and the project switcher
+#[derive(Debug, Clone, Serialize)]
+pub struct NavBar {
+  pub user: Option<String>,
+  /// Every project the request may read, for switching between them
+  pub projects: Vec<Project>,
+  /// The project the page is scoped to
+  pub project: Option<ProjectId>,
+  /// Path of the executions list in the page's scope
+  pub exe_base: String,
+  #[serde(skip)]
+  pub scope: ProjectScope,
+}
+
+impl<S> FromRequestParts<S> for NavBar
+where
+  S: Send + Sync,
+  Arc<dyn MetadataStorage>: FromRef<S>,
+{
+  type Rejection = AppError;
+
+  async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+    let Ok(CurrentUser(user)) = CurrentUser::from_request_parts(parts, state).await;
+    let scope = ProjectScope::from_request_parts(parts, state).await?;
+    let metadata = Arc::<dyn MetadataStorage>::from_ref(state);
+    let api_key = parts.extensions.get::<ApiKeyRecord>();
+    let mut projects = metadata.list_projects().await?;
+    if let Some(key) = api_key {
+      projects.retain(|p| key.allows_project(&p.id));
+    }
+    if let Some(project) = &scope.0 {
+      authorize_project(api_key, project)?;
+      if !projects.iter().any(|p| p.id == *project) {
+        return Err(StorageError::NotFound(format!("Project {} not found", project)).into());
+      }
+    }
+    Ok(NavBar {
+      user,
+      projects,
+      project: scope.0.clone(),
+      exe_base: scope.exe_base(),
+      scope,
+    })
+  }
+}
@@ -1,23 +1,43 @@
 //! Observation detail page handler
 
+use crate::api::authorize_read;
 use crate::api::observations::GetObservation;
 use crate::api::AppError;
+use crate::auth::ApiKeyRecord;
 use crate::csrf::CsrfToken;
+use crate::storage::BlobStorage;
 use crate::storage::MetadataStorage;
+use crate::ui::nav::NavBar;
+use crate::ui::snapshot::observation_snapshot;
 use axum::extract::Path;
 use axum::extract::State;
 use axum::response::Html;
+use axum::Extension;
 use minijinja::context;
 use minijinja_autoreload::AutoReloader;
 use std::sync::Arc;
 
+/// Path parameters of the observation page, which may be nested in a project
+#[derive(Debug, serde::Deserialize)]
+pub struct ObservationPath {
+  execution_id: String,
+  observation_id: String,
+}
+
 /// Observation detail (for the side panel)
-#[tracing::instrument(skip(metadata, templates))]
+#[tracing::instrument(skip(metadata, blobs, templates, api_key))]
+#[allow(clippy::too_many_arguments)]
 pub async fn observation_detail(
   State(metadata): State<Arc<dyn MetadataStorage>>,
+  State(blobs): State<Arc<dyn BlobStorage>>,
   State(templates): State<Arc<AutoReloader>>,
-  Path((execution_id, observation_id)): Path<(String, String)>,
+  api_key: Option<Extension<ApiKeyRecord>>,
+  Path(ObservationPath {
+    execution_id,
+    observation_id,
+  }): Path<ObservationPath>,
   csrf: CsrfToken,
+  nav_bar: NavBar,
 ) -> Result<Html<String>, AppError> {
   tracing::debug!(
       execution_id = %execution_id,
@@ -25,8 +45,14 @@ pub async fn observation_detail(
       "Rendering observation detail page"
   );
   let parsed_observation_id = observation_tools_shared::ObservationId::parse(&observation_id)?;
+  let api_key = api_key.as_ref().map(|Extension(key)| key);
+  let mut snapshot = None;
   let observation = match metadata.get_observation(parsed_observation_id).await {
-    Ok(obs) => Some(GetObservation::new(obs)),
+    Ok(obs) => {
+      authorize_read(metadata.as_ref(), api_key, obs.observation.execution_id).await?;
+      snapshot = observation_snapshot(metadata.as_ref(), blobs.as_ref(), &obs).await?;
+      Some(GetObservation::new(obs))
+    }
     Err(crate::storage::StorageError::NotFound(_)) => {
       // The user may go to the observation page before it's uploaded. Since the page
       // auto-refreshes, we do not throw an error so it will show up once it's
@@ -39,10 +65,12 @@ pub async fn observation_detail(
   let tmpl = env.get_template("observation_detail.html")?;
   let html = tmpl.render(context! {
       observation => observation,
+      snapshot => snapshot,
       execution_id => execution_id,
       observation_id => observation_id,
       display_threshold => observation_tools_shared::DISPLAY_THRESHOLD_BYTES,
       csrf_token => csrf.0,
+      nav_bar => nav_bar,
   })?;
   Ok(Html(html))
 }
@@ -0,0 +1,76 @@
+//! Snapshot baseline section of the observation panel
+
+use crate::api::authorize_project;
+use crate::api::AppError;
+use crate::auth::ApiKeyRecord;
+use crate::snapshot::accept_snapshot;
+use crate::snapshot::compare_with_baseline;
+use crate::snapshot::SnapshotCheck;
+use crate::snapshot::SnapshotStatus;
+use crate::storage::BlobStorage;
+use crate::storage::MetadataStorage;
+use crate::storage::ObservationWithPayloads;
+use crate::storage::StorageError;
+use crate::ui::nav::NavBar;
+use axum::extract::Path;
+use axum::extract::State;
+use axum::response::Html;
+use axum::Extension;
+use minijinja::context;
+use minijinja_autoreload::AutoReloader;
+use observation_tools_shared::ObservationId;
+use std::sync::Arc;
+
+/// Path parameters of a snapshot action, which may be nested in a project
+#[derive(Debug, serde::Deserialize)]
+pub struct SnapshotPath {
+  observation_id: String,
+}
+
+/// Compare an observation shown in the UI with its snapshot baseline. Nothing
+/// is shown for observations that cannot be compared, e.g. without payloads
+/// or before their execution was uploaded.
+pub(crate) async fn observation_snapshot(
+  metadata: &dyn MetadataStorage,
+  blobs: &dyn BlobStorage,
+  observation: &ObservationWithPayloads,
+) -> Result<Option<SnapshotCheck>, AppError> {
+  let execution = match metadata.get_execution(observation.observation.execution_id).await {
+    Ok(execution) => execution,
+    Err(StorageError::NotFound(_)) => return Ok(None),
+    Err(e) => return Err(e.into()),
+  };
+  match compare_with_baseline(metadata, blobs, &execution, observation).await {
+    Err(AppError::BadRequest(_)) => Ok(None),
+    result => result,
+  }
+}
+
+/// Make the observation the baseline of its snapshot, returning the updated
+/// snapshot section
+#[tracing::instrument(skip(metadata, templates, api_key))]
+pub async fn accept_snapshot_baseline(
+  State(metadata): State<Arc<dyn MetadataStorage>>,
+  State(templates): State<Arc<AutoReloader>>,
+  api_key: Option<Extension<ApiKeyRecord>>,
+  Path(SnapshotPath { observation_id }): Path<SnapshotPath>,
+  nav_bar: NavBar,
+) -> Result<Html<String>, AppError> {
+  let observation = metadata.get_observation(ObservationId::parse(&observation_id)?).await?;
+  let execution = metadata.get_execution(observation.observation.execution_id).await?;
+  authorize_project(api_key.as_ref().map(|Extension(key)| key), &execution.project)?;
+  let baseline = accept_snapshot(metadata.as_ref(), &execution, &observation).await?;
+  let snapshot = SnapshotCheck {
+    status: SnapshotStatus::Accepted,
+    baseline,
+    diff: None,
+  };
+  let env = templates.acquire_env()?;
+  let tmpl = env.get_template("_snapshot.html")?;
+  let html = tmpl.render(context! {
+      observation => observation.observation,
+      snapshot => snapshot,
+      nav_bar => nav_bar,
+  })?;
+  Ok(Html(html))
+}
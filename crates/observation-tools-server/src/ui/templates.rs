@@ -1,5 +1,6 @@
 //! Template initialization and filters
 
+use chrono::DateTime;
 use minijinja::path_loader;
 use minijinja::Environment;
 use minijinja::Value;
@@ -48,20 +49,47 @@ pub fn render_markdown(value: String) -> String {
 /// Parse a JSON string and return a result object for template rendering.
 /// Returns { ok: true, value: <parsed> } on success, { ok: false } on failure.
 pub fn parse_json(value: String) -> Value {
-  match serde_json::from_str::<serde_json::Value>(&value) {
-    Ok(json) => {
-      let mut map = std::collections::BTreeMap::new();
+  let mut map = std::collections::BTreeMap::new();
+  match parse_json_value(&value) {
+    Some(json) => {
       map.insert("ok".to_string(), Value::from(true));
       map.insert("value".to_string(), Value::from_serialize(&json));
-      Value::from_iter(map)
     }
-    Err(e) => {
-      error!("Failed to parse JSON in parse_json filter: {}", e);
-      let mut map = std::collections::BTreeMap::new();
+    None => {
       map.insert("ok".to_string(), Value::from(false));
-      Value::from_iter(map)
     }
   }
+  Value::from_iter(map)
+}
+
+/// Parse a JSON string, logging invalid JSON. Shared by the `parse_json`
+/// filter and payload diffs.
+pub fn parse_json_value(value: &str) -> Option<serde_json::Value> {
+  serde_json::from_str(value)
+    .inspect_err(|e| error!("Failed to parse JSON: {}", e))
+    .ok()
+}
+
+/// Format the time between two RFC 3339 timestamps, e.g. `1h 2m` or `3.5s`
+pub fn duration_filter(start: String, end: String) -> String {
+  let (Ok(start), Ok(end)) = (
+    DateTime::parse_from_rfc3339(&start),
+    DateTime::parse_from_rfc3339(&end),
+  ) else {
+    error!("Invalid timestamps in duration filter: {} - {}", start, end);
+    return String::new();
+  };
+  let millis = (end - start).num_milliseconds().max(0);
+  let seconds = millis / 1000;
+  if seconds >= 60 * 60 {
+    format!("{}h {}m", seconds / 3600, seconds % 3600 / 60)
+  } else if seconds >= 60 {
+    format!("{}m {}s", seconds / 60, seconds % 60)
+  } else if seconds >= 1 {
+    format!("{:.1}s", millis as f64 / 1000.0)
+  } else {
+    format!("{}ms", millis)
+  }
 }
 
 /// Initialize the template auto-reloader
@@ -81,6 +109,7 @@ pub fn init_templates() -> Arc<AutoReloader> {
     env.add_filter("items", items_filter);
     env.add_filter("render_markdown", render_markdown);
     env.add_filter("parse_json", parse_json);
+    env.add_filter("duration", duration_filter);
 
     if cfg!(debug_assertions) {
       tracing::info!("Running in local development mode, enabling autoreload for templates");
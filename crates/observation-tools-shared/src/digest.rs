@@ -0,0 +1,51 @@
+//! Content digests identifying payload data for deduplication
+
+use sha2::Digest;
+use sha2::Sha256;
+
+/// SHA-256 of payload data before compression, as lowercase hex. The server
+/// stores payloads with the same digest only once.
+pub fn content_digest(data: &[u8]) -> String {
+  format!("{:x}", Sha256::digest(data))
+}
+
+/// Whether `s` has the format of a digest from [`content_digest`]
+pub fn is_content_digest(s: &str) -> bool {
+  s.len() == 64 && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Computes a [`content_digest`] over data received in chunks
+#[derive(Debug, Clone, Default)]
+pub struct ContentDigest(Sha256);
+
+impl ContentDigest {
+  pub fn update(&mut self, data: &[u8]) {
+    self.0.update(data);
+  }
+
+  pub fn finish(self) -> String {
+    format!("{:x}", self.0.finalize())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_content_digest() {
+    let digest = content_digest(b"hello world");
+    assert_eq!(
+      digest,
+      "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+    );
+    assert!(is_content_digest(&digest));
+    assert!(!is_content_digest(&digest.to_uppercase()));
+    assert!(!is_content_digest("../etc/passwd"));
+
+    let mut incremental = ContentDigest::default();
+    incremental.update(b"hello ");
+    incremental.update(b"world");
+    assert_eq!(incremental.finish(), digest);
+  }
+}
@@ -0,0 +1,116 @@
+//! Content encodings for compressed payloads
+
+use crate::error::Error;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+
+/// Compression applied to payload bytes, named after the matching HTTP
+/// `Content-Encoding` values
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentEncoding {
+  /// Not compressed
+  #[default]
+  Identity,
+  Gzip,
+  Zstd,
+}
+
+impl ContentEncoding {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      ContentEncoding::Identity => "identity",
+      ContentEncoding::Gzip => "gzip",
+      ContentEncoding::Zstd => "zstd",
+    }
+  }
+
+  pub fn is_identity(&self) -> bool {
+    *self == ContentEncoding::Identity
+  }
+}
+
+impl fmt::Display for ContentEncoding {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
+impl FromStr for ContentEncoding {
+  type Err = Error;
+
+  /// Parse an encoding name, where an empty name means no compression
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.trim().to_ascii_lowercase().as_str() {
+      "" | "identity" => Ok(ContentEncoding::Identity),
+      "gzip" | "x-gzip" => Ok(ContentEncoding::Gzip),
+      "zstd" => Ok(ContentEncoding::Zstd),
+      _ => Err(Error::UnknownContentEncoding(s.to_string())),
+    }
+  }
+}
+
+#[cfg(feature = "compression")]
+impl ContentEncoding {
+  /// Compress `data`
+  pub fn encode(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+    use std::io::Write;
+    match self {
+      ContentEncoding::Identity => Ok(data.to_vec()),
+      ContentEncoding::Gzip => {
+        let mut encoder =
+          flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data)?;
+        encoder.finish()
+      }
+      ContentEncoding::Zstd => zstd::encode_all(data, zstd::DEFAULT_COMPRESSION_LEVEL),
+    }
+  }
+
+  /// Decompress `data`, failing if it expands to more than `max_size` bytes
+  pub fn decode(&self, data: &[u8], max_size: usize) -> std::io::Result<Vec<u8>> {
+    use std::io::Read;
+    let decoder: Box<dyn Read + '_> = match self {
+      ContentEncoding::Identity => return Ok(data.to_vec()),
+      ContentEncoding::Gzip => Box::new(flate2::read::GzDecoder::new(data)),
+      ContentEncoding::Zstd => Box::new(zstd::stream::read::Decoder::new(data)?),
+    };
+    let mut decoded = Vec::new();
+    decoder
+      .take((max_size as u64).saturating_add(1))
+      .read_to_end(&mut decoded)?;
+    if decoded.len() > max_size {
+      return Err(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("{} data expands to more than {} bytes", self, max_size),
+      ));
+    }
+    Ok(decoded)
+  }
+}
+
+#[cfg(all(test, feature = "compression"))]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_round_trip_and_size_limit() {
+    let data = br#"{"key": "value", "values": [1, 2, 3]}"#.repeat(100);
+    for encoding in [
+      ContentEncoding::Identity,
+      ContentEncoding::Gzip,
+      ContentEncoding::Zstd,
+    ] {
+      let encoded = encoding.encode(&data).unwrap();
+      if !encoding.is_identity() {
+        assert!(encoded.len() < data.len() / 10, "{} barely compressed", encoding);
+        assert!(encoding.decode(&encoded, data.len() - 1).is_err());
+      }
+      assert_eq!(encoding.decode(&encoded, data.len()).unwrap(), data);
+      assert_eq!(encoding.as_str().parse::<ContentEncoding>().unwrap(), encoding);
+    }
+    assert!("br".parse::<ContentEncoding>().is_err());
+  }
+}
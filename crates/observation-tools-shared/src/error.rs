@@ -12,6 +12,14 @@ pub enum Error {
   /// Invalid observation ID format
   #[error("Invalid observation ID: {0}")]
   InvalidObservationId(uuid::Error),
+
+  /// Unsupported payload content encoding
+  #[error("Unknown content encoding: {0}")]
+  UnknownContentEncoding(String),
+
+  /// Invalid project ID format
+  #[error("Invalid project ID {0:?}: use lowercase letters, digits, '-' or '_'")]
+  InvalidProjectId(String),
 }
 
 /// Result type for shared crate operations
@@ -1,17 +1,26 @@
 //! Shared types and models for observation-tools
 
+mod digest;
+mod encoding;
 pub mod error;
 mod group_id;
 pub mod models;
 mod observation;
 mod payload;
 mod payload_id;
+mod project;
+mod quota;
 
+pub use digest::content_digest;
+pub use digest::is_content_digest;
+pub use digest::ContentDigest;
+pub use encoding::ContentEncoding;
 pub use error::Error;
 pub use error::Result;
 pub use group_id::GroupId;
 pub use models::Execution;
 pub use models::ExecutionId;
+pub use models::ExecutionStatus;
 pub use observation::LogLevel;
 pub use observation::Observation;
 pub use observation::ObservationId;
@@ -23,11 +32,18 @@ pub use payload::Payload;
 pub use payload::PayloadBuilder;
 pub use payload::MIME_TYPE_RUST_DEBUG;
 pub use payload_id::PayloadId;
+pub use project::Project;
+pub use project::ProjectId;
+pub use quota::Quota;
+pub use quota::QuotaExceeded;
 
 /// Payload size threshold for blob storage (64KB)
 /// Payloads larger than this will be uploaded as separate blobs
 pub const BLOB_THRESHOLD_BYTES: usize = 65536;
 
+/// Payloads smaller than this are not compressed (1KB)
+pub const COMPRESSION_MIN_BYTES: usize = 1024;
+
 /// Number of observations to batch before uploading
 pub const BATCH_SIZE: usize = 100;
 
@@ -4,6 +4,7 @@ use chrono::DateTime;
 use chrono::Utc;
 use serde::Deserialize;
 use serde::Serialize;
+use crate::ProjectId;
 use std::collections::HashMap;
 use utoipa::ToSchema;
 use uuid::Uuid;
@@ -48,7 +49,30 @@ impl Default for ExecutionId {
 
 impl std::fmt::Display for ExecutionId {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    write!(f, "{}", self.0.as_simple().to_string())
+    write!(f, "{}", self.0.as_simple())
+  }
+}
+
+/// Lifecycle state of an execution
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub enum ExecutionStatus {
+  /// Still running, or finished without reporting a status
+  #[default]
+  Running,
+  /// Finished successfully
+  Success,
+  /// Finished with an error
+  Failure,
+  /// Stopped before completing
+  Cancelled,
+  /// Stopped sending data without finishing, as detected by the server
+  Abandoned,
+}
+
+impl ExecutionStatus {
+  /// Whether the execution has ended
+  pub fn is_finished(self) -> bool {
+    self != ExecutionStatus::Running
   }
 }
 
@@ -62,15 +86,35 @@ pub struct Execution {
   /// User-defined name for this execution
   pub name: String,
 
+  /// Project this execution belongs to
+  #[serde(default)]
+  pub project: ProjectId,
+
   /// User-defined metadata as key-value pairs
   #[serde(default)]
   pub metadata: HashMap<String, String>,
 
+  /// User-defined labels, e.g. `ci` or `nightly`
+  #[serde(default)]
+  pub tags: Vec<String>,
+
   /// When this execution was created
   pub created_at: DateTime<Utc>,
 
   /// When this execution was last updated
   pub updated_at: DateTime<Utc>,
+
+  /// Whether this execution is still running or how it finished
+  #[serde(default)]
+  pub status: ExecutionStatus,
+
+  /// When this execution finished
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub ended_at: Option<DateTime<Utc>>,
+
+  /// Error message for a failed execution
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub error: Option<String>,
 }
 
 impl Execution {
@@ -80,9 +124,14 @@ impl Execution {
     Self {
       id: ExecutionId::new(),
       name: name.into(),
+      project: ProjectId::default(),
       metadata: HashMap::new(),
+      tags: Vec::new(),
       created_at: now,
       updated_at: now,
+      status: ExecutionStatus::Running,
+      ended_at: None,
+      error: None,
     }
   }
 
@@ -96,9 +145,14 @@ impl Execution {
     Self {
       id,
       name: name.into(),
+      project: ProjectId::default(),
       metadata: HashMap::new(),
+      tags: Vec::new(),
       created_at: now,
       updated_at: now,
+      status: ExecutionStatus::Running,
+      ended_at: None,
+      error: None,
     }
   }
 
@@ -108,9 +162,25 @@ impl Execution {
     Self {
       id: ExecutionId::new(),
       name: name.into(),
+      project: ProjectId::default(),
       metadata,
+      tags: Vec::new(),
       created_at: now,
       updated_at: now,
+      status: ExecutionStatus::Running,
+      ended_at: None,
+      error: None,
     }
   }
+
+  /// Move this execution into a project
+  pub fn in_project(mut self, project: ProjectId) -> Self {
+    self.project = project;
+    self
+  }
+
+  /// How long the execution ran, if it has finished
+  pub fn duration(&self) -> Option<chrono::Duration> {
+    self.ended_at.map(|ended_at| ended_at - self.created_at)
+  }
 }
@@ -49,7 +49,7 @@ impl Default for ObservationId {
 
 impl std::fmt::Display for ObservationId {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    write!(f, "{}", self.0.as_simple().to_string())
+    write!(f, "{}", self.0.as_simple())
   }
 }
 
@@ -104,8 +104,10 @@ pub enum ObservationType {
   Group,
 }
 
-/// Log level for observations
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+/// Log level for observations, ordered from least to most severe
+#[derive(
+  Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ToSchema,
+)]
 pub enum LogLevel {
   Trace,
   Debug,
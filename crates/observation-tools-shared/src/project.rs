@@ -0,0 +1,125 @@
+//! Projects group the executions of one team or product
+
+use chrono::DateTime;
+use chrono::Utc;
+use serde::Deserialize;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Maximum length of a project ID
+const MAX_PROJECT_ID_LEN: usize = 64;
+
+/// Identifier of a project, used in URLs
+///
+/// Project IDs are slugs: 1 to 64 lowercase ASCII letters, digits, `-` or
+/// `_`, starting with a letter or digit. Executions that don't name a
+/// project belong to the [default](ProjectId::default) project.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, ToSchema)]
+#[serde(transparent)]
+#[schema(value_type = String, example = "default")]
+pub struct ProjectId(String);
+
+impl ProjectId {
+  /// ID of the project executions belong to unless they name another one
+  pub const DEFAULT: &'static str = "default";
+
+  /// Parse and validate a project ID
+  pub fn parse(s: &str) -> crate::Result<Self> {
+    let valid = !s.is_empty()
+      && s.len() <= MAX_PROJECT_ID_LEN
+      && s.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+      && s
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+    if !valid {
+      return Err(crate::Error::InvalidProjectId(s.to_string()));
+    }
+    Ok(Self(s.to_string()))
+  }
+
+  /// Get the string value of this project ID
+  pub fn as_str(&self) -> &str {
+    &self.0
+  }
+
+  /// Whether this is the default project
+  pub fn is_default(&self) -> bool {
+    self.0 == Self::DEFAULT
+  }
+}
+
+impl Default for ProjectId {
+  fn default() -> Self {
+    Self(Self::DEFAULT.to_string())
+  }
+}
+
+impl<'de> Deserialize<'de> for ProjectId {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: serde::Deserializer<'de>,
+  {
+    let s = String::deserialize(deserializer)?;
+    Self::parse(&s).map_err(serde::de::Error::custom)
+  }
+}
+
+impl std::str::FromStr for ProjectId {
+  type Err = crate::Error;
+
+  fn from_str(s: &str) -> crate::Result<Self> {
+    Self::parse(s)
+  }
+}
+
+impl std::fmt::Display for ProjectId {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_str(&self.0)
+  }
+}
+
+/// A project, grouping the executions of one team or product
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Project {
+  /// Unique identifier for this project
+  pub id: ProjectId,
+
+  /// Display name for this project
+  pub name: String,
+
+  /// When this project was created
+  pub created_at: DateTime<Utc>,
+}
+
+impl Project {
+  /// Create a new project
+  pub fn new(id: ProjectId, name: impl Into<String>) -> Self {
+    Self {
+      id,
+      name: name.into(),
+      created_at: Utc::now(),
+    }
+  }
+
+  /// The default project, named after its ID
+  pub fn default_project() -> Self {
+    Self::new(ProjectId::default(), ProjectId::DEFAULT)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_project_id() {
+    for valid in ["default", "team-a", "ci_nightly", "2024"] {
+      assert_eq!(ProjectId::parse(valid).unwrap().as_str(), valid);
+    }
+    for invalid in ["", "Team", "-a", "a/b", "a b", &"a".repeat(65)] {
+      assert!(ProjectId::parse(invalid).is_err(), "{invalid:?}");
+    }
+    assert!(serde_json::from_str::<ProjectId>("\"../x\"").is_err());
+    assert!(ProjectId::default().is_default());
+  }
+}
@@ -0,0 +1,79 @@
+//! Server quotas and the error body returned when a request exceeds one
+
+use serde::Deserialize;
+use serde::Serialize;
+use std::fmt;
+
+/// A limit the server enforces on stored observations
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Quota {
+  /// Observations stored for one execution
+  ExecutionObservations,
+  /// Payload bytes stored for one execution
+  ExecutionBytes,
+  /// Payload bytes uploaded with one API key per UTC day
+  ApiKeyDailyBytes,
+}
+
+impl Quota {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Quota::ExecutionObservations => "execution_observations",
+      Quota::ExecutionBytes => "execution_bytes",
+      Quota::ApiKeyDailyBytes => "api_key_daily_bytes",
+    }
+  }
+}
+
+impl fmt::Display for Quota {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
+/// Error body of a request rejected because it would exceed a quota. Sent
+/// with status 413 for per-execution quotas, which retrying cannot fix, and
+/// 429 with a `Retry-After` header for daily quotas.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuotaExceeded {
+  /// Human-readable description
+  pub error: String,
+  pub quota: Quota,
+  pub limit: u64,
+  /// Amount used before the request
+  pub used: u64,
+  /// Amount the request would have added
+  pub requested: u64,
+}
+
+impl QuotaExceeded {
+  pub fn new(quota: Quota, limit: u64, used: u64, requested: u64) -> Self {
+    let unit = match quota {
+      Quota::ExecutionObservations => "observations",
+      Quota::ExecutionBytes | Quota::ApiKeyDailyBytes => "bytes",
+    };
+    let scope = match quota {
+      Quota::ExecutionObservations | Quota::ExecutionBytes => "per execution",
+      Quota::ApiKeyDailyBytes => "per API key per day",
+    };
+    QuotaExceeded {
+      error: format!(
+        "Quota {} exceeded: limit of {} {} {}, {} used, {} requested",
+        quota, limit, unit, scope, used, requested
+      ),
+      quota,
+      limit,
+      used,
+      requested,
+    }
+  }
+}
+
+impl fmt::Display for QuotaExceeded {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(&self.error)
+  }
+}
+
+impl std::error::Error for QuotaExceeded {}
@@ -11,14 +11,27 @@ export enum TestId {
   // Executions List
   ExecutionLink = "ExecutionLink",
   ExecutionsListEmpty = "ExecutionsListEmpty",
+  ExecutionStatusFilter = "ExecutionStatusFilter",
+  ExecutionNameFilter = "ExecutionNameFilter",
+  ExecutionTagFilter = "ExecutionTagFilter",
+  ExecutionMetadataFilter = "ExecutionMetadataFilter",
+  ExecutionCreatedAfterFilter = "ExecutionCreatedAfterFilter",
+  ExecutionCreatedBeforeFilter = "ExecutionCreatedBeforeFilter",
+  ExecutionTag = "ExecutionTag",
 
   // Execution Detail
   ExecutionDetailTitle = "ExecutionDetailTitle",
   ExecutionDetailId = "ExecutionDetailId",
+  ExecutionStatus = "ExecutionStatus",
+  ExecutionDuration = "ExecutionDuration",
+  ExecutionError = "ExecutionError",
   ViewTabLog = "ViewTabLog",
   ViewTabPayload = "ViewTabPayload",
 
   // Observations
+  ObservationRows = "ObservationRows",
+  ObservationSearchInput = "ObservationSearchInput",
+  ObservationFilterLevel = "ObservationFilterLevel",
   ObservationListItem = "ObservationListItem",
   ObservationListItemLink = "ObservationListItemLink",
   ObservationId = "ObservationId",